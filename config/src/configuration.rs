@@ -126,7 +126,7 @@ pub struct FileConfig {
 /// Configuration for pipeline operations.
 #[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[display(
-    "PipelineConfig(current_pipeline: {current_pipeline}, default: {default}, default_params_file: {default_params_file}, process_pool_size: {process_pool_size})"
+    "PipelineConfig(current_pipeline: {current_pipeline}, default: {default}, default_params_file: {default_params_file}, process_pool_size: {process_pool_size}, default_step_timeout: {default_step_timeout}, timeout_grace_period: {timeout_grace_period}, run_cache: {run_cache}, track_outputs: {track_outputs})"
 )]
 #[serde(deny_unknown_fields)]
 pub struct PipelineConfig {
@@ -138,6 +138,15 @@ pub struct PipelineConfig {
     pub default_params_file: String,
     /// The number of command processes to run concurrently in a pipeline.
     pub process_pool_size: u32,
+    /// Seconds a step's command can run before it's terminated, unless the step sets its own timeout.
+    pub default_step_timeout: u64,
+    /// Seconds to wait after SIGTERM before a timed out command is killed with SIGKILL.
+    pub timeout_grace_period: u64,
+    /// Whether to cache step outputs by their dependencies and restore them instead of running
+    /// the step again. Off by default.
+    pub run_cache: bool,
+    /// Whether to track the outputs of steps that run with `xvc file track`.
+    pub track_outputs: bool,
 }
 
 /// Configuration for checking ignored files.
@@ -149,10 +158,51 @@ pub struct CheckIgnoreConfig {
     pub details: bool,
 }
 
+/// Configuration for storage operations.
+#[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[display(
+    "StorageConfig(bring_order: {bring_order:?}, pack_threshold: {pack_threshold}, pack_size: {pack_size}, multipart_part_size: {multipart_part_size}, multipart_concurrency: {multipart_concurrency}, max_bandwidth: {max_bandwidth}, max_concurrency: {max_concurrency}, limits: {limits:?}, limit_rate: {limit_rate})"
+)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Names or GUIDs of the storages that `xvc file bring` tries in order, when no storage is given.
+    pub bring_order: Vec<String>,
+    /// Files smaller than this many bytes are sent in pack objects. 0 disables packing.
+    pub pack_threshold: u64,
+    /// Maximum size of a pack object in bytes.
+    pub pack_size: u64,
+    /// Size of the parts in multipart uploads in bytes.
+    pub multipart_part_size: u64,
+    /// Number of parts uploaded concurrently in multipart uploads.
+    pub multipart_concurrency: usize,
+    /// Maximum transfer rate to and from each storage, e.g. `10M`. `0` is unlimited.
+    pub max_bandwidth: String,
+    /// Maximum number of concurrent transfers for each storage. `0` uses the storage settings.
+    pub max_concurrency: usize,
+    /// Limits of individual storages that override `max_bandwidth` and `max_concurrency`, e.g. `["nas.max_bandwidth=1M"]`
+    pub limits: Vec<String>,
+    /// Transfer rate that overrides `max_bandwidth` and `limits` for all storages. Set by
+    /// `--limit-rate` of `xvc file send` and `xvc file bring`. Empty uses the other settings.
+    pub limit_rate: String,
+}
+
+/// Configuration for storage credentials.
+#[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[display("CredentialConfig(helper: {helper}, file: {file})")]
+#[serde(deny_unknown_fields)]
+pub struct CredentialConfig {
+    /// The command to run to get storage credentials, in the style of `git credential` helpers.
+    /// Empty string disables the helper.
+    pub helper: String,
+    /// Path of the credentials file.
+    /// Empty string uses `credentials.toml` in the user configuration directory.
+    pub file: String,
+}
+
 /// The top-level Xvc configuration structure.
 #[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[display(
-    "XvcConfiguration(core: {core}, git: {git}, cache: {cache}, file: {file}, pipeline: {pipeline}, check_ignore: {check_ignore})"
+    "XvcConfiguration(core: {core}, git: {git}, cache: {cache}, file: {file}, pipeline: {pipeline}, check_ignore: {check_ignore}, storage: {storage}, credential: {credential})"
 )]
 #[serde(deny_unknown_fields)]
 pub struct XvcConfiguration {
@@ -169,6 +219,10 @@ pub struct XvcConfiguration {
     /// Check ignore settings.
     #[serde(rename = "check-ignore")]
     pub check_ignore: CheckIgnoreConfig,
+    /// Storage operation settings.
+    pub storage: StorageConfig,
+    /// Storage credential settings.
+    pub credential: CredentialConfig,
 }
 
 /// Optional core configuration for Xvc, used for partial updates.
@@ -292,7 +346,7 @@ pub struct OptionalFileConfig {
 /// Optional configuration for pipeline operations, used for partial updates.
 #[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize, Default)]
 #[display(
-    "OptionalPipelineConfig(current_pipeline: {current_pipeline:?}, default: {default:?}, default_params_file: {default_params_file:?}, process_pool_size: {process_pool_size:?})"
+    "OptionalPipelineConfig(current_pipeline: {current_pipeline:?}, default: {default:?}, default_params_file: {default_params_file:?}, process_pool_size: {process_pool_size:?}, default_step_timeout: {default_step_timeout:?}, timeout_grace_period: {timeout_grace_period:?}, run_cache: {run_cache:?}, track_outputs: {track_outputs:?})"
 )]
 #[serde(deny_unknown_fields)]
 pub struct OptionalPipelineConfig {
@@ -304,6 +358,14 @@ pub struct OptionalPipelineConfig {
     pub default_params_file: Option<String>,
     /// Optional number of command processes to run concurrently.
     pub process_pool_size: Option<u32>,
+    /// Optional default step timeout in seconds.
+    pub default_step_timeout: Option<u64>,
+    /// Optional grace period in seconds before killing a timed out command.
+    pub timeout_grace_period: Option<u64>,
+    /// Optional switch to use the run cache.
+    pub run_cache: Option<bool>,
+    /// Optional switch to track step outputs.
+    pub track_outputs: Option<bool>,
 }
 
 /// Optional configuration for checking ignored files, used for partial updates.
@@ -315,10 +377,48 @@ pub struct OptionalCheckIgnoreConfig {
     pub details: Option<bool>,
 }
 
+/// Optional configuration for storage operations, used for partial updates.
+#[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize, Default)]
+#[display(
+    "OptionalStorageConfig(bring_order: {bring_order:?}, pack_threshold: {pack_threshold:?}, pack_size: {pack_size:?}, multipart_part_size: {multipart_part_size:?}, multipart_concurrency: {multipart_concurrency:?}, max_bandwidth: {max_bandwidth:?}, max_concurrency: {max_concurrency:?}, limits: {limits:?}, limit_rate: {limit_rate:?})"
+)]
+#[serde(deny_unknown_fields)]
+pub struct OptionalStorageConfig {
+    /// Optional list of storages that `xvc file bring` tries in order.
+    pub bring_order: Option<Vec<String>>,
+    /// Optional size limit for files sent in pack objects.
+    pub pack_threshold: Option<u64>,
+    /// Optional maximum size of pack objects.
+    pub pack_size: Option<u64>,
+    /// Optional part size of multipart uploads.
+    pub multipart_part_size: Option<u64>,
+    /// Optional number of parts uploaded concurrently.
+    pub multipart_concurrency: Option<usize>,
+    /// Maximum transfer rate to and from each storage
+    pub max_bandwidth: Option<String>,
+    /// Maximum number of concurrent transfers for each storage
+    pub max_concurrency: Option<usize>,
+    /// Limits of individual storages
+    pub limits: Option<Vec<String>>,
+    /// Transfer rate that overrides the limits of all storages
+    pub limit_rate: Option<String>,
+}
+
+/// Optional configuration for storage credentials, used for partial updates.
+#[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize, Default)]
+#[display("OptionalCredentialConfig(helper: {helper:?}, file: {file:?})")]
+#[serde(deny_unknown_fields)]
+pub struct OptionalCredentialConfig {
+    /// Optional credential helper command.
+    pub helper: Option<String>,
+    /// Optional path of the credentials file.
+    pub file: Option<String>,
+}
+
 /// The top-level optional Xvc configuration structure, used for partial updates.
 #[derive(Display, Clone, Debug, Deserialize, PartialEq, Serialize, Default)]
 #[display(
-    "XvcOptionalConfiguration(core: {core:?}, git: {git:?}, cache: {cache:?}, file: {file:?}, pipeline: {pipeline:?}, check_ignore: {check_ignore:?})"
+    "XvcOptionalConfiguration(core: {core:?}, git: {git:?}, cache: {cache:?}, file: {file:?}, pipeline: {pipeline:?}, check_ignore: {check_ignore:?}, storage: {storage:?}, credential: {credential:?})"
 )]
 #[serde(deny_unknown_fields)]
 pub struct XvcOptionalConfiguration {
@@ -335,6 +435,10 @@ pub struct XvcOptionalConfiguration {
     /// Optional check ignore settings.
     #[serde(rename = "check-ignore")]
     pub check_ignore: Option<OptionalCheckIgnoreConfig>,
+    /// Optional storage operation settings.
+    pub storage: Option<OptionalStorageConfig>,
+    /// Optional storage credential settings.
+    pub credential: Option<OptionalCredentialConfig>,
 }
 
 impl XvcOptionalConfiguration {
@@ -382,6 +486,8 @@ impl XvcOptionalConfiguration {
             file: None,
             pipeline: None,
             check_ignore: None,
+            storage: None,
+            credential: None,
         };
 
         for (key, value) in values.iter() {
@@ -590,6 +696,38 @@ impl XvcOptionalConfiguration {
                             .process_pool_size = Some(val);
                     }
                 }
+                "pipeline.default_step_timeout" => {
+                    if let Ok(val) = value.parse::<u64>() {
+                        config
+                            .pipeline
+                            .get_or_insert_with(Default::default)
+                            .default_step_timeout = Some(val);
+                    }
+                }
+                "pipeline.timeout_grace_period" => {
+                    if let Ok(val) = value.parse::<u64>() {
+                        config
+                            .pipeline
+                            .get_or_insert_with(Default::default)
+                            .timeout_grace_period = Some(val);
+                    }
+                }
+                "pipeline.run_cache" => {
+                    if let Some(val) = Self::parse_bool(value) {
+                        config
+                            .pipeline
+                            .get_or_insert_with(Default::default)
+                            .run_cache = Some(val);
+                    }
+                }
+                "pipeline.track_outputs" => {
+                    if let Some(val) = Self::parse_bool(value) {
+                        config
+                            .pipeline
+                            .get_or_insert_with(Default::default)
+                            .track_outputs = Some(val);
+                    }
+                }
                 // check_ignore
                 "check_ignore.details" => {
                     if let Some(val) = Self::parse_bool(value) {
@@ -599,6 +737,91 @@ impl XvcOptionalConfiguration {
                             .details = Some(val);
                     }
                 }
+                // storage
+                "storage.bring_order" => {
+                    config
+                        .storage
+                        .get_or_insert_with(Default::default)
+                        .bring_order = Some(
+                        value
+                            .split(',')
+                            .map(|s| s.trim().to_string())
+                            .filter(|s| !s.is_empty())
+                            .collect(),
+                    );
+                }
+                "storage.pack_threshold" => {
+                    if let Ok(val) = value.parse::<u64>() {
+                        config
+                            .storage
+                            .get_or_insert_with(Default::default)
+                            .pack_threshold = Some(val);
+                    }
+                }
+                "storage.pack_size" => {
+                    if let Ok(val) = value.parse::<u64>() {
+                        config
+                            .storage
+                            .get_or_insert_with(Default::default)
+                            .pack_size = Some(val);
+                    }
+                }
+                "storage.multipart_part_size" => {
+                    if let Ok(val) = value.parse::<u64>() {
+                        config
+                            .storage
+                            .get_or_insert_with(Default::default)
+                            .multipart_part_size = Some(val);
+                    }
+                }
+                "storage.multipart_concurrency" => {
+                    if let Ok(val) = value.parse::<usize>() {
+                        config
+                            .storage
+                            .get_or_insert_with(Default::default)
+                            .multipart_concurrency = Some(val);
+                    }
+                }
+                "storage.max_bandwidth" => {
+                    config
+                        .storage
+                        .get_or_insert_with(Default::default)
+                        .max_bandwidth = Some(value.to_string());
+                }
+                "storage.limit_rate" => {
+                    config
+                        .storage
+                        .get_or_insert_with(Default::default)
+                        .limit_rate = Some(value.to_string());
+                }
+                "storage.max_concurrency" => {
+                    if let Ok(val) = value.parse::<usize>() {
+                        config
+                            .storage
+                            .get_or_insert_with(Default::default)
+                            .max_concurrency = Some(val);
+                    }
+                }
+                "storage.limits" => {
+                    config.storage.get_or_insert_with(Default::default).limits = Some(
+                        value
+                            .split(',')
+                            .map(|s| s.trim().to_string())
+                            .filter(|s| !s.is_empty())
+                            .collect(),
+                    );
+                }
+                // credential
+                "credential.helper" => {
+                    config
+                        .credential
+                        .get_or_insert_with(Default::default)
+                        .helper = Some(value.to_string());
+                }
+                "credential.file" => {
+                    config.credential.get_or_insert_with(Default::default).file =
+                        Some(value.to_string());
+                }
                 _ => {} // Ignore unknown keys
             }
         }
@@ -816,8 +1039,27 @@ pub fn default_config() -> XvcConfiguration {
             default: "default".to_string(),
             default_params_file: "params.yaml".to_string(),
             process_pool_size: 4,
+            default_step_timeout: 10000,
+            timeout_grace_period: 10,
+            run_cache: false,
+            track_outputs: false,
         },
         check_ignore: CheckIgnoreConfig { details: false },
+        storage: StorageConfig {
+            bring_order: vec![],
+            pack_threshold: 0,
+            pack_size: 64 * 1024 * 1024,
+            multipart_part_size: 8 * 1024 * 1024,
+            multipart_concurrency: 4,
+            max_bandwidth: "0".to_string(),
+            max_concurrency: 0,
+            limits: vec![],
+            limit_rate: "".to_string(),
+        },
+        credential: CredentialConfig {
+            helper: "".to_string(),
+            file: "".to_string(),
+        },
     }
 }
 
@@ -978,6 +1220,26 @@ pub fn merge_configs(
             .clone()
             .and_then(|p| p.process_pool_size)
             .unwrap_or(config.pipeline.process_pool_size),
+        default_step_timeout: opt_config
+            .pipeline
+            .clone()
+            .and_then(|p| p.default_step_timeout)
+            .unwrap_or(config.pipeline.default_step_timeout),
+        timeout_grace_period: opt_config
+            .pipeline
+            .clone()
+            .and_then(|p| p.timeout_grace_period)
+            .unwrap_or(config.pipeline.timeout_grace_period),
+        run_cache: opt_config
+            .pipeline
+            .clone()
+            .and_then(|p| p.run_cache)
+            .unwrap_or(config.pipeline.run_cache),
+        track_outputs: opt_config
+            .pipeline
+            .clone()
+            .and_then(|p| p.track_outputs)
+            .unwrap_or(config.pipeline.track_outputs),
     };
 
     let check_ignore = CheckIgnoreConfig {
@@ -988,6 +1250,67 @@ pub fn merge_configs(
             .unwrap_or(config.check_ignore.details),
     };
 
+    let storage = StorageConfig {
+        bring_order: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.bring_order)
+            .unwrap_or(config.storage.bring_order.clone()),
+        pack_threshold: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.pack_threshold)
+            .unwrap_or(config.storage.pack_threshold),
+        pack_size: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.pack_size)
+            .unwrap_or(config.storage.pack_size),
+        multipart_part_size: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.multipart_part_size)
+            .unwrap_or(config.storage.multipart_part_size),
+        multipart_concurrency: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.multipart_concurrency)
+            .unwrap_or(config.storage.multipart_concurrency),
+        max_bandwidth: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.max_bandwidth)
+            .unwrap_or(config.storage.max_bandwidth.clone()),
+        max_concurrency: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.max_concurrency)
+            .unwrap_or(config.storage.max_concurrency),
+        limits: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.limits)
+            .unwrap_or(config.storage.limits.clone()),
+        limit_rate: opt_config
+            .storage
+            .clone()
+            .and_then(|s| s.limit_rate)
+            .unwrap_or(config.storage.limit_rate.clone()),
+    };
+
+    let credential = CredentialConfig {
+        helper: opt_config
+            .credential
+            .clone()
+            .and_then(|c| c.helper)
+            .unwrap_or(config.credential.helper.clone()),
+        file: opt_config
+            .credential
+            .clone()
+            .and_then(|c| c.file)
+            .unwrap_or(config.credential.file.clone()),
+    };
+
     XvcConfiguration {
         core,
         git,
@@ -995,6 +1318,8 @@ pub fn merge_configs(
         file,
         pipeline,
         check_ignore,
+        storage,
+        credential,
     }
 }
 
@@ -1136,11 +1461,58 @@ default = "{pipeline_default}"
 default_params_file = "{pipeline_default_params_file}"
 # Number of command processes to run concurrently
 process_pool_size = {pipeline_process_pool_size}
+# Seconds a step can run before it's terminated, unless the step has its own --timeout
+default_step_timeout = {pipeline_default_step_timeout}
+# Seconds to wait after terminating a timed out step before killing it
+timeout_grace_period = {pipeline_timeout_grace_period}
+# Restore the outputs of steps from the cache when they were run with the same command and dependencies.
+# The outputs are moved to the cache and rechecked with file.recheck.method.
+run_cache = {pipeline_run_cache}
+# Track the outputs of steps that run with `xvc file track`
+track_outputs = {pipeline_track_outputs}
  
 [check-ignore]
 # Show details by default
 details = {check_ignore_details}
 
+[storage]
+# Storages that `xvc file bring` tries in order when no --storage is given, e.g., ["nas", "s3"]
+# Files missing in a storage are looked up in the next one.
+bring_order = {storage_bring_order}
+# Files smaller than this many bytes are sent to storages in pack objects to reduce the number of requests.
+# 0 disables packing.
+pack_threshold = {storage_pack_threshold}
+# Maximum size of a pack object in bytes
+pack_size = {storage_pack_size}
+# Files larger than this many bytes are sent to S3 compatible storages in parts of this size.
+# The minimum is 5 MiB. The part size is increased for files that would need more than 10000 parts.
+multipart_part_size = {storage_multipart_part_size}
+# Number of parts of a file uploaded concurrently
+multipart_concurrency = {storage_multipart_concurrency}
+# Maximum transfer rate to and from storages in bytes per second.
+# K, M and G suffixes can be used, e.g., "10M". "0" is unlimited.
+max_bandwidth = "{storage_max_bandwidth}"
+# Maximum number of concurrent transfers (processes, parts) for each storage.
+# 0 uses the settings of the storage.
+max_concurrency = {storage_max_concurrency}
+# Limits for individual storages in "<storage name>.<max_bandwidth|max_concurrency>=<value>" form.
+# e.g., ["office-s3.max_bandwidth=2M", "office-s3.max_concurrency=2"]
+limits = {storage_limits}
+# Transfer rate that overrides max_bandwidth and limits for all storages.
+# It's set by --limit-rate option of xvc file send and xvc file bring. Empty uses the settings above.
+limit_rate = "{storage_limit_rate}"
+
+[credential]
+# Command to get storage credentials, similar to Git credential helpers.
+# If it starts with !, the rest is run as a shell command, otherwise `git credential-<helper>` is run.
+# Xvc sends `get` as an argument and key=value lines like protocol, host, storage and profile on stdin.
+# Empty string disables the helper.
+helper = "{credential_helper}"
+# Path of the credentials file that keeps storage credentials outside of the repository.
+# Empty string uses credentials.toml in the user configuration directory, e.g., ~/.config/xvc/credentials.toml
+# Don't put this file in the repository.
+file = "{credential_file}"
+
 "##,
         xvc_repo_version = config.core.xvc_repo_version,
         verbosity = config.core.verbosity,
@@ -1167,7 +1539,22 @@ details = {check_ignore_details}
         pipeline_default = config.pipeline.default,
         pipeline_default_params_file = config.pipeline.default_params_file,
         pipeline_process_pool_size = config.pipeline.process_pool_size,
+        pipeline_default_step_timeout = config.pipeline.default_step_timeout,
+        pipeline_timeout_grace_period = config.pipeline.timeout_grace_period,
+        pipeline_run_cache = config.pipeline.run_cache,
+        pipeline_track_outputs = config.pipeline.track_outputs,
         check_ignore_details = config.check_ignore.details,
+        storage_bring_order = toml::Value::from(config.storage.bring_order.clone()),
+        storage_pack_threshold = config.storage.pack_threshold,
+        storage_pack_size = config.storage.pack_size,
+        storage_multipart_part_size = config.storage.multipart_part_size,
+        storage_multipart_concurrency = config.storage.multipart_concurrency,
+        storage_max_bandwidth = config.storage.max_bandwidth,
+        storage_max_concurrency = config.storage.max_concurrency,
+        storage_limits = toml::Value::from(config.storage.limits.clone()),
+        storage_limit_rate = config.storage.limit_rate,
+        credential_helper = config.credential.helper,
+        credential_file = config.credential.file,
     ))
 }
 
@@ -1186,6 +1573,8 @@ pub fn blank_optional_config() -> XvcOptionalConfiguration {
         file: None,
         pipeline: None,
         check_ignore: None,
+        storage: None,
+        credential: None,
     }
 }
 
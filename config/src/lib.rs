@@ -565,11 +565,67 @@ impl XvcConfig {
                 .pipeline
                 .as_ref()
                 .is_some_and(|p| p.process_pool_size.is_some()),
+            ["pipeline", "default_step_timeout"] => config
+                .pipeline
+                .as_ref()
+                .is_some_and(|p| p.default_step_timeout.is_some()),
+            ["pipeline", "timeout_grace_period"] => config
+                .pipeline
+                .as_ref()
+                .is_some_and(|p| p.timeout_grace_period.is_some()),
+            ["pipeline", "run_cache"] => config
+                .pipeline
+                .as_ref()
+                .is_some_and(|p| p.run_cache.is_some()),
+            ["pipeline", "track_outputs"] => config
+                .pipeline
+                .as_ref()
+                .is_some_and(|p| p.track_outputs.is_some()),
             // check-ignore
             ["check-ignore", "details"] => config
                 .check_ignore
                 .as_ref()
                 .is_some_and(|c| c.details.is_some()),
+            // storage
+            ["storage", "bring_order"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.bring_order.is_some()),
+            ["storage", "pack_threshold"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.pack_threshold.is_some()),
+            ["storage", "pack_size"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.pack_size.is_some()),
+            ["storage", "multipart_part_size"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.multipart_part_size.is_some()),
+            ["storage", "multipart_concurrency"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.multipart_concurrency.is_some()),
+            ["storage", "max_bandwidth"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.max_bandwidth.is_some()),
+            ["storage", "max_concurrency"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.max_concurrency.is_some()),
+            ["storage", "limits"] => config.storage.as_ref().is_some_and(|s| s.limits.is_some()),
+            ["storage", "limit_rate"] => config
+                .storage
+                .as_ref()
+                .is_some_and(|s| s.limit_rate.is_some()),
+            // credential
+            ["credential", "helper"] => config
+                .credential
+                .as_ref()
+                .is_some_and(|c| c.helper.is_some()),
+            ["credential", "file"] => config.credential.as_ref().is_some_and(|c| c.file.is_some()),
             _ => false,
         }
     }
@@ -613,8 +669,25 @@ impl XvcConfig {
             ["pipeline", "default"] |
             ["pipeline", "default_params_file"] |
             ["pipeline", "process_pool_size"] |
+            ["pipeline", "default_step_timeout"] |
+            ["pipeline", "timeout_grace_period"] |
+            ["pipeline", "run_cache"] |
+            ["pipeline", "track_outputs"] |
             // check-ignore
-            ["check-ignore", "details"]
+            ["check-ignore", "details"] |
+            // storage
+            ["storage", "bring_order"] |
+            ["storage", "pack_threshold"] |
+            ["storage", "pack_size"] |
+            ["storage", "multipart_part_size"] |
+            ["storage", "multipart_concurrency"] |
+            ["storage", "max_bandwidth"] |
+            ["storage", "max_concurrency"] |
+            ["storage", "limits"] |
+            ["storage", "limit_rate"] |
+            // credential
+            ["credential", "helper"] |
+            ["credential", "file"]
         )
     }
 }
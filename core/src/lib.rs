@@ -49,8 +49,8 @@ pub use xvc_ecs::{
 };
 
 pub use xvc_logging::{
-    XvcOutputLine, XvcOutputSender, debug, error, info, output, panic, setup_logging, trace, uwo,
-    uwr, warn, watch,
+    XvcOutputLine, XvcOutputSender, XvcProgress, XvcProgressFormat, XvcProgressRenderer, debug,
+    error, info, output, panic, setup_logging, trace, uwo, uwr, warn, watch,
 };
 
 pub use xvc_walker as walker;
@@ -81,9 +81,9 @@ pub use util::git;
 
 pub use util::file::{all_paths_and_metadata, dir_includes, glob_includes, glob_paths};
 pub use util::git::{
-    build_gitignore, exec_git, get_absolute_git_command, get_git_tracked_files, git_auto_commit,
-    git_auto_stage, git_checkout_ref, git_ignored, handle_git_automation, inside_git,
-    stash_user_staged_files, unstash_user_staged_files,
+    build_gitignore, exec_git, extract_store_at_ref, get_absolute_git_command,
+    get_git_tracked_files, git_auto_commit, git_auto_stage, git_checkout_ref, git_ignored,
+    handle_git_automation, inside_git, stash_user_staged_files, unstash_user_staged_files,
 };
 
 pub use util::XvcPathMetadataMap;
@@ -4,7 +4,9 @@ use crate::types::hashalgorithm::HashAlgorithm;
 use crate::util::file::is_text_file;
 use crate::{TextOrBinary, XvcDigest, attribute_digest};
 
-use std::{fmt::Display, path::Path};
+use std::{fmt::Display, fs::File, path::Path};
+
+use xvc_logging::XvcProgress;
 
 use crate::error::Result;
 
@@ -65,6 +67,23 @@ impl ContentDigest {
         };
         Ok(Self(digest))
     }
+
+    /// Like [Self::new], but reads the file in chunks and reports the bytes read to `progress`.
+    pub fn with_progress(
+        path: &Path,
+        algorithm: HashAlgorithm,
+        text_or_binary: TextOrBinary,
+        progress: &XvcProgress,
+    ) -> Result<Self> {
+        let text = match text_or_binary {
+            TextOrBinary::Binary => false,
+            TextOrBinary::Text => true,
+            TextOrBinary::Auto => is_text_file(path)?,
+        };
+        let reader = progress.reader(File::open(path)?);
+        Ok(Self(XvcDigest::from_reader(reader, algorithm, text)?))
+    }
+
     /// Return the inner digest
     pub fn digest(&self) -> XvcDigest {
         self.0
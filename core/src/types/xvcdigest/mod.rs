@@ -9,11 +9,11 @@ use crate::types::hashalgorithm::HashAlgorithm;
 
 use std::collections::BTreeMap;
 
-use std::{fmt::Display, fs, path::Path};
+use std::{fmt::Display, fs, io::Read, path::Path};
 use xvc_ecs::{Storable, persist};
 
 use crate::error::Result;
-use blake2::{Blake2s, Digest};
+use blake2::{Blake2s, Blake2s256, Digest};
 use relative_path::RelativePathBuf;
 
 use serde::{Deserialize, Serialize};
@@ -23,6 +23,9 @@ use sha3::Sha3_256;
 /// The digest length in bytes that is supposed to stay constant for a long time
 pub const DIGEST_LENGTH: usize = 32;
 
+/// Size of the chunks read in [XvcDigest::from_reader]
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+
 /// All content digests in Xvc are 32 bytes.
 pub type Digest32 = [u8; DIGEST_LENGTH];
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -86,6 +89,66 @@ impl XvcDigest {
         Ok(Self::from_bytes(&content, algorithm))
     }
 
+    /// Returns the digest of the content read from `reader` with the `algorithm`.
+    ///
+    /// Unlike [Self::from_binary_file], the content is hashed in chunks without loading it into
+    /// memory. When `text` is set, `CR` and `LF` are removed like [Self::from_text_file].
+    pub fn from_reader<R: Read>(reader: R, algorithm: HashAlgorithm, text: bool) -> Result<Self> {
+        let digest: Digest32 = match algorithm {
+            HashAlgorithm::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                Self::read_chunks(reader, text, |chunk| {
+                    hasher.update(chunk);
+                })?;
+                hasher.finalize().into()
+            }
+            HashAlgorithm::Blake2s => {
+                let mut hasher = Blake2s256::new();
+                Self::read_chunks(reader, text, |chunk| hasher.update(chunk))?;
+                hasher.finalize().into()
+            }
+            HashAlgorithm::SHA2_256 => {
+                let mut hasher = Sha256::new();
+                Self::read_chunks(reader, text, |chunk| hasher.update(chunk))?;
+                hasher.finalize().into()
+            }
+            HashAlgorithm::SHA3_256 => {
+                let mut hasher = Sha3_256::new();
+                Self::read_chunks(reader, text, |chunk| hasher.update(chunk))?;
+                hasher.finalize().into()
+            }
+            HashAlgorithm::AsIs => {
+                let mut content = Vec::new();
+                Self::read_chunks(reader, text, |chunk| content.extend_from_slice(chunk))?;
+                return Ok(Self::from_bytes(&content, algorithm));
+            }
+        };
+
+        Ok(Self { algorithm, digest })
+    }
+
+    fn read_chunks<R: Read>(mut reader: R, text: bool, mut f: impl FnMut(&[u8])) -> Result<()> {
+        let mut buf = vec![0u8; READ_CHUNK_SIZE];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if text {
+                let chunk = buf[..n]
+                    .iter()
+                    .copied()
+                    .filter(|c| !(*c == 0x0D || *c == 0x0A))
+                    .collect::<Vec<u8>>();
+                f(&chunk);
+            } else {
+                f(&buf[..n]);
+            }
+        }
+    }
+
     /// Returns the digest of the `content` calculated by `algorithm`
     pub fn from_content(content: &str, algorithm: HashAlgorithm) -> Self {
         Self::from_bytes(content.as_bytes(), algorithm)
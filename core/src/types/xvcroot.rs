@@ -1,13 +1,15 @@
 //! The home of [XvcRoot], the primary data structure for Xvc repository.
 //!
 //! It's used to pass around the repository information and configuration.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use xvc_config::configuration::XvcConfiguration;
 use xvc_config::configuration::XvcOptionalConfiguration;
 use xvc_config::configuration::merge_configs;
@@ -53,6 +55,8 @@ pub struct XvcRootInner {
     local_config_path: AbsolutePath,
     project_config_path: AbsolutePath,
     entity_generator: XvcEntityGenerator,
+    /// Values attached by other crates, keyed by their type. See [XvcRootInner::insert_extension].
+    extensions: RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
 }
 
 /// We wrap the XvcRootInner in an Arc to make it easier to share between threads.
@@ -201,6 +205,7 @@ impl XvcRootInner {
             absolute_path,
             config,
             entity_generator,
+            extensions: RwLock::new(HashMap::new()),
         })
     }
 
@@ -224,6 +229,27 @@ impl XvcRootInner {
         self.entity_generator.next_element()
     }
 
+    /// Attach `value` to the repository, replacing the value of the same type.
+    ///
+    /// Crates use this to pass their own state, like the storage backend registry, to commands
+    /// that receive the repository.
+    pub fn insert_extension<T: Any + Send + Sync>(&self, value: T) {
+        self.extensions
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// The value of type `T` attached with [XvcRootInner::insert_extension]
+    pub fn extension<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.extensions
+            .read()
+            .unwrap()
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|value| value.downcast::<T>().ok())
+    }
+
     /// Get the absolute path of the repository root.
     pub fn absolute_path(&self) -> &AbsolutePath {
         &self.absolute_path
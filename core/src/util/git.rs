@@ -6,6 +6,7 @@ use subprocess::Exec;
 use xvc_logging::{XvcOutputSender, debug};
 
 use crate::{Error, Result};
+use std::fs;
 use std::path::Path;
 
 use xvc_walker::{AbsolutePath, IgnoreRules, build_ignore_patterns};
@@ -76,6 +77,42 @@ pub fn exec_git(git_command: &str, xvc_directory: &str, args_str_vec: &[&str]) -
     }
 }
 
+/// Writes the stores with `type_descriptions` at `git_ref` to `store_root`.
+pub fn extract_store_at_ref(
+    git_command: &str,
+    xvc_directory: &str,
+    git_ref: &str,
+    store_root: &Path,
+    type_descriptions: &[String],
+) -> Result<()> {
+    let store_dirs = type_descriptions
+        .iter()
+        .map(|td| format!(".xvc/store/{td}-store"))
+        .collect::<Vec<_>>();
+    let mut args = vec!["ls-tree", "-r", "--name-only", "--full-name", git_ref, "--"];
+    args.extend(store_dirs.iter().map(|d| d.as_str()));
+    let files = exec_git(git_command, xvc_directory, &args)?;
+
+    for file in files.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        // Paths are relative to the Git repository root
+        let Some((_, rel_path)) = file.split_once(".xvc/store/") else {
+            continue;
+        };
+        let content = exec_git(
+            git_command,
+            xvc_directory,
+            &["show", &format!("{git_ref}:{file}")],
+        )?;
+        let target = store_root.join(rel_path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, content)?;
+    }
+
+    Ok(())
+}
+
 /// Get files tracked by git
 ///
 /// NOTE: Assumptions for this function:
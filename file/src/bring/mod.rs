@@ -6,10 +6,14 @@
 //!   Uses [fetch] and [crate::recheck::cmd_recheck] to bring the file and copy/link it to the
 //!   workspace.
 
+use std::collections::HashSet;
+use std::fs;
+use std::str::FromStr;
+
 use crate::common::{load_targets_from_store, move_to_cache};
 
 use crate::{
-    Result,
+    Error, Result,
     recheck::{RecheckCLI, cmd_recheck},
 };
 
@@ -18,14 +22,16 @@ use clap::Parser;
 use clap_complete::ArgValueCompleter;
 use xvc_core::util::completer::{strum_variants_completer, xvc_path_completer};
 use xvc_core::{
-    ContentDigest, HStore, RecheckMethod, XvcCachePath, XvcFileType, XvcMetadata, XvcRoot, XvcStore,
+    ContentDigest, HStore, RecheckMethod, XvcCachePath, XvcEntity, XvcFileType, XvcMetadata,
+    XvcRoot, XvcStore,
 };
-use xvc_core::{XvcOutputSender, debug, error, uwr, warn};
+use xvc_core::{XvcOutputSender, XvcProgress, debug, error, output, uwr, warn};
 
 use xvc_core::PathSync;
-use xvc_storage::XvcStorageEvent;
+use xvc_storage::storage::limit::parse_rate;
 use xvc_storage::storage::storage_identifier_completer;
 use xvc_storage::{StorageIdentifier, XvcStorageOperations, storage::get_storage_record};
+use xvc_storage::{XvcStorage, XvcStorageEvent};
 
 /// Bring (download, pull, fetch) files from storage.
 ///
@@ -34,14 +40,24 @@ use xvc_storage::{StorageIdentifier, XvcStorageOperations, storage::get_storage_
 #[derive(Debug, Clone, PartialEq, Eq, Parser)]
 #[command(rename_all = "kebab-case")]
 pub struct BringCLI {
-    /// Storage name or guid to send the files
-    #[arg(long, short, alias = "from", add = ArgValueCompleter::new(storage_identifier_completer))]
-    storage: StorageIdentifier,
+    /// Storage name or guid to bring the files from.
+    ///
+    /// This can be given multiple times or as a comma separated list. Storages are tried in
+    /// order, and a file is brought from the first storage that has it. If not given, the
+    /// storages in `storage.bring_order` configuration option are used.
+    #[arg(long, short, alias = "from", value_delimiter = ',', add = ArgValueCompleter::new(storage_identifier_completer))]
+    storage: Vec<StorageIdentifier>,
 
     /// Force even if the files are already present in the workspace
     #[arg(long)]
     force: bool,
 
+    /// Maximum transfer rate in bytes per second, e.g., 500K or 2M.
+    ///
+    /// Overrides `storage.max_bandwidth` and the limits of the storage for this command.
+    #[arg(long, value_parser = parse_rate)]
+    pub(crate) limit_rate: Option<u64>,
+
     /// Don't recheck (checkout) after bringing the file to cache.
     ///
     /// This makes the command similar to `git fetch` in Git.
@@ -59,14 +75,46 @@ pub struct BringCLI {
     targets: Option<Vec<String>>,
 }
 
+/// Returns the storages to bring files from, in the order they are tried.
+///
+/// These are the storages in `identifiers` if it's not empty, otherwise the storages in
+/// `storage.bring_order` configuration option.
+fn bring_storages(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    identifiers: &[StorageIdentifier],
+) -> Result<Vec<XvcStorage>> {
+    let identifiers = if identifiers.is_empty() {
+        xvc_root
+            .config()
+            .storage
+            .bring_order
+            .iter()
+            .map(|s| StorageIdentifier::from_str(s))
+            .collect::<xvc_storage::Result<Vec<StorageIdentifier>>>()?
+    } else {
+        identifiers.to_vec()
+    };
+
+    if identifiers.is_empty() {
+        return Err(Error::NoStorageToBring);
+    }
+
+    identifiers
+        .iter()
+        .map(|identifier| Ok(get_storage_record(output_snd, xvc_root, identifier)?))
+        .collect()
+}
+
 /// Download files in `opts.targets` from `opts.storage` to cache.
 ///
-/// - Retrieves the storage record from `xvc_root`.
+/// - Retrieves the storage records from `xvc_root`.
 /// - Expands globs in `opts.targets`.
 /// - Gets the corresponding cache path for each file target.
-/// - Calls `storage.receive` for each of these targets.
+/// - Calls `storage.receive` for each storage in order, with the targets not received from the
+///   previous storages.
 pub fn fetch(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, opts: &BringCLI) -> Result<()> {
-    let storage = get_storage_record(output_snd, xvc_root, &opts.storage)?;
+    let storages = bring_storages(output_snd, xvc_root, &opts.storage)?;
 
     let current_dir = xvc_root.current_dir();
     let targets = load_targets_from_store(output_snd, xvc_root, current_dir, &opts.targets)?;
@@ -91,7 +139,7 @@ pub fn fetch(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, opts: &BringCLI)
         "All files should have a content digest"
     }
 
-    let cache_paths: HStore<XvcCachePath> = target_content_digests
+    let mut cache_paths: HStore<XvcCachePath> = target_content_digests
         .iter()
         .filter_map(|(xe, cd)| {
             let xvc_path = target_files.get(xe).unwrap();
@@ -117,8 +165,15 @@ pub fn fetch(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, opts: &BringCLI)
         })
         .collect();
 
-    let (temp_dir, event) = storage
-        .receive(
+    let path_sync = PathSync::new();
+    let single_storage = storages.len() == 1;
+
+    for storage in storages {
+        if cache_paths.is_empty() {
+            break;
+        }
+
+        let received = storage.receive(
             output_snd,
             xvc_root,
             cache_paths
@@ -127,31 +182,57 @@ pub fn fetch(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, opts: &BringCLI)
                 .collect::<Vec<XvcCachePath>>()
                 .as_slice(),
             opts.force,
-        )
-        .map_err(|e| xvc_core::Error::from(anyhow::anyhow!("Remote error: {}", e)))?;
+        );
 
-    let path_sync = PathSync::new();
-    // Move the files from temp dir to cache
-    for (_, cp) in cache_paths {
-        let cache_path = cp.to_absolute_path(xvc_root);
-        let temp_path = temp_dir.temp_cache_path(&cp)?;
-        if temp_path.exists() {
-            uwr!(
-                move_to_cache(&temp_path, &cache_path, &path_sync),
-                output_snd
-            );
-        } else {
-            error!(output_snd, "Could not download {}", cp);
+        let (temp_dir, event) = match received {
+            Ok(received) => received,
+            Err(e) if single_storage => {
+                return Err(xvc_core::Error::from(anyhow::anyhow!("Remote error: {}", e)).into());
+            }
+            Err(e) => {
+                warn!(output_snd, "Cannot bring from {}: {}", storage.name(), e);
+                continue;
+            }
+        };
+
+        // Move the files from temp dir to cache
+        let mut brought = HashSet::<XvcEntity>::new();
+        let bytes_to_move = cache_paths
+            .values()
+            .filter_map(|cp| temp_dir.temp_cache_path(cp).ok())
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|md| md.len())
+            .sum();
+        let progress = XvcProgress::new(output_snd, "Moving to cache", Some(bytes_to_move));
+        for (xe, cp) in cache_paths.iter() {
+            let cache_path = cp.to_absolute_path(xvc_root);
+            let temp_path = temp_dir.temp_cache_path(cp)?;
+            if temp_path.exists() {
+                let size = fs::metadata(&temp_path).map_or(0, |md| md.len());
+                uwr!(
+                    move_to_cache(&temp_path, &cache_path, &path_sync),
+                    output_snd
+                );
+                progress.inc(size);
+                output!(output_snd, "[{}] {}", storage.name(), target_files[xe]);
+                brought.insert(*xe);
+            }
         }
+        progress.finish();
+        cache_paths.retain(|xe, _| !brought.contains(xe));
+
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
+            store.insert(
+                xvc_root.new_entity(),
+                XvcStorageEvent::Receive(event.clone()),
+            );
+            Ok(())
+        })?;
     }
 
-    xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
-        store.insert(
-            xvc_root.new_entity(),
-            XvcStorageEvent::Receive(event.clone()),
-        );
-        Ok(())
-    })?;
+    for (_, cp) in cache_paths.iter() {
+        error!(output_snd, "Could not download {}", cp);
+    }
 
     Ok(())
 }
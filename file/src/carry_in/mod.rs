@@ -15,7 +15,7 @@ use std::fs;
 use xvc_core::XvcRoot;
 use xvc_core::{ContentDigest, TextOrBinary};
 use xvc_core::{Diff, XvcCachePath};
-use xvc_core::{XvcOutputSender, info, uwo, uwr, warn, watch};
+use xvc_core::{XvcOutputSender, XvcProgress, info, uwo, uwr, warn, watch};
 
 use crate::common::compare::{diff_content_digest, diff_text_or_binary, diff_xvc_path_metadata};
 use crate::common::gitignore::make_ignore_handler;
@@ -225,8 +225,19 @@ pub fn carry_in(
     let (ignore_writer, ignore_thread) = make_ignore_handler(output_snd, xvc_root)?;
 
     let path_sync = PathSync::new();
+    let bytes_to_carry = xvc_paths_to_carry
+        .values()
+        .filter_map(|xp| fs::metadata(xp.to_absolute_path(xvc_root)).ok())
+        .map(|md| md.len())
+        .sum();
+    let progress = if bytes_to_carry > 0 {
+        XvcProgress::new(output_snd, "Moving to cache", Some(bytes_to_carry))
+    } else {
+        XvcProgress::disabled()
+    };
 
-    let copy_path_to_cache_and_recheck = |xe, xp| {
+    let copy_path_to_cache_and_recheck = |xe, xp: &XvcPath| {
+        let size = fs::metadata(xp.to_absolute_path(xvc_root)).map_or(0, |md| md.len());
         let cache_path = uwo!(cache_paths.get(xe).cloned(), output_snd);
         let abs_cache_path = cache_path.to_absolute_path(xvc_root);
         if abs_cache_path.exists() {
@@ -246,12 +257,14 @@ pub fn carry_in(
             } else {
                 info!(output_snd, "[EXISTS] {abs_cache_path} for {xp}");
             }
+            progress.inc(size);
         } else {
             uwr!(
                 move_xvc_path_to_cache(xvc_root, xp, &cache_path, &path_sync),
                 output_snd
             );
             info!(output_snd, "[CARRY] {xp} -> {cache_path}");
+            progress.inc(size);
         }
         let target_path = xp.to_absolute_path(xvc_root);
         if target_path.exists() {
@@ -283,6 +296,7 @@ pub fn carry_in(
             .for_each(|(xe, xp)| copy_path_to_cache_and_recheck(xe, xp));
     }
 
+    progress.finish();
     ignore_writer.send(None).unwrap();
     ignore_thread.join().unwrap();
 
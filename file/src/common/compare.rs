@@ -20,7 +20,7 @@ use xvc_core::{
 };
 
 use xvc_core::{HStore, XvcEntity, XvcStore};
-use xvc_core::{XvcOutputSender, debug, error, panic};
+use xvc_core::{XvcOutputSender, XvcProgress, debug, error, panic};
 
 use super::FileTextOrBinary;
 
@@ -126,6 +126,7 @@ pub fn diff_file_content_digest(
     stored_content_digest_store: &XvcStore<ContentDigest>,
     algorithm: HashAlgorithm,
     text_or_binary: FileTextOrBinary,
+    progress: &XvcProgress,
 ) -> Result<(XvcEntity, Diff<ContentDigest>)> {
     let anything_changed = xvc_path_diff.changed() || xvc_metadata_diff.changed();
 
@@ -161,7 +162,12 @@ pub fn diff_file_content_digest(
                     // text_or_binary should have changed.
                     Diff::Skipped | Diff::Identical => {
                         let path = path_from_store()?;
-                        let actual = ContentDigest::new(&path, algorithm, text_or_binary.0)?;
+                        let actual = ContentDigest::with_progress(
+                            &path,
+                            algorithm,
+                            text_or_binary.0,
+                            progress,
+                        )?;
                         compare_with_stored_digest(actual)
                     }
                     Diff::RecordMissing { .. } => {
@@ -180,7 +186,12 @@ pub fn diff_file_content_digest(
                         },
                         xvc_core::XvcFileType::File => {
                             let path = path_from_store()?;
-                            let actual = ContentDigest::new(&path, algorithm, text_or_binary.0)?;
+                            let actual = ContentDigest::with_progress(
+                                &path,
+                                algorithm,
+                                text_or_binary.0,
+                                progress,
+                            )?;
                             compare_with_stored_digest(actual)
                         }
                         xvc_core::XvcFileType::Reflink
@@ -196,14 +207,16 @@ pub fn diff_file_content_digest(
             // The path is not recorded before.
             Diff::RecordMissing { actual } => {
                 let path = actual.to_absolute_path(xvc_root);
-                let actual_digest = ContentDigest::new(&path, algorithm, text_or_binary.0)?;
+                let actual_digest =
+                    ContentDigest::with_progress(&path, algorithm, text_or_binary.0, progress)?;
                 compare_with_stored_digest(actual_digest)
             }
             // The path is changed. This can happen after a move
             // operation, for example.
             Diff::Different { actual, .. } => {
                 let path = actual.to_absolute_path(xvc_root);
-                let actual = ContentDigest::new(&path, algorithm, text_or_binary.0)?;
+                let actual =
+                    ContentDigest::with_progress(&path, algorithm, text_or_binary.0, progress)?;
                 compare_with_stored_digest(actual)
             }
             // We have a record, but the path on disk is missing.
@@ -358,37 +371,6 @@ pub fn diff_content_digest(
         *HashAlgorithm::from_config(xvc_root.config()).expect("HashAlgorithm must be configured")
     });
 
-    let diff_file = |xe| -> Result<(XvcEntity, Diff<ContentDigest>)> {
-        let xvc_path_diff = xvc_path_diff_store
-            .get(&xe)
-            .unwrap_or(&Diff::<XvcPath>::Skipped);
-        let xvc_metadata_diff = xvc_metadata_diff_store
-            .get(&xe)
-            .unwrap_or(&Diff::<XvcMetadata>::Skipped);
-
-        let text_or_binary = requested_text_or_binary.unwrap_or_else(|| {
-            stored_text_or_binary_store
-                .get(&xe)
-                .copied()
-                .unwrap_or_else(|| {
-                    *FileTextOrBinary::from_config(xvc_root.config())
-                        .expect("TextOrBinary must be configured")
-                })
-        });
-
-        diff_file_content_digest(
-            output_snd,
-            xvc_root,
-            xe,
-            xvc_path_diff,
-            xvc_metadata_diff,
-            stored_xvc_path_store,
-            stored_content_digest_store,
-            algorithm,
-            text_or_binary,
-        )
-    };
-
     let diff_dir = |xe,
                     dir_entities: &HashSet<XvcEntity>,
                     file_content_digest_store: &DiffStore<ContentDigest>| {
@@ -461,6 +443,58 @@ pub fn diff_content_digest(
         .copied()
         .collect::<HashSet<XvcEntity>>();
 
+    // Only the files with changed paths or metadata are hashed
+    let bytes_to_hash: u64 = file_entities
+        .iter()
+        .filter_map(|xe| {
+            let path_changed = xvc_path_diff_store.get(xe).is_some_and(|d| d.changed());
+            match xvc_metadata_diff_store.get(xe) {
+                Some(Diff::RecordMissing { actual }) | Some(Diff::Different { actual, .. }) => {
+                    actual.size
+                }
+                _ if path_changed => stored_xvc_metadata_store.get(xe).and_then(|md| md.size),
+                _ => None,
+            }
+        })
+        .sum();
+    let progress = if bytes_to_hash > 0 {
+        XvcProgress::new(output_snd, "Hashing", Some(bytes_to_hash))
+    } else {
+        XvcProgress::disabled()
+    };
+
+    let diff_file = |xe| -> Result<(XvcEntity, Diff<ContentDigest>)> {
+        let xvc_path_diff = xvc_path_diff_store
+            .get(&xe)
+            .unwrap_or(&Diff::<XvcPath>::Skipped);
+        let xvc_metadata_diff = xvc_metadata_diff_store
+            .get(&xe)
+            .unwrap_or(&Diff::<XvcMetadata>::Skipped);
+
+        let text_or_binary = requested_text_or_binary.unwrap_or_else(|| {
+            stored_text_or_binary_store
+                .get(&xe)
+                .copied()
+                .unwrap_or_else(|| {
+                    *FileTextOrBinary::from_config(xvc_root.config())
+                        .expect("TextOrBinary must be configured")
+                })
+        });
+
+        diff_file_content_digest(
+            output_snd,
+            xvc_root,
+            xe,
+            xvc_path_diff,
+            xvc_metadata_diff,
+            stored_xvc_path_store,
+            stored_content_digest_store,
+            algorithm,
+            text_or_binary,
+            &progress,
+        )
+    };
+
     let dir_entities = entities
         .iter()
         .filter(|xe| {
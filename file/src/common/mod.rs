@@ -453,6 +453,25 @@ pub fn recheck_from_cache(
     }
     let cache_path = cache_path.to_absolute_path(xvc_root);
     let path = xvc_path.to_absolute_path(xvc_root);
+    recheck_file(output_snd, cache_path, path, recheck_method)?;
+    uwr!(
+        ignore_writer.send(Some(IgnoreOperation::IgnoreFile {
+            file: xvc_path.clone(),
+        })),
+        output_snd
+    );
+    Ok(())
+}
+
+/// Copies / links `cache_path` to `path` with `recheck_method`, without updating the ignore
+/// files. The parent directory of `path` must exist.
+/// WARNING: If `path` is already present, it will be deleted first.
+pub fn recheck_file(
+    output_snd: &XvcOutputSender,
+    cache_path: AbsolutePath,
+    path: AbsolutePath,
+    recheck_method: RecheckMethod,
+) -> Result<()> {
     // If the file already exists, we delete it.
     if path.exists() {
         fs::remove_file(&path)?;
@@ -474,12 +493,6 @@ pub fn recheck_from_cache(
             reflink(output_snd, cache_path, path)?;
         }
     }
-    uwr!(
-        ignore_writer.send(Some(IgnoreOperation::IgnoreFile {
-            file: xvc_path.clone(),
-        })),
-        output_snd
-    );
     Ok(())
 }
 
@@ -94,6 +94,11 @@ pub enum Error {
     #[error("Multiple files found to share")]
     MultipleFilesToShare,
 
+    #[error(
+        "No storage to bring files from. Use --storage or set storage.bring_order in the configuration."
+    )]
+    NoStorageToBring,
+
     #[error("No files found to share")]
     NoFilesToShare,
 
@@ -182,6 +182,22 @@ pub struct XvcFileCLI {
     subcommand: XvcFileSubCommand,
 }
 
+impl XvcFileCLI {
+    /// Configuration options set by the options of the subcommand, e.g., `storage.limit_rate` by
+    /// `--limit-rate`.
+    pub fn subcommand_config_options(&self) -> Vec<String> {
+        let limit_rate = match &self.subcommand {
+            XvcFileSubCommand::Send(opts) => opts.limit_rate,
+            XvcFileSubCommand::Bring(opts) => opts.limit_rate,
+            _ => None,
+        };
+        limit_rate
+            .map(|rate| format!("storage.limit_rate = {rate}"))
+            .into_iter()
+            .collect()
+    }
+}
+
 /// Entry point for the `xvc file` command.
 ///
 /// It runs the subcommand specified in the command line arguments.
@@ -296,7 +312,15 @@ pub fn dispatch(cli_opts: XvcFileCLI) -> Result<()> {
         project_config_path: None,
         local_config_path: None,
         include_environment_config: !cli_opts.no_env_config,
-        command_line_config: cli_opts.config.clone(),
+        command_line_config: Some(
+            cli_opts
+                .config
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .chain(cli_opts.subcommand_config_options())
+                .collect(),
+        ),
     };
 
     let xvc_root = match load_xvc_root(xvc_config_params) {
@@ -320,6 +344,7 @@ pub fn dispatch(cli_opts: XvcFileCLI) -> Result<()> {
                     XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                     XvcOutputLine::Debug(m) => debug!("[DEBUG] {}", m),
                     XvcOutputLine::Tick(_) => {}
+                    XvcOutputLine::Progress(_) => {}
                 }
             }
         });
@@ -16,7 +16,7 @@ use xvc_core::{HStore, XvcStore};
 use xvc_core::{XvcOutputSender, error};
 use xvc_storage::{
     StorageIdentifier, XvcStorageOperations,
-    storage::{get_storage_record, storage_identifier_completer},
+    storage::{get_storage_record, limit::parse_rate, storage_identifier_completer},
 };
 
 /// Send (upload) tracked files to storage
@@ -36,6 +36,12 @@ pub struct SendCLI {
     #[arg(long)]
     force: bool,
 
+    /// Maximum transfer rate in bytes per second, e.g., 500K or 2M.
+    ///
+    /// Overrides `storage.max_bandwidth` and the limits of the storage for this command.
+    #[arg(long, value_parser = parse_rate)]
+    pub(crate) limit_rate: Option<u64>,
+
     /// Targets to send/push/upload to storage
     #[arg(add = ArgValueCompleter::new(xvc_path_completer))]
     targets: Option<Vec<String>>,
@@ -53,6 +53,7 @@ pub use xvc_pipeline::cmd_import as pipeline_import;
 pub use xvc_pipeline::cmd_list as pipeline_list;
 pub use xvc_pipeline::cmd_new as pipeline_new;
 pub use xvc_pipeline::cmd_run as pipeline_run;
+pub use xvc_pipeline::cmd_status as pipeline_status;
 pub use xvc_pipeline::cmd_update as pipeline_update;
 
 pub use xvc_pipeline::cmd_step_dependency as pipeline_step_dependency;
@@ -25,7 +25,7 @@ use xvc_core::types::xvcroot::find_root;
 use xvc_core::types::xvcroot::load_xvc_root;
 use xvc_core::util::completer::git_branch_completer;
 use xvc_core::util::completer::git_reference_completer;
-use xvc_core::{XvcOutputLine, debug, error, uwr};
+use xvc_core::{XvcOutputLine, XvcProgressFormat, XvcProgressRenderer, debug, error, uwr};
 
 use xvc_core::AbsolutePath;
 use xvc_core::CHANNEL_BOUND;
@@ -67,6 +67,11 @@ pub struct XvcCLI {
     #[arg(long)]
     pub debug: bool,
 
+    /// How to show the progress of long operations. `auto` shows progress bars when stderr is a
+    /// terminal, `json` writes each progress event as a line of JSON to stderr, `none` hides them.
+    #[arg(long, default_value = "auto")]
+    pub progress: XvcProgressFormat,
+
     /// Set working directory for the command.
     /// It doesn't create a new shell, or change the directory.
     #[arg(short = 'C')]
@@ -174,6 +179,9 @@ impl XvcCLI {
             XvcVerbosity::from(self.verbosity)
         ));
         output.push(format!("core.quiet = {}", self.quiet));
+        if let XvcSubCommand::File(file_cli) = &self.command {
+            output.extend(file_cli.subcommand_config_options());
+        }
 
         output
     }
@@ -207,6 +215,10 @@ pub enum XvcSubCommand {
     #[command(visible_aliases=&["p"])]
     Pipeline(xvc_pipeline::PipelineCLI),
 
+    /// Experiment commands to run a pipeline with different parameters and compare the results
+    #[command(visible_aliases=&["e"])]
+    Exp(xvc_pipeline::ExpCLI),
+
     /// Storage (cloud) management commands
     #[command(visible_aliases=&["s"])]
     Storage(xvc_storage::StorageCLI),
@@ -253,6 +265,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
     );
 
     let term_log_level = get_term_log_level(get_verbosity(&cli_opts));
+    let progress_format = cli_opts.progress;
 
     let xvc_root_opt = thread::scope(move |s| {
         let (output_snd, output_rec) = bounded::<Option<XvcOutputLine>>(CHANNEL_BOUND);
@@ -260,7 +273,11 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
         let output_snd_clone = output_snd.clone();
 
         let output_thread = s.spawn(move |_| {
+            let mut progress_renderer = XvcProgressRenderer::new(progress_format);
             while let Ok(Some(output_line)) = output_rec.recv() {
+                if !matches!(output_line, XvcOutputLine::Progress(_)) {
+                    progress_renderer.clear();
+                }
                 // output_str.push_str(&output_line);
                 match term_log_level {
                     LevelFilter::Off => match output_line {
@@ -270,6 +287,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
                         XvcOutputLine::Error(_) => {}
                         XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                         XvcOutputLine::Tick(_) => todo!(),
+                        XvcOutputLine::Progress(_) => {}
                         XvcOutputLine::Debug(_) => {}
                     },
                     LevelFilter::Error => match output_line {
@@ -279,6 +297,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
                         XvcOutputLine::Error(m) => eprintln!("[ERROR] {}", m),
                         XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                         XvcOutputLine::Tick(_) => todo!(),
+                        XvcOutputLine::Progress(event) => progress_renderer.show(&event),
                         XvcOutputLine::Debug(_) => {}
                     },
                     LevelFilter::Warn => match output_line {
@@ -288,6 +307,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
                         XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                         XvcOutputLine::Info(_) => {}
                         XvcOutputLine::Tick(_) => todo!(),
+                        XvcOutputLine::Progress(event) => progress_renderer.show(&event),
                         XvcOutputLine::Debug(_) => {}
                     },
                     LevelFilter::Info => match output_line {
@@ -297,6 +317,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
                         XvcOutputLine::Error(m) => eprintln!("[ERROR] {}", m),
                         XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                         XvcOutputLine::Tick(_) => todo!(),
+                        XvcOutputLine::Progress(event) => progress_renderer.show(&event),
                         XvcOutputLine::Debug(_) => {}
                     },
                     LevelFilter::Debug => match output_line {
@@ -306,6 +327,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
                         XvcOutputLine::Error(m) => eprintln!("[ERROR] {}", m),
                         XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                         XvcOutputLine::Tick(_) => todo!(),
+                        XvcOutputLine::Progress(event) => progress_renderer.show(&event),
                         XvcOutputLine::Debug(m) => eprintln!("[DEBUG] {}", m),
                     },
                     LevelFilter::Trace => match output_line {
@@ -316,6 +338,7 @@ pub fn dispatch_with_root(cli_opts: cli::XvcCLI, xvc_root_opt: XvcRootOpt) -> Re
                         XvcOutputLine::Debug(m) => eprintln!("[DEBUG] {}", m),
                         XvcOutputLine::Panic(m) => panic!("[PANIC] {}", m),
                         XvcOutputLine::Tick(_) => todo!(),
+                        XvcOutputLine::Progress(event) => progress_renderer.show(&event),
                     },
                 }
             }
@@ -474,6 +497,7 @@ pub fn collect_output(
                 XvcOutputLine::Error(_) => {}
                 XvcOutputLine::Panic(m) => output_str.push_str(&format!("[PANIC] {}", m)),
                 XvcOutputLine::Tick(_) => todo!(),
+                XvcOutputLine::Progress(_) => {}
                 XvcOutputLine::Debug(_) => {}
             },
             LevelFilter::Error => match output_line {
@@ -483,6 +507,7 @@ pub fn collect_output(
                 XvcOutputLine::Error(m) => output_str.push_str(&format!("[ERROR] {}", m)),
                 XvcOutputLine::Panic(m) => output_str.push_str(&format!("[PANIC] {}", m)),
                 XvcOutputLine::Tick(_) => todo!(),
+                XvcOutputLine::Progress(_) => {}
                 XvcOutputLine::Debug(_) => {}
             },
             LevelFilter::Warn => match output_line {
@@ -492,6 +517,7 @@ pub fn collect_output(
                 XvcOutputLine::Panic(m) => output_str.push_str(&format!("[PANIC] {}", m)),
                 XvcOutputLine::Info(_) => {}
                 XvcOutputLine::Tick(_) => todo!(),
+                XvcOutputLine::Progress(_) => {}
                 XvcOutputLine::Debug(_) => {}
             },
             LevelFilter::Info => match output_line {
@@ -501,6 +527,7 @@ pub fn collect_output(
                 XvcOutputLine::Error(m) => output_str.push_str(&format!("[ERROR] {}", m)),
                 XvcOutputLine::Panic(m) => output_str.push_str(&format!("[PANIC] {}", m)),
                 XvcOutputLine::Tick(_) => todo!(),
+                XvcOutputLine::Progress(_) => {}
                 XvcOutputLine::Debug(_) => {}
             },
             LevelFilter::Debug => match output_line {
@@ -511,6 +538,7 @@ pub fn collect_output(
                 XvcOutputLine::Panic(m) => output_str.push_str(&format!("[PANIC] {}", m)),
                 XvcOutputLine::Debug(m) => output_str.push_str(&format!("[DEBUG] {}", m)),
                 XvcOutputLine::Tick(_) => todo!(),
+                XvcOutputLine::Progress(_) => {}
             },
             LevelFilter::Trace => match output_line {
                 XvcOutputLine::Output(m) => output_str.push_str(&m),
@@ -520,6 +548,7 @@ pub fn collect_output(
                 XvcOutputLine::Debug(m) => output_str.push_str(&format!("[DEBUG] {}", m)),
                 XvcOutputLine::Panic(m) => output_str.push_str(&format!("[PANIC] {}", m)),
                 XvcOutputLine::Tick(_) => todo!(),
+                XvcOutputLine::Progress(_) => {}
             },
         }
     }
@@ -580,6 +609,15 @@ pub fn command_matcher(
                 Ok(xvc_root_opt)
             }
 
+            XvcSubCommand::Exp(opts) => {
+                pipeline::cmd_exp(
+                    output_snd,
+                    xvc_root_opt.as_ref().ok_or(Error::RequiresXvcRepository)?,
+                    opts,
+                )?;
+                Ok(xvc_root_opt)
+            }
+
             XvcSubCommand::CheckIgnore(opts) => {
                 // FIXME: We can replace this stdin with another channel
                 let stdin = io::stdin();
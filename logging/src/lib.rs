@@ -4,6 +4,10 @@
 //! Downstream crates (xvc, xvc-file, etc.) use this crate not to use stdout, stderr directly.
 #![warn(missing_docs)]
 #![forbid(unsafe_code)]
+pub mod progress;
+
+pub use progress::{XvcProgress, XvcProgressEvent, XvcProgressFormat, XvcProgressRenderer};
+
 use crossbeam_channel::Sender;
 use log::LevelFilter;
 use std::env;
@@ -277,6 +281,8 @@ pub enum XvcOutputLine {
     /// Progress bar ticks.
     /// Self::Info is also used for Tick(1)
     Tick(usize),
+    /// Byte level progress of an operation. See [progress].
+    Progress(XvcProgressEvent),
 }
 
 /// The channel type to send and receive output/log/debug messages
@@ -336,6 +342,7 @@ impl Display for XvcOutputLine {
             XvcOutputLine::Error(s) => writeln!(f, "[ERROR] {}", s),
             XvcOutputLine::Panic(s) => writeln!(f, "[PANIC] {}", s),
             XvcOutputLine::Tick(n) => write!(f, "{}", ".".repeat(*n)),
+            XvcOutputLine::Progress(e) => writeln!(f, "{}", e.to_bar()),
         }
     }
 }
@@ -0,0 +1,383 @@
+//! Byte level progress reports for long operations.
+//!
+//! Hashing, moving files to the cache and storage transfers create an [XvcProgress] and report
+//! the bytes they process with [XvcProgress::inc]. The progress sends [XvcOutputLine::Progress]
+//! events with the total, done bytes, throughput and ETA at most every [REPORT_INTERVAL], and a
+//! final event when it's finished or dropped.
+//!
+//! [XvcProgressRenderer] shows these events as a progress bar when stderr is a terminal. With
+//! `--progress json`, they are written as JSON lines instead:
+//!
+//! ```text
+//! {"label":"Sending to nas","total":3000000,"done":1048576,"bytes_per_sec":1048576,"eta_secs":2,"finished":false}
+//! ```
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{XvcOutputLine, XvcOutputSender};
+
+/// Minimum time between two progress events of an operation
+pub const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width of the progress bar in characters
+const BAR_WIDTH: usize = 30;
+
+/// A snapshot of the progress of an operation
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct XvcProgressEvent {
+    /// Description of the operation, e.g., "Hashing" or "Sending to nas"
+    pub label: String,
+    /// Total bytes, if known
+    pub total: Option<u64>,
+    /// Bytes processed so far
+    pub done: u64,
+    /// Average throughput since the start
+    pub bytes_per_sec: u64,
+    /// Estimated seconds to finish, if the total is known
+    pub eta_secs: Option<u64>,
+    /// Whether the operation is finished
+    pub finished: bool,
+}
+
+impl XvcProgressEvent {
+    /// The event as a single line of JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// The event as a progress bar, e.g.
+    /// `Hashing [#########>          ] 45% 1.3 MiB/2.9 MiB 1.0 MiB/s ETA 2s`
+    pub fn to_bar(&self) -> String {
+        let throughput = format!("{}/s", format_bytes(self.bytes_per_sec));
+        match self.total {
+            Some(total) if total > 0 => {
+                let ratio = (self.done as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                let bar = if filled >= BAR_WIDTH {
+                    "#".repeat(BAR_WIDTH)
+                } else {
+                    format!(
+                        "{}>{}",
+                        "#".repeat(filled),
+                        " ".repeat(BAR_WIDTH - filled - 1)
+                    )
+                };
+                let eta = match self.eta_secs {
+                    Some(eta) if !self.finished => format!(" ETA {}", format_duration(eta)),
+                    _ => String::new(),
+                };
+                format!(
+                    "{} [{bar}] {:>3}% {}/{} {throughput}{eta}",
+                    self.label,
+                    (ratio * 100.0) as u64,
+                    format_bytes(self.done),
+                    format_bytes(total),
+                )
+            }
+            _ => format!("{} {} {throughput}", self.label, format_bytes(self.done)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ProgressInner {
+    output_snd: XvcOutputSender,
+    label: String,
+    total: Option<u64>,
+    done: AtomicU64,
+    started: Instant,
+    last_report: Mutex<Instant>,
+    finished: AtomicBool,
+}
+
+impl ProgressInner {
+    fn event(&self, finished: bool) -> XvcProgressEvent {
+        let done = self.done.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_sec = if elapsed > 0.0 {
+            (done as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let eta_secs = match self.total {
+            Some(total) if bytes_per_sec > 0 => {
+                Some(total.saturating_sub(done).div_ceil(bytes_per_sec))
+            }
+            _ => None,
+        };
+        XvcProgressEvent {
+            label: self.label.clone(),
+            total: self.total,
+            done,
+            bytes_per_sec,
+            eta_secs,
+            finished,
+        }
+    }
+
+    fn send(&self, finished: bool) {
+        // The output channel may be closed when the command ends
+        let _ = self
+            .output_snd
+            .send(Some(XvcOutputLine::Progress(self.event(finished))));
+    }
+
+    fn finish(&self) {
+        if !self.finished.swap(true, Ordering::Relaxed) {
+            self.send(true);
+        }
+    }
+}
+
+impl Drop for ProgressInner {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Reports the bytes processed by an operation.
+///
+/// Clones share the same counter, so a progress can be passed to worker threads. The final event
+/// is sent by [Self::finish] or when the last clone is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct XvcProgress {
+    inner: Option<Arc<ProgressInner>>,
+}
+
+impl XvcProgress {
+    /// A new progress for `total` bytes, if known, labeled with `label`
+    pub fn new(output_snd: &XvcOutputSender, label: impl Into<String>, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            inner: Some(Arc::new(ProgressInner {
+                output_snd: output_snd.clone(),
+                label: label.into(),
+                total,
+                done: AtomicU64::new(0),
+                started: now,
+                last_report: Mutex::new(now),
+                finished: AtomicBool::new(false),
+            })),
+        }
+    }
+
+    /// A progress that doesn't report anything
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+
+    /// Add `bytes` to the processed bytes and report if [REPORT_INTERVAL] has passed since the
+    /// last report.
+    pub fn inc(&self, bytes: u64) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        inner.done.fetch_add(bytes, Ordering::Relaxed);
+        // Another thread is reporting if the lock is busy
+        if let Ok(mut last_report) = inner.last_report.try_lock()
+            && last_report.elapsed() >= REPORT_INTERVAL
+        {
+            *last_report = Instant::now();
+            inner.send(false);
+        }
+    }
+
+    /// Bytes processed so far
+    pub fn done(&self) -> u64 {
+        self.inner
+            .as_ref()
+            .map_or(0, |inner| inner.done.load(Ordering::Relaxed))
+    }
+
+    /// Send the final event. Later calls and drops don't send anything.
+    pub fn finish(&self) {
+        if let Some(inner) = &self.inner {
+            inner.finish();
+        }
+    }
+
+    /// Wraps `reader` to report the bytes read from it
+    pub fn reader<R: Read>(&self, reader: R) -> XvcProgressReader<R> {
+        XvcProgressReader {
+            inner: reader,
+            progress: self.clone(),
+        }
+    }
+}
+
+/// A reader that reports the bytes read to an [XvcProgress]
+#[derive(Debug)]
+pub struct XvcProgressReader<R> {
+    inner: R,
+    progress: XvcProgress,
+}
+
+impl<R: Read> Read for XvcProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.inc(n as u64);
+        Ok(n)
+    }
+}
+
+/// How [XvcProgressRenderer] shows the progress events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum XvcProgressFormat {
+    /// Progress bars when stderr is a terminal, nothing otherwise
+    #[default]
+    Auto,
+    /// A line of JSON for each event
+    Json,
+    /// Nothing
+    None,
+}
+
+impl FromStr for XvcProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "json" => Ok(Self::Json),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "Unknown progress format: {s}. Use one of auto, json or none."
+            )),
+        }
+    }
+}
+
+impl Display for XvcProgressFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Json => write!(f, "json"),
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+/// Shows [XvcProgressEvent]s on stderr.
+///
+/// By default, the events are shown as a progress bar that is redrawn in place if stderr is a
+/// terminal, and ignored otherwise. With [XvcProgressFormat::Json], each event is written as a
+/// line of JSON.
+#[derive(Debug)]
+pub struct XvcProgressRenderer {
+    format: XvcProgressFormat,
+    terminal: bool,
+    visible: bool,
+}
+
+impl Default for XvcProgressRenderer {
+    fn default() -> Self {
+        Self::new(XvcProgressFormat::default())
+    }
+}
+
+impl XvcProgressRenderer {
+    /// A renderer that shows the events in `format`
+    pub fn new(format: XvcProgressFormat) -> Self {
+        Self {
+            format,
+            terminal: io::stderr().is_terminal(),
+            visible: false,
+        }
+    }
+
+    /// Show the event
+    pub fn show(&mut self, event: &XvcProgressEvent) {
+        let mut stderr = io::stderr().lock();
+        match self.format {
+            XvcProgressFormat::Auto if self.terminal => {
+                let _ = write!(stderr, "\r\x1b[2K{}", event.to_bar());
+                if event.finished {
+                    let _ = writeln!(stderr);
+                }
+                self.visible = !event.finished;
+            }
+            XvcProgressFormat::Json => {
+                let _ = writeln!(stderr, "{}", event.to_json());
+            }
+            XvcProgressFormat::Auto | XvcProgressFormat::None => return,
+        }
+        let _ = stderr.flush();
+    }
+
+    /// Clear the progress bar before writing other lines
+    pub fn clear(&mut self) {
+        if self.visible {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+            self.visible = false;
+        }
+    }
+}
+
+/// Formats `bytes` with binary units, e.g., `1.5 MiB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{s}s"),
+        s if s < 3600 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_events() {
+        let (output_snd, output_rec) = crossbeam_channel::unbounded();
+        let progress = XvcProgress::new(&output_snd, "Hashing", Some(2048));
+        progress.inc(1024);
+        progress.clone().inc(1024);
+        assert_eq!(progress.done(), 2048);
+        drop(progress);
+
+        let events = output_rec
+            .try_iter()
+            .filter_map(|l| match l {
+                Some(XvcOutputLine::Progress(e)) => Some(e),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // Only the final event is sent within the report interval
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert!(event.finished);
+        assert_eq!(event.done, 2048);
+        assert!(
+            event
+                .to_bar()
+                .starts_with(&format!("Hashing [{}] 100%", "#".repeat(30)))
+        );
+        let parsed: XvcProgressEvent = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(&parsed, event);
+
+        assert_eq!(format_bytes(1000), "1000 B");
+        assert_eq!(format_bytes(1536 * 1024), "1.5 MiB");
+    }
+}
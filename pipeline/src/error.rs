@@ -47,6 +47,18 @@ pub enum Error {
         source: xvc_core::XvcWalkerError,
     },
 
+    #[error("Xvc File Error: {source}")]
+    FileError {
+        #[from]
+        source: xvc_file::error::Error,
+    },
+
+    #[error("Xvc Storage Error: {source}")]
+    StorageError {
+        #[from]
+        source: xvc_storage::error::Error,
+    },
+
     #[error("Cannot infer format from file extension: {extension:?}")]
     CannotInferFormatFromExtension { extension: OsString },
     #[error("Format specification for input (stdin) required.")]
@@ -62,6 +74,12 @@ pub enum Error {
     #[error("Invalid lines definition: {line}")]
     InvalidLinesFormat { line: String },
     //
+    #[error("Invalid timeout: {value}. Use seconds, or a number with s, m or h suffix.")]
+    InvalidTimeout { value: String },
+    //
+    #[error("--use-remote-cache requires the run cache. Set pipeline.run_cache = true to use it.")]
+    RunCacheDisabled,
+    //
     #[error("Step {step} not found in pipeline")]
     StepNotFoundInPipeline { step: String },
     #[error("[E1004] Json Serialization Error: {source}")]
@@ -165,9 +183,29 @@ pub enum Error {
     #[error("Invalid Parameter Format: {param} ")]
     InvalidParameterFormat { param: String },
 
+    #[error("No parameter dependency in pipeline {pipeline} uses the key: {key}")]
+    ParamKeyNotInPipeline { key: String, pipeline: String },
+
     #[error("Unsupported param file format: {path:?} ")]
     UnsupportedParamFileFormat { path: OsString },
 
+    #[error("Experiment {name} is not found")]
+    ExperimentNotFound { name: String },
+
+    #[error("Experiment {name} already found")]
+    ExperimentAlreadyFound { name: String },
+
+    #[error("Experiment {name} has no base commit to create a branch from")]
+    ExperimentHasNoBaseCommit { name: String },
+
+    #[error(
+        "The workspace has uncommitted changes. Commit or stash them before creating a branch."
+    )]
+    UncommittedChanges,
+
+    #[error("Experiment {name} couldn't run in a temporary workspace")]
+    ExperimentNotRecorded { name: String },
+
     #[error("Crossbeam Send Error for Type: {t:?} {cause:?}")]
     CrossbeamSendError { t: String, cause: String },
     #[error("Crossbeam Recv Error: {source}")]
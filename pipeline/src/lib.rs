@@ -9,8 +9,9 @@ pub mod error;
 mod pipeline;
 
 pub use crate::pipeline::api::{
-    dag::cmd_dag, delete::cmd_delete, export::cmd_export, import::cmd_import, list::cmd_list,
-    new::cmd_new, run::cmd_run, step_dependency::cmd_step_dependency, step_new::cmd_step_new,
+    dag::cmd_dag, delete::cmd_delete, exp::cmd_exp, export::cmd_export, import::cmd_import,
+    list::cmd_list, metrics::cmd_metrics, new::cmd_new, plots::cmd_plots, run::cmd_run,
+    status::cmd_status, step_dependency::cmd_step_dependency, step_new::cmd_step_new,
     step_output::cmd_step_output, step_show::cmd_step_show, step_update::cmd_step_update,
     update::cmd_update,
 };
@@ -22,7 +23,10 @@ use pipeline::api::dag::DagCLI;
 use pipeline::api::delete::DeleteCLI;
 use pipeline::api::export::ExportCLI;
 use pipeline::api::import::ImportCLI;
+use pipeline::api::metrics::MetricsCLI;
 use pipeline::api::new::NewCLI;
+use pipeline::api::plots::PlotsCLI;
+use pipeline::api::status::StatusCLI;
 use pipeline::api::update::UpdateCLI;
 pub use pipeline::deps;
 pub use pipeline::kdl;
@@ -45,16 +49,22 @@ use xvc_core::XvcRoot;
 use xvc_core::{self, XvcEntity, persist};
 
 use crate::error::{Error, Result};
-use crate::pipeline::XvcStepInvalidate;
 pub use crate::pipeline::command::CommandProcess;
 pub use crate::pipeline::command::XvcStepCommand;
 pub use crate::pipeline::deps::{XvcDependency, param::XvcParamFormat};
+pub use crate::pipeline::experiment::XvcExperimentRunner;
+use crate::pipeline::experiment::{
+    XvcExperiment, XvcExperimentMetrics, XvcExperimentOutputs, XvcExperimentParams,
+};
 pub use crate::pipeline::outs::XvcMetricsFormat;
 pub use crate::pipeline::outs::XvcOutput;
+pub use crate::pipeline::outs::XvcOutputDigest;
 pub use crate::pipeline::schema::XvcPipelineSchema;
 pub use crate::pipeline::schema::XvcStepSchema;
 pub use crate::pipeline::step::XvcStep;
+use crate::pipeline::{XvcStepInvalidate, XvcStepTimeout};
 
+pub use crate::pipeline::api::exp::ExpCLI;
 pub use crate::pipeline::api::run::RunCLI;
 
 /// Pipeline management commands
@@ -91,10 +101,22 @@ pub enum PipelineSubCommand {
     #[command(visible_aliases=&["r"])]
     Run(RunCLI),
 
+    /// Show which steps of a pipeline would run, and why
+    #[command(visible_aliases=&["st"])]
+    Status(StatusCLI),
+
     /// List all pipelines
     #[command(visible_aliases=&["l"])]
     List,
 
+    /// Show the metrics outputs of a pipeline or compare them between Git references
+    #[command(visible_aliases=&["m"])]
+    Metrics(MetricsCLI),
+
+    /// Render plots from the metrics outputs and collect the image outputs of a pipeline
+    #[command(visible_aliases=&["P"])]
+    Plots(PlotsCLI),
+
     /// Generate a Graphviz or mermaid diagram of the pipeline
     #[command(visible_aliases=&["d"])]
     Dag(DagCLI),
@@ -192,7 +214,14 @@ pub fn init(xvc_root: &XvcRoot) -> Result<()> {
     xvc_root.save_store(&XvcStore::<XvcStepCommand>::new())?;
     xvc_root.save_store(&XvcStore::<XvcDependency>::new())?;
     xvc_root.save_store(&XvcStore::<XvcOutput>::new())?;
+    xvc_root.save_store(&XvcStore::<XvcOutputDigest>::new())?;
     xvc_root.save_store(&XvcStore::<XvcStepInvalidate>::new())?;
+    xvc_root.save_store(&XvcStore::<XvcStepTimeout>::new())?;
+
+    xvc_root.save_store(&XvcStore::<XvcExperiment>::new())?;
+    xvc_root.save_store(&XvcStore::<XvcExperimentParams>::new())?;
+    xvc_root.save_store(&XvcStore::<XvcExperimentMetrics>::new())?;
+    xvc_root.save_store(&XvcStore::<XvcExperimentOutputs>::new())?;
 
     Ok(())
 }
@@ -212,9 +241,14 @@ pub fn cmd_pipeline<R: BufRead>(
     let pipeline_name = command.pipeline_name.unwrap();
     match command.subcommand {
         PipelineSubCommand::Run(opts) => cmd_run(output_snd, xvc_root, &pipeline_name, opts),
+        PipelineSubCommand::Status(opts) => cmd_status(output_snd, xvc_root, &pipeline_name, opts),
         PipelineSubCommand::New(opts) => cmd_new(xvc_root, &pipeline_name, opts),
         PipelineSubCommand::Update(opts) => cmd_update(xvc_root, &pipeline_name, opts),
         PipelineSubCommand::List => cmd_list(output_snd, xvc_root),
+        PipelineSubCommand::Metrics(opts) => {
+            cmd_metrics(output_snd, xvc_root, &pipeline_name, opts)
+        }
+        PipelineSubCommand::Plots(opts) => cmd_plots(output_snd, xvc_root, &pipeline_name, opts),
         PipelineSubCommand::Delete(opts) => cmd_delete(xvc_root, &pipeline_name, opts),
         PipelineSubCommand::Export(opts) => cmd_export(output_snd, xvc_root, &pipeline_name, opts),
         PipelineSubCommand::Dag(opts) => cmd_dag(output_snd, xvc_root, &pipeline_name, opts),
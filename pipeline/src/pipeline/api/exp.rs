@@ -0,0 +1,532 @@
+//! `xvc exp` runs a pipeline with different parameter values, and compares and promotes the
+//! results.
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use clap::Parser;
+use clap_complete::ArgValueCompleter;
+use serde::Serialize;
+use xvc_core::util::completer::strum_variants_completer;
+use xvc_core::{XvcOutputSender, XvcRoot, output, warn};
+
+use crate::error::{Error, Result};
+use crate::pipeline::api::metrics::{XvcMetricsOutputFormat, format_table};
+use crate::pipeline::experiment::{
+    XvcExperiment, XvcExperimentMetrics, XvcExperimentOutputs, XvcExperimentParams,
+    apply_experiment, base_commit, cache_pipeline_outputs, experiment_grid, experiment_names,
+    load_experiment, run_experiment, run_experiment_in_temp_dir,
+};
+use crate::pipeline::metrics::{XvcMetric, git};
+use crate::pipeline::params::XvcParamOverride;
+use crate::pipeline::run_cache::XvcRunCacheOutput;
+use crate::pipeline::util::{experiment_name_completer, pipeline_name_completer};
+
+/// Run a pipeline with different parameters and compare the results
+#[derive(Debug, Clone, Parser)]
+#[command(name = "exp")]
+pub struct ExpCLI {
+    /// Name of the pipeline to run the experiments. The default pipeline is used if not set.
+    #[arg(long, short, global = true, add = ArgValueCompleter::new(pipeline_name_completer))]
+    pub pipeline_name: Option<String>,
+
+    /// Experiment subcommand
+    #[command(subcommand)]
+    pub subcommand: ExpSubCommand,
+}
+
+/// Experiment subcommands
+#[derive(Debug, Clone, Parser)]
+#[command()]
+pub enum ExpSubCommand {
+    /// Run the pipeline for each combination of parameter values and record the results
+    #[command(visible_aliases=&["r"])]
+    Run {
+        /// Values of a parameter, as `KEY=VALUE1,VALUE2` or `FILE:KEY=VALUE1,VALUE2`. An
+        /// experiment is run for each combination of the values of all `--set` options. Write a
+        /// comma in a value as `\,`.
+        #[arg(long = "set", value_name = "[FILE:]KEY=VALUES")]
+        set: Vec<XvcParamOverride>,
+
+        /// YAML, JSON or TOML file with the parameters to set. If the file contains a list, an
+        /// experiment is run for each of its entries.
+        #[arg(long, value_hint = clap::ValueHint::FilePath)]
+        params_file: Option<PathBuf>,
+
+        /// Name of the experiment. Multiple experiments are numbered after it. They are named
+        /// `exp-1`, `exp-2`, etc. if not set.
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Run each experiment in a temporary copy of the workspace. By default, experiments are
+        /// run in the workspace one after another, and the outputs are restored afterwards.
+        #[arg(long)]
+        temp: bool,
+    },
+
+    /// Show the parameters and metrics of the experiments as a table
+    #[command(visible_aliases=&["s"])]
+    Show {
+        /// Experiments to show. All experiments of the pipeline are shown if not given.
+        #[arg(add = ArgValueCompleter::new(experiment_name_completer))]
+        names: Vec<String>,
+
+        /// Output format. One of table (default), json or markdown.
+        #[arg(long, default_value = "table",
+              add = ArgValueCompleter::new(strum_variants_completer::<XvcMetricsOutputFormat>))]
+        format: XvcMetricsOutputFormat,
+    },
+
+    /// Write the parameters of an experiment to the parameter files and restore its outputs
+    #[command(visible_aliases=&["a"])]
+    Apply {
+        /// Name of the experiment
+        #[arg(add = ArgValueCompleter::new(experiment_name_completer))]
+        name: String,
+    },
+
+    /// Create a Git branch from the commit an experiment is run on, apply the experiment and
+    /// commit its parameters. The workspace must not have uncommitted changes.
+    #[command(visible_aliases=&["b"])]
+    Branch {
+        /// Name of the experiment
+        #[arg(add = ArgValueCompleter::new(experiment_name_completer))]
+        name: String,
+
+        /// Name of the branch to create
+        branch: String,
+    },
+}
+
+/// An experiment with its results, as shown by `xvc exp show --format json`
+#[derive(Debug, Serialize)]
+struct ExperimentRecord<'a> {
+    #[serde(flatten)]
+    experiment: &'a XvcExperiment,
+    params: &'a [XvcParamOverride],
+    metrics: &'a [XvcMetric],
+    outputs: &'a [XvcRunCacheOutput],
+}
+
+/// Entry point for `xvc exp` command.
+pub fn cmd_exp(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, opts: ExpCLI) -> Result<()> {
+    let pipeline_name = opts
+        .pipeline_name
+        .unwrap_or_else(|| xvc_root.config().pipeline.default.clone());
+    match opts.subcommand {
+        ExpSubCommand::Run {
+            set,
+            params_file,
+            name,
+            temp,
+        } => cmd_exp_run(
+            output_snd,
+            xvc_root,
+            &pipeline_name,
+            &set,
+            params_file,
+            name,
+            temp,
+        ),
+        ExpSubCommand::Show { names, format } => {
+            cmd_exp_show(output_snd, xvc_root, &pipeline_name, &names, format)
+        }
+        ExpSubCommand::Apply { name } => {
+            let (experiment, params, outputs) = load_experiment(xvc_root, &name)?;
+            apply_experiment(output_snd, xvc_root, &experiment, &params, &outputs)?;
+            output!(output_snd, "Applied experiment {name}");
+            Ok(())
+        }
+        ExpSubCommand::Branch { name, branch } => {
+            cmd_exp_branch(output_snd, xvc_root, &name, &branch)
+        }
+    }
+}
+
+fn cmd_exp_branch(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    name: &str,
+    branch: &str,
+) -> Result<()> {
+    // The experiment is loaded before the checkout, as the stores of the base commit don't
+    // contain it
+    let (experiment, params, outputs) = load_experiment(xvc_root, name)?;
+    let base_commit =
+        experiment
+            .base_commit
+            .clone()
+            .ok_or_else(|| Error::ExperimentHasNoBaseCommit {
+                name: name.to_owned(),
+            })?;
+    // The changes would be carried to the new branch, or lost when the experiment is applied.
+    // Untracked files are kept by the checkout, or make it fail.
+    if !git(xvc_root, &["status", "--porcelain", "--untracked-files=no"])?
+        .trim()
+        .is_empty()
+    {
+        return Err(Error::UncommittedChanges);
+    }
+    // The branch, or the commit if HEAD is detached, to return to if the experiment can't be
+    // committed
+    let previous_head = match git(xvc_root, &["symbolic-ref", "--quiet", "--short", "HEAD"]) {
+        Ok(branch) => branch,
+        Err(_) => git(xvc_root, &["rev-parse", "HEAD"])?,
+    };
+    let previous_head = previous_head.trim();
+    git(xvc_root, &["checkout", "-b", branch, &base_commit])?;
+    let result = commit_experiment(output_snd, xvc_root, &experiment, &params, &outputs);
+    if let Err(e) = result {
+        let rollback = git(xvc_root, &["checkout", "--force", previous_head])
+            .and_then(|_| git(xvc_root, &["branch", "--delete", "--force", branch]));
+        if let Err(rollback_error) = rollback {
+            warn!(
+                output_snd,
+                "Cannot return to {previous_head} and remove branch {branch}: {rollback_error}"
+            );
+        }
+        return Err(e);
+    }
+    output!(
+        output_snd,
+        "Created branch {branch} from {base_commit} with experiment {name}"
+    );
+    Ok(())
+}
+
+/// Applies the experiment to the workspace and commits its parameters and the outputs tracked by
+/// Git
+fn commit_experiment(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    experiment: &XvcExperiment,
+    params: &XvcExperimentParams,
+    outputs: &XvcExperimentOutputs,
+) -> Result<()> {
+    let backup = apply_experiment(output_snd, xvc_root, experiment, params, outputs)?;
+    let param_files = backup
+        .paths()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    // Outputs are committed only if Git tracks them
+    let output_files = outputs
+        .outputs
+        .iter()
+        .map(|output| output.path.to_absolute_path(xvc_root))
+        .filter(|path| path.exists())
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    let git_add = |add_args: &[&str], files: &[String]| {
+        if files.is_empty() {
+            return Ok(());
+        }
+        let mut args = add_args.to_vec();
+        args.extend(files.iter().map(String::as_str));
+        git(xvc_root, &args).map(|_| ())
+    };
+    git_add(&["add", "--"], &param_files)?;
+    git_add(&["add", "-u", "--"], &output_files)?;
+    git(
+        xvc_root,
+        &[
+            "commit",
+            "--allow-empty",
+            "-m",
+            &format!("Apply experiment {}", experiment.name),
+        ],
+    )?;
+    Ok(())
+}
+
+fn cmd_exp_run(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    set: &[XvcParamOverride],
+    params_file: Option<PathBuf>,
+    name: Option<String>,
+    temp: bool,
+) -> Result<()> {
+    let grid = experiment_grid(set, params_file.as_deref())?;
+    let names = experiment_names(xvc_root, name.as_deref(), grid.len())?;
+    let base_commit = base_commit(xvc_root);
+    // Outputs in the workspace are kept in the cache to restore them after the experiments
+    let workspace_outputs = if temp {
+        Vec::new()
+    } else {
+        cache_pipeline_outputs(output_snd, xvc_root, pipeline_name)?
+    };
+    let result = names.iter().zip(&grid).try_for_each(|(name, params)| {
+        // Experiments in temporary workspaces are run by another xvc process that reports them
+        if temp {
+            run_experiment_in_temp_dir(
+                output_snd,
+                xvc_root,
+                pipeline_name,
+                name,
+                params,
+                base_commit.clone(),
+            )?;
+        } else {
+            let params_str = if params.is_empty() {
+                "Running with the current parameters".to_owned()
+            } else {
+                params
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            output!(output_snd, "[{name}] {params_str}");
+            let status = run_experiment(
+                output_snd,
+                xvc_root,
+                pipeline_name,
+                name,
+                params,
+                base_commit.clone(),
+            )?;
+            output!(output_snd, "[{name}] {status}");
+        }
+        Ok::<(), Error>(())
+    });
+    for output in workspace_outputs {
+        output.restore(output_snd, xvc_root)?;
+    }
+    result
+}
+
+fn cmd_exp_show(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    names: &[String],
+    format: XvcMetricsOutputFormat,
+) -> Result<()> {
+    for name in names {
+        XvcExperiment::from_name(xvc_root, name)?;
+    }
+    let experiments = xvc_root.load_store::<XvcExperiment>()?;
+    let params = xvc_root.load_store::<XvcExperimentParams>()?;
+    let metrics = xvc_root.load_store::<XvcExperimentMetrics>()?;
+    let outputs = xvc_root.load_store::<XvcExperimentOutputs>()?;
+    let records = experiments
+        .iter()
+        .filter(|(_, exp)| {
+            if names.is_empty() {
+                exp.pipeline == pipeline_name
+            } else {
+                names.contains(&exp.name)
+            }
+        })
+        .map(|(e, experiment)| ExperimentRecord {
+            experiment,
+            params: params.get(e).map(|p| p.params.as_slice()).unwrap_or(&[]),
+            metrics: metrics.get(e).map(|m| m.metrics.as_slice()).unwrap_or(&[]),
+            outputs: outputs.get(e).map(|o| o.outputs.as_slice()).unwrap_or(&[]),
+        })
+        .collect::<Vec<_>>();
+
+    if format == XvcMetricsOutputFormat::Json {
+        output!(output_snd, "{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    let param_name = |p: &XvcParamOverride| match &p.file {
+        Some(file) => format!("{}:{}", file.to_string_lossy(), p.key),
+        None => p.key.clone(),
+    };
+    let metric_name = |m: &XvcMetric| format!("{}:{}", m.path, m.key);
+    let param_columns = records
+        .iter()
+        .flat_map(|r| r.params.iter().map(param_name))
+        .collect::<BTreeSet<_>>();
+    let metric_columns = records
+        .iter()
+        .flat_map(|r| r.metrics.iter().map(metric_name))
+        .collect::<BTreeSet<_>>();
+
+    let missing = || "-".to_owned();
+    let rows = records
+        .iter()
+        .map(|r| {
+            let mut row = vec![
+                r.experiment.name.clone(),
+                r.experiment.status.to_string(),
+                r.experiment
+                    .base_commit
+                    .as_ref()
+                    .map(|c| c.chars().take(8).collect())
+                    .unwrap_or_else(missing),
+            ];
+            for column in &param_columns {
+                row.push(
+                    r.params
+                        .iter()
+                        .rev()
+                        .find(|p| param_name(p) == *column)
+                        .map(|p| p.value.clone())
+                        .unwrap_or_else(missing),
+                );
+            }
+            for column in &metric_columns {
+                row.push(
+                    r.metrics
+                        .iter()
+                        .find(|m| metric_name(m) == *column)
+                        .map(|m| m.value.clone())
+                        .unwrap_or_else(missing),
+                );
+            }
+            row
+        })
+        .collect::<Vec<_>>();
+    let mut headers = vec!["Experiment", "Status", "Base"];
+    headers.extend(param_columns.iter().map(String::as_str));
+    headers.extend(metric_columns.iter().map(String::as_str));
+    output!(output_snd, "{}", format_table(&headers, &rows, format));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{test_pipeline_command, test_xvc_root};
+    use serde_json::Value as JsonValue;
+    use std::fs;
+    use xvc_core::{XvcOutputLine, XvcStore};
+
+    fn exp_command(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, args: &[&str]) -> Result<()> {
+        let opts = ExpCLI::try_parse_from(
+            ["exp", "--pipeline-name", "exp-test"]
+                .into_iter()
+                .chain(args.iter().copied()),
+        )
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+        cmd_exp(output_snd, xvc_root, opts)
+    }
+
+    fn lr(path: &std::path::Path) -> Result<String> {
+        let params: JsonValue = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(params["lr"].to_string())
+    }
+
+    #[test]
+    fn test_exp_run_show_apply_and_branch() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, output_rcv) = crossbeam_channel::unbounded();
+        let pipeline = |args: &[&str]| {
+            test_pipeline_command(
+                &output_snd,
+                &xvc_root,
+                &[&["--pipeline-name", "exp-test"], args].concat(),
+            )
+        };
+        let exp = |args: &[&str]| exp_command(&output_snd, &xvc_root, args);
+        let git = |args: &[&str]| git(&xvc_root, args);
+        let root = xvc_root.absolute_path().display().to_string();
+        let dir = xvc_root.absolute_path().join("exp-test");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("params.json"), r#"{"lr": 1}"#)?;
+
+        pipeline(&["new"])?;
+        // Commands run in the current directory of the process
+        let command = format!("cd {root}/exp-test && cp params.json metrics.json");
+        pipeline(&["step", "new", "--step-name", "train", "--command", &command])?;
+        pipeline(&[
+            "step",
+            "dependency",
+            "--step-name",
+            "train",
+            "--param",
+            "exp-test/params.json::lr",
+        ])?;
+        pipeline(&[
+            "step",
+            "output",
+            "--step-name",
+            "train",
+            "--output-metric",
+            "exp-test/metrics.json",
+        ])?;
+        pipeline(&["run"])?;
+
+        exp(&["run", "--set", "lr=2,3", "--name", "exp-test"])?;
+        // The workspace is restored after the experiments
+        assert_eq!(lr(&dir.join("params.json"))?, "1");
+        assert_eq!(lr(&dir.join("metrics.json"))?, "1");
+
+        output_rcv.try_iter().for_each(drop);
+        exp(&["show", "--format", "json"])?;
+        let shown = output_rcv
+            .try_iter()
+            .flatten()
+            .find_map(|line| match line {
+                XvcOutputLine::Output(json) => Some(json),
+                _ => None,
+            })
+            .unwrap();
+        let records: JsonValue = serde_json::from_str(&shown)?;
+        let records = records.as_array().unwrap();
+        assert_eq!(records.len(), 2);
+        for (record, value) in records.iter().zip(["2", "3"]) {
+            assert_eq!(record["status"], "succeeded");
+            assert_eq!(record["params"][0]["key"], "lr");
+            assert_eq!(record["params"][0]["value"], value);
+            assert_eq!(record["metrics"][0]["key"], "lr");
+            assert_eq!(record["metrics"][0]["value"], value);
+        }
+
+        // Experiments are run without Git here, so the base commit is set to branch them
+        git(&["add", "--all"])?;
+        git(&["commit", "--quiet", "-m", "exp-test"])?;
+        let head = git(&["rev-parse", "HEAD"])?.trim().to_owned();
+        let (exp_e, experiment) = XvcExperiment::from_name(&xvc_root, "exp-test-2")?;
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcExperiment>| {
+            store.insert(
+                exp_e,
+                XvcExperiment {
+                    base_commit: Some(head.clone()),
+                    ..experiment.clone()
+                },
+            );
+            Ok(())
+        })?;
+        git(&["add", "--all"])?;
+        git(&["commit", "--quiet", "-m", "exp-test base commit"])?;
+        let branch = git(&["symbolic-ref", "--short", "HEAD"])?.trim().to_owned();
+
+        // A failing commit leaves the workspace on the previous branch
+        let hook = xvc_root.absolute_path().join(".git/hooks/pre-commit");
+        fs::create_dir_all(hook.parent().unwrap())?;
+        fs::write(&hook, "#!/bin/sh\nexit 1\n")?;
+        std::process::Command::new("chmod")
+            .args(["+x", &hook.to_string_lossy()])
+            .status()?;
+        assert!(exp(&["branch", "exp-test-2", "exp-test-branch"]).is_err());
+        fs::remove_file(&hook)?;
+        assert_eq!(git(&["symbolic-ref", "--short", "HEAD"])?.trim(), branch);
+        assert!(
+            git(&[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                "refs/heads/exp-test-branch"
+            ])
+            .is_err()
+        );
+        assert_eq!(lr(&dir.join("params.json"))?, "1");
+
+        exp(&["branch", "exp-test-2", "exp-test-branch"])?;
+        assert_eq!(
+            git(&["show", "exp-test-branch:exp-test/params.json"])?.parse::<JsonValue>()?["lr"]
+                .to_string(),
+            "3"
+        );
+        git(&["checkout", "--quiet", "--force", &branch])?;
+
+        exp(&["apply", "exp-test-2"])?;
+        assert_eq!(lr(&dir.join("params.json"))?, "3");
+        assert_eq!(lr(&dir.join("metrics.json"))?, "3");
+        Ok(())
+    }
+}
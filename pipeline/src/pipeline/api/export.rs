@@ -18,7 +18,7 @@ use xvc_core::{
 use crate::{
     XvcDependency, XvcOutput, XvcPipeline, XvcPipelineRunDir, XvcPipelineSchema, XvcStep,
     XvcStepCommand, XvcStepSchema,
-    pipeline::{XvcStepInvalidate, schema::XvcSchemaSerializationFormat},
+    pipeline::{XvcStepInvalidate, XvcStepTimeout, schema::XvcSchemaSerializationFormat},
 };
 
 #[derive(Debug, Clone, Parser)]
@@ -90,6 +90,10 @@ pub fn cmd_export(
         .load_store::<XvcStepInvalidate>()?
         .subset(steps.keys().cloned())?;
 
+    let step_timeout: HStore<XvcStepTimeout> = xvc_root
+        .load_store::<XvcStepTimeout>()?
+        .subset(steps.keys().cloned())?;
+
     let mut deps: HStore<HStore<XvcDependency>> = HStore::new();
 
     xvc_root.with_r1nstore(|rs: &R1NStore<XvcStep, XvcDependency>| {
@@ -116,6 +120,7 @@ pub fn cmd_export(
             name: s.name.clone(),
             command: commands[e].command.clone(),
             invalidate: step_invalidate.get(e).cloned().unwrap_or_default(),
+            timeout: step_timeout.get(e).map(|t| t.seconds),
             dependencies: deps[e].values().cloned().sorted().collect(),
             outputs: outs[e].values().cloned().sorted().collect(),
         };
@@ -3,13 +3,13 @@ use clap::Parser;
 use clap_complete::ArgValueCompleter;
 use log::warn;
 use std::{fs, io::BufRead, path::PathBuf};
-use xvc_core::{R1NStore, R11Store};
+use xvc_core::{R1NStore, R11Store, XvcStore};
 use xvc_core::{XvcRoot, util::completer::strum_variants_completer};
 
 use crate::{
     XvcDependency, XvcOutput, XvcPipeline, XvcPipelineRunDir, XvcPipelineSchema, XvcStep,
     XvcStepCommand,
-    pipeline::{XvcStepInvalidate, schema::XvcSchemaSerializationFormat},
+    pipeline::{XvcStepInvalidate, XvcStepTimeout, schema::XvcSchemaSerializationFormat},
 };
 
 #[derive(Debug, Clone, Parser)]
@@ -137,6 +137,13 @@ pub fn cmd_import<R: BufRead>(
             Ok(())
         })?;
 
+        if let Some(timeout) = step_schema.timeout.and_then(XvcStepTimeout::from_seconds) {
+            xvc_root.with_store_mut(|bs: &mut XvcStore<XvcStepTimeout>| {
+                bs.insert(step_e, timeout);
+                Ok(())
+            })?;
+        }
+
         xvc_root.with_r1nstore_mut(|rs: &mut R1NStore<XvcStep, XvcDependency>| {
             for dep in step_schema.dependencies.clone() {
                 let dep_e = xvc_root.new_entity();
@@ -0,0 +1,188 @@
+//! `xvc pipeline metrics` shows the metrics outputs of a pipeline and compares them between Git
+//! references.
+use clap::Parser;
+use clap_complete::ArgValueCompleter;
+use comfy_table::Table;
+use comfy_table::presets::ASCII_MARKDOWN;
+use strum_macros::{Display, EnumString, IntoStaticStr, VariantNames};
+use xvc_core::util::completer::{git_reference_completer, strum_variants_completer};
+use xvc_core::{XvcOutputSender, XvcRoot, output};
+
+use crate::error::Result;
+use crate::pipeline::metrics::{
+    XvcMetricsSource, diff_metrics, load_metrics, pipeline_metric_outputs,
+};
+
+/// Show or compare the metrics outputs of a pipeline
+#[derive(Debug, Clone, Parser)]
+#[command(name = "metrics")]
+pub struct MetricsCLI {
+    /// Metrics subcommand
+    #[command(subcommand)]
+    pub subcommand: MetricsSubCommand,
+}
+
+/// Metrics subcommands
+#[derive(Debug, Clone, Parser)]
+#[command()]
+pub enum MetricsSubCommand {
+    /// Show the values in metrics files as a table
+    #[command(visible_aliases=&["s"])]
+    Show {
+        /// Git reference (branch, tag or commit) to read the metrics from. Reads the files in
+        /// the workspace if not set.
+        #[arg(long, add = ArgValueCompleter::new(git_reference_completer))]
+        rev: Option<String>,
+
+        /// Output format. One of table (default), json or markdown.
+        #[arg(long, default_value = "table",
+              add = ArgValueCompleter::new(strum_variants_completer::<XvcMetricsOutputFormat>))]
+        format: XvcMetricsOutputFormat,
+    },
+
+    /// Compare the values in metrics files between two Git references, or a Git reference and the
+    /// workspace
+    #[command(visible_aliases=&["d"])]
+    Diff {
+        /// Git reference to compare from
+        #[arg(default_value = "HEAD", add = ArgValueCompleter::new(git_reference_completer))]
+        old: String,
+
+        /// Git reference to compare to. Compares to the files in the workspace if not set.
+        #[arg(add = ArgValueCompleter::new(git_reference_completer))]
+        new: Option<String>,
+
+        /// Show the unchanged metrics as well
+        #[arg(long)]
+        all: bool,
+
+        /// Output format. One of table (default), json or markdown.
+        #[arg(long, default_value = "table",
+              add = ArgValueCompleter::new(strum_variants_completer::<XvcMetricsOutputFormat>))]
+        format: XvcMetricsOutputFormat,
+    },
+}
+
+/// Output formats for `xvc pipeline metrics`
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, EnumString, Display, IntoStaticStr, Default, VariantNames,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum XvcMetricsOutputFormat {
+    /// Table with aligned columns
+    #[default]
+    Table,
+    /// JSON array of rows
+    Json,
+    /// Markdown table, e.g., for pull request comments
+    Markdown,
+}
+
+/// Entry point for `xvc pipeline metrics` command.
+pub fn cmd_metrics(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    opts: MetricsCLI,
+) -> Result<()> {
+    let files = pipeline_metric_outputs(xvc_root, pipeline_name)?;
+    match opts.subcommand {
+        MetricsSubCommand::Show { rev, format } => {
+            let source = XvcMetricsSource::from_git_ref(rev);
+            source.verify(xvc_root)?;
+            let metrics = load_metrics(xvc_root, &files, &source)?;
+            if format == XvcMetricsOutputFormat::Json {
+                output!(output_snd, "{}", serde_json::to_string_pretty(&metrics)?);
+                return Ok(());
+            }
+            let rows = metrics
+                .into_iter()
+                .map(|m| vec![m.path.to_string(), m.key, m.value])
+                .collect::<Vec<_>>();
+            output!(
+                output_snd,
+                "{}",
+                format_table(&["Path", "Key", "Value"], &rows, format)
+            );
+        }
+        MetricsSubCommand::Diff {
+            old,
+            new,
+            all,
+            format,
+        } => {
+            let old_source = XvcMetricsSource::GitRef(old);
+            let new_source = XvcMetricsSource::from_git_ref(new);
+            old_source.verify(xvc_root)?;
+            new_source.verify(xvc_root)?;
+            let old_metrics = load_metrics(xvc_root, &files, &old_source)?;
+            let new_metrics = load_metrics(xvc_root, &files, &new_source)?;
+            let diffs = diff_metrics(&old_metrics, &new_metrics, all);
+            if format == XvcMetricsOutputFormat::Json {
+                output!(output_snd, "{}", serde_json::to_string_pretty(&diffs)?);
+                return Ok(());
+            }
+            let missing = || "-".to_owned();
+            let rows = diffs
+                .into_iter()
+                .map(|d| {
+                    vec![
+                        d.path.to_string(),
+                        d.key,
+                        d.old.unwrap_or_else(missing),
+                        d.new.unwrap_or_else(missing),
+                        d.change.map(format_change).unwrap_or_else(missing),
+                        d.relative_change
+                            .map(|r| format!("{:+.2}%", r * 100.0))
+                            .unwrap_or_else(missing),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            let old_header = old_source.to_string();
+            let new_header = new_source.to_string();
+            output!(
+                output_snd,
+                "{}",
+                format_table(
+                    &[
+                        "Path",
+                        "Key",
+                        &old_header,
+                        &new_header,
+                        "Change",
+                        "Relative"
+                    ],
+                    &rows,
+                    format
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Formats a difference with its sign, rounding away the floating point noise, e.g., in
+/// `0.30000000000000004`.
+fn format_change(change: f64) -> String {
+    format!("{change:+.9}")
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
+
+/// Formats the rows as a table, or as a Markdown table.
+pub(crate) fn format_table(
+    headers: &[&str],
+    rows: &[Vec<String>],
+    format: XvcMetricsOutputFormat,
+) -> Table {
+    let mut table = Table::new();
+    if format == XvcMetricsOutputFormat::Markdown {
+        table.load_preset(ASCII_MARKDOWN);
+    }
+    table.set_header(headers.to_vec());
+    for row in rows {
+        table.add_row(row);
+    }
+    table
+}
@@ -1,10 +1,14 @@
 pub mod dag;
 pub mod delete;
+pub mod exp;
 pub mod export;
 pub mod import;
 pub mod list;
+pub mod metrics;
 pub mod new;
+pub mod plots;
 pub mod run;
+pub mod status;
 pub mod update;
 
 pub mod step_dependency;
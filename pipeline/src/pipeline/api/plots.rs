@@ -0,0 +1,250 @@
+//! `xvc pipeline plots` renders the metrics and image outputs of a pipeline to a report.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::Parser;
+use clap_complete::ArgValueCompleter;
+use strum_macros::{Display, EnumString, IntoStaticStr, VariantNames};
+use xvc_core::util::completer::{git_reference_completer, strum_variants_completer};
+use xvc_core::{XvcOutputSender, XvcPath, XvcRoot, output};
+
+use crate::XvcOutput;
+use crate::error::Result;
+use crate::pipeline::metrics::{XvcMetricsSource, pipeline_outputs};
+use crate::pipeline::plots::{XvcMetricsTable, XvcPlotKind, escape, render_svg};
+
+/// Render plots from the metrics outputs and collect the image outputs of a pipeline
+#[derive(Debug, Clone, Parser)]
+#[command(name = "plots")]
+pub struct PlotsCLI {
+    /// Git reference to read the outputs from. If set, its outputs are shown side by side with
+    /// the workspace, or with `NEW`. Outputs tracked by Xvc are read from the cache.
+    #[arg(add = ArgValueCompleter::new(git_reference_completer))]
+    old: Option<String>,
+
+    /// Git reference to compare `OLD` with, instead of the workspace
+    #[arg(requires = "old", add = ArgValueCompleter::new(git_reference_completer))]
+    new: Option<String>,
+
+    /// Column for the horizontal axis. The row index is used if not set.
+    #[arg(long)]
+    x: Option<String>,
+
+    /// Columns to plot, separated by commas. All numeric columns are plotted if not set.
+    #[arg(long, value_delimiter = ',')]
+    y: Vec<String>,
+
+    /// Kind of the plots. One of line (default), scatter or bar.
+    #[arg(long, default_value = "line",
+          add = ArgValueCompleter::new(strum_variants_completer::<XvcPlotKind>))]
+    kind: XvcPlotKind,
+
+    /// Report format. html (default) writes a single index.html with the plots and images
+    /// embedded. svg writes a file for each plot and copies the images.
+    #[arg(long, default_value = "html",
+          add = ArgValueCompleter::new(strum_variants_completer::<XvcPlotsFormat>))]
+    format: XvcPlotsFormat,
+
+    /// Directory to write the report
+    #[arg(long, short, default_value = "xvc-plots")]
+    output: PathBuf,
+}
+
+/// Report formats for `xvc pipeline plots`
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, EnumString, Display, IntoStaticStr, Default, VariantNames,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum XvcPlotsFormat {
+    /// A single HTML file
+    #[default]
+    Html,
+    /// A file for each plot and image
+    Svg,
+}
+
+/// A plot or image of an output read from a source
+enum ReportItem {
+    Svg(String),
+    Image(Vec<u8>),
+    Message(&'static str),
+}
+
+/// Entry point for `xvc pipeline plots` command.
+pub fn cmd_plots(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    opts: PlotsCLI,
+) -> Result<()> {
+    let sources = match (opts.old, opts.new) {
+        (Some(old), Some(new)) => {
+            vec![XvcMetricsSource::GitRef(old), XvcMetricsSource::GitRef(new)]
+        }
+        (Some(old), None) => vec![XvcMetricsSource::GitRef(old), XvcMetricsSource::Workspace],
+        (None, _) => vec![XvcMetricsSource::Workspace],
+    };
+    for source in &sources {
+        source.verify(xvc_root)?;
+    }
+
+    let mut sections: Vec<(XvcPath, Vec<ReportItem>)> = Vec::new();
+    for out in pipeline_outputs(xvc_root, pipeline_name)? {
+        let (path, items) = match out {
+            XvcOutput::Metric { path, format } => {
+                let mut items = Vec::with_capacity(sources.len());
+                for source in &sources {
+                    let item = match source.read(xvc_root, &path)? {
+                        None => ReportItem::Message("Not found"),
+                        Some(content) => {
+                            let data = XvcMetricsTable::parse(&content, format)?
+                                .plot_data(opts.x.as_deref(), &opts.y);
+                            if data.series.is_empty() {
+                                ReportItem::Message("No numeric columns to plot")
+                            } else {
+                                let title = format!("{path} ({source})");
+                                ReportItem::Svg(render_svg(&title, &data, opts.kind))
+                            }
+                        }
+                    };
+                    items.push(item);
+                }
+                (path, items)
+            }
+            XvcOutput::Image { path } => {
+                let mut items = Vec::with_capacity(sources.len());
+                for source in &sources {
+                    items.push(match source.read_bytes(xvc_root, &path)? {
+                        Some(bytes) => ReportItem::Image(bytes),
+                        None => ReportItem::Message("Not found"),
+                    });
+                }
+                (path, items)
+            }
+            XvcOutput::File { .. } => continue,
+        };
+        sections.push((path, items));
+    }
+
+    fs::create_dir_all(&opts.output)?;
+    let written = match opts.format {
+        XvcPlotsFormat::Html => {
+            let report = html_report(pipeline_name, &sources, &sections);
+            let report_path = opts.output.join("index.html");
+            fs::write(&report_path, report)?;
+            vec![report_path]
+        }
+        XvcPlotsFormat::Svg => write_files(&opts.output, &sources, &sections)?,
+    };
+    for path in written {
+        output!(output_snd, "{}", path.to_string_lossy());
+    }
+    Ok(())
+}
+
+fn html_report(
+    pipeline_name: &str,
+    sources: &[XvcMetricsSource],
+    sections: &[(XvcPath, Vec<ReportItem>)],
+) -> String {
+    let mut html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Pipeline {0}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+.row {{ display: flex; flex-wrap: wrap; gap: 1em; }}
+figure {{ margin: 0; }}
+figcaption {{ font-weight: bold; margin-bottom: 0.5em; }}
+img {{ max-width: 640px; }}
+</style>
+</head>
+<body>
+<h1>Pipeline {0}</h1>
+"#,
+        escape(pipeline_name)
+    );
+    if sections.is_empty() {
+        html.push_str("<p>The pipeline has no metric or image outputs.</p>\n");
+    }
+    for (path, items) in sections {
+        html.push_str(&format!(
+            "<h2>{}</h2>\n<div class=\"row\">\n",
+            escape(path.as_str())
+        ));
+        for (source, item) in sources.iter().zip(items) {
+            let content = match item {
+                ReportItem::Svg(svg) => svg.clone(),
+                ReportItem::Image(bytes) => format!(
+                    r#"<img src="data:{};base64,{}" alt="{}">"#,
+                    mime_type(path.as_str()),
+                    BASE64.encode(bytes),
+                    escape(path.as_str())
+                ),
+                ReportItem::Message(message) => format!("<p>{message}</p>"),
+            };
+            html.push_str(&format!(
+                "<figure>\n<figcaption>{}</figcaption>\n{}\n</figure>\n",
+                escape(&source.to_string()),
+                content
+            ));
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Writes each plot as an SVG file and copies the images to `dir`. Names of the files are the
+/// output paths with `/` replaced by `_`, suffixed with the source when there are two sources.
+fn write_files(
+    dir: &Path,
+    sources: &[XvcMetricsSource],
+    sections: &[(XvcPath, Vec<ReportItem>)],
+) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (path, items) in sections {
+        let (stem, extension) = match path.as_str().rsplit_once('.') {
+            Some((stem, extension)) => (stem.replace('/', "_"), format!(".{extension}")),
+            None => (path.as_str().replace('/', "_"), String::new()),
+        };
+        for (source, item) in sources.iter().zip(items) {
+            let suffix = if sources.len() > 1 {
+                format!("-{}", source.to_string().replace('/', "_"))
+            } else {
+                String::new()
+            };
+            let file = match item {
+                ReportItem::Svg(svg) => {
+                    let file = dir.join(format!("{stem}{extension}{suffix}.svg"));
+                    fs::write(&file, svg)?;
+                    file
+                }
+                ReportItem::Image(bytes) => {
+                    let file = dir.join(format!("{stem}{suffix}{extension}"));
+                    fs::write(&file, bytes)?;
+                    file
+                }
+                ReportItem::Message(_) => continue,
+            };
+            written.push(file);
+        }
+    }
+    Ok(written)
+}
+
+fn mime_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
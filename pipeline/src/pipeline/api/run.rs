@@ -1,26 +1,147 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+
+use std::path::PathBuf;
 
 use clap::Parser;
+use clap_complete::ArgValueCompleter;
+use xvc_core::XvcConfiguration;
 use xvc_core::XvcOutputSender;
 use xvc_core::XvcRoot;
+use xvc_storage::StorageIdentifier;
+use xvc_storage::storage::{get_storage_record, storage_identifier_completer};
 
-use crate::pipeline::the_grand_pipeline_loop;
+use crate::pipeline::api::status::print_statuses;
+use crate::pipeline::params::{XvcParamOverride, apply_param_overrides};
+use crate::pipeline::util::step_name_completer;
+use crate::pipeline::{XvcRunTargets, the_grand_pipeline_loop};
 
 /// Run a pipeline
 #[derive(Debug, Clone, Parser)]
 #[command(name = "run")]
-pub struct RunCLI {}
+pub struct RunCLI {
+    /// Steps to run. Their dependency steps are run too. All steps are run if not given.
+    #[arg(add = ArgValueCompleter::new(step_name_completer))]
+    pub targets: Vec<String>,
+
+    /// Run only this step and the steps that depend on it
+    #[arg(long, add = ArgValueCompleter::new(step_name_completer))]
+    pub from: Option<String>,
+
+    /// Run only this step and its dependency steps, i.e., stop after this step
+    #[arg(long, add = ArgValueCompleter::new(step_name_completer))]
+    pub until: Option<String>,
+
+    /// Run these steps even if their dependencies haven't changed or they are set to never run.
+    /// They are run even if they aren't selected by the targets, `--from` or `--until`. Can be
+    /// used multiple times or as a comma separated list.
+    #[arg(long, value_delimiter = ',', add = ArgValueCompleter::new(step_name_completer))]
+    pub force: Vec<String>,
+
+    /// Storage name or guid to use as a shared run cache. Outputs of steps not in the local run
+    /// cache are looked up in it before running the steps, and outputs of steps that run are sent
+    /// to it. Requires `pipeline.run_cache = true`.
+    #[arg(long, add = ArgValueCompleter::new(storage_identifier_completer))]
+    pub use_remote_cache: Option<StorageIdentifier>,
+
+    /// Set a parameter before running, as `KEY=VALUE` or `FILE:KEY=VALUE`, e.g., `train.lr=0.01`.
+    /// Without a file, the value is written to the files of the parameter dependencies that use
+    /// the key. Can be used multiple times.
+    #[arg(long = "set", value_name = "[FILE:]KEY=VALUE")]
+    pub set: Vec<XvcParamOverride>,
+
+    /// Set the parameters in a YAML, JSON or TOML file before running. Nested keys are joined
+    /// with dots, like the keys of `--set`.
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub params_file: Option<PathBuf>,
+
+    /// Keep the values set by `--set` and `--params-file` in the parameter files after the run.
+    /// The files are restored by default.
+    #[arg(long)]
+    pub keep_params: bool,
+
+    /// Don't run the steps. Only show which steps would run, and why.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the dry run result as JSON
+    #[arg(long, requires = "dry_run")]
+    pub json: bool,
+}
+
+impl RunCLI {
+    /// Returns an error if the options require a configuration that isn't set
+    fn check_config(&self, config: &XvcConfiguration) -> Result<()> {
+        if self.use_remote_cache.is_some() && !config.pipeline.run_cache {
+            return Err(Error::RunCacheDisabled);
+        }
+        Ok(())
+    }
+}
 
 /// Entry point for `xvc pipeline run` command.
 ///
 /// It loads an [`XvcPipeline`] with the name and runs [`the_grand_pipeline_loop`]
-/// with it.
+/// with the steps selected by the options.
 pub fn cmd_run(
     output_snd: &XvcOutputSender,
     xvc_root: &XvcRoot,
     pipeline_name: &str,
-    _opts: RunCLI,
+    opts: RunCLI,
 ) -> Result<()> {
+    opts.check_config(xvc_root.config())?;
     let pipeline_name = pipeline_name.to_owned();
-    the_grand_pipeline_loop(output_snd, xvc_root, pipeline_name)
+    let run_targets = XvcRunTargets {
+        targets: opts.targets,
+        from: opts.from,
+        until: opts.until,
+        force: opts.force,
+    };
+    let remote_cache = opts
+        .use_remote_cache
+        .map(|identifier| get_storage_record(output_snd, xvc_root, &identifier))
+        .transpose()?;
+    let mut overrides = match &opts.params_file {
+        Some(path) => XvcParamOverride::from_params_file(path)?,
+        None => Vec::new(),
+    };
+    overrides.extend(opts.set);
+    let backup = apply_param_overrides(output_snd, xvc_root, &pipeline_name, &overrides)?;
+    let result = the_grand_pipeline_loop(
+        output_snd,
+        xvc_root,
+        pipeline_name,
+        &run_targets,
+        opts.dry_run,
+        remote_cache.as_ref(),
+    );
+    // The files are restored even if the run fails
+    if !opts.keep_params {
+        backup.restore()?;
+    }
+    let statuses = result?;
+    if opts.dry_run {
+        print_statuses(output_snd, &statuses, opts.json)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xvc_core::configuration::default_config;
+
+    #[test]
+    fn test_remote_cache_requires_run_cache() {
+        let mut config = default_config();
+        config.pipeline.run_cache = false;
+        let opts = RunCLI::parse_from(["run", "--use-remote-cache", "shared"]);
+        assert!(matches!(
+            opts.check_config(&config),
+            Err(Error::RunCacheDisabled)
+        ));
+        assert!(RunCLI::parse_from(["run"]).check_config(&config).is_ok());
+
+        config.pipeline.run_cache = true;
+        assert!(opts.check_config(&config).is_ok());
+    }
 }
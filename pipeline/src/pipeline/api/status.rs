@@ -0,0 +1,118 @@
+//! `xvc pipeline status` and `xvc pipeline run --dry-run`.
+//!
+//! These run the comparison states of the step state machine without starting any processes and
+//! report whether each step would run, and why.
+use clap::Parser;
+use serde::Serialize;
+use strum_macros::Display;
+use xvc_core::{Diff, XvcOutputSender, XvcPath, XvcRoot, output};
+
+use crate::error::Result;
+use crate::pipeline::{XvcRunTargets, the_grand_pipeline_loop};
+use crate::{XvcDependency, XvcOutput};
+
+/// Show which steps of a pipeline would run, and why
+#[derive(Debug, Clone, Parser)]
+#[command(name = "status")]
+pub struct StatusCLI {
+    /// Print the status as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Whether a step would run
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "UPPERCASE")]
+pub enum XvcStepRunStatus {
+    /// The step would run
+    Run,
+    /// The step is up to date
+    Skip,
+    /// The step is set to never run
+    Never,
+    /// The step can't run, e.g., because its dependency steps are broken
+    Broken,
+}
+
+/// Whether a step would run and why
+#[derive(Debug, Clone, Serialize)]
+pub struct XvcStepStatus {
+    /// Name of the step
+    pub name: String,
+    /// Whether the step would run
+    pub status: XvcStepRunStatus,
+    /// Why the step would (or wouldn't) run
+    pub reasons: Vec<String>,
+    /// Dependencies of the step that have changed since the last run
+    pub changed_dependencies: Vec<Diff<XvcDependency>>,
+    /// Outputs of the step that are missing
+    pub missing_outputs: Vec<XvcOutput>,
+    /// Outputs of the step whose content changed since the last run
+    pub changed_outputs: Vec<XvcOutput>,
+    /// Dependency steps that would run before this step
+    pub dependency_steps_to_run: Vec<String>,
+}
+
+/// Entry point for `xvc pipeline status` command.
+pub fn cmd_status(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    opts: StatusCLI,
+) -> Result<()> {
+    let statuses = the_grand_pipeline_loop(
+        output_snd,
+        xvc_root,
+        pipeline_name.to_owned(),
+        &XvcRunTargets::default(),
+        true,
+        None,
+    )?;
+    print_statuses(output_snd, &statuses, opts.json)
+}
+
+/// Prints the statuses as text, or as JSON if `json` is set.
+pub(crate) fn print_statuses(
+    output_snd: &XvcOutputSender,
+    statuses: &[XvcStepStatus],
+    json: bool,
+) -> Result<()> {
+    if json {
+        output!(output_snd, "{}", serde_json::to_string_pretty(statuses)?);
+        return Ok(());
+    }
+
+    for status in statuses {
+        output!(
+            output_snd,
+            "[{}] [{}] {}",
+            status.status,
+            status.name,
+            status.reasons.join(", ")
+        );
+        for diff in &status.changed_dependencies {
+            let line = match diff {
+                Diff::RecordMissing { actual } => format!("new: {actual}"),
+                Diff::ActualMissing { record } => format!("missing: {record}"),
+                Diff::Different { actual, .. } => format!("changed: {actual}"),
+                Diff::Identical | Diff::Skipped => continue,
+            };
+            output!(output_snd, "    {line}");
+        }
+        for out in &status.missing_outputs {
+            output!(
+                output_snd,
+                "    missing output: {}",
+                XvcPath::from(out.clone())
+            );
+        }
+        for out in &status.changed_outputs {
+            output!(output_snd, "    changed output: {}", XvcPath::from(out));
+        }
+        for step in &status.dependency_steps_to_run {
+            output!(output_snd, "    dependency step runs: {step}");
+        }
+    }
+    Ok(())
+}
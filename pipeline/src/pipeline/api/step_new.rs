@@ -2,7 +2,10 @@ use crate::error::{Error, Result};
 use xvc_core::XvcRoot;
 use xvc_core::{R1NStore, R11Store, XvcStore};
 
-use crate::{XvcPipeline, XvcStep, XvcStepCommand, pipeline::XvcStepInvalidate};
+use crate::{
+    XvcPipeline, XvcStep, XvcStepCommand,
+    pipeline::{XvcStepInvalidate, XvcStepTimeout},
+};
 
 /// Creates a new step
 pub fn cmd_step_new(
@@ -11,6 +14,7 @@ pub fn cmd_step_new(
     step_name: String,
     command: String,
     changed: Option<XvcStepInvalidate>,
+    timeout: Option<XvcStepTimeout>,
 ) -> Result<()> {
     let (pipeline_e, pipeline) = XvcPipeline::from_name(xvc_root, pipeline_name)?;
     let (step_e, step) = match XvcStep::from_name(xvc_root, &pipeline_e, &step_name) {
@@ -31,6 +35,13 @@ pub fn cmd_step_new(
         Ok(())
     })?;
 
+    if let Some(timeout) = timeout.and_then(|t| XvcStepTimeout::from_seconds(t.seconds)) {
+        xvc_root.with_store_mut(|bs: &mut XvcStore<XvcStepTimeout>| {
+            bs.insert(step_e, timeout);
+            Ok(())
+        })?;
+    }
+
     xvc_root.with_store_mut(|bs: &mut XvcStore<XvcStepCommand>| {
         let step_command = XvcStepCommand {
             command: command.clone(),
@@ -4,7 +4,8 @@ use xvc_core::{XvcOutputSender, info};
 
 use crate::{
     Result, XvcDependency, XvcOutput, XvcPipeline, XvcStep, XvcStepCommand,
-    pipeline::XvcStepInvalidate,
+    pipeline::outs::XvcOutputDigest,
+    pipeline::{XvcStepInvalidate, XvcStepTimeout},
 };
 
 /// Remove a step from a pipeline
@@ -46,6 +47,12 @@ pub fn cmd_step_remove(
     )?;
 
     // Remove outputs
+    let output_entities = xvc_root
+        .load_r1nstore::<XvcStep, XvcOutput>()?
+        .children_of(&step_e)?
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
     xvc_root.with_r1nstore_mut::<XvcStep, XvcOutput>(|rs: &mut R1NStore<XvcStep, XvcOutput>| {
         let outputs = rs.children_of(&step_e)?;
         for (output_e, output) in outputs.iter() {
@@ -54,6 +61,13 @@ pub fn cmd_step_remove(
         }
         Ok(())
     })?;
+
+    xvc_root.with_store_mut(|bs: &mut XvcStore<XvcOutputDigest>| {
+        for output_e in &output_entities {
+            bs.remove(*output_e);
+        }
+        Ok(())
+    })?;
     // Remove step
 
     xvc_root.with_store_mut(|bs: &mut XvcStore<XvcStepCommand>| {
@@ -66,6 +80,11 @@ pub fn cmd_step_remove(
         Ok(())
     })?;
 
+    xvc_root.with_store_mut(|bs: &mut XvcStore<XvcStepTimeout>| {
+        bs.remove(step_e);
+        Ok(())
+    })?;
+
     xvc_root.with_r1nstore_mut::<XvcPipeline, XvcStep>(
         |rs: &mut R1NStore<XvcPipeline, XvcStep>| {
             info!(output_snd, "Removing step: {}", step);
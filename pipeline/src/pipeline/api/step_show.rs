@@ -4,7 +4,7 @@ use xvc_core::{XvcRoot, util::serde::to_json};
 
 use crate::{
     XvcDependency, XvcOutput, XvcPipeline, XvcStep, XvcStepCommand, XvcStepSchema,
-    pipeline::XvcStepInvalidate,
+    pipeline::{XvcStepInvalidate, XvcStepTimeout},
 };
 
 /// Entry point for `xvc pipeline step show` command.
@@ -24,6 +24,9 @@ pub fn cmd_step_show(
     let bs_invalidate = xvc_root.load_store::<XvcStepInvalidate>()?;
     let invalidate = bs_invalidate.get(&step_e).cloned().unwrap_or_default();
 
+    let bs_timeout = xvc_root.load_store::<XvcStepTimeout>()?;
+    let timeout = bs_timeout.get(&step_e).map(|t| t.seconds);
+
     let mut deps: XvcStore<XvcDependency> = XvcStore::new();
     xvc_root.with_r1nstore(|rs: &R1NStore<XvcStep, XvcDependency>| {
         for (dep_e, dep) in rs.children_of(&step_e)?.iter() {
@@ -44,6 +47,7 @@ pub fn cmd_step_show(
         name: step.name,
         command: command.command.clone(),
         invalidate,
+        timeout,
         dependencies: deps.iter().map(|(_, v)| v.clone()).collect(),
         outputs: outs.iter().map(|(_, v)| v.clone()).collect(),
     };
@@ -2,17 +2,22 @@ use crate::error::Result;
 use xvc_core::XvcRoot;
 use xvc_core::{R11Store, XvcStore};
 
-use crate::{XvcPipeline, XvcStep, XvcStepCommand, pipeline::XvcStepInvalidate};
+use crate::{
+    XvcPipeline, XvcStep, XvcStepCommand,
+    pipeline::{XvcStepInvalidate, XvcStepTimeout},
+};
 
 /// Entry point for `xvc pipeline step update` command.
-/// Updates the command and invalidation strategy (`when` to run) of the
-/// given step.
+/// Updates the command, invalidation strategy (`when` to run) and timeout of
+/// the given step. A timeout of `0` removes the step's timeout and the
+/// pipeline default is used.
 pub fn cmd_step_update(
     xvc_root: &XvcRoot,
     pipeline_name: &str,
     step_name: String,
     command: Option<String>,
     changed: Option<XvcStepInvalidate>,
+    timeout: Option<XvcStepTimeout>,
 ) -> Result<()> {
     let (pipeline_e, _) = XvcPipeline::from_name(xvc_root, pipeline_name)?;
     let (step_e, step) = XvcStep::from_name(xvc_root, &pipeline_e, &step_name)?;
@@ -34,5 +39,15 @@ pub fn cmd_step_update(
         })?;
     };
 
+    if let Some(timeout) = timeout {
+        xvc_root.with_store_mut(|bs: &mut XvcStore<XvcStepTimeout>| {
+            match XvcStepTimeout::from_seconds(timeout.seconds) {
+                Some(timeout) => bs.insert(step_e, timeout),
+                None => bs.remove(step_e),
+            };
+            Ok(())
+        })?;
+    }
+
     Ok(())
 }
@@ -4,13 +4,12 @@ use crossbeam_channel::{Receiver, Sender};
 
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read};
 
+use std::thread::JoinHandle;
 use std::time::Instant;
 use subprocess as sp;
 
-use xvc_file::CHANNEL_CAPACITY;
-
 use serde::{Deserialize, Serialize};
 use xvc_core::persist;
 
@@ -60,14 +59,19 @@ pub struct CommandProcess {
     pub stdout_receiver: Receiver<String>,
     /// Channel to receive stderr from
     pub stderr_receiver: Receiver<String>,
+    /// Threads that read the stdout and stderr of the process to the channels
+    pub output_readers: Vec<JoinHandle<()>>,
 }
 
 impl CommandProcess {
     /// Create a new CommandProcess by creating channels and setting other variables to their
     /// default values.
+    ///
+    /// The output channels are unbounded, so the output readers never block and
+    /// [Self::join_output_readers] can be called before the output is received.
     pub fn new(step: &XvcStep, step_command: &XvcStepCommand) -> Self {
-        let (stdout_sender, stdout_receiver) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
-        let (stderr_sender, stderr_receiver) = crossbeam_channel::bounded(CHANNEL_CAPACITY);
+        let (stdout_sender, stdout_receiver) = crossbeam_channel::unbounded();
+        let (stderr_sender, stderr_receiver) = crossbeam_channel::unbounded();
         Self {
             environment: HashMap::new(),
             step: step.clone(),
@@ -78,6 +82,7 @@ impl CommandProcess {
             stderr_sender,
             stdout_receiver,
             stderr_receiver,
+            output_readers: Vec::new(),
         }
     }
 
@@ -89,42 +94,88 @@ impl CommandProcess {
 
     /// Start executing the command in a shell. Updates birth and process variables after
     /// detaching.
+    ///
+    /// The output of the process is read line by line in separate threads, so that the process
+    /// can be polled (and terminated on timeout) while it's running.
     pub fn run(&mut self) -> Result<()> {
-        let process = sp::Exec::shell(self.step_command.command.clone())
+        let mut process = sp::Exec::shell(self.step_command.command.clone())
             .stdout(sp::Redirection::Pipe)
             .stderr(sp::Redirection::Pipe)
             .stdin(sp::Redirection::None)
             .env_extend(self.environment.iter())
             .detached()
             .start()?;
+        if let Some(stdout) = process.stdout.take() {
+            let prefix = format!("[OUT] [{}] ", self.step.name);
+            self.output_readers.push(spawn_output_reader(
+                stdout,
+                prefix,
+                self.stdout_sender.clone(),
+            ));
+        }
+        if let Some(stderr) = process.stderr.take() {
+            let prefix = format!("[ERR] [{}] ", self.step.name);
+            self.output_readers.push(spawn_output_reader(
+                stderr,
+                prefix,
+                self.stderr_sender.clone(),
+            ));
+        }
         self.process = Some(process);
         self.birth = Some(Instant::now());
         Ok(())
     }
 
-    /// Collects the output from process and sends to output channels.
-    pub fn update_output_channels(&mut self) -> Result<()> {
-        if let Some(p) = &self.process {
-            if let Some(mut stdout) = p.stdout.as_ref() {
-                let mut out = String::new();
-                stdout.read_to_string(&mut out)?;
-                if !out.is_empty() {
-                    self.stdout_sender
-                        .send(format!("[OUT] [{}] {}", self.step.name, out))
-                        .ok();
-                }
-            }
+    /// Waits until all the output of the process is sent to the output channels.
+    ///
+    /// This should only be called after the process exits.
+    pub fn join_output_readers(&mut self) {
+        for reader in self.output_readers.drain(..) {
+            reader.join().ok();
+        }
+    }
+}
 
-            if let Some(mut stderr) = p.stderr.as_ref() {
-                let mut err = String::new();
-                stderr.read_to_string(&mut err)?;
-                if !err.is_empty() {
-                    self.stderr_sender
-                        .send(format!("[ERR] [{}] {}", self.step.name, err))
-                        .ok();
-                }
+/// Sends the lines read from `output` to `sender` with `prefix` until the output is closed.
+fn spawn_output_reader(
+    output: impl Read + Send + 'static,
+    prefix: String,
+    sender: Sender<String>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(output);
+        let mut line = String::new();
+        while let Ok(n) = reader.read_line(&mut line) {
+            if n == 0 {
+                break;
+            }
+            let text = line.trim_end_matches(['\n', '\r']);
+            if sender.send(format!("{prefix}{text}")).is_err() {
+                break;
             }
+            line.clear();
         }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_output_readers_with_unread_output() -> Result<()> {
+        let step = XvcStep {
+            name: "many-lines".to_string(),
+        };
+        let step_command = XvcStepCommand {
+            command: "seq 1 200000".to_string(),
+        };
+        let mut cp = CommandProcess::new(&step, &step_command);
+        cp.run()?;
+        cp.process.as_mut().unwrap().wait()?;
+        // The readers must finish even if the output isn't received yet
+        cp.join_output_readers();
+        assert_eq!(cp.stdout_receiver.try_iter().count(), 200000);
         Ok(())
     }
 }
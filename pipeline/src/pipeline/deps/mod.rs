@@ -155,6 +155,30 @@ impl XvcDependency {
             | XvcDependency::UrlDigest(_) => None,
         }
     }
+
+    /// Returns the dependency without file metadata and HTTP headers, i.e., only with what
+    /// identifies its content. Timestamps differ between checkouts of the same content, so they
+    /// are removed for the run cache keys.
+    pub fn without_metadata(&self) -> XvcDependency {
+        let mut dep = self.clone();
+        match &mut dep {
+            XvcDependency::File(dep) => dep.xvc_metadata = None,
+            XvcDependency::GlobItems(dep) => dep.xvc_path_metadata_map.clear(),
+            XvcDependency::Glob(dep) => dep.xvc_metadata_digest = None,
+            XvcDependency::RegexItems(dep) => dep.xvc_metadata = None,
+            XvcDependency::Regex(dep) => dep.xvc_metadata = None,
+            XvcDependency::Param(dep) => dep.xvc_metadata = None,
+            XvcDependency::LineItems(dep) => dep.xvc_metadata = None,
+            XvcDependency::Lines(dep) => dep.xvc_metadata = None,
+            XvcDependency::SqliteQueryDigest(dep) => dep.xvc_metadata = None,
+            XvcDependency::UrlDigest(dep) => {
+                dep.etag = None;
+                dep.last_modified = None;
+            }
+            XvcDependency::Step(_) | XvcDependency::Generic(_) => {}
+        }
+        dep
+    }
 }
 
 /// Returns steps that depend to `to_path`
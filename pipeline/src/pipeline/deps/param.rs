@@ -300,7 +300,7 @@ impl XvcParamValue {
     /// Loads a TOML file and returns the `XvcParamPair::TOML(TomlValue)`
     /// associated with the key
     fn parse_toml(all_content: &str, key: &str) -> Result<Self> {
-        let toml_map = all_content.parse::<TomlValue>()?;
+        let toml_map = TomlValue::Table(all_content.parse::<toml::Table>()?);
         let nested_keys: Vec<&str> = key.split('.').collect();
         let mut current_scope: TomlValue = toml_map;
         for k in &nested_keys {
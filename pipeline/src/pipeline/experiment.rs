@@ -0,0 +1,731 @@
+//! Experiments are runs of a pipeline with different parameter values.
+//!
+//! An experiment is an entity with an [XvcExperiment] record. The parameter values it's run with
+//! are in [XvcExperimentParams], the values in the metrics outputs after the run are in
+//! [XvcExperimentMetrics], and the output files are copied to the cache and listed in
+//! [XvcExperimentOutputs]. The outputs in the cache allow to apply an experiment to the workspace
+//! later without running it again.
+//!
+//! Experiments run either in the workspace one after another, or in temporary copies of the
+//! workspace that share the cache of the repository.
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value as YamlValue;
+use strum_macros::Display;
+use xvc_core::types::xvcroot::XvcRootInner;
+use xvc_core::util::file::make_symlink;
+use xvc_core::{
+    ContentDigest, FromConfig, HashAlgorithm, PathSync, XvcCachePath, XvcEntity, XvcOutputSender,
+    XvcPath, XvcRoot, XvcStore, persist, warn,
+};
+use xvc_file::common::{move_to_cache, set_writable};
+
+use crate::error::{Error, Result};
+use crate::pipeline::api::status::XvcStepRunStatus;
+use crate::pipeline::metrics::{
+    XvcMetric, XvcMetricsSource, git, load_metrics, pipeline_metric_outputs, pipeline_outputs,
+};
+use crate::pipeline::params::{
+    XvcParamBackup, XvcParamOverride, apply_param_overrides, read_params_file,
+};
+use crate::pipeline::run_cache::XvcRunCacheOutput;
+use crate::pipeline::{XvcRunTargets, the_grand_pipeline_loop};
+
+/// Whether all steps of the pipeline have run successfully in an experiment
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum XvcExperimentStatus {
+    /// All steps are run or up to date
+    Succeeded,
+    /// Some of the steps are broken
+    Failed,
+}
+
+/// A run of a pipeline with a set of parameter values
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcExperiment {
+    /// Unique name of the experiment
+    pub name: String,
+    /// Name of the pipeline
+    pub pipeline: String,
+    /// The Git commit the experiment is run on. `None` if the repository doesn't use Git.
+    pub base_commit: Option<String>,
+    /// Whether the pipeline has run successfully
+    pub status: XvcExperimentStatus,
+}
+
+persist!(XvcExperiment, "xvc-experiment");
+
+/// The parameter values set for an experiment
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcExperimentParams {
+    /// Parameters in the order they are set
+    pub params: Vec<XvcParamOverride>,
+}
+
+persist!(XvcExperimentParams, "xvc-experiment-params");
+
+/// The values in the metrics outputs of the pipeline after an experiment
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcExperimentMetrics {
+    /// Metrics sorted by path and key
+    pub metrics: Vec<XvcMetric>,
+}
+
+persist!(XvcExperimentMetrics, "xvc-experiment-metrics");
+
+/// The outputs of the pipeline after an experiment, copied to the cache
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcExperimentOutputs {
+    /// Outputs sorted by path
+    pub outputs: Vec<XvcRunCacheOutput>,
+}
+
+persist!(XvcExperimentOutputs, "xvc-experiment-outputs");
+
+impl XvcExperiment {
+    /// Loads an experiment by name.
+    ///
+    /// Returns the entity and the experiment if found. Otherwise returns
+    /// [Error::ExperimentNotFound].
+    pub fn from_name(xvc_root: &XvcRoot, name: &str) -> Result<(XvcEntity, Self)> {
+        let all = xvc_root.load_store::<XvcExperiment>()?;
+        find_experiment(&all, name).ok_or_else(|| Error::ExperimentNotFound {
+            name: name.to_owned(),
+        })
+    }
+}
+
+fn find_experiment(
+    store: &XvcStore<XvcExperiment>,
+    name: &str,
+) -> Option<(XvcEntity, XvcExperiment)> {
+    store
+        .iter()
+        .find(|(_, exp)| exp.name == name)
+        .map(|(e, exp)| (*e, exp.clone()))
+}
+
+/// Builds the parameter sets of the experiments to run.
+///
+/// Each entry of `params_file` is an experiment, if it contains a list. Otherwise the file is a
+/// single experiment. The values of `sets` are separated by commas, and each combination of them
+/// is run with each entry of the file, replacing the values of the same keys in it. A comma in a
+/// value is written as `\,`.
+pub fn experiment_grid(
+    sets: &[XvcParamOverride],
+    params_file: Option<&Path>,
+) -> Result<Vec<Vec<XvcParamOverride>>> {
+    let mut grid = match params_file {
+        Some(path) => match read_params_file(path)? {
+            YamlValue::Sequence(entries) => entries
+                .iter()
+                .map(XvcParamOverride::from_value)
+                .collect::<Result<Vec<_>>>()?,
+            value => vec![XvcParamOverride::from_value(&value)?],
+        },
+        None => vec![Vec::new()],
+    };
+    for set in sets {
+        let values = split_values(&set.value);
+        grid = grid
+            .into_iter()
+            .flat_map(|params| {
+                values.iter().map(move |value| {
+                    let mut params = params.clone();
+                    params.retain(|p| p.file != set.file || p.key != set.key);
+                    params.push(XvcParamOverride {
+                        value: value.clone(),
+                        ..set.clone()
+                    });
+                    params
+                })
+            })
+            .collect();
+    }
+    Ok(grid)
+}
+
+/// Splits `value` at commas that are not escaped as `\,`.
+fn split_values(value: &str) -> Vec<String> {
+    let mut values = vec![String::new()];
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                values.last_mut().unwrap().push(',');
+            }
+            ',' => values.push(String::new()),
+            c => values.last_mut().unwrap().push(c),
+        }
+    }
+    values
+}
+
+/// Returns names for `count` new experiments.
+///
+/// If `prefix` is given, a single experiment is named as the prefix and it's an error if it's
+/// already used. Otherwise the experiments are numbered after the prefix, or `exp`, skipping the
+/// names in use.
+pub fn experiment_names(
+    xvc_root: &XvcRoot,
+    prefix: Option<&str>,
+    count: usize,
+) -> Result<Vec<String>> {
+    let existing = xvc_root.load_store::<XvcExperiment>()?;
+    let used: HashSet<&str> = existing.values().map(|exp| exp.name.as_str()).collect();
+    if let (Some(name), 1) = (prefix, count) {
+        if used.contains(name) {
+            return Err(Error::ExperimentAlreadyFound {
+                name: name.to_owned(),
+            });
+        }
+        return Ok(vec![name.to_owned()]);
+    }
+    let prefix = prefix.unwrap_or("exp");
+    Ok((1..)
+        .map(|n| format!("{prefix}-{n}"))
+        .filter(|name| !used.contains(name.as_str()))
+        .take(count)
+        .collect())
+}
+
+/// The commit checked out in the repository. `None` if Git isn't used or there are no commits.
+pub fn base_commit(xvc_root: &XvcRoot) -> Option<String> {
+    if !xvc_root.config().git.use_git {
+        return None;
+    }
+    git(xvc_root, &["rev-parse", "HEAD"])
+        .ok()
+        .map(|commit| commit.trim().to_owned())
+}
+
+/// Moves the output files of the pipeline in the workspace to the cache and rechecks them. Missing
+/// outputs are skipped.
+pub fn cache_pipeline_outputs(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+) -> Result<Vec<XvcRunCacheOutput>> {
+    let algorithm = *HashAlgorithm::from_config(xvc_root.config())?;
+    let mut outputs = Vec::new();
+    for out in pipeline_outputs(xvc_root, pipeline_name)? {
+        let path = XvcPath::from(&out);
+        let abs_path = path.to_absolute_path(xvc_root);
+        if abs_path.is_file() {
+            outputs.push(XvcRunCacheOutput::from_file(
+                output_snd, xvc_root, path, &abs_path, algorithm,
+            )?);
+        }
+    }
+    Ok(outputs)
+}
+
+/// Runs the pipeline in the workspace with `params` and records the results as the experiment
+/// `name`.
+///
+/// The parameter files are restored after the run, and the outputs are left as the experiment
+/// produced them.
+pub fn run_experiment(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    name: &str,
+    params: &[XvcParamOverride],
+    base_commit: Option<String>,
+) -> Result<XvcExperimentStatus> {
+    let backup = apply_param_overrides(output_snd, xvc_root, pipeline_name, params)?;
+    let result = the_grand_pipeline_loop(
+        output_snd,
+        xvc_root,
+        pipeline_name.to_owned(),
+        &XvcRunTargets::default(),
+        false,
+        None,
+    );
+    // The outputs of a failed run may be left from a previous run, so they are not recorded.
+    let outputs = result.and_then(|statuses| {
+        if statuses
+            .iter()
+            .any(|s| s.status == XvcStepRunStatus::Broken)
+        {
+            return Ok((XvcExperimentStatus::Failed, Vec::new(), Vec::new()));
+        }
+        let metric_files = pipeline_metric_outputs(xvc_root, pipeline_name)?;
+        let metrics = load_metrics(xvc_root, &metric_files, &XvcMetricsSource::Workspace)?;
+        let outputs = cache_pipeline_outputs(output_snd, xvc_root, pipeline_name)?;
+        Ok((XvcExperimentStatus::Succeeded, metrics, outputs))
+    });
+    // The parameter files are restored before reporting an error in reading the results
+    backup.restore()?;
+    let (status, metrics, outputs) = outputs?;
+
+    let experiment = XvcExperiment {
+        name: name.to_owned(),
+        pipeline: pipeline_name.to_owned(),
+        base_commit,
+        status,
+    };
+    record_experiment(
+        xvc_root,
+        experiment,
+        XvcExperimentParams {
+            params: params.to_vec(),
+        },
+        XvcExperimentMetrics { metrics },
+        XvcExperimentOutputs { outputs },
+    )?;
+    Ok(status)
+}
+
+fn record_experiment(
+    xvc_root: &XvcRoot,
+    experiment: XvcExperiment,
+    params: XvcExperimentParams,
+    metrics: XvcExperimentMetrics,
+    outputs: XvcExperimentOutputs,
+) -> Result<XvcEntity> {
+    let entity = xvc_root.new_entity();
+    xvc_root.with_store_mut(|store: &mut XvcStore<XvcExperiment>| {
+        store.insert(entity, experiment.clone());
+        Ok(())
+    })?;
+    xvc_root.with_store_mut(|store: &mut XvcStore<XvcExperimentParams>| {
+        store.insert(entity, params.clone());
+        Ok(())
+    })?;
+    xvc_root.with_store_mut(|store: &mut XvcStore<XvcExperimentMetrics>| {
+        store.insert(entity, metrics.clone());
+        Ok(())
+    })?;
+    xvc_root.with_store_mut(|store: &mut XvcStore<XvcExperimentOutputs>| {
+        store.insert(entity, outputs.clone());
+        Ok(())
+    })?;
+    Ok(entity)
+}
+
+/// Loads an experiment with its parameters and outputs by name.
+pub fn load_experiment(
+    xvc_root: &XvcRoot,
+    name: &str,
+) -> Result<(XvcExperiment, XvcExperimentParams, XvcExperimentOutputs)> {
+    let (entity, experiment) = XvcExperiment::from_name(xvc_root, name)?;
+    let params = xvc_root
+        .load_store::<XvcExperimentParams>()?
+        .get(&entity)
+        .cloned()
+        .unwrap_or(XvcExperimentParams { params: Vec::new() });
+    let outputs = xvc_root
+        .load_store::<XvcExperimentOutputs>()?
+        .get(&entity)
+        .cloned()
+        .unwrap_or(XvcExperimentOutputs {
+            outputs: Vec::new(),
+        });
+    Ok((experiment, params, outputs))
+}
+
+/// Writes the parameters of an experiment to the parameter files and restores its outputs from the
+/// cache. Returns the original contents of the parameter files.
+///
+/// Outputs that are not in the cache are skipped with a warning.
+pub fn apply_experiment(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    experiment: &XvcExperiment,
+    params: &XvcExperimentParams,
+    outputs: &XvcExperimentOutputs,
+) -> Result<XvcParamBackup> {
+    let backup = apply_param_overrides(output_snd, xvc_root, &experiment.pipeline, &params.params)?;
+    for output in &outputs.outputs {
+        if output.cache_path()?.to_absolute_path(xvc_root).exists() {
+            output.restore(output_snd, xvc_root)?;
+        } else {
+            warn!(
+                output_snd,
+                "[{}] Output {} is not in the cache", experiment.name, output.path
+            );
+        }
+    }
+    Ok(backup)
+}
+
+/// The `xvc` executable that runs experiments in temporary workspaces.
+///
+/// Commands use the runner attached to the repository with
+/// [XvcRootInner::insert_extension]. Otherwise the running executable is used if it's `xvc`, and
+/// `xvc` in `PATH` if it's not, e.g., when Xvc is used as a library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XvcExperimentRunner {
+    /// Path of the `xvc` executable
+    pub executable: PathBuf,
+}
+
+impl Default for XvcExperimentRunner {
+    fn default() -> Self {
+        let executable = env::current_exe()
+            .ok()
+            .filter(|exe| exe.file_stem().is_some_and(|stem| stem == "xvc"))
+            .unwrap_or_else(|| PathBuf::from("xvc"));
+        Self { executable }
+    }
+}
+
+impl XvcExperimentRunner {
+    /// The runner attached to `xvc_root`, or the default one if none is attached.
+    pub fn of(xvc_root: &XvcRoot) -> Arc<Self> {
+        xvc_root
+            .extension::<Self>()
+            .unwrap_or_else(|| Arc::new(Self::default()))
+    }
+}
+
+/// Runs the experiment `name` in a temporary copy of the workspace, and records its results in
+/// the repository.
+///
+/// The experiment is run by the [XvcExperimentRunner] executable, in its sequential mode. The
+/// copy contains the files not ignored by Git and the files tracked by Xvc, but not `.git` and the
+/// cache. The copy has its own cache, and the outputs of the experiment are moved to the cache of
+/// the repository after the run, so that they can be applied to the workspace. The copy is removed
+/// after the run.
+pub fn run_experiment_in_temp_dir(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    name: &str,
+    params: &[XvcParamOverride],
+    base_commit: Option<String>,
+) -> Result<XvcExperimentStatus> {
+    let temp_dir = env::temp_dir().join(format!("xvc-exp-{}", uuid::Uuid::new_v4()));
+    let result = copy_workspace(output_snd, xvc_root, &temp_dir).and_then(|_| {
+        run_in_dir(xvc_root, &temp_dir, pipeline_name, name, params)?;
+        let store_dir = temp_dir
+            .join(XvcRootInner::XVC_DIR)
+            .join(XvcRootInner::STORE_DIR);
+        let not_recorded = || Error::ExperimentNotRecorded {
+            name: name.to_owned(),
+        };
+        let experiments = XvcStore::<XvcExperiment>::load_store(&store_dir)?;
+        let (entity, experiment) = find_experiment(&experiments, name).ok_or_else(not_recorded)?;
+        let metrics = XvcStore::<XvcExperimentMetrics>::load_store(&store_dir)?
+            .get(&entity)
+            .cloned()
+            .ok_or_else(not_recorded)?;
+        let outputs = XvcStore::<XvcExperimentOutputs>::load_store(&store_dir)?
+            .get(&entity)
+            .cloned()
+            .ok_or_else(not_recorded)?;
+        collect_outputs(xvc_root, &temp_dir, &outputs)?;
+        Ok((experiment.status, metrics, outputs))
+    });
+    if let Err(e) = remove_workspace_copy(&temp_dir) {
+        warn!(
+            output_snd,
+            "Cannot remove temporary workspace {}: {e}",
+            temp_dir.to_string_lossy()
+        );
+    }
+    let (status, metrics, outputs) = result?;
+
+    let experiment = XvcExperiment {
+        name: name.to_owned(),
+        pipeline: pipeline_name.to_owned(),
+        base_commit,
+        status,
+    };
+    record_experiment(
+        xvc_root,
+        experiment,
+        XvcExperimentParams {
+            params: params.to_vec(),
+        },
+        metrics,
+        outputs,
+    )?;
+    Ok(status)
+}
+
+fn run_in_dir(
+    xvc_root: &XvcRoot,
+    dir: &Path,
+    pipeline_name: &str,
+    name: &str,
+    params: &[XvcParamOverride],
+) -> Result<()> {
+    // Step commands run in the current directory, so it's set instead of passing `-C`.
+    let mut command = Command::new(&XvcExperimentRunner::of(xvc_root).executable);
+    command
+        .current_dir(dir)
+        .args(["--skip-git", "exp", "--pipeline-name", pipeline_name])
+        .args(["run", "--name", name]);
+    for param in params {
+        // Files are made relative to the root, which is the current directory of the child
+        // process.
+        let file = param
+            .file
+            .as_ref()
+            .map(|file| XvcPath::new(xvc_root, xvc_root.current_dir(), file))
+            .transpose()?
+            .map(|path| PathBuf::from(path.as_str()));
+        let param = XvcParamOverride {
+            file,
+            key: param.key.clone(),
+            value: param.value.replace(',', "\\,"),
+        };
+        command.arg("--set").arg(param.to_string());
+    }
+    // The child process reports its errors. A failed run is detected by the missing record.
+    command.status()?;
+    Ok(())
+}
+
+/// The entries of `.xvc` copied to the workspace copy. Other entries, like the cache, the run cache
+/// and the transfer journals, belong to the repository.
+const XVC_STATE_ENTRIES: &[&str] = &[
+    XvcRootInner::STORE_DIR,
+    "ec",
+    "guid",
+    "pipelines",
+    "config.toml",
+    "config.local.toml",
+];
+
+/// Copies the workspace to `dir` for an experiment.
+///
+/// The files not ignored by Git are copied, including their uncommitted changes. The files tracked
+/// by Xvc are linked to the cache of the repository, as they are ignored by Git. Only the stores,
+/// the entity counter and the configuration are copied from `.xvc`. Without Git, all files except
+/// `.git` and `.xvc` are copied.
+fn copy_workspace(output_snd: &XvcOutputSender, xvc_root: &XvcRoot, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let root = xvc_root.absolute_path();
+    match git(
+        xvc_root,
+        &[
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+        ],
+    ) {
+        Ok(files) => {
+            for file in files.split('\0').filter(|f| !f.is_empty()) {
+                let from = root.join(file);
+                // .xvc is copied below. Deleted files are still listed until they are committed.
+                if file.starts_with(&format!("{}/", XvcRootInner::XVC_DIR))
+                    || fs::symlink_metadata(&from).is_err()
+                {
+                    continue;
+                }
+                let to = dir.join(file);
+                if let Some(parent) = to.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                copy_entry(&from, &to)?;
+            }
+        }
+        Err(_) => {
+            for entry in fs::read_dir(root)? {
+                let entry = entry?;
+                if entry.file_name() != ".git" && entry.file_name() != XvcRootInner::XVC_DIR {
+                    copy_entry(&entry.path(), &dir.join(entry.file_name()))?;
+                }
+            }
+        }
+    }
+
+    let xvc_dir = dir.join(XvcRootInner::XVC_DIR);
+    fs::create_dir_all(&xvc_dir)?;
+    for entry in XVC_STATE_ENTRIES {
+        let from = xvc_root.xvc_dir().join(entry);
+        if from.exists() {
+            copy_entry(&from, &xvc_dir.join(entry))?;
+        }
+    }
+
+    let paths = xvc_root.load_store::<XvcPath>()?;
+    let digests = xvc_root.load_store::<ContentDigest>()?;
+    for (xe, path) in paths.iter() {
+        let Some(digest) = digests.get(xe) else {
+            continue;
+        };
+        let cache_path = XvcCachePath::new(path, digest)?.to_absolute_path(xvc_root);
+        if !cache_path.is_file() {
+            warn!(output_snd, "{path} is not in the cache. It's not copied.");
+            continue;
+        }
+        let target = dir.join(path.as_str());
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&target).is_ok() {
+            fs::remove_file(&target)?;
+        }
+        make_symlink(&cache_path, &target)?;
+    }
+    Ok(())
+}
+
+/// Moves the `outputs` of an experiment from the cache of the workspace copy in `dir` to the cache
+/// of the repository.
+fn collect_outputs(xvc_root: &XvcRoot, dir: &Path, outputs: &XvcExperimentOutputs) -> Result<()> {
+    let path_sync = PathSync::new();
+    for output in &outputs.outputs {
+        let cache_path = output.cache_path()?;
+        let target = cache_path.to_absolute_path(xvc_root);
+        let source = dir
+            .join(XvcRootInner::XVC_DIR)
+            .join(cache_path.inner().as_str());
+        if target.exists() || !source.is_file() {
+            continue;
+        }
+        // The copy may be on another file system, so the file is copied next to the cache before
+        // moving it.
+        let staged = xvc_root
+            .xvc_dir()
+            .join(format!("exp-output-{}", uuid::Uuid::new_v4()));
+        fs::copy(&source, &staged)?;
+        move_to_cache(&staged, &target, &path_sync)?;
+    }
+    Ok(())
+}
+
+/// Removes the workspace copy in `dir`. Its cache directories are read-only, so they are made
+/// writable first.
+fn remove_workspace_copy(dir: &Path) -> Result<()> {
+    fn make_dirs_writable(dir: &Path) -> Result<()> {
+        set_writable(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                make_dirs_writable(&entry.path())?;
+            }
+        }
+        Ok(())
+    }
+    let xvc_dir = dir.join(XvcRootInner::XVC_DIR);
+    if xvc_dir.is_dir() {
+        make_dirs_writable(&xvc_dir)?;
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+fn copy_entry(from: &Path, to: &Path) -> Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+    if file_type.is_symlink() {
+        make_symlink(fs::read_link(from)?, to)?;
+    } else if file_type.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_entry(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::test_xvc_root;
+    use xvc_core::XvcDigest;
+
+    #[test]
+    fn test_experiment_grid() -> Result<()> {
+        let sets = ["train.lr=0.1,0.01", "params.yaml:model=a\\,b,c"]
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<Vec<XvcParamOverride>>>()?;
+        let grid = experiment_grid(&sets, None)?
+            .into_iter()
+            .map(|params| params.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            grid,
+            vec![
+                vec!["train.lr=0.1", "params.yaml:model=a,b"],
+                vec!["train.lr=0.1", "params.yaml:model=c"],
+                vec!["train.lr=0.01", "params.yaml:model=a,b"],
+                vec!["train.lr=0.01", "params.yaml:model=c"],
+            ]
+        );
+        assert_eq!(experiment_grid(&[], None)?, vec![Vec::new()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_workspace() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, _output_rcv) = crossbeam_channel::unbounded();
+        let dir = xvc_root.absolute_path().join("copy-workspace");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("staged.txt"), "staged")?;
+        fs::write(dir.join("untracked.txt"), "untracked")?;
+        fs::write(dir.join("ignored.txt"), "ignored")?;
+        fs::write(dir.join(".gitignore"), "ignored.txt\ndata.txt\n")?;
+        git(&xvc_root, &["add", "copy-workspace/staged.txt"])?;
+
+        // data.txt is tracked by Xvc and it's in the cache
+        let data_path = XvcPath::new(
+            &xvc_root,
+            xvc_root.absolute_path(),
+            Path::new("copy-workspace/data.txt"),
+        )?;
+        let digest = ContentDigest::from(XvcDigest::from_content("data", HashAlgorithm::Blake3));
+        let cache_path = XvcCachePath::new(&data_path, &digest)?.to_absolute_path(&xvc_root);
+        fs::create_dir_all(cache_path.parent().unwrap())?;
+        fs::write(&cache_path, "data")?;
+        let data_e = xvc_root.new_entity();
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcPath>| {
+            store.insert(data_e, data_path.clone());
+            Ok(())
+        })?;
+        xvc_root.with_store_mut(|store: &mut XvcStore<ContentDigest>| {
+            store.insert(data_e, digest);
+            Ok(())
+        })?;
+
+        let temp_dir = tempfile::tempdir()?;
+        let copy = temp_dir.path().join("copy");
+        copy_workspace(&output_snd, &xvc_root, &copy)?;
+        let copied = copy.join("copy-workspace");
+        assert_eq!(fs::read_to_string(copied.join("staged.txt"))?, "staged");
+        assert_eq!(
+            fs::read_to_string(copied.join("untracked.txt"))?,
+            "untracked"
+        );
+        assert!(!copied.join("ignored.txt").exists());
+        assert_eq!(
+            fs::read_link(copied.join("data.txt"))?,
+            cache_path.to_path_buf()
+        );
+        // Only the state of the repository is copied from .xvc, not its cache
+        let xvc_dir = copy.join(XvcRootInner::XVC_DIR);
+        assert!(xvc_dir.join(XvcRootInner::STORE_DIR).is_dir());
+        assert!(
+            !xvc_dir
+                .join(cache_path.strip_prefix(xvc_root.xvc_dir()).unwrap())
+                .exists()
+        );
+        assert!(!copy.join(".git").exists());
+
+        remove_workspace_copy(&copy)?;
+        assert!(!copy.exists());
+        Ok(())
+    }
+}
@@ -150,6 +150,10 @@ fn step_node(step: &XvcStepSchema, dep_ids: &[Option<String>]) -> KdlNode {
         node.entries_mut()
             .push(KdlEntry::new_prop("when", step.invalidate.to_string()));
     }
+    if let Some(timeout) = step.timeout {
+        node.entries_mut()
+            .push(KdlEntry::new_prop("timeout", timeout as i128));
+    }
 
     let children = node.ensure_children();
 
@@ -10,7 +10,7 @@
 //!     node "images" glob-items="data/images/*"
 //!     node "params" param="params.yaml::train"
 //!
-//!     step "train" command="python src/train.py" when="by_dependencies" {
+//!     step "train" command="python src/train.py" when="by_dependencies" timeout="2h" {
 //!         deps "images" "params" file="data/train.bin"
 //!         after "preprocess"
 //!         outs {
@@ -22,7 +22,8 @@
 //! ```
 //!
 //! Node type properties use the same spec syntax as the corresponding
-//! `xvc pipeline step dependency` flags. Runtime bookkeeping (digests,
+//! `xvc pipeline step dependency` flags. `timeout` is an integer in seconds or
+//! a string like `"15m"`, as in `xvc pipeline step new --timeout`. Runtime bookkeeping (digests,
 //! metadata) never appears in KDL documents.
 //!
 //! Unit tests for this module live in `iesahin/xvc-mono`'s `xvc-test` crate
@@ -15,7 +15,7 @@ use crate::pipeline::deps::{
     RegexItemsDep, SqliteQueryDep, StepDep, UrlDigestDep,
 };
 use crate::pipeline::schema::{XvcPipelineSchema, XvcStepSchema};
-use crate::pipeline::{XvcOutput, XvcStepInvalidate};
+use crate::pipeline::{XvcOutput, XvcStepInvalidate, XvcStepTimeout};
 use crate::{XvcDependency, XvcMetricsFormat};
 
 /// Dependency node type properties, shared by `node` declarations and inline
@@ -306,9 +306,31 @@ fn parse_step(step: &KdlNode, nodes: &BTreeMap<String, XvcDependency>) -> Result
         None => XvcStepInvalidate::default(),
     };
 
+    // Seconds as an integer, or a string with a suffix like "15m"
+    let timeout = match step.get("timeout") {
+        Some(v) => {
+            let seconds = match v.as_integer() {
+                Some(seconds) => u64::try_from(seconds).ok(),
+                None => string_value(v, "step timeout")?
+                    .parse::<XvcStepTimeout>()
+                    .ok()
+                    .map(|t| t.seconds),
+            };
+            match seconds {
+                Some(seconds) => XvcStepTimeout::from_seconds(seconds).map(|t| t.seconds),
+                None => {
+                    return err(format!(
+                        "step '{name}': timeout={v} must be seconds or a duration like \"15m\""
+                    ));
+                }
+            }
+        }
+        None => None,
+    };
+
     for entry in step.entries() {
         match entry.name().map(|n| n.value()) {
-            None | Some("command") | Some("when") => {}
+            None | Some("command") | Some("when") | Some("timeout") => {}
             Some(other) => {
                 return err(format!("step '{name}': unknown property '{other}'"));
             }
@@ -366,6 +388,7 @@ fn parse_step(step: &KdlNode, nodes: &BTreeMap<String, XvcDependency>) -> Result
         name,
         command,
         invalidate,
+        timeout,
         dependencies,
         outputs,
     })
@@ -0,0 +1,394 @@
+//! Reading the metrics files written by pipeline steps.
+//!
+//! [XvcOutput::Metric] files are flattened to key/value rows, so that they can be shown as a table
+//! and compared between Git references. Nested JSON keys are joined with dots, and array elements
+//! are keyed by their index, e.g., `train.losses.0`. Rows of CSV and TSV files are keyed by their
+//! index and column name, e.g., `0.accuracy`. Files with a single row are keyed by the column names
+//! only.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use subprocess::Exec;
+use xvc_core::{
+    ContentDigest, R1NStore, Storable, XvcCachePath, XvcPath, XvcRoot, XvcStore, exec_git,
+    extract_store_at_ref, get_absolute_git_command,
+};
+
+use crate::error::Result;
+use crate::{XvcMetricsFormat, XvcOutput, XvcPipeline, XvcStep};
+
+/// A single value in a metrics file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcMetric {
+    /// Path of the metrics file
+    pub path: XvcPath,
+    /// Flattened key of the value in the file
+    pub key: String,
+    /// The value as written in the file
+    pub value: String,
+}
+
+/// The change of a metric between two versions of a metrics file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct XvcMetricDiff {
+    /// Path of the metrics file
+    pub path: XvcPath,
+    /// Flattened key of the value in the file
+    pub key: String,
+    /// The value in the old version. `None` if the key is not found.
+    pub old: Option<String>,
+    /// The value in the new version. `None` if the key is not found.
+    pub new: Option<String>,
+    /// `new - old`, if both values are numbers
+    pub change: Option<f64>,
+    /// `(new - old) / |old|`, if both values are numbers and `old` is not zero
+    pub relative_change: Option<f64>,
+}
+
+/// Where to read the metrics files from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XvcMetricsSource {
+    /// The files in the working directory
+    Workspace,
+    /// The files committed to a Git reference, e.g., a branch, tag or commit
+    GitRef(String),
+}
+
+impl XvcMetricsSource {
+    /// Creates a source from an optional Git reference. `None` is the workspace.
+    pub fn from_git_ref(git_ref: Option<String>) -> Self {
+        match git_ref {
+            Some(git_ref) => Self::GitRef(git_ref),
+            None => Self::Workspace,
+        }
+    }
+
+    /// Reads the content of `path` from the source. Returns `None` if the file is not found.
+    ///
+    /// Files committed to Git references are read with `git show`. Files tracked by Xvc are found
+    /// in the stores of the reference and read from the cache, so they must be in the local cache,
+    /// e.g., brought with `xvc file bring`.
+    pub fn read(&self, xvc_root: &XvcRoot, path: &XvcPath) -> Result<Option<String>> {
+        match self.read_bytes(xvc_root, path)? {
+            Some(bytes) => Ok(Some(String::from_utf8(bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the content of a (possibly binary) file from the source, like [Self::read].
+    pub fn read_bytes(&self, xvc_root: &XvcRoot, path: &XvcPath) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Workspace => {
+                let abs_path = path.to_absolute_path(xvc_root);
+                if abs_path.is_file() {
+                    Ok(Some(fs::read(abs_path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+            Self::GitRef(git_ref) => {
+                // exec_git returns the output as a string, so we run `git show` here to keep the
+                // bytes of binary files. Files tracked by Xvc are ignored by Git, so a file found
+                // in Git is never in the store.
+                let git_command = get_absolute_git_command(&xvc_root.config().git.command)?;
+                let capture = Exec::cmd(&git_command)
+                    .arg("-C")
+                    .arg(xvc_root.absolute_path().as_os_str())
+                    .arg("show")
+                    .arg(format!("{git_ref}:./{path}"))
+                    .capture()
+                    .map_err(anyhow::Error::from)?;
+                if capture.exit_status.success() {
+                    return Ok(Some(capture.stdout));
+                }
+                read_cached_at_ref(xvc_root, &git_command, git_ref, path)
+            }
+        }
+    }
+
+    /// Checks that the Git reference exists, so that a misspelled reference is not reported as
+    /// missing metrics.
+    pub fn verify(&self, xvc_root: &XvcRoot) -> Result<()> {
+        if let Self::GitRef(git_ref) = self {
+            git(
+                xvc_root,
+                &["rev-parse", "--verify", &format!("{git_ref}^{{commit}}")],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for XvcMetricsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Workspace => write!(f, "workspace"),
+            Self::GitRef(git_ref) => write!(f, "{git_ref}"),
+        }
+    }
+}
+
+/// Reads `path` from the cache with the content digest recorded in the stores at `git_ref`.
+/// Returns `None` if the file is not tracked at `git_ref` or its content is not in the cache.
+fn read_cached_at_ref(
+    xvc_root: &XvcRoot,
+    git_command: &str,
+    git_ref: &str,
+    path: &XvcPath,
+) -> Result<Option<Vec<u8>>> {
+    let temp_dir = tempfile::tempdir()?;
+    extract_store_at_ref(
+        git_command,
+        &xvc_root.absolute_path().to_string_lossy(),
+        git_ref,
+        temp_dir.path(),
+        &[
+            <XvcPath as Storable>::type_description(),
+            <ContentDigest as Storable>::type_description(),
+        ],
+    )?;
+    let path_store = XvcStore::<XvcPath>::load_store(temp_dir.path())?;
+    let digest_store = XvcStore::<ContentDigest>::load_store(temp_dir.path())?;
+    let Some(digest) = path_store
+        .entity_by_value(path)
+        .and_then(|xe| digest_store.get(&xe))
+    else {
+        return Ok(None);
+    };
+    let cache_path = XvcCachePath::new(path, digest)?.to_absolute_path(xvc_root);
+    if cache_path.is_file() {
+        Ok(Some(fs::read(cache_path)?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub(crate) fn git(xvc_root: &XvcRoot, args: &[&str]) -> Result<String> {
+    let git_command = get_absolute_git_command(&xvc_root.config().git.command)?;
+    let xvc_directory = xvc_root.absolute_path().to_string_lossy();
+    Ok(exec_git(&git_command, &xvc_directory, args)?)
+}
+
+/// Returns the outputs of the steps in a pipeline, sorted by path.
+pub fn pipeline_outputs(xvc_root: &XvcRoot, pipeline_name: &str) -> Result<Vec<XvcOutput>> {
+    let (pipeline_e, _) = XvcPipeline::from_name(xvc_root, pipeline_name)?;
+    let pipeline_steps = xvc_root
+        .load_r1nstore::<XvcPipeline, XvcStep>()?
+        .children_of(&pipeline_e)?;
+    let all_outputs: R1NStore<XvcStep, XvcOutput> = xvc_root.load_r1nstore()?;
+    let mut outputs = Vec::new();
+    for step_e in pipeline_steps.keys() {
+        outputs.extend(all_outputs.children_of(step_e)?.values().cloned());
+    }
+    Ok(outputs.into_iter().sorted().dedup().collect())
+}
+
+/// Returns the paths and formats of the [XvcOutput::Metric] outputs of the steps in a pipeline,
+/// sorted by path.
+pub fn pipeline_metric_outputs(
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+) -> Result<Vec<(XvcPath, XvcMetricsFormat)>> {
+    Ok(pipeline_outputs(xvc_root, pipeline_name)?
+        .into_iter()
+        .filter_map(|out| match out {
+            XvcOutput::Metric { path, format } => Some((path, format)),
+            XvcOutput::File { .. } | XvcOutput::Image { .. } => None,
+        })
+        .collect())
+}
+
+/// Reads the metrics `files` from `source` and flattens them to rows.
+///
+/// Files that are not found in the source are skipped.
+pub fn load_metrics(
+    xvc_root: &XvcRoot,
+    files: &[(XvcPath, XvcMetricsFormat)],
+    source: &XvcMetricsSource,
+) -> Result<Vec<XvcMetric>> {
+    let mut metrics = Vec::new();
+    for (path, format) in files {
+        if let Some(content) = source.read(xvc_root, path)? {
+            for (key, value) in parse_metrics(&content, *format)? {
+                metrics.push(XvcMetric {
+                    path: path.clone(),
+                    key,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(metrics)
+}
+
+/// Flattens the content of a metrics file to key/value pairs.
+///
+/// Files with [XvcMetricsFormat::Unknown] format have no values.
+pub fn parse_metrics(content: &str, format: XvcMetricsFormat) -> Result<Vec<(String, String)>> {
+    let mut rows = Vec::new();
+    match format {
+        XvcMetricsFormat::JSON => {
+            let value: JsonValue = serde_json::from_str(content)?;
+            flatten_json("", &value, &mut rows);
+        }
+        XvcMetricsFormat::CSV | XvcMetricsFormat::TSV => {
+            let delimiter = if format == XvcMetricsFormat::CSV {
+                ','
+            } else {
+                '\t'
+            };
+            let (header, records) = parse_delimited(content, delimiter);
+            let single_row = records.len() == 1;
+            for (i, record) in records.iter().enumerate() {
+                for (column, value) in header.iter().zip(record) {
+                    let key = if single_row {
+                        column.clone()
+                    } else {
+                        format!("{i}.{column}")
+                    };
+                    rows.push((key, value.clone()));
+                }
+            }
+        }
+        XvcMetricsFormat::Unknown => {}
+    }
+    Ok(rows)
+}
+
+fn flatten_json(prefix: &str, value: &JsonValue, rows: &mut Vec<(String, String)>) {
+    let join = |key: &str| {
+        if prefix.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{prefix}.{key}")
+        }
+    };
+    match value {
+        JsonValue::Object(map) => {
+            for (key, value) in map {
+                flatten_json(&join(key), value, rows);
+            }
+        }
+        JsonValue::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                flatten_json(&join(&i.to_string()), value, rows);
+            }
+        }
+        JsonValue::String(s) => rows.push((prefix.to_owned(), s.clone())),
+        JsonValue::Number(_) | JsonValue::Bool(_) | JsonValue::Null => {
+            rows.push((prefix.to_owned(), value.to_string()))
+        }
+    }
+}
+
+/// Splits the content of a CSV or TSV file to its header and records.
+///
+/// Fields can be quoted with `"` to contain the delimiter, and `""` in a quoted field is a quote.
+/// Empty lines are skipped.
+pub fn parse_delimited(content: &str, delimiter: char) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut lines = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| split_delimited(line, delimiter));
+    let header = lines.next().unwrap_or_default();
+    (header, lines.collect())
+}
+
+fn split_delimited(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => {
+                fields.push(field.trim().to_owned());
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+/// Compares two sets of metrics by path and key.
+///
+/// Unless `all` is set, only the metrics that are added, removed or changed are returned.
+pub fn diff_metrics(old: &[XvcMetric], new: &[XvcMetric], all: bool) -> Vec<XvcMetricDiff> {
+    // The first value of a key is used, if there are more than one
+    let index = |metrics: &[XvcMetric]| {
+        let mut values = BTreeMap::<(XvcPath, String), String>::new();
+        for m in metrics {
+            values
+                .entry((m.path.clone(), m.key.clone()))
+                .or_insert_with(|| m.value.clone());
+        }
+        values
+    };
+    let old_values = index(old);
+    let new_values = index(new);
+    let keys = old_values
+        .keys()
+        .chain(new_values.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let mut diffs = Vec::new();
+    for path_key in keys {
+        let old_value = old_values.get(&path_key).cloned();
+        let new_value = new_values.get(&path_key).cloned();
+        if !all && old_value == new_value {
+            continue;
+        }
+        let numbers = old_value
+            .as_ref()
+            .and_then(|v| v.parse::<f64>().ok())
+            .zip(new_value.as_ref().and_then(|v| v.parse::<f64>().ok()));
+        let change = numbers.map(|(o, n)| n - o);
+        let relative_change = numbers
+            .filter(|(o, _)| *o != 0.0)
+            .map(|(o, n)| (n - o) / o.abs());
+        let (path, key) = path_key;
+        diffs.push(XvcMetricDiff {
+            path,
+            key,
+            old: old_value,
+            new: new_value,
+            change,
+            relative_change,
+        });
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metrics() -> Result<()> {
+        let json = r#"{"train": {"loss": 0.25, "acc": [0.5, 0.75]}, "model": "cnn"}"#;
+        let rows = parse_metrics(json, XvcMetricsFormat::JSON)?;
+        assert!(rows.contains(&("train.loss".to_owned(), "0.25".to_owned())));
+        assert!(rows.contains(&("train.acc.1".to_owned(), "0.75".to_owned())));
+        assert!(rows.contains(&("model".to_owned(), "cnn".to_owned())));
+
+        let csv = "epoch,loss\n1,0.5\n2,\"0.25\"\n";
+        let rows = parse_metrics(csv, XvcMetricsFormat::CSV)?;
+        assert_eq!(rows[3], ("1.loss".to_owned(), "0.25".to_owned()));
+
+        let tsv = "epoch\tloss\n2\t0.25\n";
+        let rows = parse_metrics(tsv, XvcMetricsFormat::TSV)?;
+        assert_eq!(rows[1], ("loss".to_owned(), "0.25".to_owned()));
+        Ok(())
+    }
+}
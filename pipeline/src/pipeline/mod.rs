@@ -2,8 +2,13 @@
 pub mod api;
 pub mod command;
 pub mod deps;
+pub mod experiment;
 pub mod kdl;
+pub mod metrics;
 pub mod outs;
+pub mod params;
+pub mod plots;
+pub mod run_cache;
 pub mod schema;
 pub mod step;
 pub mod util;
@@ -11,19 +16,24 @@ pub mod util;
 use self::command::XvcStepCommand;
 use self::deps::XvcDependency;
 use self::deps::compare::superficial_compare_dependency;
-use self::outs::XvcOutput;
+use self::outs::{XvcOutput, XvcOutputDigest};
+use self::run_cache::{XvcRunCacheEntry, XvcRunCacheKey, XvcRunCacheOutput};
 use self::step::XvcStep;
 use anyhow::anyhow;
+use clap::Parser;
 
 use itertools::Itertools;
 use xvc_core::{FromConfig, XvcPathMetadataProvider};
-use xvc_file::CHANNEL_CAPACITY;
+use xvc_file::{CHANNEL_CAPACITY, TrackCLI, cmd_track};
 
 use crate::deps::compare::thorough_compare_dependency;
 use crate::deps::dependencies_to_path;
 use crate::error::{Error, Result};
+use crate::pipeline::api::status::{XvcStepRunStatus, XvcStepStatus};
 use crate::pipeline::command::CommandProcess;
 use crate::{XvcPipeline, XvcPipelineRunDir};
+use xvc_storage::XvcStorage;
+use xvc_storage::storage::index::XvcStorageIndex;
 
 use crossbeam_channel::{Receiver, Select, Sender, bounded};
 
@@ -33,13 +43,16 @@ use petgraph::algo::toposort;
 use petgraph::data::Build;
 use petgraph::dot::Dot;
 use petgraph::prelude::DiGraphMap;
+use petgraph::visit::{Dfs, IntoNeighbors, Reversed, Visitable};
 
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::fs;
+use std::str::FromStr;
 
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{self, ScopedJoinHandle, sleep};
 use std::time::Duration;
 use strum_macros::{Display, EnumString, VariantNames};
@@ -65,7 +78,7 @@ use xvc_core::{HStore, R1NStore, XvcEntity, XvcStore, persist};
 )]
 #[strum(serialize_all = "snake_case")]
 pub enum XvcStepInvalidate {
-    /// Change when dependencies change or outputs missing
+    /// Change when dependencies change or outputs are missing or changed
     #[default]
     ByDependencies,
     /// Always consider changed
@@ -76,6 +89,58 @@ pub enum XvcStepInvalidate {
 
 persist!(XvcStepInvalidate, "xvc-step-invalidate");
 
+/// Maximum time a step's command can run before it's terminated.
+///
+/// Steps without a timeout use `pipeline.default_step_timeout` from the configuration. A
+/// timeout of `0` means the step has no timeout of its own.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct XvcStepTimeout {
+    /// Timeout in seconds
+    pub seconds: u64,
+}
+
+persist!(XvcStepTimeout, "xvc-step-timeout");
+
+impl XvcStepTimeout {
+    /// A timeout of `seconds`. A zero timeout is the same as not setting it, so this returns
+    /// `None` for `0`.
+    pub fn from_seconds(seconds: u64) -> Option<Self> {
+        (seconds > 0).then_some(Self { seconds })
+    }
+
+    /// The timeout as a [Duration]
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.seconds)
+    }
+}
+
+impl FromStr for XvcStepTimeout {
+    type Err = Error;
+
+    /// Parses seconds with an optional `s`, `m` or `h` suffix, e.g., `90`, `90s`, `15m` or `2h`.
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = || Error::InvalidTimeout {
+            value: value.to_string(),
+        };
+        let s = value.trim();
+        let (number, multiplier) = match s.chars().last() {
+            Some('s') => (&s[..s.len() - 1], 1),
+            Some('m') => (&s[..s.len() - 1], 60),
+            Some('h') => (&s[..s.len() - 1], 60 * 60),
+            _ => (s, 1),
+        };
+        let number = number.trim().parse::<u64>().map_err(|_| invalid())?;
+        let seconds = number.checked_mul(multiplier).ok_or_else(invalid)?;
+        Ok(Self { seconds })
+    }
+}
+
+impl std::fmt::Display for XvcStepTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}s", self.seconds)
+    }
+}
+
 /// Adds dependencies to `graph` in the form of `XvcDependency::Step`. These are called explicit
 /// dependencies, as steps are defined explicitly to be depending to each other.
 /// All steps depend on the `start_step_entity` step that's run always. It's used to collect all independent (parallel)
@@ -170,6 +235,83 @@ pub fn add_implicit_dependencies(
 
 type DependencyGraph = DiGraphMap<XvcEntity, XvcDependency>;
 
+/// Selects the steps of a pipeline to run. All steps are run by default.
+#[derive(Debug, Clone, Default)]
+pub struct XvcRunTargets {
+    /// Run only these steps and their dependency steps
+    pub targets: Vec<String>,
+    /// Run only this step and the steps that depend on it
+    pub from: Option<String>,
+    /// Run only this step and its dependency steps, i.e., stop after this step
+    pub until: Option<String>,
+    /// Run these steps even if they are not changed or set to never run
+    pub force: Vec<String>,
+}
+
+fn step_entity(pipeline_steps: &HStore<XvcStep>, name: &str) -> Result<XvcEntity> {
+    pipeline_steps
+        .entity_by_value(&XvcStep {
+            name: name.to_string(),
+        })
+        .ok_or_else(|| Error::StepNotFoundInPipeline {
+            step: name.to_string(),
+        })
+}
+
+/// Steps reachable from `start` in `graph`, including `start`.
+fn reachable_steps<G>(graph: G, start: XvcEntity) -> HashSet<XvcEntity>
+where
+    G: IntoNeighbors<NodeId = XvcEntity> + Visitable,
+{
+    let mut reachable = HashSet::new();
+    let mut dfs = Dfs::new(graph, start);
+    while let Some(step_e) = dfs.next(graph) {
+        reachable.insert(step_e);
+    }
+    reachable
+}
+
+/// Returns the steps selected by `run_targets`.
+///
+/// Targets add the steps with their dependency steps, `from` limits the selection to the step and
+/// its dependents, and `until` to the step and its dependencies. Forced steps are always selected,
+/// even if they are outside of the targets, `from` and `until`. Edges in `graph` point from
+/// dependent steps to their dependencies.
+fn select_steps(
+    pipeline_steps: &HStore<XvcStep>,
+    graph: &DependencyGraph,
+    run_targets: &XvcRunTargets,
+) -> Result<HashSet<XvcEntity>> {
+    let mut selected: HashSet<XvcEntity> = if run_targets.targets.is_empty() {
+        pipeline_steps.keys().copied().collect()
+    } else {
+        let mut selected = HashSet::new();
+        for target in &run_targets.targets {
+            let target_e = step_entity(pipeline_steps, target)?;
+            selected.extend(reachable_steps(graph, target_e));
+        }
+        selected
+    };
+
+    if let Some(from) = &run_targets.from {
+        let from_e = step_entity(pipeline_steps, from)?;
+        let downstream = reachable_steps(Reversed(graph), from_e);
+        selected.retain(|step_e| downstream.contains(step_e));
+    }
+
+    if let Some(until) = &run_targets.until {
+        let until_e = step_entity(pipeline_steps, until)?;
+        let upstream = reachable_steps(graph, until_e);
+        selected.retain(|step_e| upstream.contains(step_e));
+    }
+
+    for forced in &run_targets.force {
+        selected.insert(step_entity(pipeline_steps, forced)?);
+    }
+
+    Ok(selected)
+}
+
 use step::*;
 
 /// These run conditions may be exposed to the user. We use this struct here for clarity and
@@ -179,6 +321,7 @@ use step::*;
 struct RunConditions {
     never: bool,
     always: bool,
+    forced: bool,
     ignore_broken_dep_steps: bool,
     ignore_missing_outputs: bool,
 }
@@ -191,7 +334,11 @@ pub struct StepStateParams<'a> {
     pmp: &'a XvcPathMetadataProvider,
     run_conditions: &'a RunConditions,
     pipeline_rundir: &'a XvcPath,
-    terminate_timeout_processes: bool,
+    timeout_grace_period: Duration,
+    dry_run: bool,
+    run_cache: bool,
+    remote_cache: Option<&'a XvcStorage>,
+    remote_cache_index: &'a Mutex<Option<XvcStorageIndex>>,
     algorithm: HashAlgorithm,
 
     command_process: Arc<RwLock<CommandProcess>>,
@@ -200,6 +347,7 @@ pub struct StepStateParams<'a> {
 
     dependency_diffs: Arc<RwLock<HStore<Diff<XvcDependency>>>>,
     output_diffs: Arc<RwLock<HStore<Diff<XvcOutput>>>>,
+    output_digests: Arc<RwLock<HStore<XvcOutputDigest>>>,
 
     step_e: XvcEntity,
     step: &'a XvcStep,
@@ -211,6 +359,8 @@ pub struct StepStateParams<'a> {
     recorded_dependencies: &'a R1NStore<XvcStep, XvcDependency>,
     step_dependencies: &'a HashSet<XvcEntity>,
     step_outputs: &'a HStore<XvcOutput>,
+    recorded_outputs: &'a R1NStore<XvcStep, XvcOutput>,
+    recorded_output_digests: &'a XvcStore<XvcOutputDigest>,
 }
 
 /// This structure is passed to step_threads as a parameter.
@@ -227,16 +377,22 @@ struct StepThreadParams<'a> {
     dependency_graph: &'a DependencyGraph,
     step_timeout: &'a Duration,
     run_conditions: &'a RunConditions,
-    terminate_on_timeout: bool,
+    timeout_grace_period: Duration,
+    dry_run: bool,
+    run_cache: bool,
+    remote_cache: Option<&'a XvcStorage>,
+    remote_cache_index: &'a Mutex<Option<XvcStorageIndex>>,
     algorithm: HashAlgorithm,
     pmp: &'a XvcPathMetadataProvider,
     process_pool_size: usize,
     recorded_dependencies: &'a R1NStore<XvcStep, XvcDependency>,
     recorded_outputs: &'a R1NStore<XvcStep, XvcOutput>,
+    recorded_output_digests: &'a XvcStore<XvcOutputDigest>,
 
     // TODO: We can convert these to HStore<Arc<RwLock<...>>>
     dependency_diffs: Arc<RwLock<HStore<Diff<XvcDependency>>>>,
     output_diffs: Arc<RwLock<HStore<Diff<XvcOutput>>>>,
+    output_digests: Arc<RwLock<HStore<XvcOutputDigest>>>,
 }
 
 /// # Xvc Pipeline Dependency Graph Rules
@@ -273,11 +429,24 @@ struct StepThreadParams<'a> {
 /// creates a dependency between `training` and `evaluation` steps.
 type StateTransition<'a> = Result<(XvcStepState, StepStateParams<'a>)>;
 
+/// Runs the steps of the pipeline named `pipeline_name` selected by `run_targets` in dependency
+/// order.
+///
+/// With `dry_run`, steps are compared with their records but not run, and the stores are not
+/// updated. Steps that would run are considered as run for their dependent steps. Returns whether
+/// each step would run and why, in dependency order. Without `dry_run`, the returned statuses
+/// show which steps have run, and which are broken.
+///
+/// When `remote_cache` is given, the run cache entries not found locally are received from it,
+/// and the new entries are sent to it.
 pub fn the_grand_pipeline_loop(
     output_snd: &XvcOutputSender,
     xvc_root: &XvcRoot,
     pipeline_name: String,
-) -> Result<()> {
+    run_targets: &XvcRunTargets,
+    dry_run: bool,
+    remote_cache: Option<&XvcStorage>,
+) -> Result<Vec<XvcStepStatus>> {
     let config = xvc_root.config();
     let (pipeline_e, _) = XvcPipeline::from_name(xvc_root, &pipeline_name)?;
 
@@ -333,7 +502,7 @@ pub fn the_grand_pipeline_loop(
 
     info!("Pipeline Graph:\n{}\n", debug_output);
     // Topological sort to get the cycles and run order
-    let sorted_steps = match toposort(&dependency_graph, None) {
+    let mut sorted_steps = match toposort(&dependency_graph, None) {
         Ok(vec) => vec,
         Err(c) => {
             let step_node = c.node_id();
@@ -345,34 +514,67 @@ pub fn the_grand_pipeline_loop(
         }
     };
 
+    // Remove the steps that are not selected to run. Dependency steps that are not selected are
+    // considered done.
+    let selected_steps = select_steps(&pipeline_steps, &dependency_graph, run_targets)?;
+    sorted_steps.retain(|step_e| selected_steps.contains(step_e));
+    for step_e in pipeline_steps.keys() {
+        if !selected_steps.contains(step_e) {
+            dependency_graph.remove_node(*step_e);
+        }
+    }
+
     // We are ready to run the pipeline state loop with the sorted steps
 
     let run_never = RunConditions {
         never: true,
         always: false,
+        forced: false,
         ignore_missing_outputs: false,
         ignore_broken_dep_steps: false,
     };
 
     //  This is the DVC behavior. It doesn't run when _only_ dependency timestamp changed. For
-    //  Makefile behavior `dependencies_new` can be set to `true`.
+    //  Makefile behavior `dependencies_new` can be set to `true`. Missing outputs and outputs
+    //  whose content changed since the last run also cause the step to run.
     let run_calculated = RunConditions {
         never: false,
         always: false,
+        forced: false,
         ignore_broken_dep_steps: false,
-        ignore_missing_outputs: true,
+        ignore_missing_outputs: false,
     };
 
     let run_always = RunConditions {
         never: false,
         always: true,
+        forced: false,
         ignore_missing_outputs: true,
         ignore_broken_dep_steps: true,
     };
 
+    // Forced steps run regardless of their invalidation option, unless their dependency steps
+    // are broken.
+    let run_forced = RunConditions {
+        never: false,
+        always: true,
+        forced: true,
+        ignore_missing_outputs: true,
+        ignore_broken_dep_steps: false,
+    };
+
+    let forced_steps = run_targets
+        .force
+        .iter()
+        .map(|name| step_entity(&pipeline_steps, name))
+        .collect::<Result<HashSet<XvcEntity>>>()?;
+
     let run_conditions: HStore<RunConditions> = pipeline_steps
         .iter()
         .map(|(step_e, _)| {
+            if forced_steps.contains(step_e) {
+                return (*step_e, run_forced);
+            }
             match consider_changed[step_e] {
                 // If the step has no dependencies, we run it always
                 XvcStepInvalidate::ByDependencies => {
@@ -390,15 +592,33 @@ pub fn the_grand_pipeline_loop(
         .collect();
 
     let step_states = Arc::new(RwLock::new(
-        pipeline_steps
+        sorted_steps
             .iter()
-            .map(|(step_e, _)| (*step_e, step::XvcStepState::begin()))
+            .map(|step_e| (*step_e, step::XvcStepState::begin()))
             .collect(),
     ));
 
     let process_pool_size: usize = xvc_root.config().pipeline.process_pool_size as usize;
-    let default_step_timeout: u64 = 10000;
-    let terminate_on_timeout = true;
+    let default_step_timeout = Duration::from_secs(config.pipeline.default_step_timeout);
+    let timeout_grace_period = Duration::from_secs(config.pipeline.timeout_grace_period);
+    let run_cache = config.pipeline.run_cache;
+    // Steps run in parallel, but the transfer journal and the storage events are loaded and saved
+    // as a whole. Transfers to the remote cache are run one at a time to not lose updates. The
+    // files in the remote cache are listed when an entry is first looked up.
+    let remote_cache_index = Mutex::new(None);
+    let timeouts = xvc_root.load_store::<XvcStepTimeout>()?;
+    // Steps without a timeout use the pipeline default
+    let step_timeouts: HStore<Duration> = pipeline_steps
+        .keys()
+        .map(|step_e| {
+            let timeout = timeouts
+                .get(step_e)
+                .and_then(|t| XvcStepTimeout::from_seconds(t.seconds))
+                .map(|t| t.duration())
+                .unwrap_or(default_step_timeout);
+            (*step_e, timeout)
+        })
+        .collect();
 
     let step_commands = xvc_root.load_store::<XvcStepCommand>()?;
 
@@ -419,8 +639,6 @@ pub fn the_grand_pipeline_loop(
         .map(|(step_e, (_, r))| (*step_e, r.clone()))
         .collect();
 
-    let step_timeout = Duration::from_secs(default_step_timeout);
-
     let recorded_dependencies = xvc_root
         .load_r1nstore::<XvcStep, XvcDependency>()
         .expect("Cannot load store");
@@ -428,8 +646,11 @@ pub fn the_grand_pipeline_loop(
         .load_r1nstore::<XvcStep, XvcOutput>()
         .expect("Cannot load store");
 
+    let recorded_output_digests = xvc_root.load_store::<XvcOutputDigest>()?;
+
     let dependency_diffs = Arc::new(RwLock::new(HStore::new()));
     let output_diffs = Arc::new(RwLock::new(HStore::new()));
+    let output_digests = Arc::new(RwLock::new(HStore::new()));
 
     // FIXME: Why don't we use state_bulletin_receiver here?
     #[allow(unused_variables)]
@@ -462,9 +683,13 @@ pub fn the_grand_pipeline_loop(
                             state_sender: state_channels[step_e].0.clone(),
                             current_states: step_states.clone(),
                             dependency_graph: &dependency_graph,
-                            step_timeout: &step_timeout,
+                            step_timeout: &step_timeouts[step_e],
                             run_conditions: &run_conditions[step_e],
-                            terminate_on_timeout,
+                            timeout_grace_period,
+                            dry_run,
+                            run_cache,
+                            remote_cache,
+                            remote_cache_index: &remote_cache_index,
                             pmp: &pmp,
                             output_snd: &output_snd,
                             step_commands: &step_commands,
@@ -473,8 +698,10 @@ pub fn the_grand_pipeline_loop(
                             algorithm,
                             recorded_dependencies: &recorded_dependencies,
                             recorded_outputs: &recorded_outputs,
+                            recorded_output_digests: &recorded_output_digests,
                             dependency_diffs: dependency_diffs.clone(),
                             output_diffs: output_diffs.clone(),
+                            output_digests: output_digests.clone(),
                         };
                         step_state_handler(*step_e, step_thread_params)
                     }),
@@ -504,8 +731,10 @@ pub fn the_grand_pipeline_loop(
             Ok(false)
         }
     });
-    // We only save the stores if the pipeline was run successfully
-    if let Ok(true) = done_successfully {
+    if dry_run {
+        done_successfully?;
+    } else if let Ok(true) = done_successfully {
+        // We only save the stores if the pipeline was run successfully
         xvc_root.with_store_mut(|store: &mut XvcStore<XvcDependency>| {
             dependency_diffs
                 .read()
@@ -513,14 +742,168 @@ pub fn the_grand_pipeline_loop(
                 .map(|diffs| update_with_actual(store, diffs, true, true))?
         })?;
 
+        // Missing outputs are not removed from the records, they are expected to be created when
+        // their steps run.
         xvc_root.with_store_mut(|store: &mut XvcStore<XvcOutput>| {
             output_diffs
                 .read()
                 .as_deref()
-                .map(|output_diffs| update_with_actual(store, output_diffs, true, true))?
+                .map(|output_diffs| update_with_actual(store, output_diffs, true, false))?
+        })?;
+
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcOutputDigest>| {
+            for (out_e, digest) in output_digests.read()?.iter() {
+                store.insert(*out_e, *digest);
+            }
+            Ok(())
         })?;
+
+        if config.pipeline.track_outputs {
+            let step_states = step_states.read()?;
+            let run_steps = sorted_steps
+                .iter()
+                .filter(|step_e| matches!(step_states[step_e], XvcStepState::DoneByRunning(_)));
+            let mut outputs = Vec::<String>::new();
+            for step_e in run_steps {
+                for out in recorded_outputs.children_of(step_e)?.values() {
+                    outputs.push(XvcPath::from(out).to_string());
+                }
+            }
+            track_outputs(output_snd, xvc_root, &outputs)?;
+        }
     }
-    Ok(())
+
+    let step_states = step_states.read()?;
+    let dependency_diffs = dependency_diffs.read()?;
+    let output_diffs = output_diffs.read()?;
+    sorted_steps
+        .iter()
+        .rev()
+        .map(|step_e| {
+            step_status(
+                *step_e,
+                &pipeline_steps,
+                &step_states,
+                &run_conditions[step_e],
+                &consider_changed,
+                &dependency_graph,
+                &recorded_dependencies,
+                &recorded_outputs,
+                &dependency_diffs,
+                &output_diffs,
+            )
+        })
+        .collect()
+}
+
+/// Whether the step would run and why, from the final state of a dry run.
+#[allow(clippy::too_many_arguments)]
+fn step_status(
+    step_e: XvcEntity,
+    pipeline_steps: &HStore<XvcStep>,
+    step_states: &HStore<XvcStepState>,
+    run_conditions: &RunConditions,
+    consider_changed: &XvcStore<XvcStepInvalidate>,
+    dependency_graph: &DependencyGraph,
+    recorded_dependencies: &R1NStore<XvcStep, XvcDependency>,
+    recorded_outputs: &R1NStore<XvcStep, XvcOutput>,
+    dependency_diffs: &HStore<Diff<XvcDependency>>,
+    output_diffs: &HStore<Diff<XvcOutput>>,
+) -> Result<XvcStepStatus> {
+    let changed_dependencies: Vec<Diff<XvcDependency>> = recorded_dependencies
+        .children_of(&step_e)?
+        .keys()
+        .filter_map(|dep_e| dependency_diffs.get(dep_e))
+        .filter(|diff| diff.changed())
+        .cloned()
+        .collect();
+
+    let step_outputs = recorded_outputs.children_of(&step_e)?;
+    let missing_outputs: Vec<XvcOutput> = step_outputs
+        .keys()
+        .filter_map(|out_e| match output_diffs.get(out_e) {
+            Some(Diff::ActualMissing { record }) => Some(record.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let changed_outputs: Vec<XvcOutput> = step_outputs
+        .keys()
+        .filter_map(|out_e| match output_diffs.get(out_e) {
+            Some(Diff::Different { actual, .. }) => Some(actual.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let dependency_steps_to_run: Vec<String> = dependency_steps(step_e, dependency_graph)?
+        .iter()
+        .filter(|dep_step_e| {
+            matches!(
+                step_states.get(dep_step_e),
+                Some(XvcStepState::DoneByRunning(_))
+            )
+        })
+        .map(|dep_step_e| pipeline_steps[dep_step_e].name.clone())
+        .sorted()
+        .collect();
+
+    let mut reasons = Vec::<String>::new();
+    let status = match &step_states[&step_e] {
+        XvcStepState::DoneByRunning(_) => {
+            if run_conditions.forced {
+                reasons.push("forced".to_string());
+            } else if run_conditions.always {
+                if consider_changed.get(&step_e) == Some(&XvcStepInvalidate::Always) {
+                    reasons.push("always runs".to_string());
+                } else {
+                    reasons.push("has no dependencies".to_string());
+                }
+            }
+            if !changed_dependencies.is_empty() {
+                reasons.push("dependencies changed".to_string());
+            }
+            if !missing_outputs.is_empty() {
+                reasons.push("outputs missing".to_string());
+            }
+            if !changed_outputs.is_empty() {
+                reasons.push("outputs changed".to_string());
+            }
+            if !dependency_steps_to_run.is_empty() {
+                reasons.push("dependency steps run".to_string());
+            }
+            XvcStepRunStatus::Run
+        }
+        XvcStepState::DoneWithoutRunning(DoneWithoutRunningState::FromRunNever) => {
+            reasons.push("never runs".to_string());
+            XvcStepRunStatus::Never
+        }
+        XvcStepState::DoneWithoutRunning(_) => {
+            reasons.push("up to date".to_string());
+            XvcStepRunStatus::Skip
+        }
+        XvcStepState::Broken(BrokenState::FromDependencyStepsFinishedBroken) => {
+            reasons.push("dependency steps are broken".to_string());
+            XvcStepRunStatus::Broken
+        }
+        XvcStepState::Broken(BrokenState::FromHasMissingDependencies) => {
+            reasons.push("dependencies are missing".to_string());
+            XvcStepRunStatus::Broken
+        }
+        state => {
+            reasons.push(format!("finished in {state:?}"));
+            XvcStepRunStatus::Broken
+        }
+    };
+
+    Ok(XvcStepStatus {
+        name: pipeline_steps[&step_e].name.clone(),
+        status,
+        reasons,
+        changed_dependencies,
+        missing_outputs,
+        changed_outputs,
+        dependency_steps_to_run,
+    })
 }
 
 /// Return steps that `step_e` depends on the dependency_graph.
@@ -601,7 +984,11 @@ fn step_state_handler(step_e: XvcEntity, params: StepThreadParams) -> Result<()>
         command_process,
         // TODO: Convert this to AtomicUsize
         available_process_slots: Arc::new(RwLock::new(params.process_pool_size)),
-        terminate_timeout_processes: params.terminate_on_timeout,
+        timeout_grace_period: params.timeout_grace_period,
+        dry_run: params.dry_run,
+        run_cache: params.run_cache,
+        remote_cache: params.remote_cache,
+        remote_cache_index: params.remote_cache_index,
         current_states,
         step_timeout: params.step_timeout,
         run_conditions: params.run_conditions,
@@ -613,8 +1000,11 @@ fn step_state_handler(step_e: XvcEntity, params: StepThreadParams) -> Result<()>
         recorded_dependencies: params.recorded_dependencies,
         step_dependencies: &step_dependencies,
         step_outputs: &step_outputs,
+        recorded_outputs: params.recorded_outputs,
         dependency_diffs: params.dependency_diffs,
         output_diffs: params.output_diffs,
+        output_digests: params.output_digests,
+        recorded_output_digests: params.recorded_output_digests,
         process_poll_milliseconds,
     };
 
@@ -691,6 +1081,9 @@ fn step_state_handler(step_e: XvcEntity, params: StepThreadParams) -> Result<()>
                     s_comparing_diffs_and_outputs_f_thorough_diffs_changed(s, step_params)?
                 }
             },
+            XvcStepState::WaitingToRun(s) if step_params.dry_run => {
+                s_waiting_to_run_f_dry_run(s, step_params)?
+            }
             XvcStepState::WaitingToRun(s) => match s {
                 WaitingToRunState::FromDiffsHasChanged => {
                     s_waiting_to_run_f_diffs_has_changed(s, step_params)?
@@ -715,6 +1108,9 @@ fn step_state_handler(step_e: XvcEntity, params: StepThreadParams) -> Result<()>
                     s_broken_f_dependency_steps_finished_broken(s, step_params)?
                 }
                 BrokenState::FromProcessTimeout => s_broken_f_process_timeout(s, step_params)?,
+                BrokenState::FromProcessKilledAfterTimeout => {
+                    s_broken_f_process_killed_after_timeout(s, step_params)?
+                }
                 BrokenState::FromProcessReturnedNonZero => {
                     s_broken_f_process_returned_non_zero(s, step_params)?
                 }
@@ -724,6 +1120,8 @@ fn step_state_handler(step_e: XvcEntity, params: StepThreadParams) -> Result<()>
                 DoneByRunningState::FromProcessCompletedSuccessfully => {
                     s_done_f_process_completed_successfully(s, step_params)?
                 }
+                DoneByRunningState::FromDryRun => s_done_f_dry_run(s, step_params)?,
+                DoneByRunningState::FromRestoredFromRunCache => s_done(s, step_params)?,
                 DoneByRunningState::FromKeepDone => {
                     (XvcStepState::DoneByRunning(s.clone()), step_params)
                 }
@@ -865,12 +1263,12 @@ fn s_comparing_diffs_and_outputs_f_thorough_diffs_not_changed<'a>(
     // Create a new scope to read params.output_diffs
     {
         let output_diffs = params.output_diffs.read()?;
-        if output_diffs
-            .iter()
-            .any(|(_, diff)| matches!(diff, Diff::ActualMissing { .. }))
-        {
+        if output_diffs.iter().any(|(_, diff)| diff.changed()) {
             // TODO: Update MISSING_OUTPUTS environment variable
-            info!(params.output_snd, "[{}] Missing Outputs", params.step.name);
+            info!(
+                params.output_snd,
+                "[{}] Missing or changed outputs", params.step.name
+            );
             changed = true;
         } else {
             info!(
@@ -927,12 +1325,12 @@ fn s_comparing_diffs_and_outputs_f_superficial_diffs_not_changed<'a>(
 
     {
         let output_diffs = params.output_diffs.read()?;
-        if output_diffs
-            .iter()
-            .any(|(_, diff)| matches!(diff, Diff::ActualMissing { .. }))
-        {
+        if output_diffs.iter().any(|(_, diff)| diff.changed()) {
             // TODO: Update MISSING_OUTPUTS environment variable
-            info!(params.output_snd, "[{}] Missing Outputs", params.step.name);
+            info!(
+                params.output_snd,
+                "[{}] Missing or changed outputs", params.step.name
+            );
             changed = true;
         } else {
             info!(
@@ -967,7 +1365,11 @@ fn s_checking_superficial_diffs<'a>(
         .iter()
         .map(|(dep_e, _dep)| {
             let cmp_diff = uwr!(
-                superficial_compare_dependency(&params, *dep_e),
+                dry_run_diff(
+                    &params,
+                    *dep_e,
+                    superficial_compare_dependency(&params, *dep_e)
+                ),
                 params.output_snd
             );
             (*dep_e, cmp_diff)
@@ -989,6 +1391,30 @@ fn s_checking_superficial_diffs<'a>(
     }
 }
 
+/// In dry runs, a dependency that can't be compared, e.g., an output of a step that would run but
+/// hasn't run yet, is considered missing.
+fn dry_run_diff(
+    params: &StepStateParams,
+    dep_e: XvcEntity,
+    diff: Result<Diff<XvcDependency>>,
+) -> Result<Diff<XvcDependency>> {
+    match diff {
+        Err(e) if params.dry_run => match params.recorded_dependencies.children.get(&dep_e) {
+            Some(record) => {
+                debug!(
+                    params.output_snd,
+                    "[{}] Cannot compare {record}: {e}", params.step.name
+                );
+                Ok(Diff::ActualMissing {
+                    record: record.clone(),
+                })
+            }
+            None => Err(e),
+        },
+        diff => diff,
+    }
+}
+
 fn s_checking_superficial_diffs_f_missing_outputs_ignored<'a>(
     s: &CheckingSuperficialDiffsState,
     params: StepStateParams<'a>,
@@ -1020,7 +1446,11 @@ fn s_checking_thorough_diffs_f_superficial_diffs_changed<'a>(
         .map(|(dep_e, dep)| {
             if dep.changed() {
                 let cmp_diff = uwr!(
-                    thorough_compare_dependency(&params, *dep_e),
+                    dry_run_diff(
+                        &params,
+                        *dep_e,
+                        thorough_compare_dependency(&params, *dep_e)
+                    ),
                     params.output_snd
                 );
                 (*dep_e, cmp_diff)
@@ -1062,7 +1492,11 @@ fn s_checking_thorough_diffs_f_superficial_diffs_ignored<'a>(
         .iter()
         .map(|(dep_e, _dep)| {
             let cmp_diff = uwr!(
-                thorough_compare_dependency(&params, *dep_e),
+                dry_run_diff(
+                    &params,
+                    *dep_e,
+                    thorough_compare_dependency(&params, *dep_e)
+                ),
                 params.output_snd
             );
             (*dep_e, cmp_diff)
@@ -1100,40 +1534,28 @@ fn s_checking_outputs_f_dependency_steps_finished_ignored<'a>(
     s_checking_missing_outputs(s, params)
 }
 
+/// Compares an output with its record. Outputs with a recorded digest are compared by their
+/// content, others only by their existence.
+///
+/// As outputs don't keep their digests, a changed output is reported as [Diff::Different] with the
+/// same record and actual values.
 fn compare_output(params: &StepStateParams, out_e: XvcEntity) -> Result<Diff<XvcOutput>> {
     let output = params.step_outputs.get(&out_e).unwrap();
+    let path = XvcPath::from(output).to_absolute_path(params.xvc_root);
+    if !path.exists() {
+        return Ok(Diff::ActualMissing {
+            record: output.clone(),
+        });
+    }
 
-    match output {
-        record @ XvcOutput::File { path } => {
-            let path = path.to_absolute_path(params.xvc_root);
-            if path.exists() {
-                Ok(Diff::Identical)
-            } else {
-                Ok(Diff::ActualMissing {
-                    record: record.clone(),
-                })
-            }
-        }
-        record @ XvcOutput::Metric { path, .. } => {
-            let path = path.to_absolute_path(params.xvc_root);
-            if path.exists() {
-                Ok(Diff::Identical)
-            } else {
-                Ok(Diff::ActualMissing {
-                    record: record.clone(),
-                })
-            }
-        }
-        record @ XvcOutput::Image { path } => {
-            let path = path.to_absolute_path(params.xvc_root);
-            if path.exists() {
-                Ok(Diff::Identical)
-            } else {
-                Ok(Diff::ActualMissing {
-                    record: record.clone(),
-                })
-            }
+    match params.recorded_output_digests.get(&out_e) {
+        Some(digest) if !digest.matches(params.xvc_root, output, params.algorithm)? => {
+            Ok(Diff::Different {
+                record: output.clone(),
+                actual: output.clone(),
+            })
         }
+        _ => Ok(Diff::Identical),
     }
 }
 
@@ -1148,16 +1570,11 @@ fn s_checking_missing_outputs<'a>(
         return Ok((s.checked_outputs(), params));
     }
 
-    let mut missing = false;
-
     params.output_diffs.write()?.extend(
         step_outs
             .iter()
             .map(|(out_e, _out)| {
                 let out_diff = uwr!(compare_output(&params, *out_e), params.output_snd);
-                if matches!(out_diff, Diff::ActualMissing { .. }) {
-                    missing = true;
-                }
                 (*out_e, out_diff)
             })
             .collect::<HStore<Diff<XvcOutput>>>(),
@@ -1171,6 +1588,7 @@ fn s_running_f_start_process<'a>(
     params: StepStateParams<'a>,
 ) -> StateTransition<'a> {
     update_command_environment(params.command_process.clone(), &params)?;
+    unlink_cached_outputs(&params)?;
     let command_process = params.command_process.clone();
     let mut command_process = command_process.write()?;
     command_process.run()?;
@@ -1180,6 +1598,24 @@ fn s_running_f_start_process<'a>(
     Ok((s.wait_process(), params))
 }
 
+/// Removes the outputs of the step that are symlinks or read-only hardlinks to the cache, as
+/// rechecked by the run cache or `xvc file track`. Otherwise the command would write to the cache
+/// through them.
+fn unlink_cached_outputs(params: &StepStateParams<'_>) -> Result<()> {
+    for out in params.step_outputs.values() {
+        let path = XvcPath::from(out).to_absolute_path(params.xvc_root);
+        let Ok(metadata) = path.symlink_metadata() else {
+            continue;
+        };
+        if metadata.file_type().is_symlink()
+            || (metadata.is_file() && metadata.permissions().readonly())
+        {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
 fn update_command_environment(
     command_process: Arc<RwLock<CommandProcess>>,
     params: &StepStateParams<'_>,
@@ -1369,8 +1805,7 @@ fn s_running_f_wait_process<'a>(
     let sleep_duration = Duration::from_millis(params.process_poll_milliseconds);
     loop {
         let send_output = |cp: Arc<RwLock<CommandProcess>>| -> Result<()> {
-            let mut cp = cp.write()?;
-            cp.update_output_channels()?;
+            let cp = cp.read()?;
             // We currently pass all the output to the main thread
             // In the future, these can be passed to different channels.
             let output_snd = params.output_snd;
@@ -1410,20 +1845,38 @@ fn s_running_f_wait_process<'a>(
                         );
                         return_state = None;
                     } else {
-                        if params.terminate_timeout_processes {
+                        error!(
+                            params.output_snd,
+                            "Step {} with command {} timed out after {}s. Terminating.",
+                            &step.name,
+                            &step_command,
+                            timeout.as_secs()
+                        );
+                        process.terminate().ok();
+                        // Give the process a chance to clean up before killing it
+                        let terminated = matches!(
+                            process.wait_timeout(params.timeout_grace_period),
+                            Ok(Some(_))
+                        );
+                        if terminated {
+                            return_state = Some(s.process_timeout());
+                        } else {
                             error!(
                                 params.output_snd,
-                                "Process timeout for step {} with command {} ",
+                                "Step {} didn't terminate in {}s. Killing.",
                                 &step.name,
-                                &step_command
+                                params.timeout_grace_period.as_secs()
                             );
-                            process.terminate().ok();
+                            process.kill().ok();
+                            process.wait().ok();
+                            return_state = Some(s.process_killed_after_timeout());
                         }
-                        return_state = Some(s.process_timeout());
                     }
                 }
 
                 Some(exit_code) => {
+                    // The readers finish when the process closes its output
+                    cp.join_output_readers();
                     if exit_code.success() {
                         output!(
                             params.output_snd,
@@ -1431,6 +1884,18 @@ fn s_running_f_wait_process<'a>(
                             step.name,
                             step_command
                         );
+                        if let Err(e) = record_output_digests(&params) {
+                            warn!(
+                                params.output_snd,
+                                "[{}] Cannot calculate output digests: {e}", step.name
+                            );
+                        }
+                        if let Err(e) = save_to_run_cache(&params) {
+                            warn!(
+                                params.output_snd,
+                                "[{}] Cannot add outputs to the run cache: {e}", step.name
+                            );
+                        }
                         return_state = Some(s.process_completed_successfully());
                     } else {
                         // We don't distinguish between non-zero exits, signals, and other
@@ -1463,6 +1928,21 @@ fn s_running_f_wait_process<'a>(
     Ok((return_state.unwrap(), params))
 }
 
+/// In dry runs, steps that would run are considered done without starting a process.
+fn s_waiting_to_run_f_dry_run<'a>(
+    s: &WaitingToRunState,
+    params: StepStateParams<'a>,
+) -> StateTransition<'a> {
+    Ok((s.dry_run(), params))
+}
+
+fn s_done_f_dry_run<'a>(
+    s: &DoneByRunningState,
+    params: StepStateParams<'a>,
+) -> StateTransition<'a> {
+    Ok((s.keep_done(), params))
+}
+
 fn s_waiting_to_run_f_process_pool_full<'a>(
     s: &WaitingToRunState,
     params: StepStateParams<'a>,
@@ -1477,6 +1957,14 @@ fn s_waiting_to_run_f_diffs_has_changed<'a>(
     s: &WaitingToRunState,
     params: StepStateParams<'a>,
 ) -> StateTransition<'a> {
+    match restore_from_run_cache(&params) {
+        Ok(true) => return Ok((s.restored_from_run_cache(), params)),
+        Ok(false) => {}
+        Err(e) => warn!(
+            params.output_snd,
+            "[{}] Cannot restore outputs from the run cache: {e}", params.step.name
+        ),
+    }
     if params.available_process_slots.read()?.gt(&0) {
         Ok((s.start_process(), params))
     } else {
@@ -1495,6 +1983,166 @@ fn s_waiting_to_run_f_run_always<'a>(
     }
 }
 
+/// Calculates the digests of the outputs after the step runs, to be recorded when the pipeline
+/// finishes.
+fn record_output_digests(params: &StepStateParams) -> Result<()> {
+    let mut output_digests = params.output_digests.write()?;
+    for (out_e, out) in params.step_outputs.iter() {
+        if let Some(digest) = XvcOutputDigest::new(params.xvc_root, out, params.algorithm)? {
+            output_digests.insert(*out_e, digest);
+        }
+    }
+    Ok(())
+}
+
+/// Tracks `outputs` with `xvc file track` after the pipeline runs. Outputs already tracked are
+/// updated with their new content.
+fn track_outputs(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    outputs: &[String],
+) -> Result<()> {
+    if outputs.is_empty() {
+        return Ok(());
+    }
+    let track_opts = TrackCLI::try_parse_from(
+        ["track", "--force"]
+            .into_iter()
+            .map(String::from)
+            .chain(outputs.iter().cloned()),
+    )
+    .map_err(anyhow::Error::from)?;
+    cmd_track(output_snd, xvc_root, track_opts)?;
+    Ok(())
+}
+
+/// Returns the run cache key of the step with the current values of its dependencies.
+///
+/// Returns `None` if the run cache is disabled, or the step has no outputs to restore, or it has
+/// missing dependencies.
+fn run_cache_key(params: &StepStateParams) -> Result<Option<XvcRunCacheKey>> {
+    if !params.run_cache || params.dry_run || params.step_outputs.is_empty() {
+        return Ok(None);
+    }
+    let deps = params.recorded_dependencies.children_of(&params.step_e)?;
+    let dependency_diffs = params.dependency_diffs.read()?;
+    let mut current_deps = Vec::with_capacity(deps.len());
+    for (dep_e, dep) in deps.iter() {
+        match dependency_diffs.get(dep_e) {
+            Some(Diff::RecordMissing { actual }) | Some(Diff::Different { actual, .. }) => {
+                current_deps.push(actual.clone())
+            }
+            Some(Diff::ActualMissing { .. }) => return Ok(None),
+            Some(Diff::Identical) | Some(Diff::Skipped) | None => current_deps.push(dep.clone()),
+        }
+    }
+    // Step dependencies are identified only by name, so the outputs of the steps this step
+    // depends on are added to the key with their current digests.
+    let output_digests = params.output_digests.read()?;
+    let mut dependency_outputs = Vec::new();
+    for dep_step_e in params.step_dependencies {
+        for (out_e, out) in params.recorded_outputs.children_of(dep_step_e)?.iter() {
+            let digest = output_digests
+                .get(out_e)
+                .or_else(|| params.recorded_output_digests.get(out_e));
+            if let Some(digest) = digest {
+                dependency_outputs.push(XvcRunCacheOutput {
+                    path: XvcPath::from(out),
+                    content_digest: digest.content_digest,
+                });
+            }
+        }
+    }
+    let outputs = params.step_outputs.values().cloned().collect::<Vec<_>>();
+    Ok(Some(XvcRunCacheKey::new(
+        params.step_command,
+        &current_deps,
+        &dependency_outputs,
+        &outputs,
+        params.algorithm,
+    )?))
+}
+
+/// Restores the outputs of the step from the run cache if there is an entry for its current
+/// dependencies. If the entry or its outputs are not in the local cache, they are received from
+/// the remote cache. Returns whether the outputs are restored.
+fn restore_from_run_cache(params: &StepStateParams) -> Result<bool> {
+    let Some(key) = run_cache_key(params)? else {
+        return Ok(false);
+    };
+    let mut entry = XvcRunCacheEntry::load(params.xvc_root, &key)?;
+    let available = match &entry {
+        Some(entry) => entry.is_available(params.xvc_root)?,
+        None => false,
+    };
+    if !available && let Some(storage) = params.remote_cache {
+        let mut remote_cache_index = params.remote_cache_index.lock()?;
+        let storage_index = match &mut *remote_cache_index {
+            Some(index) => index,
+            None => remote_cache_index.insert(XvcRunCacheEntry::storage_index(
+                params.output_snd,
+                params.xvc_root,
+                storage,
+            )?),
+        };
+        entry = XvcRunCacheEntry::receive(
+            params.output_snd,
+            params.xvc_root,
+            storage,
+            storage_index,
+            &key,
+        )?;
+    }
+    let Some(entry) = entry else {
+        return Ok(false);
+    };
+    if !entry.is_available(params.xvc_root)? {
+        return Ok(false);
+    }
+    entry.restore(params.output_snd, params.xvc_root)?;
+    record_output_digests(params)?;
+    output!(
+        params.output_snd,
+        "[CACHED] [{}] ({})\n",
+        params.step.name,
+        params.step_command
+    );
+    Ok(true)
+}
+
+/// Copies the outputs of a successfully run step to the cache and records them in the run cache.
+/// The entry is sent to the remote cache, if there is one.
+fn save_to_run_cache(params: &StepStateParams) -> Result<()> {
+    let Some(key) = run_cache_key(params)? else {
+        return Ok(());
+    };
+    match XvcRunCacheEntry::from_outputs(
+        params.output_snd,
+        params.xvc_root,
+        params.step_command,
+        params.step_outputs,
+        params.algorithm,
+    )? {
+        Some(entry) => {
+            entry.save(params.xvc_root, &key)?;
+            match params.remote_cache {
+                Some(storage) => {
+                    let _transfer = params.remote_cache_index.lock()?;
+                    entry.send(params.output_snd, params.xvc_root, storage, &key)
+                }
+                None => Ok(()),
+            }
+        }
+        None => {
+            debug!(
+                params.output_snd,
+                "[{}] Some outputs are missing. Not adding to the run cache.", params.step.name
+            );
+            Ok(())
+        }
+    }
+}
+
 /// Broken stays always Broken
 fn s_broken_f_process_returned_non_zero<'a>(
     s: &BrokenState,
@@ -1510,6 +2158,13 @@ fn s_broken_f_process_timeout<'a>(
     Ok((s.keep_broken(), params))
 }
 
+fn s_broken_f_process_killed_after_timeout<'a>(
+    s: &BrokenState,
+    params: StepStateParams<'a>,
+) -> StateTransition<'a> {
+    Ok((s.keep_broken(), params))
+}
+
 fn s_broken_f_dependency_steps_finished_broken<'a>(
     s: &BrokenState,
     params: StepStateParams<'a>,
@@ -1546,3 +2201,476 @@ fn s_done_f_process_completed_successfully<'a>(
 ) -> StateTransition<'a> {
     s_done(s, params)
 }
+
+/// The Xvc repository for unit tests. Other tests wait until it's dropped.
+#[cfg(test)]
+pub(crate) struct TestXvcRoot {
+    xvc_root: XvcRoot,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestXvcRoot {
+    type Target = XvcRoot;
+
+    fn deref(&self) -> &Self::Target {
+        &self.xvc_root
+    }
+}
+
+/// Returns the Xvc repository for unit tests.
+///
+/// The entity generator can be loaded only once in a process, so all unit tests share a
+/// repository in a temporary Git repository. Tests must use distinct pipelines and paths in it.
+///
+/// The run cache is enabled, and outputs are rechecked as symlinks to tell the outputs restored
+/// from the cache from the ones written by the steps.
+#[cfg(test)]
+pub(crate) fn test_xvc_root() -> TestXvcRoot {
+    use std::sync::LazyLock;
+    use xvc_core::configuration::{
+        OptionalFileConfig, OptionalFileRecheckConfig, OptionalGitConfig, OptionalPipelineConfig,
+    };
+    use xvc_core::types::xvcroot::init_xvc_root;
+    use xvc_core::{
+        AbsolutePath, XvcLoadParams, XvcOptionalConfiguration, blank_optional_config, exec_git,
+    };
+
+    static XVC_ROOT: LazyLock<XvcRoot> = LazyLock::new(|| {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let dir_str = dir.to_string_lossy();
+        exec_git("git", &dir_str, &["init", "--quiet"]).unwrap();
+        exec_git("git", &dir_str, &["config", "user.name", "Xvc Test"]).unwrap();
+        exec_git("git", &dir_str, &["config", "user.email", "test@xvc.dev"]).unwrap();
+        let config_opts = XvcLoadParams {
+            xvc_root_dir: None,
+            current_dir: AbsolutePath::from(&dir),
+            include_system_config: false,
+            include_user_config: false,
+            include_project_config: true,
+            include_local_config: true,
+            project_config_path: None,
+            local_config_path: None,
+            include_environment_config: false,
+            command_line_config: None,
+        };
+        let initial_config = XvcOptionalConfiguration {
+            git: Some(OptionalGitConfig {
+                use_git: Some(false),
+                command: None,
+                auto_commit: Some(false),
+                auto_stage: Some(false),
+            }),
+            file: Some(OptionalFileConfig {
+                recheck: Some(OptionalFileRecheckConfig {
+                    method: Some("symlink".to_string()),
+                }),
+                ..Default::default()
+            }),
+            pipeline: Some(OptionalPipelineConfig {
+                run_cache: Some(true),
+                ..Default::default()
+            }),
+            ..blank_optional_config()
+        };
+        let xvc_root = init_xvc_root(&dir, config_opts, &initial_config).unwrap();
+        crate::init(&xvc_root).unwrap();
+        xvc_root
+    });
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    TestXvcRoot {
+        xvc_root: XVC_ROOT.clone(),
+        _lock: lock,
+    }
+}
+
+/// Runs `xvc pipeline` with `args` in the test repository
+#[cfg(test)]
+pub(crate) fn test_pipeline_command(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    args: &[&str],
+) -> Result<()> {
+    let command =
+        crate::PipelineCLI::try_parse_from(std::iter::once("pipeline").chain(args.iter().copied()))
+            .map_err(|e| anyhow!("{e}"))?;
+    crate::cmd_pipeline(std::io::empty(), output_snd, xvc_root, command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::deps::StepDep;
+
+    #[test]
+    fn test_step_timeout_from_str() -> Result<()> {
+        assert_eq!(XvcStepTimeout::from_str("90")?.seconds, 90);
+        assert_eq!(XvcStepTimeout::from_str("90s")?.seconds, 90);
+        assert_eq!(XvcStepTimeout::from_str("15m")?.seconds, 15 * 60);
+        assert_eq!(XvcStepTimeout::from_str("2h")?.seconds, 2 * 60 * 60);
+        assert_eq!(XvcStepTimeout::from_str("0")?.seconds, 0);
+        assert_eq!(XvcStepTimeout::from_seconds(0), None);
+        assert_eq!(
+            XvcStepTimeout::from_seconds(90),
+            Some(XvcStepTimeout { seconds: 90 })
+        );
+        assert!(XvcStepTimeout::from_str(&format!("{}h", u64::MAX)).is_err());
+        assert!(XvcStepTimeout::from_str("99999999999999999999").is_err());
+        assert!(XvcStepTimeout::from_str("15d").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_steps() -> Result<()> {
+        // d depends on b and c, which both depend on a
+        let names = ["a", "b", "c", "d"];
+        let pipeline_steps: HStore<XvcStep> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                (
+                    XvcEntity::from((i as u64 + 1, 0)),
+                    XvcStep {
+                        name: name.to_string(),
+                    },
+                )
+            })
+            .collect();
+        let e = |name: &str| step_entity(&pipeline_steps, name).unwrap();
+        let mut graph = DependencyGraph::new();
+        for (from, to) in [("b", "a"), ("c", "a"), ("d", "b"), ("d", "c")] {
+            graph.add_edge(
+                e(from),
+                e(to),
+                XvcDependency::Step(StepDep {
+                    name: to.to_string(),
+                }),
+            );
+        }
+        let select = |run_targets: XvcRunTargets| -> Result<Vec<String>> {
+            let mut selected = select_steps(&pipeline_steps, &graph, &run_targets)?
+                .iter()
+                .map(|step_e| pipeline_steps[step_e].name.clone())
+                .collect::<Vec<_>>();
+            selected.sort();
+            Ok(selected)
+        };
+
+        assert_eq!(select(XvcRunTargets::default())?, ["a", "b", "c", "d"]);
+        assert_eq!(
+            select(XvcRunTargets {
+                targets: vec!["b".to_string()],
+                ..Default::default()
+            })?,
+            ["a", "b"]
+        );
+        assert_eq!(
+            select(XvcRunTargets {
+                from: Some("b".to_string()),
+                ..Default::default()
+            })?,
+            ["b", "d"]
+        );
+        assert_eq!(
+            select(XvcRunTargets {
+                until: Some("c".to_string()),
+                ..Default::default()
+            })?,
+            ["a", "c"]
+        );
+        assert_eq!(
+            select(XvcRunTargets {
+                targets: vec!["d".to_string()],
+                from: Some("b".to_string()),
+                until: Some("d".to_string()),
+                ..Default::default()
+            })?,
+            ["b", "d"]
+        );
+        // Forced steps are selected even if they are outside of the selection
+        assert_eq!(
+            select(XvcRunTargets {
+                from: Some("c".to_string()),
+                force: vec!["b".to_string()],
+                ..Default::default()
+            })?,
+            ["b", "c", "d"]
+        );
+        assert!(
+            select(XvcRunTargets {
+                force: vec!["e".to_string()],
+                ..Default::default()
+            })
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_starts_no_process() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, _output_rcv) = crossbeam_channel::unbounded();
+        let cmd = |args: &[&str]| {
+            test_pipeline_command(
+                &output_snd,
+                &xvc_root,
+                &[&["--pipeline-name", "dry-run"], args].concat(),
+            )
+        };
+        let dir = xvc_root.absolute_path().join("dry-run");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("a.txt"), "a")?;
+        fs::write(dir.join("b.txt"), "b")?;
+
+        cmd(&["new"])?;
+        for (step, input, output) in [
+            ("changed-dep", "a.txt", "a.out"),
+            ("missing-out", "b.txt", "b.out"),
+        ] {
+            // Commands run in the current directory of the process
+            let command = format!(
+                "cd {} && cp dry-run/{input} dry-run/{output} && touch dry-run/{step}.ran",
+                xvc_root.absolute_path().display()
+            );
+            cmd(&["step", "new", "--step-name", step, "--command", &command])?;
+            cmd(&[
+                "step",
+                "dependency",
+                "--step-name",
+                step,
+                "--file",
+                &format!("dry-run/{input}"),
+            ])?;
+            cmd(&[
+                "step",
+                "output",
+                "--step-name",
+                step,
+                "--output-file",
+                &format!("dry-run/{output}"),
+            ])?;
+        }
+        cmd(&["run"])?;
+        assert!(dir.join("changed-dep.ran").exists());
+        assert!(dir.join("missing-out.ran").exists());
+        fs::remove_file(dir.join("changed-dep.ran"))?;
+        fs::remove_file(dir.join("missing-out.ran"))?;
+
+        fs::write(dir.join("a.txt"), "changed a")?;
+        fs::remove_file(dir.join("b.out"))?;
+        let statuses = the_grand_pipeline_loop(
+            &output_snd,
+            &xvc_root,
+            "dry-run".to_string(),
+            &XvcRunTargets::default(),
+            true,
+            None,
+        )?;
+        let status = |name: &str| statuses.iter().find(|s| s.name == name).unwrap();
+
+        let changed_dep = status("changed-dep");
+        assert_eq!(changed_dep.status, XvcStepRunStatus::Run);
+        assert_eq!(changed_dep.reasons, ["dependencies changed"]);
+        assert_eq!(changed_dep.changed_dependencies.len(), 1);
+        assert!(changed_dep.missing_outputs.is_empty());
+
+        let missing_out = status("missing-out");
+        assert_eq!(missing_out.status, XvcStepRunStatus::Run);
+        assert_eq!(missing_out.reasons, ["outputs missing"]);
+        assert!(missing_out.changed_dependencies.is_empty());
+        assert_eq!(missing_out.missing_outputs.len(), 1);
+
+        // Neither step has run, and nothing is recreated
+        assert!(!dir.join("changed-dep.ran").exists());
+        assert!(!dir.join("missing-out.ran").exists());
+        assert!(!dir.join("b.out").exists());
+        assert_eq!(fs::read_to_string(dir.join("a.out"))?, "a");
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_cache_restores_outputs() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, _output_rcv) = crossbeam_channel::unbounded();
+        let cmd = |args: &[&str]| {
+            test_pipeline_command(
+                &output_snd,
+                &xvc_root,
+                &[&["--pipeline-name", "run-cache"], args].concat(),
+            )
+        };
+        let root = xvc_root.absolute_path().display().to_string();
+        let dir = xvc_root.absolute_path().join("run-cache");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("input.txt"), "1")?;
+
+        cmd(&["new"])?;
+        // Commands run in the current directory of the process
+        cmd(&[
+            "step",
+            "new",
+            "--step-name",
+            "up",
+            "--command",
+            &format!("cd {root}/run-cache && cp input.txt up.out && echo up >> runs.log"),
+        ])?;
+        cmd(&[
+            "step",
+            "dependency",
+            "--step-name",
+            "up",
+            "--file",
+            "run-cache/input.txt",
+        ])?;
+        cmd(&[
+            "step",
+            "output",
+            "--step-name",
+            "up",
+            "--output-file",
+            "run-cache/up.out",
+        ])?;
+        // down depends only on the step, so its key changes only with the output of up
+        cmd(&[
+            "step",
+            "new",
+            "--step-name",
+            "down",
+            "--command",
+            &format!("cd {root}/run-cache && cp up.out down.out && echo down >> runs.log"),
+        ])?;
+        cmd(&["step", "dependency", "--step-name", "down", "--step", "up"])?;
+        cmd(&[
+            "step",
+            "output",
+            "--step-name",
+            "down",
+            "--output-file",
+            "run-cache/down.out",
+        ])?;
+
+        let runs = || fs::read_to_string(dir.join("runs.log"));
+        cmd(&["run"])?;
+        assert_eq!(runs()?, "up\ndown\n");
+        assert_eq!(fs::read_to_string(dir.join("down.out"))?, "1");
+
+        fs::write(dir.join("input.txt"), "2")?;
+        cmd(&["run"])?;
+        assert_eq!(runs()?, "up\ndown\nup\ndown\n");
+        assert_eq!(fs::read_to_string(dir.join("down.out"))?, "2");
+
+        // Both steps are restored from the cache with the configured recheck method
+        fs::write(dir.join("input.txt"), "1")?;
+        cmd(&["run"])?;
+        assert_eq!(runs()?, "up\ndown\nup\ndown\n");
+        for output in ["up.out", "down.out"] {
+            let path = dir.join(output);
+            assert!(path.symlink_metadata()?.file_type().is_symlink());
+            assert!(fs::read_link(&path)?.starts_with(xvc_root.xvc_dir()));
+            assert_eq!(fs::read_to_string(&path)?, "1");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_outputs_invalidate_steps() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, _output_rcv) = crossbeam_channel::unbounded();
+        let cmd = |args: &[&str]| {
+            test_pipeline_command(
+                &output_snd,
+                &xvc_root,
+                &[&["--pipeline-name", "changed-outputs"], args].concat(),
+            )
+        };
+        let root = xvc_root.absolute_path().display().to_string();
+        let dir = xvc_root.absolute_path().join("changed-outputs");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("input.txt"), "input")?;
+
+        cmd(&["new"])?;
+        // Commands run in the current directory of the process
+        for (step, input, output) in [
+            ("up", "input.txt", "up.out"),
+            ("down", "up.out", "down.out"),
+        ] {
+            let command = format!(
+                "cd {root}/changed-outputs && cp {input} {output} && echo {step} >> runs.log"
+            );
+            cmd(&["step", "new", "--step-name", step, "--command", &command])?;
+            cmd(&[
+                "step",
+                "dependency",
+                "--step-name",
+                step,
+                "--file",
+                &format!("changed-outputs/{input}"),
+            ])?;
+            cmd(&[
+                "step",
+                "output",
+                "--step-name",
+                step,
+                "--output-file",
+                &format!("changed-outputs/{output}"),
+            ])?;
+        }
+        cmd(&["run"])?;
+        let statuses = || {
+            the_grand_pipeline_loop(
+                &output_snd,
+                &xvc_root,
+                "changed-outputs".to_string(),
+                &XvcRunTargets::default(),
+                true,
+                None,
+            )
+        };
+        let status = |statuses: &[XvcStepStatus], name: &str| {
+            statuses.iter().find(|s| s.name == name).unwrap().clone()
+        };
+        assert!(
+            statuses()?
+                .iter()
+                .all(|s| s.status == XvcStepRunStatus::Skip)
+        );
+
+        // Outputs are rechecked as symlinks to the cache, so they are replaced to change them
+        let up_out = dir.join("up.out");
+        fs::remove_file(&up_out)?;
+        fs::write(&up_out, "corrupted")?;
+        let changed = statuses()?;
+        let up = status(&changed, "up");
+        assert_eq!(up.status, XvcStepRunStatus::Run);
+        assert_eq!(up.reasons, ["outputs changed"]);
+        assert_eq!(up.changed_outputs.len(), 1);
+        let down = status(&changed, "down");
+        assert_eq!(down.status, XvcStepRunStatus::Run);
+        assert!(down.reasons.contains(&"dependency steps run".to_string()));
+        assert_eq!(down.dependency_steps_to_run, ["up"]);
+
+        fs::remove_file(&up_out)?;
+        let missing = statuses()?;
+        let up = status(&missing, "up");
+        assert_eq!(up.status, XvcStepRunStatus::Run);
+        assert_eq!(up.reasons, ["outputs missing"]);
+        assert_eq!(up.missing_outputs.len(), 1);
+        let down = status(&missing, "down");
+        assert_eq!(down.status, XvcStepRunStatus::Run);
+        assert_eq!(down.dependency_steps_to_run, ["up"]);
+
+        // Running the pipeline brings the output back and the steps up to date
+        fs::write(&up_out, "corrupted")?;
+        cmd(&["run"])?;
+        assert_eq!(fs::read_to_string(&up_out)?, "input");
+        assert_eq!(fs::read_to_string(dir.join("down.out"))?, "input");
+        assert!(
+            statuses()?
+                .iter()
+                .all(|s| s.status == XvcStepRunStatus::Skip)
+        );
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use rayon::*;
 
 use strum_macros::Display;
 use xvc_core::persist;
-use xvc_core::{XvcPath, XvcRoot};
+use xvc_core::{ContentDigest, HashAlgorithm, TextOrBinary, XvcMetadata, XvcPath, XvcRoot};
 
 use crate::error::{Error, Result};
 
@@ -104,3 +104,57 @@ impl XvcOutput {
             .map_err(|source| Error::IoError { source })
     }
 }
+
+/// The content digest of an output, recorded after its step runs.
+///
+/// The metadata is used to skip calculating the digest again when the output file hasn't been
+/// modified since.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, PartialOrd, Ord)]
+pub struct XvcOutputDigest {
+    /// Metadata of the output file when the digest is calculated
+    pub xvc_metadata: XvcMetadata,
+    /// Digest of the output file
+    pub content_digest: ContentDigest,
+}
+
+persist!(XvcOutputDigest, "xvc-output-digest");
+
+impl XvcOutputDigest {
+    /// Calculates the digest of `output` with `algorithm`. Returns `None` if the output file is
+    /// missing.
+    pub fn new(
+        xvc_root: &XvcRoot,
+        output: &XvcOutput,
+        algorithm: HashAlgorithm,
+    ) -> Result<Option<Self>> {
+        let xvc_path: XvcPath = output.into();
+        let abs_path = xvc_path.to_absolute_path(xvc_root);
+        if !abs_path.is_file() {
+            return Ok(None);
+        }
+        let xvc_metadata = XvcMetadata::from(abs_path.metadata());
+        let content_digest = ContentDigest::new(&abs_path, algorithm, TextOrBinary::Binary)?;
+        Ok(Some(Self {
+            xvc_metadata,
+            content_digest,
+        }))
+    }
+
+    /// Whether the output file still has the content of this digest.
+    ///
+    /// Calculates the digest only if the metadata of the file has changed.
+    pub fn matches(
+        &self,
+        xvc_root: &XvcRoot,
+        output: &XvcOutput,
+        algorithm: HashAlgorithm,
+    ) -> Result<bool> {
+        let xvc_path: XvcPath = output.into();
+        let abs_path = xvc_path.to_absolute_path(xvc_root);
+        if XvcMetadata::from(abs_path.metadata()) == self.xvc_metadata {
+            return Ok(true);
+        }
+        Ok(Self::new(xvc_root, output, algorithm)?
+            .is_some_and(|actual| actual.content_digest == self.content_digest))
+    }
+}
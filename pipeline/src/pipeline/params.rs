@@ -0,0 +1,551 @@
+//! Overriding the values of parameters used by [ParamDep]s before running a pipeline.
+//!
+//! The values are written to the parameter files, so that the dependency comparison picks them up
+//! like manual edits. The rest of the files is kept as far as possible: TOML files are edited with
+//! [toml_edit], and only the text of the value is replaced in JSON and YAML files. YAML files with
+//! the value on a different line than its key are rewritten with [serde_yaml], which drops their
+//! comments.
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use serde_yaml::Value as YamlValue;
+use xvc_core::{AbsolutePath, R1NStore, XvcOutputSender, XvcPath, XvcRoot, info};
+
+use crate::deps::{ParamDep, XvcParamFormat};
+use crate::error::{Error, Result};
+use crate::{XvcDependency, XvcPipeline, XvcStep};
+
+/// A value to set for a parameter, in the form of `KEY=VALUE` or `FILE:KEY=VALUE`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcParamOverride {
+    /// The parameter file. If not given, the files of the parameter dependencies that use the key
+    /// are changed.
+    pub file: Option<PathBuf>,
+    /// Key like `train.lr`
+    pub key: String,
+    /// The value, parsed as YAML to get its type, e.g., `0.01`, `true` or `adam`
+    pub value: String,
+}
+
+impl FromStr for XvcParamOverride {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidParameterFormat {
+            param: s.to_owned(),
+        };
+        let (param, value) = s.split_once('=').ok_or_else(invalid)?;
+        let (file, key) = match param.rsplit_once(':') {
+            Some((file, key)) => (Some(PathBuf::from(file)), key),
+            None => (None, param),
+        };
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            file,
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl Display for XvcParamOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}={}", file.to_string_lossy(), self.key, self.value),
+            None => write!(f, "{}={}", self.key, self.value),
+        }
+    }
+}
+
+impl XvcParamOverride {
+    /// Reads the overrides from a YAML, JSON or TOML file. Nested keys are joined with dots, e.g.,
+    /// `{"train": {"lr": 0.01}}` sets `train.lr`.
+    pub fn from_params_file(path: &Path) -> Result<Vec<Self>> {
+        Self::from_value(&read_params_file(path)?)
+    }
+
+    /// Converts a mapping of keys to values to overrides. Nested keys are joined with dots.
+    pub fn from_value(value: &YamlValue) -> Result<Vec<Self>> {
+        let mut overrides = Vec::new();
+        flatten_params("", value, &mut overrides)?;
+        Ok(overrides)
+    }
+}
+
+/// Reads a YAML, JSON or TOML file as a YAML value.
+pub fn read_params_file(path: &Path) -> Result<YamlValue> {
+    let content = fs::read_to_string(path)?;
+    let value = match XvcParamFormat::from_path(path) {
+        XvcParamFormat::YAML => serde_yaml::from_str(&content)?,
+        XvcParamFormat::JSON => serde_yaml::to_value(serde_json::from_str::<JsonValue>(&content)?)?,
+        XvcParamFormat::TOML => serde_yaml::to_value(content.parse::<toml::Table>()?)?,
+        XvcParamFormat::Unknown => {
+            return Err(Error::UnsupportedParamFileFormat {
+                path: path.as_os_str().into(),
+            });
+        }
+    };
+    Ok(value)
+}
+
+fn flatten_params(
+    prefix: &str,
+    value: &YamlValue,
+    overrides: &mut Vec<XvcParamOverride>,
+) -> Result<()> {
+    match value {
+        YamlValue::Mapping(map) => {
+            for (key, value) in map {
+                let key = match key {
+                    YamlValue::String(s) => s.clone(),
+                    key => serde_yaml::to_string(key)?.trim_end().to_owned(),
+                };
+                let key = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_params(&key, value, overrides)?;
+            }
+        }
+        value => overrides.push(XvcParamOverride {
+            file: None,
+            key: prefix.to_owned(),
+            value: value_text(value, XvcParamFormat::YAML)?,
+        }),
+    }
+    Ok(())
+}
+
+/// The contents of the parameter files before the overrides are written
+#[derive(Debug, Default)]
+pub struct XvcParamBackup {
+    files: Vec<(AbsolutePath, String)>,
+}
+
+impl XvcParamBackup {
+    /// Paths of the changed files
+    pub fn paths(&self) -> impl Iterator<Item = &AbsolutePath> {
+        self.files.iter().map(|(path, _)| path)
+    }
+
+    /// Writes the original contents back to the parameter files.
+    pub fn restore(self) -> Result<()> {
+        for (path, content) in self.files {
+            fs::write(path, content)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the `overrides` to the parameter files used by the steps of the pipeline, and returns
+/// the original contents of the changed files.
+///
+/// Overrides without a file are written to the files of the [ParamDep]s whose keys are the same
+/// as, contain or are contained in the key. It's an error if there is no such dependency.
+pub fn apply_param_overrides(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    pipeline_name: &str,
+    overrides: &[XvcParamOverride],
+) -> Result<XvcParamBackup> {
+    if overrides.is_empty() {
+        return Ok(XvcParamBackup::default());
+    }
+    let (pipeline_e, _) = XvcPipeline::from_name(xvc_root, pipeline_name)?;
+    let pipeline_steps = xvc_root
+        .load_r1nstore::<XvcPipeline, XvcStep>()?
+        .children_of(&pipeline_e)?;
+    let all_deps: R1NStore<XvcStep, XvcDependency> = xvc_root.load_r1nstore()?;
+    let mut param_deps: Vec<ParamDep> = Vec::new();
+    for step_e in pipeline_steps.keys() {
+        for dep in all_deps.children_of(step_e)?.values() {
+            if let XvcDependency::Param(param_dep) = dep {
+                param_deps.push(param_dep.clone());
+            }
+        }
+    }
+
+    // Files and the overrides to write to them, in the order they are given
+    let mut edits: BTreeMap<(XvcPath, XvcParamFormat), Vec<&XvcParamOverride>> = BTreeMap::new();
+    for param in overrides {
+        match &param.file {
+            Some(file) => {
+                let path = XvcPath::new(xvc_root, xvc_root.current_dir(), file)?;
+                let format = XvcParamFormat::from_xvc_path(&path);
+                edits.entry((path, format)).or_default().push(param);
+            }
+            None => {
+                let mut found = false;
+                for dep in &param_deps {
+                    let uses_key = dep.key == param.key
+                        || param.key.starts_with(&format!("{}.", dep.key))
+                        || dep.key.starts_with(&format!("{}.", param.key));
+                    if uses_key {
+                        let files = edits.entry((dep.path.clone(), dep.format)).or_default();
+                        if !files.contains(&param) {
+                            files.push(param);
+                        }
+                        found = true;
+                    }
+                }
+                if !found {
+                    return Err(Error::ParamKeyNotInPipeline {
+                        key: param.key.clone(),
+                        pipeline: pipeline_name.to_owned(),
+                    });
+                }
+            }
+        }
+    }
+
+    // All files are changed in memory first, so that none of them is written if a key is missing
+    let mut changed = Vec::with_capacity(edits.len());
+    for ((path, format), params) in edits {
+        let abs_path = path.to_absolute_path(xvc_root);
+        let original = fs::read_to_string(&abs_path)?;
+        let mut content = original.clone();
+        for param in params {
+            content = set_param(&abs_path, &content, format, &param.key, &param.value)?;
+            info!(
+                output_snd,
+                "Set {} = {} in {}", param.key, param.value, path
+            );
+        }
+        changed.push((abs_path, original, content));
+    }
+
+    let mut backup = XvcParamBackup::default();
+    for (abs_path, original, content) in changed {
+        fs::write(&abs_path, content)?;
+        backup.files.push((abs_path, original));
+    }
+    Ok(backup)
+}
+
+/// Sets the value of `key` in the `content` of the parameter file at `path`, and returns the new
+/// content.
+///
+/// `value` is parsed as YAML, so `0.01` is a number and `"0.01"` is a string. The key must exist in
+/// the document.
+pub fn set_param(
+    path: &Path,
+    content: &str,
+    format: XvcParamFormat,
+    key: &str,
+    value: &str,
+) -> Result<String> {
+    let value: YamlValue = match serde_yaml::from_str(value) {
+        Ok(YamlValue::Null) if !matches!(value.trim(), "null" | "~") => {
+            YamlValue::String(value.to_owned())
+        }
+        Ok(value) => value,
+        Err(_) => YamlValue::String(value.to_owned()),
+    };
+    let not_found = || Error::KeyNotFoundInDocument {
+        key: key.to_owned(),
+        path: path.to_path_buf(),
+    };
+    match format {
+        XvcParamFormat::JSON => {
+            let span = json_value_span(content, key).ok_or_else(not_found)?;
+            Ok(replace_span(content, span, &value_text(&value, format)?))
+        }
+        XvcParamFormat::YAML => match yaml_value_span(content, key) {
+            Some(span) => Ok(replace_span(content, span, &value_text(&value, format)?)),
+            None => {
+                let mut document: YamlValue = serde_yaml::from_str(content)?;
+                let mut current = &mut document;
+                for k in key.split('.') {
+                    current = current.get_mut(k).ok_or_else(not_found)?;
+                }
+                *current = value;
+                Ok(serde_yaml::to_string(&document)?)
+            }
+        },
+        XvcParamFormat::TOML => {
+            let mut document = content
+                .parse::<toml_edit::DocumentMut>()
+                .map_err(|e| anyhow::anyhow!(e))?;
+            let mut current = document.as_item_mut();
+            for k in key.split('.') {
+                current = current.get_mut(k).ok_or_else(not_found)?;
+            }
+            let mut new_value = toml_value(&value)?;
+            if let Some(old_value) = current.as_value() {
+                *new_value.decor_mut() = old_value.decor().clone();
+            }
+            *current = toml_edit::Item::Value(new_value);
+            Ok(document.to_string())
+        }
+        XvcParamFormat::Unknown => Err(Error::UnsupportedParamFileFormat {
+            path: path.as_os_str().into(),
+        }),
+    }
+}
+
+fn replace_span(content: &str, span: Range<usize>, text: &str) -> String {
+    format!("{}{}{}", &content[..span.start], text, &content[span.end..])
+}
+
+/// Text of the value to write in a JSON or YAML file. Scalars in YAML are written in YAML, other
+/// values are written in JSON, which is valid YAML flow style.
+fn value_text(value: &YamlValue, format: XvcParamFormat) -> Result<String> {
+    match value {
+        YamlValue::Bool(_) | YamlValue::Number(_) | YamlValue::String(_) | YamlValue::Null
+            if format == XvcParamFormat::YAML =>
+        {
+            Ok(serde_yaml::to_string(value)?.trim_end().to_owned())
+        }
+        value => Ok(serde_json::to_string(&serde_json::to_value(value)?)?),
+    }
+}
+
+fn toml_value(value: &YamlValue) -> Result<toml_edit::Value> {
+    let invalid = || Error::InvalidParameterFormat {
+        param: format!("{value:?}"),
+    };
+    Ok(match value {
+        YamlValue::Bool(b) => (*b).into(),
+        YamlValue::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().ok_or_else(invalid)?.into(),
+        },
+        YamlValue::String(s) => s.as_str().into(),
+        YamlValue::Sequence(values) => {
+            let mut array = toml_edit::Array::new();
+            for value in values {
+                array.push(toml_value(value)?);
+            }
+            array.into()
+        }
+        YamlValue::Null | YamlValue::Mapping(_) | YamlValue::Tagged(_) => return Err(invalid()),
+    })
+}
+
+/// Finds the byte range of the value of a (dotted) key in a YAML document with block mappings.
+///
+/// Returns `None` if the key is not found, or its value is not on the same line as the key.
+fn yaml_value_span(content: &str, key: &str) -> Option<Range<usize>> {
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("---") {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        while parents.last().is_some_and(|(i, _)| *i >= indent) {
+            parents.pop();
+        }
+        if trimmed.starts_with("- ") {
+            continue;
+        }
+        let Some(colon) = find_unquoted(trimmed, |rest| {
+            rest.starts_with(": ") || rest == ":" || rest.starts_with(":\t")
+        }) else {
+            continue;
+        };
+        let line_key = trimmed[..colon].trim().trim_matches(['"', '\'']);
+        let path = parents
+            .iter()
+            .map(|(_, k)| k.as_str())
+            .chain(std::iter::once(line_key))
+            .collect::<Vec<_>>()
+            .join(".");
+        let after_colon = &trimmed[colon + 1..];
+        let value = after_colon.trim_start();
+        if value.is_empty() || value.starts_with('#') {
+            parents.push((indent, line_key.to_owned()));
+            continue;
+        }
+        if path == key {
+            if value.starts_with(['|', '>']) {
+                return None;
+            }
+            let value_start = line_start + indent + colon + 1 + (after_colon.len() - value.len());
+            let value_len = find_unquoted(value, |rest| rest.starts_with(" #"))
+                .map(|comment| value[..comment].trim_end().len())
+                .unwrap_or_else(|| value.trim_end().len());
+            return Some(value_start..value_start + value_len);
+        }
+    }
+    None
+}
+
+/// Returns the first byte index in `text` where `pred` holds for the rest of the text, skipping
+/// quoted strings.
+fn find_unquoted(text: &str, pred: impl Fn(&str) -> bool) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if pred(&text[i..]) => return Some(i),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Finds the byte range of the value of a (dotted) key in a JSON document.
+fn json_value_span(content: &str, key: &str) -> Option<Range<usize>> {
+    let target = key.split('.').collect::<Vec<_>>();
+    let mut scanner = JsonScanner {
+        bytes: content.as_bytes(),
+        pos: 0,
+        found: None,
+    };
+    scanner.value(&mut Vec::new(), &target)?;
+    scanner.found
+}
+
+/// A minimal JSON scanner that records the position of a value while skipping over the document
+struct JsonScanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    found: Option<Range<usize>>,
+}
+
+impl JsonScanner<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, b: u8) -> Option<()> {
+        self.skip_whitespace();
+        (self.peek()? == b).then(|| self.pos += 1)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.pos += 1;
+        while self.peek()? != b'"' {
+            if self.peek()? == b'\\' {
+                self.pos += 1;
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        serde_json::from_slice(&self.bytes[start..self.pos]).ok()
+    }
+
+    fn value(&mut self, path: &mut Vec<String>, target: &[&str]) -> Option<()> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match self.peek()? {
+            b'{' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek()? == b'}' {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        path.push(key);
+                        self.value(path, target)?;
+                        path.pop();
+                        self.skip_whitespace();
+                        match self.peek()? {
+                            b',' => self.pos += 1,
+                            b'}' => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return None,
+                        }
+                    }
+                }
+            }
+            b'[' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek()? == b']' {
+                    self.pos += 1;
+                } else {
+                    let mut i = 0;
+                    loop {
+                        path.push(i.to_string());
+                        self.value(path, target)?;
+                        path.pop();
+                        i += 1;
+                        self.skip_whitespace();
+                        match self.peek()? {
+                            b',' => self.pos += 1,
+                            b']' => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return None,
+                        }
+                    }
+                }
+            }
+            b'"' => {
+                self.string()?;
+            }
+            _ => {
+                while self
+                    .peek()
+                    .is_some_and(|b| !matches!(b, b',' | b'}' | b']') && !b.is_ascii_whitespace())
+                {
+                    self.pos += 1;
+                }
+            }
+        }
+        if self.found.is_none() && path.iter().map(String::as_str).eq(target.iter().copied()) {
+            self.found = Some(start..self.pos);
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_param() -> Result<()> {
+        let path = Path::new("params");
+        let yaml = "train:\n  lr: 0.1 # learning rate\n  epochs: 10\nmodel: cnn\n";
+        assert_eq!(
+            set_param(path, yaml, XvcParamFormat::YAML, "train.lr", "0.01")?,
+            "train:\n  lr: 0.01 # learning rate\n  epochs: 10\nmodel: cnn\n"
+        );
+
+        let json = "{\n  \"train\": {\"lr\": 0.1, \"epochs\": 10},\n  \"model\": \"cnn\"\n}\n";
+        assert_eq!(
+            set_param(path, json, XvcParamFormat::JSON, "model", "rnn")?,
+            "{\n  \"train\": {\"lr\": 0.1, \"epochs\": 10},\n  \"model\": \"rnn\"\n}\n"
+        );
+
+        let toml = "[train]\nlr = 0.1 # learning rate\nepochs = 10\n";
+        assert_eq!(
+            set_param(path, toml, XvcParamFormat::TOML, "train.epochs", "20")?,
+            "[train]\nlr = 0.1 # learning rate\nepochs = 20\n"
+        );
+
+        assert!(set_param(path, yaml, XvcParamFormat::YAML, "train.momentum", "0.9").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+//! Rendering plots from the metrics files written by pipeline steps.
+//!
+//! Metrics files are read as tables: CSV and TSV files by their header and rows, JSON files as an
+//! array of objects, an object of arrays, or a single object as a single row. The selected columns
+//! are drawn as line, scatter or bar plots to standalone SVG documents.
+use std::fmt::Write;
+
+use serde_json::Value as JsonValue;
+use strum_macros::{Display, EnumString, IntoStaticStr, VariantNames};
+
+use crate::XvcMetricsFormat;
+use crate::error::Result;
+use crate::pipeline::metrics::parse_delimited;
+
+/// Kinds of plots
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, EnumString, Display, IntoStaticStr, Default, VariantNames,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum XvcPlotKind {
+    /// Points connected with lines
+    #[default]
+    Line,
+    /// Points without lines
+    Scatter,
+    /// Grouped bars for each x value
+    Bar,
+}
+
+/// A table read from a metrics file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XvcMetricsTable {
+    /// Column names
+    pub columns: Vec<String>,
+    /// Rows of values, in the order of the columns
+    pub rows: Vec<Vec<String>>,
+}
+
+impl XvcMetricsTable {
+    /// Reads the table from the content of a metrics file.
+    ///
+    /// Files with [XvcMetricsFormat::Unknown] format have an empty table.
+    pub fn parse(content: &str, format: XvcMetricsFormat) -> Result<Self> {
+        match format {
+            XvcMetricsFormat::CSV => {
+                let (columns, rows) = parse_delimited(content, ',');
+                Ok(Self { columns, rows })
+            }
+            XvcMetricsFormat::TSV => {
+                let (columns, rows) = parse_delimited(content, '\t');
+                Ok(Self { columns, rows })
+            }
+            XvcMetricsFormat::JSON => Ok(Self::from_json(&serde_json::from_str(content)?)),
+            XvcMetricsFormat::Unknown => Ok(Self::default()),
+        }
+    }
+
+    fn from_json(value: &JsonValue) -> Self {
+        let to_cell = |v: &JsonValue| match v {
+            JsonValue::String(s) => s.clone(),
+            v => v.to_string(),
+        };
+        let mut table = Self::default();
+        match value {
+            // [{"epoch": 1, "loss": 0.5}, {"epoch": 2, "loss": 0.4}]
+            JsonValue::Array(records) => {
+                for record in records {
+                    if let JsonValue::Object(map) = record {
+                        for key in map.keys() {
+                            if !table.columns.contains(key) {
+                                table.columns.push(key.clone());
+                            }
+                        }
+                    }
+                }
+                for record in records {
+                    if let JsonValue::Object(map) = record {
+                        let row = table
+                            .columns
+                            .iter()
+                            .map(|c| map.get(c).map(to_cell).unwrap_or_default())
+                            .collect();
+                        table.rows.push(row);
+                    }
+                }
+            }
+            // {"epoch": [1, 2], "loss": [0.5, 0.4]}
+            JsonValue::Object(map) if map.values().any(JsonValue::is_array) => {
+                let len = map
+                    .values()
+                    .filter_map(JsonValue::as_array)
+                    .map(Vec::len)
+                    .max()
+                    .unwrap_or(0);
+                let arrays = map
+                    .iter()
+                    .filter_map(|(k, v)| v.as_array().map(|a| (k, a)))
+                    .collect::<Vec<_>>();
+                table.columns = arrays.iter().map(|(k, _)| (*k).clone()).collect();
+                for i in 0..len {
+                    let row = arrays
+                        .iter()
+                        .map(|(_, a)| a.get(i).map(to_cell).unwrap_or_default())
+                        .collect();
+                    table.rows.push(row);
+                }
+            }
+            // {"loss": 0.5, "acc": 0.8}
+            JsonValue::Object(map) => {
+                table.columns = map.keys().cloned().collect();
+                table.rows.push(map.values().map(to_cell).collect());
+            }
+            _ => {}
+        }
+        table
+    }
+
+    fn column(&self, name: &str) -> Option<Vec<&str>> {
+        let i = self.columns.iter().position(|c| c == name)?;
+        Some(
+            self.rows
+                .iter()
+                .map(|row| row.get(i).map(String::as_str).unwrap_or(""))
+                .collect(),
+        )
+    }
+
+    /// Selects the data to plot.
+    ///
+    /// `x` is the column for the horizontal axis. If it's not set or not found, the row index is
+    /// used. `y` are the columns to draw. If it's empty, all numeric columns other than `x` are
+    /// drawn. Columns in `y` that are not in the table are ignored.
+    pub fn plot_data(&self, x: Option<&str>, y: &[String]) -> XvcPlotData {
+        let (x_label, x_values) = match x.and_then(|x| self.column(x).map(|values| (x, values))) {
+            Some((x, values)) => (x.to_owned(), values.iter().map(|v| v.to_string()).collect()),
+            None => (
+                "step".to_owned(),
+                (0..self.rows.len()).map(|i| i.to_string()).collect(),
+            ),
+        };
+        let y_columns = if y.is_empty() {
+            self.columns
+                .iter()
+                .filter(|c| Some(c.as_str()) != x)
+                .filter(|c| {
+                    self.column(c).is_some_and(|values| {
+                        values
+                            .iter()
+                            .all(|v| v.is_empty() || v.parse::<f64>().is_ok())
+                            && values.iter().any(|v| !v.is_empty())
+                    })
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        } else {
+            y.iter()
+                .filter(|c| self.columns.contains(c))
+                .cloned()
+                .collect()
+        };
+        let series = y_columns
+            .into_iter()
+            .map(|name| {
+                let values = self
+                    .column(&name)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|v| v.parse::<f64>().ok())
+                    .collect();
+                (name, values)
+            })
+            .collect();
+        XvcPlotData {
+            x_label,
+            x_values,
+            series,
+        }
+    }
+}
+
+/// The values to draw in a plot
+#[derive(Debug, Clone, PartialEq)]
+pub struct XvcPlotData {
+    /// Label of the horizontal axis
+    pub x_label: String,
+    /// Values on the horizontal axis. If all of them are numbers, they are placed by their values,
+    /// otherwise they are placed in order.
+    pub x_values: Vec<String>,
+    /// Names and values of the series. Values that are not numbers are `None` and not drawn.
+    pub series: Vec<(String, Vec<Option<f64>>)>,
+}
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 400.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 150.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+const COLORS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+
+/// Draws `data` as an SVG document.
+pub fn render_svg(title: &str, data: &XvcPlotData, kind: XvcPlotKind) -> String {
+    let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    let n = data.x_values.len();
+
+    let numeric_x = data
+        .x_values
+        .iter()
+        .map(|x| x.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()
+        .filter(|_| kind != XvcPlotKind::Bar);
+    // Positions of the x values in 0..1
+    let x_positions: Vec<f64> = match &numeric_x {
+        Some(xs) => {
+            let (min, max) = min_max(xs.iter().copied(), false);
+            xs.iter().map(|x| (x - min) / (max - min)).collect()
+        }
+        None if kind == XvcPlotKind::Bar => (0..n).map(|i| (i as f64 + 0.5) / n as f64).collect(),
+        None => (0..n).map(|i| i as f64 / (n.max(2) - 1) as f64).collect(),
+    };
+    let (y_min, y_max) = min_max(
+        data.series
+            .iter()
+            .flat_map(|(_, ys)| ys.iter().flatten().copied()),
+        kind == XvcPlotKind::Bar,
+    );
+    let px = |pos: f64| MARGIN_LEFT + pos * plot_width;
+    let py = |y: f64| MARGIN_TOP + plot_height - (y - y_min) / (y_max - y_min) * plot_height;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}" font-family="sans-serif" font-size="12">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{WIDTH}" height="{HEIGHT}" fill="white"/>"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="20" text-anchor="middle" font-size="14">{}</text>"#,
+        MARGIN_LEFT + plot_width / 2.0,
+        escape(title)
+    );
+
+    // Axes, ticks and grid lines
+    let bottom = MARGIN_TOP + plot_height;
+    let _ = writeln!(
+        svg,
+        r#"<path d="M{MARGIN_LEFT} {MARGIN_TOP} V{bottom} H{}" fill="none" stroke="black"/>"#,
+        MARGIN_LEFT + plot_width
+    );
+    for i in 0..=4 {
+        let y = y_min + (y_max - y_min) * i as f64 / 4.0;
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{MARGIN_LEFT}" y1="{0}" x2="{1}" y2="{0}" stroke="#ddd"/><text x="{2}" y="{0}" text-anchor="end" dominant-baseline="middle">{3}</text>"##,
+            py(y),
+            MARGIN_LEFT + plot_width,
+            MARGIN_LEFT - 5.0,
+            format_number(y)
+        );
+    }
+    let x_ticks: Vec<(f64, String)> = match &numeric_x {
+        Some(xs) => {
+            let (min, max) = min_max(xs.iter().copied(), false);
+            (0..=4)
+                .map(|i| {
+                    let x = min + (max - min) * i as f64 / 4.0;
+                    (i as f64 / 4.0, format_number(x))
+                })
+                .collect()
+        }
+        None => {
+            let step = n.div_ceil(10).max(1);
+            (0..n)
+                .step_by(step)
+                .map(|i| (x_positions[i], data.x_values[i].clone()))
+                .collect()
+        }
+    };
+    for (pos, label) in x_ticks {
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+            px(pos),
+            bottom + 16.0,
+            escape(&label)
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+        MARGIN_LEFT + plot_width / 2.0,
+        HEIGHT - 10.0,
+        escape(&data.x_label)
+    );
+
+    // Series
+    let series_count = data.series.len().max(1);
+    for (s, (name, ys)) in data.series.iter().enumerate() {
+        let color = COLORS[s % COLORS.len()];
+        let points = ys
+            .iter()
+            .zip(&x_positions)
+            .filter_map(|(y, pos)| y.map(|y| (*pos, y)))
+            .collect::<Vec<_>>();
+        match kind {
+            XvcPlotKind::Line => {
+                let coords = points
+                    .iter()
+                    .map(|(pos, y)| format!("{:.2},{:.2}", px(*pos), py(*y)))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let _ = writeln!(
+                    svg,
+                    r#"<polyline points="{coords}" fill="none" stroke="{color}" stroke-width="2"/>"#
+                );
+            }
+            XvcPlotKind::Scatter => {
+                for (pos, y) in &points {
+                    let _ = writeln!(
+                        svg,
+                        r#"<circle cx="{:.2}" cy="{:.2}" r="3" fill="{color}"/>"#,
+                        px(*pos),
+                        py(*y)
+                    );
+                }
+            }
+            XvcPlotKind::Bar => {
+                let group_width = plot_width / n.max(1) as f64 * 0.8;
+                let bar_width = group_width / series_count as f64;
+                for (pos, y) in &points {
+                    let x = px(*pos) - group_width / 2.0 + bar_width * s as f64;
+                    let (top, bar_bottom) = (py(y.max(0.0)), py(y.min(0.0)));
+                    let _ = writeln!(
+                        svg,
+                        r#"<rect x="{x:.2}" y="{top:.2}" width="{bar_width:.2}" height="{:.2}" fill="{color}"/>"#,
+                        bar_bottom - top
+                    );
+                }
+            }
+        }
+        let legend_y = MARGIN_TOP + 18.0 * s as f64;
+        let legend_x = MARGIN_LEFT + plot_width + 10.0;
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{legend_x}" y="{}" width="12" height="12" fill="{color}"/><text x="{}" y="{}">{}</text>"#,
+            legend_y,
+            legend_x + 18.0,
+            legend_y + 10.0,
+            escape(name)
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Returns the range of the values, widened if it's empty or a single value, and including zero if
+/// `include_zero` is set.
+fn min_max(values: impl Iterator<Item = f64>, include_zero: bool) -> (f64, f64) {
+    let (mut min, mut max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+        (min.min(v), max.max(v))
+    });
+    if include_zero {
+        min = min.min(0.0);
+        max = max.max(0.0);
+    }
+    if !min.is_finite() || !max.is_finite() {
+        (0.0, 1.0)
+    } else if min == max {
+        (min - 0.5, max + 0.5)
+    } else {
+        (min, max)
+    }
+}
+
+fn format_number(value: f64) -> String {
+    if value != 0.0 && (value.abs() >= 1e6 || value.abs() < 1e-3) {
+        format!("{value:.2e}")
+    } else {
+        format!("{value:.4}")
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_owned()
+    }
+}
+
+/// Escapes the text to use in XML and HTML documents.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plot_data() -> Result<()> {
+        let records = r#"[{"epoch": 1, "loss": 0.5}, {"epoch": 2, "loss": 0.4}]"#;
+        let arrays = r#"{"epoch": [1, 2], "loss": [0.5, 0.4]}"#;
+        for json in [records, arrays] {
+            let data =
+                XvcMetricsTable::parse(json, XvcMetricsFormat::JSON)?.plot_data(Some("epoch"), &[]);
+            assert_eq!(data.x_values, vec!["1", "2"]);
+            assert_eq!(
+                data.series,
+                vec![("loss".to_owned(), vec![Some(0.5), Some(0.4)])]
+            );
+        }
+
+        let csv = "name,loss\na,0.5\nb,x\n";
+        let data = XvcMetricsTable::parse(csv, XvcMetricsFormat::CSV)?.plot_data(None, &[]);
+        assert_eq!(data.x_label, "step");
+        assert!(data.series.is_empty());
+        Ok(())
+    }
+}
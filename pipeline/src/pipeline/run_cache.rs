@@ -0,0 +1,474 @@
+//! Run cache for pipeline steps.
+//!
+//! After a step runs successfully, its output files are moved to the Xvc cache and an entry
+//! listing them is written under `.xvc/run-cache/`. The entry is keyed by the command, the contents
+//! of the dependencies and the output paths of the step. When the step needs to run again with the
+//! same key, e.g., after switching back to a branch, its outputs are restored from the cache
+//! instead of running the command. Outputs are copied or linked from the cache with
+//! `file.recheck.method`, like the files tracked by `xvc file`.
+//!
+//! Entries and their outputs can be sent to and received from storages, so that the outputs of a
+//! step run in one clone of the repository can be restored in another.
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use xvc_core::{
+    AbsolutePath, ContentDigest, FromConfig, HStore, HashAlgorithm, PathSync, RecheckMethod,
+    TextOrBinary, XvcCachePath, XvcDigest, XvcOutputSender, XvcPath, XvcRoot, XvcStore,
+};
+use xvc_file::common::{move_to_cache, recheck_file};
+use xvc_storage::storage::index::{XvcStorageIndex, read_index};
+use xvc_storage::{XvcStorage, XvcStorageEvent, XvcStorageOperations};
+
+use crate::error::Result;
+use crate::pipeline::command::XvcStepCommand;
+use crate::{XvcDependency, XvcOutput};
+
+/// The directory under `.xvc/` that contains the run cache entries
+pub use xvc_storage::storage::index::RUN_CACHE_DIR;
+
+/// The digest of a step's command, dependency contents and output paths, in hexadecimal.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct XvcRunCacheKey(String);
+
+impl XvcRunCacheKey {
+    /// Calculates the key from the command, the current values of the dependencies, the outputs
+    /// of the steps it depends on and the outputs of a step.
+    ///
+    /// Dependencies and outputs are sorted, so the key doesn't depend on the order they are
+    /// added to the step. Metadata of the dependencies is ignored.
+    pub fn new(
+        command: &XvcStepCommand,
+        dependencies: &[XvcDependency],
+        dependency_outputs: &[XvcRunCacheOutput],
+        outputs: &[XvcOutput],
+        algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        let mut deps = dependencies
+            .iter()
+            .map(|dep| serde_json::to_string(&dep.without_metadata()))
+            .collect::<serde_json::Result<Vec<String>>>()?;
+        deps.sort();
+        let mut dep_outs = dependency_outputs
+            .iter()
+            .map(|out| format!("{}:{}", out.path, out.content_digest))
+            .collect::<Vec<String>>();
+        dep_outs.sort();
+        let mut outs = outputs
+            .iter()
+            .map(|out| XvcPath::from(out).to_string())
+            .collect::<Vec<String>>();
+        outs.sort();
+        let content = serde_json::to_string(&(&command.command, deps, dep_outs, outs))?;
+        Ok(Self(XvcDigest::from_content(&content, algorithm).hex_str()))
+    }
+
+    /// The path of the entry for this key, relative to `.xvc/`
+    pub fn entry_path(&self) -> XvcCachePath {
+        let (prefix, rest) = self.0.split_at(3);
+        XvcCachePath::custom(&format!("{RUN_CACHE_DIR}/{prefix}/{rest}.json"))
+    }
+}
+
+/// An output file of a step stored in the cache
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct XvcRunCacheOutput {
+    /// Path of the output in the repository
+    pub path: XvcPath,
+    /// Digest of the output file content
+    pub content_digest: ContentDigest,
+}
+
+impl XvcRunCacheOutput {
+    /// Moves the file at `abs_path` to the cache as the content of `path`, and rechecks it back
+    /// with `file.recheck.method`. The file is left as is if its content is already in the cache.
+    ///
+    /// `abs_path` is normally `path` in the workspace, but it may be in another copy of the
+    /// repository.
+    pub fn from_file(
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: XvcPath,
+        abs_path: &Path,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        // Outputs are restored byte by byte, so we don't use text digests that ignore line
+        // endings.
+        let content_digest = ContentDigest::new(abs_path, algorithm, TextOrBinary::Binary)?;
+        let output = Self {
+            path,
+            content_digest,
+        };
+        let cache_path = output.cache_path()?.to_absolute_path(xvc_root);
+        if !cache_path.exists() {
+            let abs_path = AbsolutePath::from(abs_path);
+            move_to_cache(&abs_path, &cache_path, &PathSync::new())?;
+            let recheck_method = *RecheckMethod::from_config(xvc_root.config())?;
+            recheck_file(output_snd, cache_path, abs_path, recheck_method)?;
+        }
+        Ok(output)
+    }
+
+    /// The path of the output content in the cache
+    pub fn cache_path(&self) -> Result<XvcCachePath> {
+        Ok(XvcCachePath::new(&self.path, &self.content_digest)?)
+    }
+
+    /// Copies or links the output from the cache to its path with `file.recheck.method`,
+    /// replacing the existing file.
+    pub fn restore(&self, output_snd: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<()> {
+        let cache_path = self.cache_path()?.to_absolute_path(xvc_root);
+        let path = self.path.to_absolute_path(xvc_root);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let recheck_method = *RecheckMethod::from_config(xvc_root.config())?;
+        recheck_file(output_snd, cache_path, path, recheck_method)?;
+        Ok(())
+    }
+}
+
+/// The outputs produced by a step for a [XvcRunCacheKey]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcRunCacheEntry {
+    /// The command that produced the outputs
+    pub command: String,
+    /// The output files
+    pub outputs: Vec<XvcRunCacheOutput>,
+}
+
+impl XvcRunCacheEntry {
+    /// Loads the entry for `key`. Returns `None` if there is no entry for it.
+    pub fn load(xvc_root: &XvcRoot, key: &XvcRunCacheKey) -> Result<Option<Self>> {
+        let path = key.entry_path().to_absolute_path(xvc_root);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    /// Writes the entry for `key`, replacing the existing one.
+    pub fn save(&self, xvc_root: &XvcRoot, key: &XvcRunCacheKey) -> Result<()> {
+        let path = key.entry_path().to_absolute_path(xvc_root);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Moves the `outputs` of a step to the cache and returns the entry listing them.
+    ///
+    /// Returns `None` if any of the outputs is missing, as the entry couldn't restore them.
+    pub fn from_outputs(
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        command: &XvcStepCommand,
+        outputs: &HStore<XvcOutput>,
+        algorithm: HashAlgorithm,
+    ) -> Result<Option<Self>> {
+        let mut cached_outputs = Vec::with_capacity(outputs.len());
+        for out in outputs.values() {
+            let path = XvcPath::from(out);
+            let abs_path = path.to_absolute_path(xvc_root);
+            if !abs_path.is_file() {
+                return Ok(None);
+            }
+            cached_outputs.push(XvcRunCacheOutput::from_file(
+                output_snd, xvc_root, path, &abs_path, algorithm,
+            )?);
+        }
+        cached_outputs.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Some(Self {
+            command: command.command.clone(),
+            outputs: cached_outputs,
+        }))
+    }
+
+    /// Sends the entry for `key` and its outputs to `storage`.
+    ///
+    /// Outputs already in the storage index are not sent again.
+    pub fn send(
+        &self,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        storage: &XvcStorage,
+        key: &XvcRunCacheKey,
+    ) -> Result<()> {
+        let mut paths = self
+            .outputs
+            .iter()
+            .map(XvcRunCacheOutput::cache_path)
+            .collect::<Result<Vec<XvcCachePath>>>()?;
+        paths.push(key.entry_path());
+        let event = storage.send(output_snd, xvc_root, &paths, false)?;
+        record_storage_event(xvc_root, XvcStorageEvent::Send(event))
+    }
+
+    /// Returns the files in `storage`, from its index if it has one, to look up the entries
+    /// before receiving them.
+    pub fn storage_index(
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        storage: &XvcStorage,
+    ) -> Result<XvcStorageIndex> {
+        match read_index(storage, output_snd, xvc_root)? {
+            Some((index, _)) => Ok(index),
+            None => {
+                let list_event = storage.as_dyn().list(output_snd, xvc_root)?;
+                Ok(XvcStorageIndex::from_storage_paths(&list_event.paths))
+            }
+        }
+    }
+
+    /// Receives the entry for `key` and its outputs from `storage` to the cache.
+    ///
+    /// Returns `None` without contacting the storage if `storage_index` doesn't have an entry
+    /// for `key`.
+    pub fn receive(
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        storage: &XvcStorage,
+        storage_index: &XvcStorageIndex,
+        key: &XvcRunCacheKey,
+    ) -> Result<Option<Self>> {
+        let entry_path = key.entry_path();
+        if !storage_index.contains(&entry_path) {
+            return Ok(None);
+        }
+        let (temp_dir, event) = storage.receive(
+            output_snd,
+            xvc_root,
+            std::slice::from_ref(&entry_path),
+            false,
+        )?;
+        let temp_entry_path = temp_dir.temp_cache_path(&entry_path)?;
+        if !temp_entry_path.exists() {
+            return Ok(None);
+        }
+        record_storage_event(xvc_root, XvcStorageEvent::Receive(event))?;
+        let entry: Self = serde_json::from_str(&fs::read_to_string(&temp_entry_path)?)?;
+
+        let mut missing = Vec::with_capacity(entry.outputs.len());
+        for output in &entry.outputs {
+            let cache_path = output.cache_path()?;
+            if !cache_path.to_absolute_path(xvc_root).exists() {
+                missing.push(cache_path);
+            }
+        }
+        if !missing.is_empty() {
+            let (temp_dir, event) = storage.receive(output_snd, xvc_root, &missing, false)?;
+            let path_sync = PathSync::new();
+            for cache_path in &missing {
+                let temp_path = temp_dir.temp_cache_path(cache_path)?;
+                if temp_path.exists() {
+                    move_to_cache(
+                        &temp_path,
+                        &cache_path.to_absolute_path(xvc_root),
+                        &path_sync,
+                    )?;
+                }
+            }
+            record_storage_event(xvc_root, XvcStorageEvent::Receive(event))?;
+        }
+
+        entry.save(xvc_root, key)?;
+        Ok(Some(entry))
+    }
+
+    /// Whether all outputs of the entry are in the cache
+    pub fn is_available(&self, xvc_root: &XvcRoot) -> Result<bool> {
+        for output in &self.outputs {
+            if !output.cache_path()?.to_absolute_path(xvc_root).exists() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Copies or links the outputs from the cache to their paths, replacing the existing files.
+    pub fn restore(&self, output_snd: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<()> {
+        for output in &self.outputs {
+            output.restore(output_snd, xvc_root)?;
+        }
+        Ok(())
+    }
+}
+
+fn record_storage_event(xvc_root: &XvcRoot, event: XvcStorageEvent) -> Result<()> {
+    xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
+        store.insert(xvc_root.new_entity(), event.clone());
+        Ok(())
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::deps::file::FileDep;
+    use crate::pipeline::test_xvc_root;
+    use relative_path::RelativePathBuf;
+    use xvc_core::XvcOutputLine;
+    use xvc_storage::{XvcLocalStorage, XvcStorageGuid};
+
+    fn path(p: &str) -> XvcPath {
+        XvcPath::from(RelativePathBuf::from(p))
+    }
+
+    fn digest(content: &str) -> ContentDigest {
+        ContentDigest::from(XvcDigest::from_content(content, HashAlgorithm::Blake3))
+    }
+
+    fn file_dep(p: &str, content: &str) -> XvcDependency {
+        XvcDependency::File(FileDep {
+            path: path(p),
+            xvc_metadata: None,
+            content_digest: Some(digest(content)),
+        })
+    }
+
+    fn out(p: &str) -> XvcOutput {
+        XvcOutput::File { path: path(p) }
+    }
+
+    fn dep_out(p: &str, content: &str) -> XvcRunCacheOutput {
+        XvcRunCacheOutput {
+            path: path(p),
+            content_digest: digest(content),
+        }
+    }
+
+    #[test]
+    fn test_receive_from_storage() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, output_rcv) = crossbeam_channel::unbounded();
+        let storage_dir = tempfile::tempdir()?;
+        let mut storage = XvcStorage::new(XvcLocalStorage {
+            guid: XvcStorageGuid::new(),
+            name: "run-cache-receive".to_string(),
+            path: storage_dir.path().to_path_buf(),
+        });
+        storage.init(&output_snd, &xvc_root)?;
+
+        let output_path = path("run-cache-receive/out.txt");
+        let abs_output_path = output_path.to_absolute_path(&xvc_root);
+        fs::create_dir_all(abs_output_path.parent().unwrap())?;
+        fs::write(&abs_output_path, "received")?;
+        let command = XvcStepCommand {
+            command: "make".to_string(),
+        };
+        let outputs = [XvcOutput::File {
+            path: output_path.clone(),
+        }];
+        let key = XvcRunCacheKey::new(&command, &[], &[], &outputs, HashAlgorithm::Blake3)?;
+        let entry = XvcRunCacheEntry {
+            command: command.command.clone(),
+            outputs: vec![XvcRunCacheOutput::from_file(
+                &output_snd,
+                &xvc_root,
+                output_path,
+                &abs_output_path,
+                HashAlgorithm::Blake3,
+            )?],
+        };
+        entry.save(&xvc_root, &key)?;
+        entry.send(&output_snd, &xvc_root, &storage, &key)?;
+        fs::remove_file(key.entry_path().to_absolute_path(&xvc_root))?;
+        fs::remove_file(entry.outputs[0].cache_path()?.to_absolute_path(&xvc_root))?;
+        let storage_index = XvcRunCacheEntry::storage_index(&output_snd, &xvc_root, &storage)?;
+
+        // Keys that aren't in the storage are missed silently, without receiving anything
+        output_rcv.try_iter().for_each(drop);
+        let events = xvc_root.load_store::<XvcStorageEvent>()?.len();
+        let missing_key = XvcRunCacheKey::new(
+            &XvcStepCommand {
+                command: "make all".to_string(),
+            },
+            &[],
+            &[],
+            &outputs,
+            HashAlgorithm::Blake3,
+        )?;
+        assert_eq!(
+            XvcRunCacheEntry::receive(
+                &output_snd,
+                &xvc_root,
+                &storage,
+                &storage_index,
+                &missing_key
+            )?,
+            None
+        );
+        assert_eq!(xvc_root.load_store::<XvcStorageEvent>()?.len(), events);
+        assert!(
+            output_rcv
+                .try_iter()
+                .flatten()
+                .all(|line| !matches!(line, XvcOutputLine::Warn(_) | XvcOutputLine::Error(_)))
+        );
+
+        let received =
+            XvcRunCacheEntry::receive(&output_snd, &xvc_root, &storage, &storage_index, &key)?;
+        assert_eq!(received.as_ref(), Some(&entry));
+        assert!(entry.is_available(&xvc_root)?);
+        assert_eq!(XvcRunCacheEntry::load(&xvc_root, &key)?, Some(entry));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_cache_key() -> Result<()> {
+        let key = |command: &str,
+                   deps: &[XvcDependency],
+                   dep_outs: &[XvcRunCacheOutput],
+                   outs: &[XvcOutput]| {
+            XvcRunCacheKey::new(
+                &XvcStepCommand {
+                    command: command.to_owned(),
+                },
+                deps,
+                dep_outs,
+                outs,
+                HashAlgorithm::Blake3,
+            )
+        };
+        let deps = [file_dep("a.txt", "a"), file_dep("b.txt", "b")];
+        let dep_outs = [dep_out("up.out", "up")];
+        let outs = [out("x.out"), out("y.out")];
+        let base = key("make", &deps, &dep_outs, &outs)?;
+
+        // The order of dependencies and outputs doesn't matter
+        assert_eq!(
+            key(
+                "make",
+                &[deps[1].clone(), deps[0].clone()],
+                &dep_outs,
+                &[outs[1].clone(), outs[0].clone()]
+            )?,
+            base
+        );
+
+        assert_ne!(key("make all", &deps, &dep_outs, &outs)?, base);
+        assert_ne!(
+            key(
+                "make",
+                &[file_dep("a.txt", "changed"), deps[1].clone()],
+                &dep_outs,
+                &outs
+            )?,
+            base
+        );
+        assert_ne!(
+            key("make", &deps, &[dep_out("up.out", "changed")], &outs)?,
+            base
+        );
+        assert_ne!(key("make", &deps, &[], &outs)?, base);
+        assert_ne!(
+            key("make", &deps, &dep_outs, &[out("x.out"), out("z.out")])?,
+            base
+        );
+        Ok(())
+    }
+}
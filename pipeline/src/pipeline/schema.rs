@@ -63,6 +63,9 @@ pub struct XvcStepSchema {
     pub command: String,
     /// When we consider the step as changed?
     pub invalidate: XvcStepInvalidate,
+    /// Timeout of the step in seconds. The pipeline default is used if not set or 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
     /// List of dependencies of the step.
     /// These do not require a separate schema.
     pub dependencies: Vec<XvcDependency>,
@@ -15,10 +15,10 @@ use xvc_core::XvcOutputSender;
 use xvc_core::XvcRoot;
 use xvc_core::{XvcEntity, persist};
 
-use super::XvcStepInvalidate;
 use super::api::step_list::cmd_step_list;
 use super::api::step_remove::cmd_step_remove;
 use super::util::step_name_completer;
+use super::{XvcStepInvalidate, XvcStepTimeout};
 
 /// Step creation, dependency, output commands
 #[derive(Debug, Clone, Parser)]
@@ -58,6 +58,11 @@ pub enum StepSubCommand {
         /// This is used to freeze or invalidate a step manually.
         #[arg(long, add = ArgValueCompleter::new(strum_variants_completer::<XvcStepInvalidate>))]
         when: Option<XvcStepInvalidate>,
+
+        /// Maximum time the command can run, e.g., 90, 90s, 15m or 2h. The command is terminated
+        /// when it's exceeded. Steps without a timeout, or with 0, use `pipeline.default_step_timeout`.
+        #[arg(long)]
+        timeout: Option<XvcStepTimeout>,
     },
 
     /// Remove a step from a pipeline
@@ -83,6 +88,11 @@ pub enum StepSubCommand {
         /// This is used to freeze or invalidate a step manually.
         #[arg(long, add = ArgValueCompleter::new(strum_variants_completer::<XvcStepInvalidate>))]
         when: Option<XvcStepInvalidate>,
+
+        /// Maximum time the command can run, e.g., 90, 90s, 15m or 2h. The command is terminated
+        /// when it's exceeded. 0 removes the step's timeout to use `pipeline.default_step_timeout`.
+        #[arg(long)]
+        timeout: Option<XvcStepTimeout>,
     },
 
     /// Add a dependency to a step
@@ -249,7 +259,15 @@ pub fn handle_step_cli(
             step_name,
             command,
             when: changed,
-        } => cmd_step_new(xvc_root, pipeline_name, step_name, command, changed),
+            timeout,
+        } => cmd_step_new(
+            xvc_root,
+            pipeline_name,
+            step_name,
+            command,
+            changed,
+            timeout,
+        ),
 
         StepSubCommand::Remove { step_name } => {
             cmd_step_remove(output_snd, xvc_root, pipeline_name, step_name)
@@ -259,7 +277,15 @@ pub fn handle_step_cli(
             step_name,
             command,
             when: changed,
-        } => cmd_step_update(xvc_root, pipeline_name, step_name, command, changed),
+            timeout,
+        } => cmd_step_update(
+            xvc_root,
+            pipeline_name,
+            step_name,
+            command,
+            changed,
+            timeout,
+        ),
 
         dep_opts @ StepSubCommand::Dependency { .. } => {
             cmd_step_dependency(output_snd, xvc_root, pipeline_name, dep_opts)
@@ -346,8 +372,11 @@ impl XvcStep {
 //     WaitingToRun --> WaitingToRun: ProcessPoolFull
 //     WaitingToRun --> Running: StartProcess
 //     WaitingToRun --> Broken: CannotStartProcess
+//     WaitingToRun --> Done: DryRun
+//     WaitingToRun --> Done: RestoredFromRunCache
 //     Running --> Running: WaitProcess
 //     Running --> Broken: ProcessTimeout
+//     Running --> Broken: ProcessKilledAfterTimeout
 //     Running --> Done: ProcessCompletedSuccessfully
 //     Running --> Broken: ProcessReturnedNonZero
 //     Broken --> Broken: KeepBroken
@@ -441,6 +470,14 @@ state_machine! {
             WaitingToRun => Broken
         }
 
+        DryRun {
+            WaitingToRun => DoneByRunning
+        }
+
+        RestoredFromRunCache {
+            WaitingToRun => DoneByRunning
+        }
+
         WaitProcess {
             Running => Running
         }
@@ -449,6 +486,10 @@ state_machine! {
             Running => Broken
         }
 
+        ProcessKilledAfterTimeout {
+            Running => Broken
+        }
+
         ProcessCompletedSuccessfully {
             Running => DoneByRunning
         }
@@ -3,6 +3,7 @@ use std::{env, ffi::OsStr};
 use clap_complete::CompletionCandidate;
 use xvc_core::util::completer::load_store_for_completion;
 
+use crate::pipeline::experiment::XvcExperiment;
 use crate::{XvcPipeline, XvcStep, error::Error};
 
 /// Return all pipeline names starting with `prefix`
@@ -25,6 +26,25 @@ pub fn pipeline_name_completer(prefix: &OsStr) -> Vec<CompletionCandidate> {
         .unwrap_or_default()
 }
 
+/// Return all experiment names starting with `prefix`
+pub fn experiment_name_completer(prefix: &OsStr) -> Vec<CompletionCandidate> {
+    let prefix = prefix.to_str().unwrap_or("");
+    env::current_dir()
+        .map_err(Error::from)
+        .map(|current_dir| {
+            load_store_for_completion::<XvcExperiment>(&current_dir)
+                .map(|xvc_experiment_store| {
+                    xvc_experiment_store
+                        .filter(|_, exp| exp.name.starts_with(prefix))
+                        .values()
+                        .map(|exp| exp.name.clone().into())
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .unwrap_or_default()
+}
+
 /// Return all step names starting with `prefix`
 pub fn step_name_completer(prefix: &OsStr) -> Vec<CompletionCandidate> {
     // This must be safe as we don't allow Non-UTF-8 strings for storage identifiers
@@ -0,0 +1,133 @@
+//! An example storage helper that keeps files in a local directory.
+//!
+//! Install it to `PATH` as `xvc-storage-dir` and use it with
+//!
+//! ```shell
+//! xvc storage new helper --name my-storage --helper dir --url /path/to/storage/dir
+//! ```
+//!
+//! See [xvc_storage::storage::helper] for the protocol.
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use xvc_storage::storage::helper::{HelperRequest, HelperResponse};
+
+const GUID_FILENAME: &str = ".xvc-guid";
+
+fn main() -> io::Result<()> {
+    let Some(storage_dir) = std::env::args().nth(1).map(PathBuf::from) else {
+        eprintln!("Usage: xvc-storage-dir STORAGE_DIR");
+        std::process::exit(1);
+    };
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<HelperRequest>(&line) {
+            Ok(request) => handle(&storage_dir, request),
+            Err(e) => HelperResponse::Error {
+                error: format!("Cannot parse request: {e}"),
+            },
+        };
+        writeln!(stdout, "{}", serde_json::to_string(&response)?)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+fn handle(storage_dir: &Path, request: HelperRequest) -> HelperResponse {
+    match request {
+        HelperRequest::Capabilities => HelperResponse::Capabilities {
+            capabilities: ["init", "list", "send", "receive", "delete"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+        },
+        HelperRequest::Init { guid } => {
+            let guid_path = storage_dir.join(GUID_FILENAME);
+            let result = fs::create_dir_all(storage_dir).and_then(|_| {
+                if guid_path.exists() {
+                    fs::read_to_string(&guid_path).map(|g| g.trim().to_string())
+                } else {
+                    fs::write(&guid_path, &guid).map(|_| guid)
+                }
+            });
+            match result {
+                Ok(guid) => HelperResponse::Init { guid },
+                Err(e) => HelperResponse::Error {
+                    error: e.to_string(),
+                },
+            }
+        }
+        HelperRequest::List { repo_guid } => {
+            let mut paths = Vec::new();
+            list_files(storage_dir, &storage_dir.join(&repo_guid), &mut paths);
+            paths.sort();
+            HelperResponse::Paths {
+                paths,
+                errors: vec![],
+            }
+        }
+        HelperRequest::Send { files, force } => batch(files.into_iter().map(|f| {
+            let target = storage_dir.join(&f.storage_path);
+            if target.exists() && !force {
+                return Ok(f.storage_path);
+            }
+            copy(&f.local_path, &target).map(|_| f.storage_path)
+        })),
+        HelperRequest::Receive { files } => batch(files.into_iter().map(|f| {
+            copy(&storage_dir.join(&f.storage_path), &f.local_path).map(|_| f.storage_path)
+        })),
+        HelperRequest::Delete { storage_paths } => batch(
+            storage_paths
+                .into_iter()
+                .map(|sp| fs::remove_file(storage_dir.join(&sp)).map(|_| sp)),
+        ),
+        HelperRequest::Share { .. } => HelperResponse::Error {
+            error: "share is not supported".to_string(),
+        },
+    }
+}
+
+fn batch(results: impl Iterator<Item = io::Result<String>>) -> HelperResponse {
+    let mut paths = Vec::new();
+    let mut errors = Vec::new();
+    for result in results {
+        match result {
+            Ok(path) => paths.push(path),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    HelperResponse::Paths { paths, errors }
+}
+
+fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to).map(|_| ())
+}
+
+fn list_files(root: &Path, dir: &Path, paths: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            list_files(root, &path, paths);
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            let rel = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            paths.push(rel);
+        }
+    }
+}
@@ -6,6 +6,7 @@ use std::io;
 use thiserror::Error as ThisError;
 
 use crate::StorageIdentifier;
+use crate::storage::XvcStorageDeleteEvent;
 
 /// Error messages for xvc-storage
 #[derive(ThisError, Debug)]
@@ -69,6 +70,9 @@ pub enum Error {
     #[error("Process Error.\nSTDOUT:\n{stdout}\nSTDERR:\n{stderr}")]
     ProcessError { stdout: String, stderr: String },
 
+    #[error("Transfer of {path} stopped unexpectedly")]
+    TransferWorkerPanicked { path: String },
+
     #[error("Cannot Find Executable: {source}")]
     WhichError {
         #[from]
@@ -76,13 +80,16 @@ pub enum Error {
     },
 
     #[error(
-        "Cloud credentials for storage '{storage_name}' not found. Please set ONE of the following pairs of environment variables: {var_pairs:?}"
+        "Credentials for storage '{storage_name}' not found. Tried the following sources: {sources:#?}"
     )]
     CloudCredentialsNotFound {
         storage_name: String,
-        var_pairs: Vec<(String, String)>,
+        sources: Vec<String>,
     },
 
+    #[error("Cannot read credentials file {path}: {message}")]
+    CredentialsFileError { path: String, message: String },
+
     #[cfg(any(feature = "s3", feature = "minio"))]
     #[error("Cloud Credentials Error: {source}")]
     CloudCredentialsError {
@@ -105,16 +112,8 @@ pub enum Error {
     #[error("This storage type does not support file sharing with signed URLs")]
     StorageDoesNotSupportSignedUrls,
 
-    #[error(
-        "Access token for storage '{storage_name}' not found. Please set one of the following environment variables: {vars:?}"
-    )]
-    CloudTokenNotFound {
-        storage_name: String,
-        vars: Vec<String>,
-    },
-
-    #[cfg(feature = "dropbox")]
-    #[error("Dropbox HTTP Error: {source}")]
+    #[cfg(any(feature = "dropbox", feature = "azure", feature = "http"))]
+    #[error("HTTP Error: {source}")]
     ReqwestError {
         #[from]
         source: reqwest::Error,
@@ -124,6 +123,75 @@ pub enum Error {
     #[error("Dropbox API Error: {0}")]
     DropboxApiError(String),
 
+    #[cfg(feature = "azure")]
+    #[error("Azure API Error: {0}")]
+    AzureApiError(String),
+
+    #[cfg(feature = "azure")]
+    #[error("Azure API Error: {method} {path}: {status}: {message}")]
+    AzureResponseError {
+        method: String,
+        path: String,
+        status: u16,
+        error_code: Option<String>,
+        message: String,
+    },
+
+    #[cfg(feature = "azure")]
+    #[error(
+        "Azure storage '{storage_name}' requires an account key in the connection string to create shared access signatures"
+    )]
+    AzureAccountKeyRequired { storage_name: String },
+
+    #[error("Received file {cache_path} doesn't match its digest. Its digest is {actual}")]
+    DigestMismatch { cache_path: String, actual: String },
+
+    #[error("Temporary HTTP Error {status}: {message}")]
+    TransientHttpError { status: u16, message: String },
+
+    #[error(
+        "Invalid transfer rate '{value}'. Use a number of bytes per second with an optional K, M or G suffix, e.g., 10M"
+    )]
+    InvalidTransferRate { value: String },
+
+    #[error(
+        "Invalid storage limit '{limit}'. Use <storage name>.<max_bandwidth|max_concurrency>=<value>"
+    )]
+    InvalidStorageLimit { limit: String },
+
+    #[error("Storage '{storage_name}' is read-only")]
+    StorageIsReadOnly { storage_name: String },
+
+    #[error("This storage type does not support index manifests")]
+    StorageIndexNotSupported,
+
+    #[error("Storage index was modified by another process")]
+    StorageIndexConflict,
+
+    #[error("Storage helper {helper} doesn't support {command}")]
+    HelperDoesNotSupport { helper: String, command: String },
+
+    #[error("Storage helper {helper} error: {message}")]
+    HelperError { helper: String, message: String },
+
+    #[error("Storage helper {helper} protocol error: {message}")]
+    HelperProtocolError { helper: String, message: String },
+
+    #[error("Storage backend {tag} is not available in this binary")]
+    UnknownStorageBackend { tag: String },
+
+    #[error("Cannot delete {} files: {errors:#?}", errors.len())]
+    PartialDelete {
+        deleted: XvcStorageDeleteEvent,
+        errors: Vec<String>,
+    },
+
+    #[error("Command Line Error: {source}")]
+    ClapError {
+        #[from]
+        source: clap::Error,
+    },
+
     #[error("JSON Error: {source}")]
     SerdeJsonError {
         #[from]
@@ -144,6 +212,45 @@ where
 }
 
 impl Error {
+    /// Whether the operation may succeed when tried again, e.g., after a dropped connection, a
+    /// timeout, throttling (HTTP 429) or a server error (HTTP 5xx).
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::TransientHttpError { .. } => true,
+            Error::IoError { source } => matches!(
+                source.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            #[cfg(any(feature = "s3", feature = "minio"))]
+            Error::S3Error { source } => match source {
+                s3::error::S3Error::HttpFailWithBody(status, _) => {
+                    *status == 408 || *status == 429 || *status >= 500
+                }
+                s3::error::S3Error::HttpFail
+                | s3::error::S3Error::Io(_)
+                | s3::error::S3Error::Reqwest(_) => true,
+                _ => false,
+            },
+            #[cfg(any(feature = "dropbox", feature = "azure", feature = "http"))]
+            Error::ReqwestError { source } => {
+                source.is_timeout()
+                    || source.is_connect()
+                    || source.is_request()
+                    || source.is_body()
+                    || source
+                        .status()
+                        .is_some_and(|s| s.as_u16() == 429 || s.is_server_error())
+            }
+            _ => false,
+        }
+    }
+
     /// Write error message to stderr using [log::debug] and return the error
     pub fn debug(self) -> Self {
         debug!("{}", self);
@@ -12,10 +12,13 @@ use std::path::PathBuf;
 use std::str::FromStr;
 
 pub use crate::error::{Error, Result};
-use clap::{Parser, Subcommand};
+use clap::{ArgMatches, Command, FromArgMatches, Parser, Subcommand};
 
 use clap_complete::ArgValueCompleter;
 use derive_more::Display;
+use storage::index::{XvcStorageIndex, local_cache_paths, read_index, replace_index};
+use storage::pack::XvcPackedFiles;
+use storage::registry::XvcStorageRegistry;
 pub use storage::{
     XvcLocalStorage, XvcStorage, XvcStorageEvent, XvcStorageGuid, XvcStorageOperations,
 };
@@ -56,300 +59,153 @@ pub enum StorageSubCommand {
     /// Configure a new storage
     #[command(subcommand, visible_aliases=&["n"])]
     New(StorageNewSubCommand),
-}
 
-/// Add a new storage
-#[derive(Debug, Clone, Subcommand)]
-#[command()]
-pub enum StorageNewSubCommand {
-    /// Add a new local storage
+    /// Check the credentials and the connection of a storage.
     ///
-    /// A local storage is a directory accessible from the local file system.
-    /// Xvc will use common file operations for this directory without accessing the network.
+    /// Reports where the credentials are found (environment variables, credentials file,
+    /// credential helper) and lists the files in the storage.
     #[command()]
-    Local {
-        /// Directory (outside the repository) to be set as a storage
-        #[arg(long, value_hint=clap::ValueHint::DirPath)]
-        path: PathBuf,
-
-        /// Name of the storage.
-        ///
-        /// Recommended to keep this name unique to refer easily.
-        #[arg(long, short)]
+    Test {
+        /// Name or GUID of the storage to be tested
+        #[arg(short, long, add = ArgValueCompleter::new(storage_identifier_completer))]
         name: String,
     },
 
-    /// Add a new generic storage.
+    /// Rebuild the index of a storage from a listing of its files.
     ///
-    /// ⚠️ Please note that this is an advanced method to configure storages.
-    /// You may damage your repository and local and storage files with incorrect configurations.
-    ///
-    /// Please see https://docs.xvc.dev/ref/xvc-storage-new-generic.html for examples and make
-    /// necessary backups.
+    /// Storages with an index use it instead of listing the files in `xvc storage status` and
+    /// `xvc file send`. The index is updated when files are sent or deleted. Run this to create
+    /// the index the first time, or when it gets out of sync.
     #[command()]
-    Generic {
-        /// Name of the storage.
-        ///
-        /// Recommended to keep this name unique to refer easily.
-        #[arg(long = "name", short = 'n')]
+    Reindex {
+        /// Name or GUID of the storage to be reindexed
+        #[arg(short, long, add = ArgValueCompleter::new(storage_identifier_completer))]
         name: String,
-        /// Command to initialize the storage.
-        /// This command is run once after defining the storage.
-        ///
-        /// You can use {URL} and {STORAGE_DIR}  as shortcuts.
-        #[arg(long = "init", short = 'i', value_hint=clap::ValueHint::CommandString)]
-        init_command: String,
-        /// Command to list the files in storage
-        ///
-        /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
-        #[arg(long = "list", short = 'l', value_hint=clap::ValueHint::CommandString)]
-        list_command: String,
-        /// Command to download a file from storage.
-        ///
-        /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
-        #[arg(long = "download", short = 'd', value_hint=clap::ValueHint::CommandString)]
-        download_command: String,
-        /// Command to upload a file to storage.
-        ///
-        /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
-        #[arg(long = "upload", short = 'u',value_hint=clap::ValueHint::CommandString )]
-        upload_command: String,
-        /// The delete command to remove a file from storage
-        /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
-        #[arg(long = "delete", short = 'D',value_hint=clap::ValueHint::CommandString )]
-        delete_command: String,
-        /// Number of maximum processes to run simultaneously
-        #[arg(long = "processes", short = 'M', default_value_t = 1)]
-        max_processes: usize,
-        /// You can set a string to replace {URL} placeholder in commands
-        #[arg(long, value_hint=clap::ValueHint::Url)]
-        url: Option<String>,
-        /// You can set a string to replace {STORAGE_DIR} placeholder in commands
-        #[arg(long)]
-        storage_dir: Option<String>,
     },
 
-    /// Add a new rsync storages
+    /// Compare the files in the local cache with the files in a storage.
     ///
-    /// Uses rsync in separate processes to communicate.
-    /// This can be used when you already have an SSH/Rsync connection.
-    /// It doesn't prompt for any passwords. The connection must be set up with ssh keys beforehand.
+    /// Uses the index of the storage if it has one, otherwise lists the files in the storage.
     #[command()]
-    Rsync {
-        /// Name of the storage.
-        ///
-        /// Recommended to keep this name unique to refer easily.
-        #[arg(long = "name", short = 'n')]
+    Status {
+        /// Name or GUID of the storage
+        #[arg(short, long, add = ArgValueCompleter::new(storage_identifier_completer))]
         name: String,
-        /// Hostname for the connection in the form host.example.com  (without @, : or protocol)
-        #[arg(long, value_hint=clap::ValueHint::Hostname)]
-        host: String,
-        /// Port number for the connection in the form 22.
-        /// Doesn't add port number to connection string if not given.
-        #[arg(long)]
-        port: Option<usize>,
-        /// User name for the connection, the part before @ in user@example.com (without @,
-        /// hostname).
-        /// User name isn't included in connection strings if not given.
-        #[arg(long, value_hint=clap::ValueHint::Username)]
-        user: Option<String>,
-        /// storage directory in the host to store the files.
-        #[arg(long)]
-        storage_dir: String,
     },
 
-    #[cfg(feature = "rclone")]
-    /// Add a new rclone storage
+    /// Delete the files in a storage that are not referenced by Git refs.
     ///
-    /// Uses the rclone configuration to connect to the storage. The remotestorage must already be
-    /// configure with `rclone config`.
+    /// Files referenced by the current Xvc store are always kept. Packs are kept if any of their
+    /// files is referenced.
     #[command()]
-    Rclone {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
+    Gc {
+        /// Name or GUID of the storage
+        #[arg(short, long, add = ArgValueCompleter::new(storage_identifier_completer))]
         name: String,
 
-        /// The name of the remote in rclone configuration
-        ///
-        /// This is the "remote" part in "remote://dir/" URL.
-        #[arg(long)]
-        remote_name: String,
+        /// Git refs (branches, tags, commits) whose files are kept. All refs are used by default.
+        #[arg(long = "ref", value_delimiter = ',')]
+        refs: Vec<String>,
 
-        /// The directory in the remote to store the files.
-        ///
-        /// This is the "dir" part in "remote://dir/" URL.
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
-    },
+        /// Also keep the last N versions of each file in the history of each ref
+        #[arg(long, default_value_t = 0)]
+        keep_last: usize,
 
-    #[cfg(feature = "s3")]
-    /// Add a new S3 storage
-    ///
-    /// Reads credentials from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
-    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
-    #[command()]
-    S3 {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// You can set a directory in the bucket with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
-        /// S3 bucket name
+        /// Only list the files that would be deleted
         #[arg(long)]
-        bucket_name: String,
-        /// AWS region
-        #[arg(long)]
-        region: String,
+        dry_run: bool,
     },
 
-    #[cfg(feature = "minio")]
-    /// Add a new Minio storage
+    /// Report the space used by the files of Git refs in storages.
     ///
-    /// Reads credentials from `MINIO_ACCESS_KEY` and `MINIO_SECRET_ACCESS_KEY` environment variables.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
-    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+    /// Joins the listing (or the index) of each storage with the file sizes recorded at each ref,
+    /// and reports the bytes per storage, per top level directory and per ref. Files referred by
+    /// more than one ref are counted once and reported as shared.
     #[command()]
-    Minio {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// Minio server url in the form https://myserver.example.com:9090
-        #[arg(long, value_hint=clap::ValueHint::Url)]
-        endpoint: String,
-        /// Bucket name
-        #[arg(long)]
-        bucket_name: String,
-        /// Region of the server
-        #[arg(long)]
-        region: String,
-        /// You can set a directory in the bucket with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
-    },
+    Usage {
+        /// Name or GUID of the storage. All storages are reported by default.
+        #[arg(short, long, add = ArgValueCompleter::new(storage_identifier_completer))]
+        name: Option<String>,
 
-    #[cfg(feature = "digital-ocean")]
-    /// Add a new Digital Ocean storage
-    ///
-    /// Reads credentials from `DIGITAL_OCEAN_ACCESS_KEY_ID` and `DIGITAL_OCEAN_SECRET_ACCESS_KEY` environment variables.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
-    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
-    #[command()]
-    DigitalOcean {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// Bucket name
-        #[arg(long)]
-        bucket_name: String,
-        /// Region of the server
-        #[arg(long)]
-        region: String,
-        /// You can set a directory in the bucket with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
-    },
+        /// Git refs (branches, tags, commits) to report. All refs are used by default.
+        #[arg(long = "ref", value_delimiter = ',')]
+        refs: Vec<String>,
 
-    #[cfg(feature = "r2")]
-    /// Add a new R2 storage
-    ///
-    /// Reads credentials from `R2_ACCESS_KEY_ID` and `R2_SECRET_ACCESS_KEY` environment variables.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
-    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
-    #[command()]
-    R2 {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// R2 account ID
+        /// Print the report as JSON
         #[arg(long)]
-        account_id: String,
-        /// Bucket name
-        #[arg(long)]
-        bucket_name: String,
-        /// You can set a directory in the bucket with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
+        json: bool,
     },
 
-    #[cfg(feature = "gcs")]
-    /// Add a new Google Cloud Storage storage
+    #[cfg(feature = "http")]
+    /// Export the cached files to a directory that can be served by a static web server.
     ///
-    /// Reads credentials from `GCS_ACCESS_KEY_ID` and `GCS_SECRET_ACCESS_KEY` environment variables.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
-    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+    /// Writes the files in `{dir}/{repo_guid}/{cache_path}` layout, along with `.xvc-guid` and
+    /// `{repo_guid}/.xvc-index` files. The directory can then be used with `xvc storage new http`.
     #[command()]
-    Gcs {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// Bucket name
-        #[arg(long)]
-        bucket_name: String,
-        /// Region of the server, e.g., europe-west3
-        #[arg(long)]
-        region: String,
-        /// You can set a directory in the bucket with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
+    ExportStatic {
+        /// Directory to write the files
+        #[arg(value_hint=clap::ValueHint::DirPath)]
+        dir: PathBuf,
     },
+}
 
-    #[cfg(feature = "wasabi")]
-    /// Add a new Wasabi storage
-    ///
-    /// Reads credentials from `WASABI_ACCESS_KEY_ID` and `WASABI_SECRET_ACCESS_KEY` environment variables.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
-    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
-    #[command()]
-    Wasabi {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// Bucket name
-        #[arg(long)]
-        bucket_name: String,
-        /// Endpoint for the server, complete with the region if there is
-        ///
-        /// e.g. for eu-central-1 region, use s3.eu-central-1.wasabisys.com as the endpoint.
-        #[arg(long, default_value = "s3.wasabisys.com")]
-        endpoint: String,
-        /// You can set a directory in the bucket with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
-    },
+/// Add a new storage
+///
+/// The subcommands are built from the [built-in storage backends][storage::registry]. Each
+/// backend defines its own arguments. The parsed arguments are kept in [Self::matches] and
+/// passed to the backend when the command runs. Other subcommands are kept as external
+/// subcommands and parsed with the registry of the repository.
+#[derive(Debug, Clone)]
+pub struct StorageNewSubCommand {
+    /// The subcommand name of the storage backend, e.g. `local`
+    pub backend: String,
+    /// Arguments of the subcommand
+    pub matches: ArgMatches,
+}
 
-    #[cfg(feature = "dropbox")]
-    /// Add a new Dropbox storage
-    ///
-    /// Reads credentials from `DROPBOX_ACCESS_TOKEN` environment variable.
-    /// Alternatively you can use `XVC_STORAGE_ACCESS_TOKEN_<storage_name>` environment variable
-    /// if you have multiple storages of this type.
-    #[command()]
-    Dropbox {
-        /// Name of the storage
-        ///
-        /// This must be unique among all storages of the project
-        #[arg(long = "name", short = 'n')]
-        name: String,
-        /// You can set a directory in Dropbox with this prefix
-        #[arg(long, default_value = "")]
-        storage_prefix: String,
-    },
+impl FromArgMatches for StorageNewSubCommand {
+    fn from_arg_matches(matches: &ArgMatches) -> std::result::Result<Self, clap::Error> {
+        match matches.subcommand() {
+            Some((name, sub_matches)) => Ok(Self {
+                backend: name.to_string(),
+                matches: sub_matches.clone(),
+            }),
+            None => Err(clap::Error::raw(
+                clap::error::ErrorKind::MissingSubcommand,
+                "A storage type is required",
+            )),
+        }
+    }
+
+    fn update_from_arg_matches(
+        &mut self,
+        matches: &ArgMatches,
+    ) -> std::result::Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Subcommand for StorageNewSubCommand {
+    fn augment_subcommands(cmd: Command) -> Command {
+        XvcStorageRegistry::default()
+            .backends()
+            .iter()
+            .fold(cmd, |cmd, registration| {
+                cmd.subcommand((registration.command)())
+            })
+            .subcommand_required(true)
+            .allow_external_subcommands(true)
+    }
+
+    fn augment_subcommands_for_update(cmd: Command) -> Command {
+        Self::augment_subcommands(cmd)
+    }
+
+    fn has_subcommand(_name: &str) -> bool {
+        // Backends that are not built-in are found in the registry when the command runs
+        true
+    }
 }
 
 /// Specifies a storage by either a name or a GUID.
@@ -397,169 +253,70 @@ pub fn cmd_storage(
         StorageSubCommand::List => cmd_storage_list(input, output_snd, xvc_root),
         StorageSubCommand::Remove { name } => cmd_storage_remove(input, output_snd, xvc_root, name),
         StorageSubCommand::New(new) => cmd_storage_new(input, output_snd, xvc_root, new),
-    }
-}
-
-/// Configure a new storage.
-///
-/// The available storages and their configuration is dependent to compilation.
-/// In minimum, it includes [local][cmd_storage_new_local] and
-/// [generic][cmd_storage_new_generic].
-///
-/// This function matches [StorageNewSubCommand] and calls the appropriate
-/// function from child modules. Most of the available options are behind
-/// feature flags, that also guard the modules.
-fn cmd_storage_new(
-    input: std::io::StdinLock,
-    output_snd: &XvcOutputSender,
-    xvc_root: &XvcRoot,
-    sc: StorageNewSubCommand,
-) -> Result<()> {
-    match sc {
-        StorageNewSubCommand::Local { path, name } => {
-            storage::local::cmd_storage_new_local(input, output_snd, xvc_root, path, name)
+        StorageSubCommand::Test { name } => cmd_storage_test(input, output_snd, xvc_root, name),
+        StorageSubCommand::Reindex { name } => {
+            cmd_storage_reindex(input, output_snd, xvc_root, name)
         }
-        StorageNewSubCommand::Generic {
+        StorageSubCommand::Status { name } => cmd_storage_status(input, output_snd, xvc_root, name),
+        StorageSubCommand::Gc {
             name,
-            init_command,
-            list_command,
-            download_command,
-            upload_command,
-            delete_command,
-            max_processes,
-            url,
-            storage_dir,
-        } => storage::generic::cmd_storage_new_generic(
+            refs,
+            keep_last,
+            dry_run,
+        } => storage::gc::cmd_storage_gc(
             input,
             output_snd,
             xvc_root,
-            name,
-            url,
-            storage_dir,
-            max_processes,
-            init_command,
-            list_command,
-            download_command,
-            upload_command,
-            delete_command,
+            StorageIdentifier::from_str(&name)?,
+            refs,
+            keep_last,
+            dry_run,
         ),
-
-        #[cfg(feature = "rclone")]
-        StorageNewSubCommand::Rclone {
-            name,
-            remote_name,
-            storage_prefix,
-        } => {
-            storage::rclone::cmd_new_rclone(output_snd, xvc_root, name, remote_name, storage_prefix)
-        }
-
-        #[cfg(feature = "s3")]
-        StorageNewSubCommand::S3 {
-            name,
-            storage_prefix,
-            bucket_name,
-            region,
-        } => storage::s3::cmd_new_s3(
-            output_snd,
-            xvc_root,
-            name,
-            region,
-            bucket_name,
-            storage_prefix,
-        ),
-        #[cfg(feature = "minio")]
-        StorageNewSubCommand::Minio {
-            name,
-            endpoint,
-            bucket_name,
-            storage_prefix,
-            region,
-        } => storage::minio::cmd_new_minio(
-            input,
-            output_snd,
-            xvc_root,
-            name,
-            endpoint,
-            bucket_name,
-            region,
-            storage_prefix,
-        ),
-        #[cfg(feature = "digital-ocean")]
-        StorageNewSubCommand::DigitalOcean {
-            name,
-            bucket_name,
-            region,
-            storage_prefix,
-        } => storage::digital_ocean::cmd_new_digital_ocean(
-            input,
-            output_snd,
-            xvc_root,
-            name,
-            bucket_name,
-            region,
-            storage_prefix,
-        ),
-        #[cfg(feature = "r2")]
-        StorageNewSubCommand::R2 {
-            name,
-            account_id,
-            bucket_name,
-            storage_prefix,
-        } => storage::r2::cmd_new_r2(
+        StorageSubCommand::Usage { name, refs, json } => storage::usage::cmd_storage_usage(
             input,
             output_snd,
             xvc_root,
-            name,
-            account_id,
-            bucket_name,
-            storage_prefix,
+            name.as_deref()
+                .map(StorageIdentifier::from_str)
+                .transpose()?,
+            refs,
+            json,
         ),
-        #[cfg(feature = "gcs")]
-        StorageNewSubCommand::Gcs {
-            name,
-            bucket_name,
-            region,
-            storage_prefix,
-        } => storage::gcs::cmd_new_gcs(
-            input,
-            output_snd,
-            xvc_root,
-            name,
-            bucket_name,
-            region,
-            storage_prefix,
-        ),
-        #[cfg(feature = "wasabi")]
-        StorageNewSubCommand::Wasabi {
-            name,
-            bucket_name,
-            endpoint,
-            storage_prefix,
-        } => storage::wasabi::cmd_new_wasabi(
-            output_snd,
-            xvc_root,
-            name,
-            bucket_name,
-            endpoint,
-            storage_prefix,
-        ),
-        StorageNewSubCommand::Rsync {
-            name,
-            host,
-            port,
-            user,
-            storage_dir,
-        } => {
-            storage::rsync::cmd_new_rsync(output_snd, xvc_root, name, host, port, user, storage_dir)
+        #[cfg(feature = "http")]
+        StorageSubCommand::ExportStatic { dir } => {
+            storage::http::cmd_export_static(input, output_snd, xvc_root, dir)
         }
-        #[cfg(feature = "dropbox")]
-        StorageNewSubCommand::Dropbox {
-            name,
-            storage_prefix,
-        } => storage::dropbox::cmd_new_dropbox(input, output_snd, xvc_root, name, storage_prefix),
     }
 }
 
+/// Configure a new storage.
+///
+/// The available storages and their configuration is dependent to compilation and the
+/// [registered backends][storage::registry]. In minimum, it includes
+/// [local][storage::local::cmd_storage_new_local] and
+/// [generic][storage::generic::cmd_storage_new_generic].
+///
+/// This function finds the backend of [StorageNewSubCommand] in the registry of `xvc_root` and
+/// runs it. Arguments of external subcommands are parsed with the backend's command.
+fn cmd_storage_new(
+    input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    sc: StorageNewSubCommand,
+) -> Result<()> {
+    let registration = XvcStorageRegistry::of(xvc_root)
+        .find_by_subcommand(&sc.backend)
+        .ok_or_else(|| Error::UnknownStorageBackend {
+            tag: sc.backend.clone(),
+        })?;
+    let matches = match sc.matches.try_get_many::<String>("").ok().flatten() {
+        Some(args) => (registration.command)()
+            .try_get_matches_from(std::iter::once(&sc.backend).chain(args))?,
+        None => sc.matches,
+    };
+    (registration.run_new)(input, output_snd, xvc_root, &matches)
+}
+
 /// Removes a storage from the configurations.
 ///
 /// This doesn't remove the history associated with them.
@@ -584,6 +341,102 @@ fn cmd_storage_remove(
     Ok(())
 }
 
+/// Checks whether a storage can be used.
+///
+/// Prints the [source][storage::credentials::XvcCredentialSource] of the credentials and the
+/// number of files listed in the storage.
+fn cmd_storage_test(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    identifier: String,
+) -> Result<()> {
+    let identifier = StorageIdentifier::from_str(&identifier)?;
+    let storage = get_storage_record(output_snd, xvc_root, &identifier)?;
+    match storage.credential_source(xvc_root)? {
+        Some(source) => output!(output_snd, "Credentials: {source}"),
+        None => output!(output_snd, "Credentials: not required"),
+    }
+    let list_event = storage.list(output_snd, xvc_root)?;
+    output!(
+        output_snd,
+        "Connection: OK ({} files)",
+        list_event.paths.len()
+    );
+    Ok(())
+}
+
+/// Rebuilds the [index][storage::index] of a storage.
+///
+/// Lists the files with the storage backend, bypassing the current index, and replaces the index
+/// with the result.
+fn cmd_storage_reindex(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    identifier: String,
+) -> Result<()> {
+    let identifier = StorageIdentifier::from_str(&identifier)?;
+    let storage = get_storage_record(output_snd, xvc_root, &identifier)?;
+    let list_event = storage.as_dyn().list(output_snd, xvc_root)?;
+    let index = XvcStorageIndex::from_storage_paths(&list_event.paths);
+    replace_index(&storage, output_snd, xvc_root, &index)?;
+    output!(
+        output_snd,
+        "Indexed {} files in {}",
+        index.len(),
+        storage.name()
+    );
+    Ok(())
+}
+
+/// Compares the files in the local cache with the files in a storage.
+///
+/// Prints the number of files in both and lists the local files missing in the storage.
+fn cmd_storage_status(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    identifier: String,
+) -> Result<()> {
+    let identifier = StorageIdentifier::from_str(&identifier)?;
+    let storage = get_storage_record(output_snd, xvc_root, &identifier)?;
+    let index = match read_index(&storage, output_snd, xvc_root)? {
+        Some((index, _)) => {
+            output!(output_snd, "Index: {} files", index.len());
+            index
+        }
+        None => {
+            let list_event = storage.as_dyn().list(output_snd, xvc_root)?;
+            let index = XvcStorageIndex::from_storage_paths(&list_event.paths);
+            output!(output_snd, "Listed: {} files (no index)", index.len());
+            index
+        }
+    };
+
+    let packed_files = XvcPackedFiles::load_listed(
+        &storage,
+        output_snd,
+        xvc_root,
+        &index.storage_paths(xvc_root),
+    )?;
+    if !packed_files.is_empty() {
+        output!(output_snd, "Packed: {} files", packed_files.len());
+    }
+
+    let local_paths = local_cache_paths(xvc_root)?;
+    let missing = local_paths
+        .iter()
+        .filter(|cp| !index.contains(cp) && !packed_files.contains(cp))
+        .collect::<Vec<_>>();
+    output!(output_snd, "Local cache: {} files", local_paths.len());
+    output!(output_snd, "Not in storage: {} files", missing.len());
+    for cp in missing {
+        output!(output_snd, "[MISSING] {cp}");
+    }
+    Ok(())
+}
+
 /// Lists all available storages.
 ///
 /// It runs [XvcStorage::display] and lists all elements line by line to
@@ -593,10 +446,11 @@ fn cmd_storage_list(
     output_snd: &XvcOutputSender,
     xvc_root: &XvcRoot,
 ) -> Result<()> {
+    let registry = XvcStorageRegistry::of(xvc_root);
     let store: XvcStore<XvcStorage> = xvc_root.load_store()?;
 
     for (_, s) in store.iter() {
-        output!(output_snd, "{}\n", s);
+        output!(output_snd, "{}\n", registry.resolve(s.clone())?);
     }
 
     Ok(())
@@ -1,13 +1,21 @@
 //! Home for async operations for S3 compatible storage services.
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
 use std::str::FromStr;
 
 use futures::StreamExt;
+use http_types::HeaderMap;
+use http_types::HeaderValue;
+use http_types::header::{IF_MATCH, IF_NONE_MATCH};
 use regex::Regex;
 use s3::Bucket;
 use s3::Region;
 use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::serde_types::Part;
 use tokio::io::AsyncWriteExt;
+use xvc_core::AbsolutePath;
 use xvc_core::XvcCachePath;
 use xvc_core::XvcOutputSender;
 use xvc_core::XvcRoot;
@@ -29,6 +37,21 @@ use super::XvcStoragePath;
 use super::XvcStorageReceiveEvent;
 use super::XvcStorageSendEvent;
 use super::XvcStorageTempDir;
+use super::credentials::{
+    ACCESS_KEY_ID, SECRET_ACCESS_KEY, XvcCredentialResolver, XvcCredentialSource, XvcCredentialSpec,
+};
+use super::index::{RUN_CACHE_ENTRY_PATTERN, XVC_STORAGE_INDEX_FILENAME, XvcStorageIndexObject};
+use super::journal::{XvcMultipartUpload, XvcTransferJournal};
+use super::limit::{XvcRateLimiter, XvcTransferLimits, receive_progress, send_progress};
+use super::retry::XvcRetryPolicy;
+use super::verify::verify_digest;
+
+/// Minimum part size of multipart uploads, except the last part
+pub const MIN_MULTIPART_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// Maximum number of parts in a multipart upload
+pub const MAX_MULTIPART_PARTS: u64 = 10_000;
+/// Content type of the multipart uploads
+const MULTIPART_CONTENT_TYPE: &str = "application/octet-stream";
 
 /// Operations for S3 compatible storage services. Each service implements functions in this trait
 /// for xvc file send and xvc file bring commands to work with the common functions.
@@ -38,10 +61,23 @@ pub(crate) trait XvcS3StorageOperations {
     fn storage_prefix(&self) -> String;
     /// GUID for the storage. This is generated when the storage is first initialized.
     fn guid(&self) -> &XvcStorageGuid;
+    /// Name of the storage to find its [limits][XvcTransferLimits]
+    fn storage_name(&self) -> &str;
     /// Get the bucket for the storage
-    fn get_bucket(&self) -> Result<Box<Bucket>>;
-    /// Get the credentials for the
-    fn credentials(&self) -> Result<Credentials>;
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>>;
+    /// Describe the credentials of the storage for [XvcCredentialResolver]
+    fn credential_spec(&self) -> XvcCredentialSpec;
+    /// Get the credentials for the storage from the sources in [XvcCredentialResolver]
+    fn credentials(&self, xvc_root: &XvcRoot) -> Result<Credentials> {
+        let credentials = XvcCredentialResolver::new(xvc_root).resolve(&self.credential_spec())?;
+        Ok(Credentials::new(
+            credentials.get(ACCESS_KEY_ID),
+            credentials.get(SECRET_ACCESS_KEY),
+            None,
+            None,
+            None,
+        )?)
+    }
     /// Name of the bucket
     fn bucket_name(&self) -> String;
     /// Build the storage path for the S3 compatible storage
@@ -57,27 +93,49 @@ pub(crate) trait XvcS3StorageOperations {
     /// Region of the bucket
     fn region(&self) -> String;
 
+    /// The key prefix that contains the paths built with [Self::build_storage_path]
+    fn list_prefix(&self) -> String {
+        format!("{}/{}/", self.storage_prefix(), self.guid())
+    }
+
+    /// Matches the cache files and run cache entries under [Self::list_prefix]
+    fn list_regex(&self) -> Regex {
+        Regex::new(&format!(
+            "^{prefix}(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
+            prefix = regex::escape(&self.list_prefix()),
+            cp = r#"[a-zA-Z][0-9]"#,
+            d3 = r#"[0-9A-Fa-f]{3}"#,
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
+        ))
+        .unwrap()
+    }
+
     /// Write GUID to the storage when first initializing the storage
-    async fn write_storage_guid(&self) -> Result<()> {
+    async fn write_storage_guid(
+        &self,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<()> {
         let guid_str = self.guid().to_string();
         let guid_bytes = guid_str.as_bytes();
-        let bucket = self.get_bucket()?;
-        let response = bucket
-            .put_object(
-                format!("{}/{}", self.storage_prefix(), XVC_STORAGE_GUID_FILENAME),
-                guid_bytes,
-            )
-            .await;
-
-        match response {
-            Ok(_) => Ok(()),
-            Err(err) => Err(Error::S3Error { source: err }),
-        }
+        let bucket = &self.get_bucket(xvc_root)?;
+        let guid_path = &format!("{}/{}", self.storage_prefix(), XVC_STORAGE_GUID_FILENAME);
+        XvcRetryPolicy::default()
+            .a_retry(output_snd, guid_path, || async move {
+                bucket.put_object(guid_path, guid_bytes).await?;
+                Ok(())
+            })
+            .await
     }
 
     /// Initialze the bucket as Xvc storage by adding a GUID
-    async fn a_init(&mut self, output_snd: &XvcOutputSender) -> Result<XvcStorageInitEvent> {
-        let res_response = self.write_storage_guid().await;
+    async fn a_init(
+        &mut self,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<XvcStorageInitEvent> {
+        let res_response = self.write_storage_guid(output_snd, xvc_root).await;
 
         let guid = self.guid().clone();
 
@@ -96,29 +154,22 @@ pub(crate) trait XvcS3StorageOperations {
         output: &XvcOutputSender,
         xvc_root: &xvc_core::XvcRoot,
     ) -> Result<XvcStorageListEvent> {
-        let credentials = self.credentials()?;
+        let credentials = self.credentials(xvc_root)?;
         let region = Region::from_str(&self.region()).unwrap_or("us-east-1".parse().unwrap());
         let bucket = Bucket::new(&self.bucket_name(), region, credentials)?;
-        let xvc_guid = xvc_root.guid();
-        let prefix = self.storage_prefix().clone();
-
-        let res_list = bucket
-            .list(
-                format!("{}/{}", self.storage_prefix(), xvc_guid),
-                Some("/".to_string()),
-            )
+
+        let bucket = &bucket;
+        let list_prefix = &self.list_prefix();
+        let res_list = XvcRetryPolicy::default()
+            .a_retry(output, list_prefix, || async move {
+                Ok(bucket.list(list_prefix.clone(), None).await?)
+            })
             .await;
 
         match res_list {
             Ok(list_all) => {
                 // select only the matching elements
-                let re = Regex::new(&format!(
-                    "{prefix}/{xvc_guid}/{cp}/{d3}/{d3}/{d58}/0\\..*$",
-                    cp = r#"[a-zA-Z][0-9]"#,
-                    d3 = r#"[0-9A-Fa-f]{3}"#,
-                    d58 = r#"[0-9A-Fa-f]{58}"#
-                ))
-                .unwrap();
+                let re = self.list_regex();
 
                 let paths = list_all
                     .iter()
@@ -139,32 +190,73 @@ pub(crate) trait XvcS3StorageOperations {
 
             Err(err) => {
                 error!(output, "{}", err);
-                Err(Error::S3Error { source: err })
+                Err(err)
             }
         }
     }
 
-    /// Send files to S3 compatible storage
+    /// Send files in `source` to S3 compatible storage
+    ///
+    /// Each file is recorded in the [transfer journal][super::journal] after it's sent. Files
+    /// larger than `storage.multipart_part_size` are streamed in parts with
+    /// [Self::a_send_multipart].
     async fn a_send(
         &self,
         output_snd: &XvcOutputSender,
         xvc_root: &xvc_core::XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[xvc_core::XvcCachePath],
         _force: bool,
     ) -> crate::Result<super::XvcStorageSendEvent> {
         let mut copied_paths = Vec::<XvcStoragePath>::new();
 
-        let bucket = self.get_bucket()?;
+        let bucket = self.get_bucket(xvc_root)?;
+        let mut journal = XvcTransferJournal::load(xvc_root, self.guid())?;
+        let retry = XvcRetryPolicy::default();
+        let part_size = xvc_root
+            .config()
+            .storage
+            .multipart_part_size
+            .max(MIN_MULTIPART_PART_SIZE);
+        let limits = XvcTransferLimits::for_storage(xvc_root, self.storage_name())?;
+        let concurrency = limits.concurrency(xvc_root.config().storage.multipart_concurrency);
+        let limiter = &limits.rate_limiter().with_progress(send_progress(
+            output_snd,
+            source,
+            self.storage_name(),
+            paths,
+        ));
 
         for cache_path in paths {
             let storage_path = self.build_storage_path(cache_path);
-            let abs_cache_path = cache_path.to_absolute_path(xvc_root);
-
-            let mut path = tokio::fs::File::open(&abs_cache_path).await?;
-
-            let res_response = bucket
-                .put_object_stream(&mut path, storage_path.as_str())
-                .await;
+            let abs_cache_path = source.temp_cache_path(cache_path)?;
+            let size = fs::metadata(&abs_cache_path)?.len();
+
+            let res_response = if size > part_size {
+                self.a_send_multipart(
+                    output_snd,
+                    &bucket,
+                    &mut journal,
+                    cache_path,
+                    &abs_cache_path,
+                    &storage_path,
+                    part_size,
+                    concurrency,
+                    limiter,
+                )
+                .await
+            } else {
+                let content = &tokio::fs::read(&abs_cache_path).await?;
+                let bucket = &bucket;
+                let key = storage_path.as_str();
+                retry
+                    .a_retry(output_snd, key, || async move {
+                        limiter.a_throttle(content.len() as u64).await;
+                        bucket.put_object(key, content).await?;
+                        Ok(())
+                    })
+                    .await
+            };
 
             match res_response {
                 Ok(_) => {
@@ -174,6 +266,8 @@ pub(crate) trait XvcS3StorageOperations {
                         abs_cache_path,
                         storage_path.as_str()
                     );
+                    journal.mark_sent(cache_path);
+                    journal.save()?;
                     copied_paths.push(storage_path);
                 }
                 Err(err) => {
@@ -188,37 +282,196 @@ pub(crate) trait XvcS3StorageOperations {
         })
     }
 
+    /// Send a file with a multipart upload.
+    ///
+    /// The file is read and sent in parts of `part_size` bytes, `concurrency` parts at a time, so
+    /// at most `part_size * concurrency` bytes are kept in memory. The part size is increased if
+    /// the file needs more than [MAX_MULTIPART_PARTS] parts. Each part waits for `limiter` before
+    /// it's sent.
+    ///
+    /// The upload id and the ETag of each uploaded part are recorded in the [transfer
+    /// journal][super::journal]. If the upload is interrupted, the next call uploads only the
+    /// remaining parts. If the storage doesn't know the upload anymore, it's started again.
+    #[allow(clippy::too_many_arguments)]
+    async fn a_send_multipart(
+        &self,
+        output_snd: &XvcOutputSender,
+        bucket: &Bucket,
+        journal: &mut XvcTransferJournal,
+        cache_path: &XvcCachePath,
+        abs_cache_path: &AbsolutePath,
+        storage_path: &XvcStoragePath,
+        part_size: u64,
+        concurrency: usize,
+        limiter: &XvcRateLimiter,
+    ) -> Result<()> {
+        let retry = XvcRetryPolicy::default();
+        let key = storage_path.as_str();
+        let size = fs::metadata(abs_cache_path)?.len();
+
+        loop {
+            let resumed = journal.upload(cache_path).is_some();
+            let mut upload = match journal.upload(cache_path) {
+                Some(upload) => {
+                    info!(
+                        output_snd,
+                        "[RESUME] {cache_path}: {}/{} parts already sent",
+                        upload.parts.len(),
+                        size.div_ceil(upload.part_size)
+                    );
+                    upload.clone()
+                }
+                None => {
+                    let response = retry
+                        .a_retry(output_snd, key, || async move {
+                            Ok(bucket
+                                .initiate_multipart_upload(key, MULTIPART_CONTENT_TYPE)
+                                .await?)
+                        })
+                        .await?;
+                    XvcMultipartUpload {
+                        upload_id: response.upload_id,
+                        part_size: part_size.max(size.div_ceil(MAX_MULTIPART_PARTS)),
+                        parts: BTreeMap::new(),
+                    }
+                }
+            };
+            journal.set_upload(cache_path, upload.clone());
+            journal.save()?;
+
+            let part_size = upload.part_size;
+            let n_parts = size.div_ceil(part_size).max(1) as u32;
+            let upload_id = &upload.upload_id.clone();
+            let remaining = (1..=n_parts)
+                .filter(|part_number| !upload.parts.contains_key(part_number))
+                .collect::<Vec<_>>();
+            let mut parts = futures::stream::iter(remaining)
+                .map(|part_number| async move {
+                    let chunk = &read_part(abs_cache_path, part_number, part_size)?;
+                    retry
+                        .a_retry(output_snd, key, || async move {
+                            limiter.a_throttle(chunk.len() as u64).await;
+                            Ok(bucket
+                                .put_multipart_chunk(
+                                    chunk.clone(),
+                                    key,
+                                    part_number,
+                                    upload_id,
+                                    MULTIPART_CONTENT_TYPE,
+                                )
+                                .await?)
+                        })
+                        .await
+                })
+                .buffer_unordered(concurrency.max(1));
+
+            let mut expired = false;
+            while let Some(part) = parts.next().await {
+                match part {
+                    Ok(part) => {
+                        upload.parts.insert(part.part_number, part.etag);
+                        journal.set_upload(cache_path, upload.clone());
+                        journal.save()?;
+                    }
+                    // The resumed upload is expired or aborted
+                    Err(Error::S3Error {
+                        source: S3Error::HttpFailWithBody(404, _),
+                    }) if resumed => {
+                        expired = true;
+                        break;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            drop(parts);
+
+            if expired {
+                info!(output_snd, "[RESTART] {cache_path}: upload is expired");
+                journal.remove_upload(cache_path);
+                journal.save()?;
+                continue;
+            }
+
+            let parts = &upload
+                .parts
+                .iter()
+                .map(|(part_number, etag)| Part {
+                    part_number: *part_number,
+                    etag: etag.clone(),
+                })
+                .collect::<Vec<_>>();
+            retry
+                .a_retry(output_snd, key, || async move {
+                    bucket
+                        .complete_multipart_upload(key, upload_id, parts.clone())
+                        .await?;
+                    Ok(())
+                })
+                .await?;
+            journal.remove_upload(cache_path);
+            journal.save()?;
+            return Ok(());
+        }
+    }
+
     /// Receive files from S3 compatible storage
+    ///
+    /// Files are downloaded to the [receive directory][XvcTransferJournal::receive_dir] of the
+    /// storage. Files received in an interrupted run are not downloaded again, and partially
+    /// downloaded files are resumed with range requests. Objects are streamed to the files and
+    /// each file is [verified][super::verify] against the digest in its cache path.
     async fn a_receive(
         &self,
         output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
         paths: &[xvc_core::XvcCachePath],
         _force: bool,
     ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
         let mut copied_paths = Vec::<XvcStoragePath>::new();
 
-        let bucket = self.get_bucket()?;
-        let temp_dir = XvcStorageTempDir::new()?;
+        let bucket = &self.get_bucket(xvc_root)?;
+        let temp_dir = XvcTransferJournal::receive_dir(xvc_root, self.guid())?;
+        let mut journal = XvcTransferJournal::load(xvc_root, self.guid())?;
+        journal.prune_received(&temp_dir);
+        let retry = XvcRetryPolicy::default();
+        let limiter = &XvcTransferLimits::for_storage(xvc_root, self.storage_name())?
+            .rate_limiter()
+            .with_progress(receive_progress(output_snd, self.storage_name()));
 
         for cache_path in paths {
             let storage_path = self.build_storage_path(cache_path);
+            if journal.is_received(&temp_dir, cache_path)? {
+                info!(output_snd, "[RESUME] {cache_path} is already received");
+                copied_paths.push(storage_path);
+                continue;
+            }
             let abs_cache_dir = temp_dir.temp_cache_dir(cache_path)?;
             fs::create_dir_all(&abs_cache_dir)?;
-            let abs_cache_path = temp_dir.temp_cache_path(cache_path)?;
-            let response_data_stream = bucket.get_object_stream(storage_path.as_str()).await;
+            let abs_cache_path = &temp_dir.temp_cache_path(cache_path)?;
+            let key = storage_path.as_str();
+
+            let response = retry
+                .a_retry(output_snd, key, || async move {
+                    a_download(bucket, key, abs_cache_path, limiter).await
+                })
+                .await
+                .and_then(|()| {
+                    verify_digest(cache_path, abs_cache_path).inspect_err(|_| {
+                        // Download it from the start next time
+                        let _ = fs::remove_file(abs_cache_path);
+                    })
+                });
 
-            match response_data_stream {
-                Ok(mut response) => {
+            match response {
+                Ok(()) => {
                     info!(
                         output_snd,
                         "{} -> {}",
                         storage_path.as_str(),
                         abs_cache_path
                     );
-                    let mut async_cache_path = tokio::fs::File::create(&abs_cache_path).await?;
-                    while let Some(chunk) = response.bytes().next().await {
-                        async_cache_path.write_all(&chunk?).await?;
-                    }
+                    journal.mark_received(cache_path);
+                    journal.save()?;
                     copied_paths.push(storage_path);
                 }
                 Err(err) => {
@@ -237,36 +490,174 @@ pub(crate) trait XvcS3StorageOperations {
     }
 
     /// Delete files from S3 compatible storage
+    ///
+    /// Failures don't stop the other deletions. If any file cannot be deleted, returns
+    /// [Error::PartialDelete] with the deleted files and the errors.
     async fn a_delete(
         &self,
         output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
         paths: &[XvcCachePath],
     ) -> Result<XvcStorageDeleteEvent> {
         let mut deleted_paths = Vec::<XvcStoragePath>::new();
+        let mut errors = Vec::<String>::new();
 
-        let bucket = self.get_bucket()?;
+        let bucket = &self.get_bucket(xvc_root)?;
+        let retry = XvcRetryPolicy::default();
 
         for cache_path in paths {
             let storage_path = self.build_storage_path(cache_path);
-            bucket.delete_object(storage_path.as_str()).await?;
-            info!(output, "[DELETE] {}", storage_path.as_str());
-            deleted_paths.push(storage_path);
+            let key = storage_path.as_str();
+            let res_delete = retry
+                .a_retry(output, key, || async move {
+                    bucket.delete_object(key).await?;
+                    Ok(())
+                })
+                .await;
+            match res_delete {
+                Ok(_) => {
+                    info!(output, "[DELETE] {}", storage_path.as_str());
+                    deleted_paths.push(storage_path);
+                }
+                Err(err) => {
+                    error!(output, "{}", err);
+                    errors.push(format!("{}: {}", storage_path, err));
+                }
+            }
         }
 
-        Ok(XvcStorageDeleteEvent {
+        let deleted = XvcStorageDeleteEvent {
             guid: self.guid().clone(),
             paths: deleted_paths,
-        })
+        };
+        if errors.is_empty() {
+            Ok(deleted)
+        } else {
+            Err(Error::PartialDelete { deleted, errors })
+        }
+    }
+
+    /// Key of the [index manifest][super::index] in the bucket
+    fn index_key(&self) -> String {
+        format!(
+            "{}/{}/{}",
+            self.storage_prefix(),
+            self.guid(),
+            XVC_STORAGE_INDEX_FILENAME
+        )
+    }
+
+    /// Read the index manifest with its ETag
+    async fn a_read_index(
+        &self,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<Option<XvcStorageIndexObject>> {
+        let bucket = &self.get_bucket(xvc_root)?;
+        let key = &self.index_key();
+        let response = XvcRetryPolicy::default()
+            .a_retry(output_snd, key, || async move {
+                Ok(bucket.get_object(key).await?)
+            })
+            .await;
+        let response = match response {
+            Ok(response) if response.status_code() == 404 => return Ok(None),
+            Ok(response) => response,
+            Err(Error::S3Error {
+                source: S3Error::HttpFailWithBody(404, _),
+            }) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let content = response
+            .to_string()
+            .map_err(|e| anyhow::anyhow!("Storage index is not valid UTF-8: {e}"))?;
+        let version = response
+            .headers()
+            .into_iter()
+            .find_map(|(k, v)| k.eq_ignore_ascii_case("etag").then_some(v));
+        Ok(Some(XvcStorageIndexObject { content, version }))
+    }
+
+    /// Write the index manifest with a conditional request.
+    ///
+    /// Uses `If-Match: {etag}` when the index exists and `If-None-Match: *` when it doesn't. The
+    /// service responds with 412 (or 409 for concurrent conditional writes) if the index has
+    /// changed.
+    async fn a_write_index(
+        &self,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        content: &str,
+        version: Option<&str>,
+    ) -> Result<()> {
+        let bucket = self.get_bucket(xvc_root)?;
+        let mut headers = HeaderMap::new();
+        let (name, value) = match version {
+            Some(etag) => (IF_MATCH, etag),
+            None => (IF_NONE_MATCH, "*"),
+        };
+        headers.insert(
+            name,
+            HeaderValue::from_str(value)
+                .map_err(|e| anyhow::anyhow!("Invalid storage index version {value}: {e}"))?,
+        );
+        let bucket = &bucket;
+        let key = &self.index_key();
+        let headers = &headers;
+        let response = XvcRetryPolicy::default()
+            .a_retry(output_snd, key, || async move {
+                Ok(bucket
+                    .put_object_with_headers(key, content.as_bytes(), Some(headers.clone()))
+                    .await?)
+            })
+            .await;
+        match response {
+            Ok(response) if matches!(response.status_code(), 409 | 412) => {
+                Err(Error::StorageIndexConflict)
+            }
+            Ok(_) => Ok(()),
+            Err(Error::S3Error {
+                source: S3Error::HttpFailWithBody(409 | 412, _),
+            }) => Err(Error::StorageIndexConflict),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Receive a byte range of a file from S3 compatible storage
+    async fn a_receive_range(
+        &self,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>> {
+        // Ranges are inclusive and can't be empty
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let bucket = &self.get_bucket(xvc_root)?;
+        let storage_path = self.build_storage_path(path);
+        let key = storage_path.as_str();
+        let response = XvcRetryPolicy::default()
+            .a_retry(output_snd, key, || async move {
+                Ok(bucket
+                    .get_object_range(key, offset, Some(offset + length - 1))
+                    .await?)
+            })
+            .await?;
+        Ok(response.bytes().to_vec())
     }
 
     /// Share files from S3 compatible storage for a duration with a signed url
     async fn a_share(
         &self,
         output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
         path: &XvcCachePath,
         duration: std::time::Duration,
     ) -> Result<XvcStorageExpiringShareEvent> {
-        let bucket = self.get_bucket()?;
+        let bucket = self.get_bucket(xvc_root)?;
         // These are optional
         // let mut custom_queries = HashMap::new();
         // custom_queries.insert(
@@ -291,16 +682,55 @@ pub(crate) trait XvcS3StorageOperations {
     }
 }
 
+/// Read the part with `part_number` (starting from 1) of the file
+fn read_part(path: &AbsolutePath, part_number: u32, part_size: u64) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start((part_number as u64 - 1) * part_size))?;
+    let mut chunk = Vec::new();
+    file.take(part_size).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Download `key` to `path` at the rate of `limiter`. If `path` has a partial download, only the
+/// rest of the object is downloaded.
+async fn a_download(
+    bucket: &Bucket,
+    key: &str,
+    path: &AbsolutePath,
+    limiter: &XvcRateLimiter,
+) -> Result<()> {
+    let offset = fs::metadata(path).map(|md| md.len()).unwrap_or(0);
+    let mut file = limiter.writer(
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?,
+    );
+    let res = if offset == 0 {
+        bucket.get_object_to_writer(key, &mut file).await
+    } else {
+        bucket
+            .get_object_range_to_writer(key, offset, None, &mut file)
+            .await
+    };
+    match res {
+        Ok(_) => Ok(file.flush().await?),
+        // The partial download is already complete
+        Err(S3Error::HttpFailWithBody(416, _)) if offset > 0 => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
 impl<T: XvcS3StorageOperations> XvcStorageOperations for T {
-    // FIXME: Do we need xvc_root here?
-    fn init(&mut self, output: &XvcOutputSender, _xvc_root: &XvcRoot) -> Result<XvcStorageInitEvent>
+    fn init(&mut self, output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageInitEvent>
     where
         Self: Sized,
     {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()?;
-        rt.block_on(self.a_init(output))
+        rt.block_on(self.a_init(output, xvc_root))
     }
 
     fn list(
@@ -315,10 +745,11 @@ impl<T: XvcS3StorageOperations> XvcStorageOperations for T {
         rt.block_on(self.a_list(output, xvc_root))
     }
 
-    fn send(
+    fn send_from(
         &self,
         output: &XvcOutputSender,
         xvc_root: &xvc_core::XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[xvc_core::XvcCachePath],
         force: bool,
     ) -> crate::Result<super::XvcStorageSendEvent> {
@@ -326,14 +757,13 @@ impl<T: XvcS3StorageOperations> XvcStorageOperations for T {
             .enable_all()
             .build()
             .unwrap();
-        rt.block_on(self.a_send(output, xvc_root, paths, force))
+        rt.block_on(self.a_send(output, xvc_root, source, paths, force))
     }
 
     fn receive(
         &self,
         output: &XvcOutputSender,
-        // FIXME: Do we need xvc_root here?
-        _xvc_root: &xvc_core::XvcRoot,
+        xvc_root: &xvc_core::XvcRoot,
         paths: &[xvc_core::XvcCachePath],
         force: bool,
     ) -> crate::Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
@@ -341,28 +771,26 @@ impl<T: XvcS3StorageOperations> XvcStorageOperations for T {
             .enable_all()
             .build()
             .unwrap();
-        rt.block_on(self.a_receive(output, paths, force))
+        rt.block_on(self.a_receive(output, xvc_root, paths, force))
     }
 
     fn delete(
         &self,
         output: &XvcOutputSender,
-        // FIXME: Do we need xvc_root?
-        _xvc_root: &xvc_core::XvcRoot,
+        xvc_root: &xvc_core::XvcRoot,
         paths: &[xvc_core::XvcCachePath],
     ) -> crate::Result<super::XvcStorageDeleteEvent> {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
             .unwrap();
-        rt.block_on(self.a_delete(output, paths))
+        rt.block_on(self.a_delete(output, xvc_root, paths))
     }
 
     fn share(
         &self,
         output: &XvcOutputSender,
-        //  FIXME: Do we need xvc_root here?
-        _xvc_root: &xvc_core::XvcRoot,
+        xvc_root: &xvc_core::XvcRoot,
         path: &XvcCachePath,
         duration: std::time::Duration,
     ) -> Result<XvcStorageExpiringShareEvent> {
@@ -370,6 +798,139 @@ impl<T: XvcS3StorageOperations> XvcStorageOperations for T {
             .enable_all()
             .build()
             .unwrap();
-        rt.block_on(self.a_share(output, path, duration))
+        rt.block_on(self.a_share(output, xvc_root, path, duration))
+    }
+
+    fn credential_source(&self, xvc_root: &XvcRoot) -> Result<Option<XvcCredentialSource>> {
+        let credentials = XvcCredentialResolver::new(xvc_root).resolve(&self.credential_spec())?;
+        Ok(Some(credentials.source))
+    }
+
+    fn read_index(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<Option<XvcStorageIndexObject>> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(self.a_read_index(output, xvc_root))
+    }
+
+    fn write_index(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        content: &str,
+        version: Option<&str>,
+    ) -> Result<()> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(self.a_write_index(output, xvc_root, content, version))
+    }
+
+    fn receive_range(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(self.a_receive_range(output, xvc_root, path, offset, length))
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that [XvcS3StorageOperations::a_list] finds the paths the files are sent to.
+    fn assert_sent_paths_are_listed<T: XvcS3StorageOperations>(storage: &T) {
+        let cache_paths = [
+            XvcCachePath::custom(&format!("b3/123/456/{}/0.bin", "7".repeat(58))),
+            XvcCachePath::custom("run-cache/abc/def0123.json"),
+        ];
+        let list_prefix = storage.list_prefix();
+        let list_regex = storage.list_regex();
+        for cache_path in cache_paths {
+            let storage_path = storage.build_storage_path(&cache_path);
+            assert!(
+                storage_path.as_str().starts_with(&list_prefix),
+                "{storage_path} is not in {list_prefix}"
+            );
+            assert!(
+                list_regex.is_match(storage_path.as_str()),
+                "{storage_path} doesn't match {list_regex}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sent_paths_are_listed() {
+        let name = "storage".to_string();
+        let bucket_name = "bucket".to_string();
+        let storage_prefix = "xvc-prefix".to_string();
+
+        #[cfg(feature = "s3")]
+        assert_sent_paths_are_listed(&super::super::s3::XvcS3Storage {
+            guid: XvcStorageGuid::new(),
+            name: name.clone(),
+            region: "us-east-1".to_string(),
+            bucket_name: bucket_name.clone(),
+            storage_prefix: storage_prefix.clone(),
+            profile: None,
+        });
+        #[cfg(feature = "minio")]
+        assert_sent_paths_are_listed(&super::super::minio::XvcMinioStorage {
+            guid: XvcStorageGuid::new(),
+            name: name.clone(),
+            region: "us-east-1".to_string(),
+            bucket_name: bucket_name.clone(),
+            storage_prefix: storage_prefix.clone(),
+            endpoint: "http://127.0.0.1:9000".to_string(),
+            profile: None,
+        });
+        #[cfg(feature = "r2")]
+        assert_sent_paths_are_listed(&super::super::r2::XvcR2Storage {
+            guid: XvcStorageGuid::new(),
+            name: name.clone(),
+            account_id: "account".to_string(),
+            bucket_name: bucket_name.clone(),
+            storage_prefix: storage_prefix.clone(),
+            profile: None,
+        });
+        #[cfg(feature = "wasabi")]
+        assert_sent_paths_are_listed(&super::super::wasabi::XvcWasabiStorage {
+            guid: XvcStorageGuid::new(),
+            name: name.clone(),
+            endpoint: "s3.wasabisys.com".to_string(),
+            bucket_name: bucket_name.clone(),
+            storage_prefix: storage_prefix.clone(),
+            profile: None,
+        });
+        #[cfg(feature = "gcs")]
+        assert_sent_paths_are_listed(&super::super::gcs::XvcGcsStorage {
+            guid: XvcStorageGuid::new(),
+            name: name.clone(),
+            region: "europe-west1".to_string(),
+            bucket_name: bucket_name.clone(),
+            storage_prefix: storage_prefix.clone(),
+            profile: None,
+        });
+        #[cfg(feature = "digital-ocean")]
+        assert_sent_paths_are_listed(&super::super::digital_ocean::XvcDigitalOceanStorage {
+            guid: XvcStorageGuid::new(),
+            name,
+            region: "fra1".to_string(),
+            bucket_name,
+            storage_prefix,
+            profile: None,
+        });
     }
 }
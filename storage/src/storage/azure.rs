@@ -0,0 +1,1064 @@
+//! Azure Blob Storage remote storage implementation.
+//!
+//! Uses the Blob service REST API directly. Requests are authorized either with Shared Key
+//! signatures built from a connection string, or with a SAS token appended to each URL.
+//!
+//! The [Azurite](https://github.com/Azure/Azurite) emulator can be used for local testing by
+//! setting `AZURE_STORAGE_CONNECTION_STRING=UseDevelopmentStorage=true`.
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::sync::{Arc, OnceLock};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::Utc;
+use hmac::{Hmac, KeyInit, Mac};
+use regex::Regex;
+use reqwest::blocking::{Body, Client, Response};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use url::Url;
+use xvc_core::R1NStore;
+use xvc_core::XvcCachePath;
+use xvc_core::XvcOutputSender;
+use xvc_core::XvcRoot;
+use xvc_core::{error, info, output};
+
+use super::index::RUN_CACHE_ENTRY_PATTERN;
+use super::limit::{receive_progress, send_progress};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use crate::{XvcStorageGuid, XvcStorageOperations};
+
+use super::{
+    XVC_STORAGE_GUID_FILENAME, XvcStorageDeleteEvent, XvcStorageExpiringShareEvent,
+    XvcStorageInitEvent, XvcStorageListEvent, XvcStoragePath, XvcStorageReceiveEvent,
+    XvcStorageSendEvent, XvcStorageTempDir,
+};
+
+/// The Blob service REST API version used in all requests.
+const AZURE_API_VERSION: &str = "2021-08-06";
+
+/// Files larger than this are uploaded as a list of blocks instead of a single Put Blob request.
+const AZURE_SINGLE_PUT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The size of each block in block blob uploads.
+const AZURE_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Account name of the Azurite emulator.
+const AZURITE_ACCOUNT_NAME: &str = "devstoreaccount1";
+
+/// Well-known (public) account key of the Azurite emulator.
+const AZURITE_ACCOUNT_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+/// Blob endpoint of the Azurite emulator.
+const AZURITE_BLOB_ENDPOINT: &str = "http://127.0.0.1:10000/devstoreaccount1";
+
+/// Entry point for `xvc storage new azure` command.
+///
+/// Creates a new [XvcAzureStorage], calls its [init][XvcAzureStorage::init] to write the
+/// `.xvc-guid` blob to the container, and saves the storage record and init event in the ECS.
+/// TODO: Reduce the number of parameters of this function.
+#[allow(clippy::too_many_arguments)]
+pub fn cmd_new_azure(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    name: String,
+    account_name: String,
+    container_name: String,
+    storage_prefix: String,
+    endpoint: Option<String>,
+) -> Result<()> {
+    let mut storage = XvcAzureStorage {
+        guid: XvcStorageGuid::new(),
+        name,
+        account_name,
+        container_name,
+        storage_prefix,
+        endpoint,
+        session: AzureSession::default(),
+    };
+
+    let init_event = storage.init(output_snd, xvc_root)?;
+
+    xvc_root.with_r1nstore_mut(|store: &mut R1NStore<XvcStorage, XvcStorageEvent>| {
+        let store_e = xvc_root.new_entity();
+        let event_e = xvc_root.new_entity();
+        store.insert(
+            store_e,
+            XvcStorage::new(storage.clone()),
+            event_e,
+            XvcStorageEvent::Init(init_event.clone()),
+        );
+        Ok(())
+    })?;
+
+    info!(output_snd, "Created Azure Storage: {:#?}", storage);
+
+    Ok(())
+}
+
+/// Add a new Azure Blob Storage storage
+///
+/// Reads a connection string from `AZURE_STORAGE_CONNECTION_STRING` or a SAS token from
+/// `AZURE_STORAGE_SAS_TOKEN` environment variables.
+/// Alternatively you can use `XVC_STORAGE_CONNECTION_STRING_<storage_name>` or
+/// `XVC_STORAGE_SAS_TOKEN_<storage_name>` environment variables if you have multiple storages of this type.
+///
+/// Use `UseDevelopmentStorage=true` as the connection string to connect to the Azurite emulator.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "azure")]
+pub struct NewAzureStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Storage account name
+    #[arg(long)]
+    pub account_name: String,
+    /// Blob container name. It's created if it doesn't exist.
+    #[arg(long)]
+    pub container_name: String,
+    /// You can set a directory in the container with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Blob service endpoint, e.g. http://127.0.0.1:10000/devstoreaccount1 for Azurite.
+    ///
+    /// Defaults to the endpoint in the connection string or https://<account_name>.blob.core.windows.net
+    #[arg(long, value_hint=clap::ValueHint::Url)]
+    pub endpoint: Option<String>,
+}
+
+/// Registration of the Azure backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Azure",
+    command: NewAzureStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewAzureStorageCLI {
+            name,
+            account_name,
+            container_name,
+            storage_prefix,
+            endpoint,
+        } = NewAzureStorageCLI::from_arg_matches(matches)?;
+        cmd_new_azure(
+            input,
+            output_snd,
+            xvc_root,
+            name,
+            account_name,
+            container_name,
+            storage_prefix,
+            endpoint,
+        )
+    },
+    deserialize: deserialize_backend::<XvcAzureStorage>,
+};
+
+/// An Azure Blob Storage container as a remote storage.
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcAzureStorage {
+    /// The GUID of the storage.
+    pub guid: XvcStorageGuid,
+    /// The name of the storage.
+    pub name: String,
+    /// The storage account name.
+    pub account_name: String,
+    /// The blob container in the storage account.
+    pub container_name: String,
+    /// The "directory" in the container to store the files, without leading/trailing slashes.
+    pub storage_prefix: String,
+    /// Blob service endpoint, e.g. `http://127.0.0.1:10000/devstoreaccount1` for Azurite.
+    ///
+    /// When `None`, the endpoint in the connection string or
+    /// `https://{account_name}.blob.core.windows.net` is used.
+    pub endpoint: Option<String>,
+    /// HTTP client and credentials reused by all requests to the Blob service.
+    #[serde(skip)]
+    session: AzureSession,
+}
+
+/// The HTTP client and credentials of a storage, built on first use and shared by its clones.
+///
+/// The session isn't a part of the storage's identity, so all instances compare equal.
+#[derive(Clone, Debug, Default)]
+struct AzureSession {
+    client: Arc<OnceLock<Client>>,
+    connection: Arc<OnceLock<(AzureConnectionString, AzureCredentials)>>,
+}
+
+impl AzureSession {
+    fn client(&self) -> &Client {
+        self.client.get_or_init(Client::new)
+    }
+}
+
+impl PartialEq for AzureSession {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for AzureSession {}
+
+impl PartialOrd for AzureSession {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AzureSession {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+crate::xvc_storage_backend!(XvcAzureStorage, "Azure", |s| format!(
+    "Azure:   {}\t{}\t{}/{}/{}",
+    s.name, s.guid, s.account_name, s.container_name, s.storage_prefix
+));
+
+/// How requests to the Blob service are authorized.
+#[derive(Clone, Debug)]
+enum AzureCredentials {
+    /// Shared Key authorization with the decoded account key.
+    SharedKey { account_key: Vec<u8> },
+    /// A SAS token appended to every request URL.
+    Sas { token: String },
+}
+
+/// Values parsed from an Azure Storage connection string.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct AzureConnectionString {
+    account_name: Option<String>,
+    account_key: Option<String>,
+    blob_endpoint: Option<String>,
+    sas_token: Option<String>,
+}
+
+impl AzureConnectionString {
+    /// Parses `Key1=Value1;Key2=Value2` connection strings.
+    ///
+    /// `UseDevelopmentStorage=true` is expanded to the Azurite account, key and endpoint.
+    fn parse(s: &str) -> Self {
+        let mut cs = AzureConnectionString::default();
+        let mut protocol = "https".to_string();
+        let mut endpoint_suffix = "core.windows.net".to_string();
+
+        for part in s.split(';').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            // Values (account keys, SAS tokens) may contain `=`, so split only once.
+            let Some((key, value)) = part.split_once('=') else {
+                continue;
+            };
+            match key {
+                "AccountName" => cs.account_name = Some(value.to_string()),
+                "AccountKey" => cs.account_key = Some(value.to_string()),
+                "BlobEndpoint" => cs.blob_endpoint = Some(value.to_string()),
+                "SharedAccessSignature" => cs.sas_token = Some(value.to_string()),
+                "DefaultEndpointsProtocol" => protocol = value.to_string(),
+                "EndpointSuffix" => endpoint_suffix = value.to_string(),
+                "UseDevelopmentStorage" if value.eq_ignore_ascii_case("true") => {
+                    cs.account_name = Some(AZURITE_ACCOUNT_NAME.to_string());
+                    cs.account_key = Some(AZURITE_ACCOUNT_KEY.to_string());
+                    cs.blob_endpoint = Some(AZURITE_BLOB_ENDPOINT.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        if cs.blob_endpoint.is_none()
+            && let Some(account_name) = &cs.account_name
+        {
+            cs.blob_endpoint = Some(format!(
+                "{protocol}://{account_name}.blob.{endpoint_suffix}"
+            ));
+        }
+
+        cs
+    }
+}
+
+/// Computes base64(HMAC-SHA256(key, message)) as used by Shared Key and SAS signatures.
+fn sign(key: &[u8], message: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|e| Error::AzureApiError(format!("Invalid account key: {e}")))?;
+    mac.update(message.as_bytes());
+    Ok(BASE64.encode(mac.finalize().into_bytes()))
+}
+
+/// Builds the Shared Key string-to-sign for a request.
+///
+/// Only `Content-Length` and `Content-Type` from the standard headers are used by Xvc, the
+/// others are left empty. `x-ms-*` headers in `ms_headers` must have lowercase names.
+fn shared_key_string_to_sign(
+    method: &str,
+    account_name: &str,
+    url: &Url,
+    content_length: u64,
+    content_type: &str,
+    ms_headers: &BTreeMap<String, String>,
+) -> String {
+    let content_length = if content_length == 0 {
+        String::new()
+    } else {
+        content_length.to_string()
+    };
+
+    let canonicalized_headers: String = ms_headers
+        .iter()
+        .map(|(k, v)| format!("{k}:{v}\n"))
+        .collect();
+
+    let mut query_params = BTreeMap::<String, Vec<String>>::new();
+    for (k, v) in url.query_pairs() {
+        query_params
+            .entry(k.to_lowercase())
+            .or_default()
+            .push(v.to_string());
+    }
+    let canonicalized_query: String = query_params
+        .iter()
+        .map(|(k, vs)| format!("\n{k}:{}", vs.join(",")))
+        .collect();
+
+    let canonicalized_resource = format!("/{account_name}{}{canonicalized_query}", url.path());
+
+    format!(
+        "{method}\n\n\n{content_length}\n\n{content_type}\n\n\n\n\n\n\n{canonicalized_headers}{canonicalized_resource}"
+    )
+}
+
+impl XvcAzureStorage {
+    /// Storage prefix without leading/trailing slashes.
+    fn storage_prefix_trimmed(&self) -> String {
+        self.storage_prefix.trim_matches('/').to_string()
+    }
+
+    /// The blob name of a cache path in the container:
+    /// `{storage_prefix}/{repo_guid}/{cache_path}`. This is also recorded as the
+    /// [XvcStoragePath] in storage events.
+    fn build_storage_path(&self, xvc_root: &XvcRoot, cache_path: &XvcCachePath) -> XvcStoragePath {
+        let prefix = self.storage_prefix_trimmed();
+        let xvc_guid = xvc_root.guid();
+        if prefix.is_empty() {
+            XvcStoragePath::from(format!("{xvc_guid}/{cache_path}"))
+        } else {
+            XvcStoragePath::from(format!("{prefix}/{xvc_guid}/{cache_path}"))
+        }
+    }
+
+    /// The blob name of the `.xvc-guid` file.
+    fn guid_blob_name(&self) -> String {
+        let prefix = self.storage_prefix_trimmed();
+        if prefix.is_empty() {
+            XVC_STORAGE_GUID_FILENAME.to_string()
+        } else {
+            format!("{prefix}/{XVC_STORAGE_GUID_FILENAME}")
+        }
+    }
+
+    /// Returns the connection string and credentials of the session, reading them from the
+    /// environment on first use.
+    fn connection(&self) -> Result<(AzureConnectionString, AzureCredentials)> {
+        if let Some(connection) = self.session.connection.get() {
+            return Ok(connection.clone());
+        }
+        let connection = self.read_connection()?;
+        Ok(self.session.connection.get_or_init(|| connection).clone())
+    }
+
+    /// Reads the connection string or SAS token from the environment.
+    ///
+    /// Looks for `XVC_STORAGE_CONNECTION_STRING_<name>`, `AZURE_STORAGE_CONNECTION_STRING`,
+    /// `XVC_STORAGE_SAS_TOKEN_<name>` and `AZURE_STORAGE_SAS_TOKEN` in that order.
+    fn read_connection(&self) -> Result<(AzureConnectionString, AzureCredentials)> {
+        let specific_cs_var = format!("XVC_STORAGE_CONNECTION_STRING_{}", self.name);
+        let generic_cs_var = "AZURE_STORAGE_CONNECTION_STRING";
+        let specific_sas_var = format!("XVC_STORAGE_SAS_TOKEN_{}", self.name);
+        let generic_sas_var = "AZURE_STORAGE_SAS_TOKEN";
+
+        let connection_string = env::var(&specific_cs_var)
+            .or_else(|_| env::var(generic_cs_var))
+            .map(|cs| AzureConnectionString::parse(&cs))
+            .unwrap_or_default();
+
+        if let Some(account_key) = &connection_string.account_key {
+            let account_key = BASE64
+                .decode(account_key)
+                .map_err(|e| Error::AzureApiError(format!("Cannot decode account key: {e}")))?;
+            return Ok((
+                connection_string,
+                AzureCredentials::SharedKey { account_key },
+            ));
+        }
+
+        if let Some(token) = connection_string.sas_token.clone() {
+            return Ok((connection_string, AzureCredentials::Sas { token }));
+        }
+
+        if let Ok(token) = env::var(&specific_sas_var).or_else(|_| env::var(generic_sas_var)) {
+            return Ok((connection_string, AzureCredentials::Sas { token }));
+        }
+
+        Err(Error::CloudCredentialsNotFound {
+            storage_name: self.name.clone(),
+            sources: [
+                specific_cs_var.as_str(),
+                generic_cs_var,
+                specific_sas_var.as_str(),
+                generic_sas_var,
+            ]
+            .iter()
+            .map(|v| format!("environment variable {v}"))
+            .collect(),
+        })
+    }
+
+    /// The blob service endpoint, without a trailing slash.
+    fn blob_endpoint(&self, connection_string: &AzureConnectionString) -> String {
+        self.endpoint
+            .clone()
+            .or_else(|| connection_string.blob_endpoint.clone())
+            .unwrap_or_else(|| format!("https://{}.blob.core.windows.net", self.account_name))
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// URL of the container, or of a blob in the container when `blob_name` is given.
+    fn resource_url(
+        &self,
+        connection_string: &AzureConnectionString,
+        blob_name: Option<&str>,
+    ) -> Result<Url> {
+        let mut url = Url::parse(&self.blob_endpoint(connection_string))
+            .map_err(|e| Error::AzureApiError(format!("Invalid blob endpoint: {e}")))?;
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| Error::AzureApiError("Blob endpoint cannot be a base".to_string()))?;
+            segments.pop_if_empty().push(&self.container_name);
+            if let Some(blob_name) = blob_name {
+                segments.extend(blob_name.split('/'));
+            }
+        }
+        Ok(url)
+    }
+
+    /// Sends an authorized request to the Blob service and returns the response if the status
+    /// is a success.
+    fn request(
+        &self,
+        method: reqwest::Method,
+        mut url: Url,
+        ms_headers: Vec<(&str, String)>,
+        body: Option<(Body, u64, &str)>,
+    ) -> Result<Response> {
+        let (connection_string, credentials) = self.connection()?;
+        let account_name = connection_string
+            .account_name
+            .clone()
+            .unwrap_or_else(|| self.account_name.clone());
+
+        let mut headers = BTreeMap::<String, String>::new();
+        headers.insert(
+            "x-ms-date".to_string(),
+            Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        );
+        headers.insert("x-ms-version".to_string(), AZURE_API_VERSION.to_string());
+        for (k, v) in ms_headers {
+            headers.insert(k.to_lowercase(), v);
+        }
+
+        let (content_length, content_type) = body
+            .as_ref()
+            .map(|(_, len, ct)| (*len, ct.to_string()))
+            .unwrap_or((0, String::new()));
+
+        let authorization = match credentials {
+            AzureCredentials::SharedKey { account_key } => {
+                let string_to_sign = shared_key_string_to_sign(
+                    method.as_str(),
+                    &account_name,
+                    &url,
+                    content_length,
+                    &content_type,
+                    &headers,
+                );
+                Some(format!(
+                    "SharedKey {account_name}:{}",
+                    sign(&account_key, &string_to_sign)?
+                ))
+            }
+            AzureCredentials::Sas { token } => {
+                let token = token.trim_start_matches('?');
+                let query = match url.query() {
+                    Some(q) => format!("{q}&{token}"),
+                    None => token.to_string(),
+                };
+                url.set_query(Some(&query));
+                None
+            }
+        };
+
+        let mut request = self.session.client().request(method.clone(), url.clone());
+        for (k, v) in &headers {
+            request = request.header(k.as_str(), v.as_str());
+        }
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        request = match body {
+            Some((body, len, ct)) => request
+                .header("Content-Length", len)
+                .header("Content-Type", ct)
+                .body(body),
+            None => request,
+        };
+
+        let response = request.send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let error_code = response
+                .headers()
+                .get("x-ms-error-code")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let text = response.text()?;
+            return Err(Error::AzureResponseError {
+                method: method.to_string(),
+                path: url.path().to_string(),
+                status: status.as_u16(),
+                error_code,
+                message: text,
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// Creates the container, ignoring the error if it already exists.
+    fn create_container(&self, output: &XvcOutputSender) -> Result<()> {
+        let (connection_string, _) = self.connection()?;
+        let mut url = self.resource_url(&connection_string, None)?;
+        url.query_pairs_mut().append_pair("restype", "container");
+        match self.request(
+            reqwest::Method::PUT,
+            url,
+            vec![],
+            Some((Body::from(Vec::new()), 0, "")),
+        ) {
+            Ok(_) => {
+                info!(output, "Created Azure container {}", self.container_name);
+                Ok(())
+            }
+            Err(Error::AzureResponseError {
+                status: 409,
+                error_code: Some(code),
+                ..
+            }) if code == "ContainerAlreadyExists" => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Uploads `content` as a single block blob.
+    fn put_blob(&self, blob_name: &str, body: Body, len: u64) -> Result<()> {
+        let (connection_string, _) = self.connection()?;
+        let url = self.resource_url(&connection_string, Some(blob_name))?;
+        self.request(
+            reqwest::Method::PUT,
+            url,
+            vec![("x-ms-blob-type", "BlockBlob".to_string())],
+            Some((body, len, "application/octet-stream")),
+        )?;
+        Ok(())
+    }
+
+    /// Uploads a local file to `blob_name`.
+    ///
+    /// Files up to [AZURE_SINGLE_PUT_MAX_SIZE] are sent with a single request. Larger files are
+    /// sent in [AZURE_BLOCK_SIZE] blocks and committed with a block list.
+    fn upload_file(&self, blob_name: &str, path: &std::path::Path) -> Result<()> {
+        let file_len = fs::metadata(path)?.len();
+        let mut file = fs::File::open(path)?;
+
+        if file_len <= AZURE_SINGLE_PUT_MAX_SIZE {
+            return self.put_blob(blob_name, Body::sized(file, file_len), file_len);
+        }
+
+        let (connection_string, _) = self.connection()?;
+        let blob_url = self.resource_url(&connection_string, Some(blob_name))?;
+        let mut block_ids = Vec::<String>::new();
+        let mut buffer = vec![0u8; AZURE_BLOCK_SIZE];
+
+        loop {
+            let mut filled = 0;
+            while filled < AZURE_BLOCK_SIZE {
+                let n = file.read(&mut buffer[filled..])?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            if filled == 0 {
+                break;
+            }
+
+            // All block ids in a blob must have the same length
+            let block_id = BASE64.encode(format!("{:010}", block_ids.len()));
+            let mut block_url = blob_url.clone();
+            block_url
+                .query_pairs_mut()
+                .append_pair("comp", "block")
+                .append_pair("blockid", &block_id);
+            self.request(
+                reqwest::Method::PUT,
+                block_url,
+                vec![],
+                Some((
+                    Body::from(buffer[..filled].to_vec()),
+                    filled as u64,
+                    "application/octet-stream",
+                )),
+            )?;
+            block_ids.push(block_id);
+        }
+
+        let block_list = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?><BlockList>{}</BlockList>",
+            block_ids
+                .iter()
+                .map(|id| format!("<Latest>{id}</Latest>"))
+                .collect::<String>()
+        );
+        let mut block_list_url = blob_url;
+        block_list_url
+            .query_pairs_mut()
+            .append_pair("comp", "blocklist");
+        let len = block_list.len() as u64;
+        self.request(
+            reqwest::Method::PUT,
+            block_list_url,
+            vec![],
+            Some((Body::from(block_list), len, "application/xml")),
+        )?;
+
+        Ok(())
+    }
+
+    /// Downloads `blob_name` and writes it to `path`.
+    fn download_file(&self, blob_name: &str, path: &std::path::Path) -> Result<()> {
+        let (connection_string, _) = self.connection()?;
+        let url = self.resource_url(&connection_string, Some(blob_name))?;
+        let mut response = self.request(reqwest::Method::GET, url, vec![], None)?;
+        let mut file = fs::File::create(path)?;
+        response.copy_to(&mut file)?;
+        Ok(())
+    }
+
+    /// Deletes `blob_name` from the container.
+    fn delete_blob(&self, blob_name: &str) -> Result<()> {
+        let (connection_string, _) = self.connection()?;
+        let url = self.resource_url(&connection_string, Some(blob_name))?;
+        self.request(reqwest::Method::DELETE, url, vec![], None)?;
+        Ok(())
+    }
+
+    /// Lists all blob names in the container starting with `prefix`, following continuation
+    /// markers.
+    fn list_blobs(&self, prefix: &str) -> Result<Vec<String>> {
+        let (connection_string, _) = self.connection()?;
+        let name_re = Regex::new("<Name>([^<]*)</Name>").unwrap();
+        let marker_re = Regex::new("<NextMarker>([^<]+)</NextMarker>").unwrap();
+        let mut names = Vec::new();
+        let mut marker: Option<String> = None;
+
+        loop {
+            let mut url = self.resource_url(&connection_string, None)?;
+            {
+                let mut query = url.query_pairs_mut();
+                query
+                    .append_pair("restype", "container")
+                    .append_pair("comp", "list")
+                    .append_pair("prefix", prefix);
+                if let Some(marker) = &marker {
+                    query.append_pair("marker", marker);
+                }
+            }
+
+            let body = self
+                .request(reqwest::Method::GET, url, vec![], None)?
+                .text()?;
+
+            names.extend(
+                name_re
+                    .captures_iter(&body)
+                    .map(|c| c[1].replace("&amp;", "&")),
+            );
+
+            match marker_re.captures(&body) {
+                Some(c) => marker = Some(c[1].to_string()),
+                None => break,
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Creates a read-only service SAS URL for `blob_name` valid for `period`.
+    ///
+    /// This requires Shared Key credentials, as the SAS is signed with the account key.
+    fn blob_sas_url(&self, blob_name: &str, period: std::time::Duration) -> Result<String> {
+        let (connection_string, credentials) = self.connection()?;
+        let AzureCredentials::SharedKey { account_key } = credentials else {
+            return Err(Error::AzureAccountKeyRequired {
+                storage_name: self.name.clone(),
+            });
+        };
+        let account_name = connection_string
+            .account_name
+            .clone()
+            .unwrap_or_else(|| self.account_name.clone());
+
+        let expiry = (Utc::now()
+            + chrono::Duration::from_std(period)
+                .map_err(|e| Error::AzureApiError(format!("Invalid share duration: {e}")))?)
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string();
+
+        let permissions = "r";
+        let resource = "b";
+        let canonicalized_resource =
+            format!("/blob/{account_name}/{}/{blob_name}", self.container_name);
+        // Fields: sp, st, se, canonicalizedResource, si, sip, spr, sv, sr, snapshot, ses,
+        // rscc, rscd, rsce, rscl, rsct
+        let string_to_sign = format!(
+            "{permissions}\n\n{expiry}\n{canonicalized_resource}\n\n\n\n{AZURE_API_VERSION}\n{resource}\n\n\n\n\n\n\n"
+        );
+        let signature = sign(&account_key, &string_to_sign)?;
+
+        let mut url = self.resource_url(&connection_string, Some(blob_name))?;
+        url.query_pairs_mut()
+            .append_pair("sv", AZURE_API_VERSION)
+            .append_pair("se", &expiry)
+            .append_pair("sr", resource)
+            .append_pair("sp", permissions)
+            .append_pair("sig", &signature);
+
+        Ok(url.to_string())
+    }
+}
+
+impl XvcStorageOperations for XvcAzureStorage {
+    /// Creates the container if it doesn't exist and writes the `.xvc-guid` blob to
+    /// `storage_prefix`.
+    ///
+    /// Fails if the container cannot be created or reached.
+    fn init(
+        &mut self,
+        output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+    ) -> Result<XvcStorageInitEvent> {
+        self.create_container(output)?;
+
+        let guid_blob_name = self.guid_blob_name();
+        let guid_bytes = self.guid.to_string().into_bytes();
+        let len = guid_bytes.len() as u64;
+        self.put_blob(&guid_blob_name, Body::from(guid_bytes), len)?;
+
+        info!(
+            output,
+            "Initialized Azure storage at {}/{}", self.container_name, guid_blob_name
+        );
+
+        Ok(XvcStorageInitEvent {
+            guid: self.guid.clone(),
+        })
+    }
+
+    /// Lists all blobs in the storage that match the Xvc cache path pattern:
+    ///
+    /// {storage_prefix}/{XVC_GUID}/[a-zA-Z][0-9]/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{58}/0
+    ///
+    /// and the run cache entries in `{XVC_GUID}/run-cache/`
+    fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
+        let xvc_guid = xvc_root.guid();
+        let prefix = self.storage_prefix_trimmed();
+        let prefix_pattern = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{prefix}/")
+        };
+
+        let re = Regex::new(&format!(
+            "^{prefix_pattern}{xvc_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
+            cp = r#"[a-zA-Z][0-9]"#,
+            d3 = r#"[0-9A-Fa-f]{3}"#,
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
+        ))
+        .unwrap();
+
+        let paths = self
+            .list_blobs(&format!("{prefix_pattern}{xvc_guid}/"))?
+            .into_iter()
+            .filter(|p| re.is_match(p))
+            .map(XvcStoragePath::from)
+            .collect();
+
+        Ok(XvcStorageListEvent {
+            guid: self.guid.clone(),
+            paths,
+        })
+    }
+
+    fn send_from(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
+        paths: &[XvcCachePath],
+        _force: bool,
+    ) -> Result<XvcStorageSendEvent> {
+        let mut sent_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+        let progress = send_progress(output, source, &self.name, paths);
+
+        for cache_path in paths {
+            let storage_path = self.build_storage_path(xvc_root, cache_path);
+            let abs_cache_path = source.temp_cache_path(cache_path)?;
+
+            match self.upload_file(storage_path.as_str(), &abs_cache_path) {
+                Ok(_) => {
+                    progress.inc(fs::metadata(&abs_cache_path).map_or(0, |md| md.len()));
+                    info!(output, "{} -> {}", abs_cache_path, storage_path);
+                    sent_paths.push(storage_path);
+                }
+                Err(err) => error!(output, "{}", err),
+            }
+        }
+
+        Ok(XvcStorageSendEvent {
+            guid: self.guid.clone(),
+            paths: sent_paths,
+        })
+    }
+
+    fn receive(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        paths: &[XvcCachePath],
+        _force: bool,
+    ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
+        let temp_dir = XvcStorageTempDir::new()?;
+        let mut received_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+        let progress = receive_progress(output, &self.name);
+
+        for cache_path in paths {
+            let storage_path = self.build_storage_path(xvc_root, cache_path);
+            let cache_dir = temp_dir.temp_cache_dir(cache_path)?;
+            fs::create_dir_all(&cache_dir)?;
+            let local_path = temp_dir.temp_cache_path(cache_path)?;
+
+            match self.download_file(storage_path.as_str(), &local_path) {
+                Ok(_) => {
+                    progress.inc(fs::metadata(&local_path).map_or(0, |md| md.len()));
+                    info!(output, "{} -> {}", storage_path, local_path);
+                    received_paths.push(storage_path);
+                }
+                Err(err) => {
+                    // Don't leave partial downloads in the temp dir
+                    let _ = fs::remove_file(&local_path);
+                    error!(output, "{}", err)
+                }
+            }
+        }
+
+        Ok((
+            temp_dir,
+            XvcStorageReceiveEvent {
+                guid: self.guid.clone(),
+                paths: received_paths,
+            },
+        ))
+    }
+
+    fn delete(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        paths: &[XvcCachePath],
+    ) -> Result<XvcStorageDeleteEvent> {
+        let mut deleted_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+
+        for cache_path in paths {
+            let storage_path = self.build_storage_path(xvc_root, cache_path);
+
+            match self.delete_blob(storage_path.as_str()) {
+                Ok(_) => {
+                    info!(output, "[DELETE] {}", storage_path);
+                    deleted_paths.push(storage_path);
+                }
+                Err(err) => error!(output, "{}", err),
+            }
+        }
+
+        Ok(XvcStorageDeleteEvent {
+            guid: self.guid.clone(),
+            paths: deleted_paths,
+        })
+    }
+
+    /// Shares a file with a read-only service SAS URL valid for `period`.
+    ///
+    /// The SAS is signed with the account key from the connection string, so this doesn't work
+    /// with SAS token credentials.
+    fn share(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        period: std::time::Duration,
+    ) -> Result<XvcStorageExpiringShareEvent> {
+        let storage_path = self.build_storage_path(xvc_root, path);
+        let signed_url = self.blob_sas_url(storage_path.as_str(), period)?;
+
+        info!(output, "[SHARED] {}", storage_path);
+        output!(output, "{}", signed_url);
+
+        Ok(XvcStorageExpiringShareEvent {
+            guid: self.guid.clone(),
+            path: storage_path,
+            signed_url,
+            expiration_seconds: period.as_secs() as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_xvc_root;
+
+    /// A storage with the Azurite credentials, without reading them from the environment.
+    fn azurite_storage(name: &str, container_name: &str, endpoint: &str) -> XvcAzureStorage {
+        let storage = XvcAzureStorage {
+            guid: XvcStorageGuid::new(),
+            name: name.to_string(),
+            account_name: AZURITE_ACCOUNT_NAME.to_string(),
+            container_name: container_name.to_string(),
+            storage_prefix: "xvc-test".to_string(),
+            endpoint: Some(endpoint.to_string()),
+            session: AzureSession::default(),
+        };
+        let account_key = BASE64.decode(AZURITE_ACCOUNT_KEY).unwrap();
+        storage
+            .session
+            .connection
+            .set((
+                AzureConnectionString::parse("UseDevelopmentStorage=true"),
+                AzureCredentials::SharedKey { account_key },
+            ))
+            .unwrap();
+        storage
+    }
+
+    #[test]
+    fn test_parse_connection_string() {
+        let cs = AzureConnectionString::parse(
+            "DefaultEndpointsProtocol=https;AccountName=myaccount;AccountKey=a2V5==;EndpointSuffix=core.windows.net",
+        );
+        assert_eq!(cs.account_name.as_deref(), Some("myaccount"));
+        assert_eq!(cs.account_key.as_deref(), Some("a2V5=="));
+        assert_eq!(
+            cs.blob_endpoint.as_deref(),
+            Some("https://myaccount.blob.core.windows.net")
+        );
+
+        let azurite = AzureConnectionString::parse("UseDevelopmentStorage=true");
+        assert_eq!(azurite.account_name.as_deref(), Some(AZURITE_ACCOUNT_NAME));
+        assert_eq!(
+            azurite.blob_endpoint.as_deref(),
+            Some(AZURITE_BLOB_ENDPOINT)
+        );
+    }
+
+    #[test]
+    fn test_shared_key_string_to_sign() {
+        let url = Url::parse(
+            "http://127.0.0.1:10000/devstoreaccount1/mycontainer?restype=container&comp=list&prefix=abc/",
+        )
+        .unwrap();
+        let headers = BTreeMap::from([
+            (
+                "x-ms-date".to_string(),
+                "Mon, 19 Oct 2026 10:00:00 GMT".to_string(),
+            ),
+            ("x-ms-version".to_string(), AZURE_API_VERSION.to_string()),
+        ]);
+        let sts = shared_key_string_to_sign("GET", "devstoreaccount1", &url, 0, "", &headers);
+        assert_eq!(
+            sts,
+            "GET\n\n\n\n\n\n\n\n\n\n\n\nx-ms-date:Mon, 19 Oct 2026 10:00:00 GMT\nx-ms-version:2021-08-06\n/devstoreaccount1/devstoreaccount1/mycontainer\ncomp:list\nprefix:abc/\nrestype:container"
+        );
+    }
+
+    #[test]
+    fn test_init_fails_on_unreachable_container() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output, _output_rcv) = crossbeam_channel::unbounded();
+        // Nothing listens on the discard port
+        let mut storage = azurite_storage(
+            "unreachable",
+            "xvc-unreachable",
+            "http://127.0.0.1:9/devstoreaccount1",
+        );
+        assert!(storage.init(&output, &xvc_root).is_err());
+        Ok(())
+    }
+
+    /// Runs against a local Azurite emulator when `XVC_TEST_AZURITE` is set, e.g.
+    /// `docker run -p 10000:10000 mcr.microsoft.com/azure-storage/azurite azurite-blob --blobHost 0.0.0.0`
+    #[test]
+    fn test_azurite_roundtrip() -> Result<()> {
+        if env::var("XVC_TEST_AZURITE").is_err() {
+            return Ok(());
+        }
+        let xvc_root = test_xvc_root();
+        let (output, _output_rcv) = crossbeam_channel::unbounded();
+        let container_name = format!("xvc-test-{}", XvcStorageGuid::new());
+        let mut storage = azurite_storage("azurite", &container_name, AZURITE_BLOB_ENDPOINT);
+        storage.init(&output, &xvc_root)?;
+        // A second init finds the existing container
+        storage.init(&output, &xvc_root)?;
+
+        let cp = XvcCachePath::custom(&format!("b3/d00/000/{}/0.bin", "0".repeat(58)));
+        let content = b"azurite".repeat(100);
+        let path = cp.to_absolute_path(&xvc_root);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, &content)?;
+
+        let sent = storage.send(&output, &xvc_root, std::slice::from_ref(&cp), false)?;
+        let storage_path = storage.build_storage_path(&xvc_root, &cp);
+        assert_eq!(sent.paths, vec![storage_path.clone()]);
+        assert_eq!(
+            storage.list(&output, &xvc_root)?.paths,
+            vec![storage_path.clone()]
+        );
+
+        let (temp_dir, received) =
+            storage.receive(&output, &xvc_root, std::slice::from_ref(&cp), false)?;
+        assert_eq!(received.paths, vec![storage_path.clone()]);
+        assert_eq!(fs::read(temp_dir.temp_cache_path(&cp)?)?, content);
+
+        let deleted = storage.delete(&output, &xvc_root, &[cp])?;
+        assert_eq!(deleted.paths, vec![storage_path]);
+        assert!(storage.list(&output, &xvc_root)?.paths.is_empty());
+        Ok(())
+    }
+}
@@ -8,15 +8,19 @@ use crate::storage::{
     XvcStorageReceiveEvent, XvcStorageSendEvent,
 };
 
-use xvc_core::XvcOutputSender;
+use xvc_core::{XvcOutputSender, info, warn};
 
-use crate::Result;
+use crate::{Error, Result};
 
 use xvc_core::{XvcCachePath, XvcRoot};
 
 use crate::XvcStorage;
 
 use super::XvcStorageTempDir;
+use super::credentials::XvcCredentialSource;
+use super::index::{self, XvcStorageIndex, XvcStorageIndexObject};
+use super::journal::XvcTransferJournal;
+use super::pack::{self, XvcPackedFiles};
 
 /// All storages implement this trait. xvc storage new   and xvc file send / bring / remove
 /// commands use this trait to communicate with the storages.
@@ -35,6 +39,21 @@ pub trait XvcStorageOperations {
         xvc_root: &XvcRoot,
         paths: &[XvcCachePath],
         force: bool,
+    ) -> Result<XvcStorageSendEvent> {
+        let cache_dir = XvcStorageTempDir::at(xvc_root.xvc_dir().clone());
+        self.send_from(output, xvc_root, &cache_dir, paths, force)
+    }
+    /// Sends the files in `source` instead of the cache directory. Files in `source` are laid out
+    /// with their cache paths.
+    ///
+    /// Used to send [packs][super::pack] that are built in a temporary directory.
+    fn send_from(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
+        paths: &[XvcCachePath],
+        force: bool,
     ) -> Result<XvcStorageSendEvent>;
     /// Used by xvc file bring command to bring files from the storage.
     fn receive(
@@ -60,6 +79,53 @@ pub trait XvcStorageOperations {
         path: &XvcCachePath,
         period: Duration,
     ) -> Result<XvcStorageExpiringShareEvent>;
+
+    /// Used by xvc storage test command to report where the credentials are found.
+    ///
+    /// Returns `None` for storages that don't use [XvcCredentialResolver][super::credentials::XvcCredentialResolver].
+    fn credential_source(&self, _xvc_root: &XvcRoot) -> Result<Option<XvcCredentialSource>> {
+        Ok(None)
+    }
+
+    /// Reads the [index manifest][super::index] of the repository in the storage.
+    ///
+    /// Returns `None` if the storage doesn't have an index or doesn't support them.
+    fn read_index(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+    ) -> Result<Option<XvcStorageIndexObject>> {
+        Ok(None)
+    }
+
+    /// Writes the [index manifest][super::index] of the repository to the storage.
+    ///
+    /// The index is written only if its version in the storage is still `version`, or it doesn't
+    /// exist when `version` is `None`. Otherwise returns [Error::StorageIndexConflict].
+    fn write_index(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _content: &str,
+        _version: Option<&str>,
+    ) -> Result<()> {
+        Err(Error::StorageIndexNotSupported)
+    }
+
+    /// Reads `length` bytes starting from `offset` of a file in the storage.
+    ///
+    /// Used to receive files in [packs][super::pack] without downloading the whole pack. Returns
+    /// `None` if the storage doesn't support range requests.
+    fn receive_range(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _path: &XvcCachePath,
+        _offset: u64,
+        _length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
 }
 
 impl XvcStorageOperations for XvcStorage {
@@ -71,10 +137,22 @@ impl XvcStorageOperations for XvcStorage {
         self.as_dyn_mut().init(output, xvc_root)
     }
 
+    /// Lists the files from the index of the storage if it has one. Otherwise lists the storage.
     fn list(&self, output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
-        self.as_dyn().list(output, xvc_root)
+        match index::read_index(self, output, xvc_root)? {
+            Some((index, _)) => Ok(XvcStorageListEvent {
+                guid: self.0.guid().clone(),
+                paths: index.storage_paths(xvc_root),
+            }),
+            None => self.as_dyn().list(output, xvc_root),
+        }
     }
 
+    /// Sends the files to the storage and adds them to its index.
+    ///
+    /// Unless `force` is set, files already in the index and files sent in an interrupted send
+    /// (recorded in the [transfer journal][super::journal]) are not sent again. When
+    /// `storage.pack_threshold` is set, small files are sent in [packs][super::pack].
     fn send(
         &self,
         output: &XvcOutputSender,
@@ -82,7 +160,91 @@ impl XvcStorageOperations for XvcStorage {
         paths: &[XvcCachePath],
         force: bool,
     ) -> Result<XvcStorageSendEvent> {
-        self.as_dyn().send(output, xvc_root, paths, force)
+        let guid = self.0.guid();
+        let index = index::read_index(self, output, xvc_root)?.map(|(index, _)| index);
+        let pack_threshold = xvc_root.config().storage.pack_threshold;
+        let journal = XvcTransferJournal::load(xvc_root, guid)?;
+
+        let mut paths = paths.to_vec();
+        if !force {
+            paths.retain(|cp| {
+                if index.as_ref().is_some_and(|index| index.contains(cp)) {
+                    info!(output, "[SKIPPED] {cp} is already in the storage");
+                    false
+                } else if journal.is_sent(cp) {
+                    info!(output, "[SKIPPED] {cp} is sent in an interrupted transfer");
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        let (small, large) = if pack_threshold > 0 {
+            pack::split_small_files(xvc_root, paths.clone(), pack_threshold)
+        } else {
+            (vec![], paths.clone())
+        };
+
+        let mut send_event = XvcStorageSendEvent {
+            guid: guid.clone(),
+            paths: vec![],
+        };
+        if !large.is_empty() {
+            let event = self.as_dyn().send(output, xvc_root, &large, force)?;
+            send_event.paths.extend(event.paths);
+        }
+        if !small.is_empty() {
+            let event = pack::send_packs(self, output, xvc_root, &small, force)?;
+            send_event.paths.extend(event.paths);
+        }
+
+        let sent = XvcStorageIndex::from_storage_paths(&send_event.paths);
+        // Backends may update the journal while sending
+        let mut journal = XvcTransferJournal::load(xvc_root, guid)?;
+        let packed_files = if small.is_empty() {
+            XvcPackedFiles::default()
+        } else {
+            XvcPackedFiles::from_events(xvc_root, guid)?
+        };
+        if paths
+            .iter()
+            .all(|cp| sent.contains(cp) || packed_files.contains(cp))
+        {
+            journal.clear_sent();
+        } else {
+            sent.cache_paths()
+                .iter()
+                .for_each(|cp| journal.mark_sent(cp));
+        }
+        journal.save()?;
+
+        if index.is_some() {
+            let sent = sent
+                .cache_paths()
+                .iter()
+                .map(|cp| cp.to_string())
+                .collect::<Vec<_>>();
+            // The files are in the storage already. A stale index only causes them to be sent
+            // again.
+            if let Err(e) = index::update_index(self, output, xvc_root, &sent, &[]) {
+                warn!(output, "Cannot update the storage index: {e}");
+            }
+        }
+        Ok(send_event)
+    }
+
+    /// Sends the files with the backend. Unlike [Self::send], the index isn't updated.
+    fn send_from(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
+        paths: &[XvcCachePath],
+        force: bool,
+    ) -> Result<XvcStorageSendEvent> {
+        self.as_dyn()
+            .send_from(output, xvc_root, source, paths, force)
     }
 
     fn receive(
@@ -92,7 +254,44 @@ impl XvcStorageOperations for XvcStorage {
         paths: &[XvcCachePath],
         force: bool,
     ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
-        self.as_dyn().receive(output, xvc_root, paths, force)
+        let packed_files = XvcPackedFiles::from_events(xvc_root, self.0.guid())?;
+        let (mut in_packs, direct): (Vec<XvcCachePath>, Vec<XvcCachePath>) = paths
+            .iter()
+            .cloned()
+            .partition(|cp| packed_files.contains(cp));
+
+        let (temp_dir, mut receive_event) = if direct.is_empty() {
+            (
+                XvcStorageTempDir::new()?,
+                XvcStorageReceiveEvent {
+                    guid: self.0.guid().clone(),
+                    paths: vec![],
+                },
+            )
+        } else {
+            self.as_dyn().receive(output, xvc_root, &direct, force)?
+        };
+
+        // Files that are not received may be in packs sent from another branch or repository
+        let received = XvcStorageIndex::from_storage_paths(&receive_event.paths);
+        let missing = direct
+            .into_iter()
+            .filter(|cp| !received.contains(cp))
+            .collect::<Vec<_>>();
+        let packed_files = if missing.is_empty() {
+            packed_files
+        } else {
+            let packed_files = XvcPackedFiles::load(self, output, xvc_root)?;
+            in_packs.extend(missing.into_iter().filter(|cp| packed_files.contains(cp)));
+            packed_files
+        };
+
+        if !in_packs.is_empty() {
+            let received =
+                pack::receive_packed(self, output, xvc_root, &packed_files, &in_packs, &temp_dir)?;
+            receive_event.paths.extend(received);
+        }
+        Ok((temp_dir, receive_event))
     }
 
     fn delete(
@@ -101,7 +300,36 @@ impl XvcStorageOperations for XvcStorage {
         xvc_root: &XvcRoot,
         paths: &[XvcCachePath],
     ) -> Result<XvcStorageDeleteEvent> {
-        self.as_dyn().delete(output, xvc_root, paths)
+        let packed_files = XvcPackedFiles::from_events(xvc_root, self.0.guid())?;
+        let mut paths = paths
+            .iter()
+            .filter(|cp| match packed_files.get(cp) {
+                Some(pf) => {
+                    warn!(
+                        output,
+                        "{cp} is in pack {} and cannot be deleted separately", pf.pack
+                    );
+                    false
+                }
+                None => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        // Members of the deleted packs are deleted with them
+        let members = paths
+            .iter()
+            .filter(|cp| pack::is_pack(cp))
+            .map(pack::members_path)
+            .collect::<Vec<_>>();
+        paths.extend(members);
+        // Remove from the index first to keep it a subset of the files in the storage
+        let removed = paths.iter().map(|cp| cp.to_string()).collect::<Vec<_>>();
+        index::update_index(self, output, xvc_root, &[], &removed)?;
+        // Deleted files are sent again in the next send
+        let mut journal = XvcTransferJournal::load(xvc_root, self.0.guid())?;
+        paths.iter().for_each(|cp| journal.forget_sent(cp));
+        journal.save()?;
+        self.as_dyn().delete(output, xvc_root, &paths)
     }
 
     fn share(
@@ -113,4 +341,39 @@ impl XvcStorageOperations for XvcStorage {
     ) -> Result<XvcStorageExpiringShareEvent> {
         self.as_dyn().share(output, xvc_root, path, period)
     }
+
+    fn credential_source(&self, xvc_root: &XvcRoot) -> Result<Option<XvcCredentialSource>> {
+        self.as_dyn().credential_source(xvc_root)
+    }
+
+    fn read_index(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<Option<XvcStorageIndexObject>> {
+        self.as_dyn().read_index(output, xvc_root)
+    }
+
+    fn write_index(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        content: &str,
+        version: Option<&str>,
+    ) -> Result<()> {
+        self.as_dyn()
+            .write_index(output, xvc_root, content, version)
+    }
+
+    fn receive_range(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        self.as_dyn()
+            .receive_range(output, xvc_root, path, offset, length)
+    }
 }
@@ -0,0 +1,547 @@
+//! Credential resolution for storages.
+//!
+//! Storages that need secrets, like access keys or tokens, describe them with an
+//! [XvcCredentialSpec] and use [XvcCredentialResolver] to find them. The sources are tried in the
+//! following order:
+//!
+//! 1. Storage specific environment variables, e.g., `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>`
+//! 2. The section of the credentials file with the storage's profile name. If the storage doesn't
+//!    have a profile, the section with the storage name is used.
+//! 3. The `credential.helper` command
+//! 4. Storage type environment variables, e.g., `AWS_ACCESS_KEY_ID`
+//! 5. The `default` section of the credentials file
+//!
+//! The credentials file is a TOML file outside of the repository. Its path is set by
+//! `credential.file` option and it's `credentials.toml` in the user configuration directory by
+//! default, e.g., `~/.config/xvc/credentials.toml`.
+//!
+//! ```toml
+//! [default]
+//! access_key_id = "AKIA..."
+//! secret_access_key = "..."
+//!
+//! [my-minio]
+//! access_key_id = "minio-user"
+//! secret_access_key = "..."
+//!
+//! [my-dropbox]
+//! access_token = "sl...."
+//! ```
+//!
+//! The credential helper works like Git credential helpers. If `credential.helper` starts with
+//! `!`, the rest is run as a shell command. If it's an absolute path, it's run directly.
+//! Otherwise, `git credential-<helper>` is run. Xvc adds `get` as the last argument and sends
+//! `protocol`, `host`, `storage` and `profile` as `key=value` lines to its standard input. The
+//! helper prints `key=value` lines. `username` and `password` are used as the access key id and
+//! secret access key for S3 compatible storages, and `password` is used as the access token for
+//! token based storages. Keys in the credentials file format can also be printed directly.
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use derive_more::Display;
+use directories_next::{BaseDirs, UserDirs};
+use subprocess::{Exec, Redirection};
+use xvc_core::{XvcConfiguration, XvcRoot};
+
+use crate::{Error, Result};
+
+/// Key for access key ids in credentials file and helper output
+pub const ACCESS_KEY_ID: &str = "access_key_id";
+/// Key for secret access keys in credentials file and helper output
+pub const SECRET_ACCESS_KEY: &str = "secret_access_key";
+/// Key for access tokens in credentials file and helper output
+pub const ACCESS_TOKEN: &str = "access_token";
+/// Name of the credentials file in the user configuration directory
+pub const XVC_CREDENTIALS_FILENAME: &str = "credentials.toml";
+/// The section of the credentials file used when nothing else is found
+pub const DEFAULT_CREDENTIAL_PROFILE: &str = "default";
+
+/// Returns the host part of `url` to send to credential helpers, e.g., `localhost:9000` for
+/// `http://localhost:9000/`
+pub fn url_host(url: &str) -> String {
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    without_scheme
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// A secret value required by a storage and the environment variables it can be read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XvcCredentialKey {
+    /// The key in the credentials file and the helper output, e.g., `access_key_id`
+    pub key: &'static str,
+    /// Storage specific environment variable, e.g., `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>`
+    pub storage_var: String,
+    /// Storage type environment variable, e.g., `AWS_ACCESS_KEY_ID`
+    pub type_var: &'static str,
+}
+
+/// Describes the credentials a storage needs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XvcCredentialSpec {
+    /// Type of the storage, e.g., `s3`. Sent to the helper as `protocol=xvc-<storage_type>`.
+    pub storage_type: &'static str,
+    /// Name of the storage
+    pub storage_name: String,
+    /// The profile (section) in the credentials file
+    pub profile: Option<String>,
+    /// Host of the storage service. Sent to the helper as `host=<host>`.
+    pub host: String,
+    /// The values required to connect to the storage
+    pub keys: Vec<XvcCredentialKey>,
+}
+
+impl XvcCredentialSpec {
+    /// Access key id and secret access key pair, used by S3 compatible storages.
+    ///
+    /// Storage specific environment variables are `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+    /// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>`.
+    pub fn access_key_pair(
+        storage_type: &'static str,
+        storage_name: &str,
+        profile: Option<&str>,
+        host: String,
+        type_access_key_var: &'static str,
+        type_secret_key_var: &'static str,
+    ) -> Self {
+        Self {
+            storage_type,
+            storage_name: storage_name.to_string(),
+            profile: profile.map(|p| p.to_string()),
+            host,
+            keys: vec![
+                XvcCredentialKey {
+                    key: ACCESS_KEY_ID,
+                    storage_var: format!("XVC_STORAGE_ACCESS_KEY_ID_{storage_name}"),
+                    type_var: type_access_key_var,
+                },
+                XvcCredentialKey {
+                    key: SECRET_ACCESS_KEY,
+                    storage_var: format!("XVC_STORAGE_SECRET_ACCESS_KEY_{storage_name}"),
+                    type_var: type_secret_key_var,
+                },
+            ],
+        }
+    }
+
+    /// A single access token.
+    ///
+    /// Storage specific environment variable is `XVC_STORAGE_ACCESS_TOKEN_<storage_name>`.
+    pub fn access_token(
+        storage_type: &'static str,
+        storage_name: &str,
+        profile: Option<&str>,
+        host: String,
+        type_var: &'static str,
+    ) -> Self {
+        Self {
+            storage_type,
+            storage_name: storage_name.to_string(),
+            profile: profile.map(|p| p.to_string()),
+            host,
+            keys: vec![XvcCredentialKey {
+                key: ACCESS_TOKEN,
+                storage_var: format!("XVC_STORAGE_ACCESS_TOKEN_{storage_name}"),
+                type_var,
+            }],
+        }
+    }
+
+    /// The section of the credentials file for this storage
+    pub fn profile_name(&self) -> &str {
+        self.profile.as_deref().unwrap_or(&self.storage_name)
+    }
+
+    /// Returns the values of all keys if they are all present and not empty in `values`
+    fn select(&self, values: &HashMap<String, String>) -> Option<HashMap<String, String>> {
+        self.keys
+            .iter()
+            .map(|k| match values.get(k.key) {
+                Some(v) if !v.is_empty() => Some((k.key.to_string(), v.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Reads the keys from the environment variables returned by `var`
+    fn env_values(
+        &self,
+        var: impl Fn(&XvcCredentialKey) -> &str,
+    ) -> (Vec<String>, Option<HashMap<String, String>>) {
+        let vars: Vec<String> = self.keys.iter().map(|k| var(k).to_string()).collect();
+        let values = self
+            .keys
+            .iter()
+            .zip(vars.iter())
+            .map(|(k, v)| match env::var(v) {
+                Ok(value) if !value.is_empty() => Some((k.key.to_string(), value)),
+                _ => None,
+            })
+            .collect();
+        (vars, values)
+    }
+
+    /// Input lines sent to the credential helper
+    fn helper_input(&self) -> String {
+        let mut input = format!(
+            "protocol=xvc-{}\nhost={}\nstorage={}\n",
+            self.storage_type, self.host, self.storage_name
+        );
+        if let Some(profile) = &self.profile {
+            input.push_str(&format!("profile={profile}\n"));
+        }
+        input.push('\n');
+        input
+    }
+
+    /// Parses the `key=value` lines printed by a credential helper.
+    ///
+    /// `username` is used for the first key and `password` for the last key if these keys are
+    /// not printed directly.
+    fn parse_helper_output(&self, output: &str) -> Option<HashMap<String, String>> {
+        let mut values: HashMap<String, String> = output
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+
+        if self.keys.len() > 1
+            && let (Some(first), Some(username)) = (self.keys.first(), values.get("username"))
+        {
+            let username = username.clone();
+            values.entry(first.key.to_string()).or_insert(username);
+        }
+        if let (Some(last), Some(password)) = (self.keys.last(), values.get("password")) {
+            let password = password.clone();
+            values.entry(last.key.to_string()).or_insert(password);
+        }
+
+        self.select(&values)
+    }
+}
+
+/// Where the credentials of a storage are found
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+pub enum XvcCredentialSource {
+    /// Storage specific environment variables
+    #[display("environment variables {}", _0.join(", "))]
+    StorageEnv(Vec<String>),
+    /// A section of the credentials file
+    #[display("credentials file {} [{profile}]", path.to_string_lossy())]
+    CredentialsFile {
+        /// Path of the credentials file
+        path: PathBuf,
+        /// Section in the file
+        profile: String,
+    },
+    /// The credential helper command
+    #[display("credential helper {command}")]
+    Helper {
+        /// The command line run to get the credentials
+        command: String,
+    },
+    /// Storage type environment variables
+    #[display("environment variables {}", _0.join(", "))]
+    TypeEnv(Vec<String>),
+}
+
+/// Credentials found for a storage
+#[derive(Clone)]
+pub struct XvcCredentials {
+    /// Where the credentials are found
+    pub source: XvcCredentialSource,
+    values: HashMap<String, String>,
+}
+
+impl XvcCredentials {
+    /// Get the value of `key`, e.g., [ACCESS_KEY_ID]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+}
+
+/// Doesn't print the secret values
+impl fmt::Debug for XvcCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys: Vec<&String> = self.values.keys().collect();
+        keys.sort();
+        f.debug_struct("XvcCredentials")
+            .field("source", &self.source)
+            .field("keys", &keys)
+            .finish()
+    }
+}
+
+/// Finds credentials for storages from the sources listed in the [module
+/// documentation][self].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XvcCredentialResolver {
+    /// `credential.helper` option, `None` if it's empty
+    pub helper: Option<String>,
+    /// Git command to run `git credential-<helper>`
+    pub git_command: String,
+    /// Path of the credentials file, `None` if it can't be determined
+    pub file: Option<PathBuf>,
+}
+
+impl XvcCredentialResolver {
+    /// Create a resolver from the repository configuration
+    pub fn new(xvc_root: &XvcRoot) -> Self {
+        Self::from_config(xvc_root.config())
+    }
+
+    /// Create a resolver from `credential.helper`, `credential.file` and `git.command` options
+    pub fn from_config(config: &XvcConfiguration) -> Self {
+        let helper = if config.credential.helper.trim().is_empty() {
+            None
+        } else {
+            Some(config.credential.helper.clone())
+        };
+
+        let file = if config.credential.file.is_empty() {
+            BaseDirs::new().map(|d| d.config_dir().join("xvc").join(XVC_CREDENTIALS_FILENAME))
+        } else if let Some(rest) = config.credential.file.strip_prefix("~/") {
+            UserDirs::new().map(|d| d.home_dir().join(rest))
+        } else {
+            Some(PathBuf::from(&config.credential.file))
+        };
+
+        Self {
+            helper,
+            git_command: config.git.command.clone(),
+            file,
+        }
+    }
+
+    /// Find the credentials described by `spec`.
+    ///
+    /// Returns [Error::CloudCredentialsNotFound] with the list of sources tried if none of them
+    /// has all the keys.
+    pub fn resolve(&self, spec: &XvcCredentialSpec) -> Result<XvcCredentials> {
+        let mut tried = Vec::<String>::new();
+
+        let (vars, values) = spec.env_values(|k| &k.storage_var);
+        if let Some(values) = values {
+            return Ok(XvcCredentials {
+                source: XvcCredentialSource::StorageEnv(vars),
+                values,
+            });
+        }
+        tried.push(XvcCredentialSource::StorageEnv(vars).to_string());
+
+        let profiles = self.read_credentials_file()?;
+
+        if let Some(credentials) =
+            self.file_credentials(&profiles, spec, spec.profile_name(), &mut tried)
+        {
+            return Ok(credentials);
+        }
+
+        if let Some(helper) = &self.helper {
+            let command = self.helper_command(helper);
+            if let Some(values) = self.run_helper(&command, spec, &mut tried)? {
+                return Ok(XvcCredentials {
+                    source: XvcCredentialSource::Helper { command },
+                    values,
+                });
+            }
+        }
+
+        let (vars, values) = spec.env_values(|k| k.type_var);
+        if let Some(values) = values {
+            return Ok(XvcCredentials {
+                source: XvcCredentialSource::TypeEnv(vars),
+                values,
+            });
+        }
+        tried.push(XvcCredentialSource::TypeEnv(vars).to_string());
+
+        if spec.profile_name() != DEFAULT_CREDENTIAL_PROFILE
+            && let Some(credentials) =
+                self.file_credentials(&profiles, spec, DEFAULT_CREDENTIAL_PROFILE, &mut tried)
+        {
+            return Ok(credentials);
+        }
+
+        Err(Error::CloudCredentialsNotFound {
+            storage_name: spec.storage_name.clone(),
+            sources: tried,
+        })
+    }
+
+    /// Reads all sections of the credentials file. Returns an empty map if the file doesn't exist.
+    fn read_credentials_file(&self) -> Result<HashMap<String, HashMap<String, String>>> {
+        match &self.file {
+            Some(path) if path.is_file() => {
+                let content = std::fs::read_to_string(path)?;
+                toml::from_str(&content).map_err(|e| Error::CredentialsFileError {
+                    path: path.to_string_lossy().to_string(),
+                    message: e.to_string(),
+                })
+            }
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    fn file_credentials(
+        &self,
+        profiles: &HashMap<String, HashMap<String, String>>,
+        spec: &XvcCredentialSpec,
+        profile: &str,
+        tried: &mut Vec<String>,
+    ) -> Option<XvcCredentials> {
+        let path = self.file.clone()?;
+        let source = XvcCredentialSource::CredentialsFile {
+            path,
+            profile: profile.to_string(),
+        };
+        match profiles
+            .get(profile)
+            .and_then(|section| spec.select(section))
+        {
+            Some(values) => Some(XvcCredentials { source, values }),
+            None => {
+                tried.push(source.to_string());
+                None
+            }
+        }
+    }
+
+    /// The command line to run for `helper`, following Git conventions
+    fn helper_command(&self, helper: &str) -> String {
+        let helper = helper.trim();
+        if let Some(command) = helper.strip_prefix('!') {
+            format!("{command} get")
+        } else if helper
+            .split_whitespace()
+            .next()
+            .is_some_and(|executable| std::path::Path::new(executable).is_absolute())
+        {
+            format!("{helper} get")
+        } else {
+            format!("{} credential-{helper} get", self.git_command)
+        }
+    }
+
+    /// Runs the helper command. Returns `None` if the helper fails or doesn't return all keys.
+    fn run_helper(
+        &self,
+        command: &str,
+        spec: &XvcCredentialSpec,
+        tried: &mut Vec<String>,
+    ) -> Result<Option<HashMap<String, String>>> {
+        let capture = Exec::shell(command)
+            .stdin(spec.helper_input())
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()?;
+
+        if !capture.success() {
+            tried.push(format!(
+                "credential helper {command} (failed: {})",
+                capture.stderr_str().trim()
+            ));
+            return Ok(None);
+        }
+
+        let values = spec.parse_helper_output(&capture.stdout_str());
+        if values.is_none() {
+            tried.push(format!("credential helper {command}"));
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(file: PathBuf, helper: Option<&str>) -> XvcCredentialResolver {
+        XvcCredentialResolver {
+            helper: helper.map(|h| h.to_string()),
+            git_command: "git".to_string(),
+            file: Some(file),
+        }
+    }
+
+    #[test]
+    fn test_credentials_file_profiles() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(XVC_CREDENTIALS_FILENAME);
+        std::fs::write(
+            &path,
+            r#"
+[default]
+access_key_id = "default-id"
+secret_access_key = "default-secret"
+
+[team]
+access_key_id = "team-id"
+secret_access_key = "team-secret"
+"#,
+        )?;
+        let resolver = resolver(path.clone(), None);
+
+        let spec = XvcCredentialSpec::access_key_pair(
+            "s3",
+            "xvc-credential-test-profile",
+            Some("team"),
+            "s3.amazonaws.com".to_string(),
+            "XVC_TEST_UNSET_ACCESS_KEY_ID",
+            "XVC_TEST_UNSET_SECRET_ACCESS_KEY",
+        );
+        let credentials = resolver.resolve(&spec)?;
+        assert_eq!(credentials.get(ACCESS_KEY_ID), Some("team-id"));
+        assert_eq!(
+            credentials.source,
+            XvcCredentialSource::CredentialsFile {
+                path: path.clone(),
+                profile: "team".to_string()
+            }
+        );
+
+        let spec = XvcCredentialSpec::access_token(
+            "dropbox",
+            "xvc-credential-test-default",
+            None,
+            "api.dropboxapi.com".to_string(),
+            "XVC_TEST_UNSET_ACCESS_TOKEN",
+        );
+        // default section doesn't have access_token
+        assert!(matches!(
+            resolver.resolve(&spec),
+            Err(Error::CloudCredentialsNotFound { .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credential_helper() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let resolver = resolver(
+            dir.path().join(XVC_CREDENTIALS_FILENAME),
+            Some("!f() { grep -q storage=helped && echo username=u && echo password=p; }; f"),
+        );
+        let spec = XvcCredentialSpec::access_key_pair(
+            "minio",
+            "helped",
+            None,
+            "localhost:9000".to_string(),
+            "XVC_TEST_UNSET_ACCESS_KEY_ID",
+            "XVC_TEST_UNSET_SECRET_ACCESS_KEY",
+        );
+        let credentials = resolver.resolve(&spec)?;
+        assert_eq!(credentials.get(ACCESS_KEY_ID), Some("u"));
+        assert_eq!(credentials.get(SECRET_ACCESS_KEY), Some("p"));
+        assert!(matches!(
+            credentials.source,
+            XvcCredentialSource::Helper { .. }
+        ));
+        Ok(())
+    }
+}
@@ -1,17 +1,18 @@
 //! Digital Ocean Spaces remote storage implementation.
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
-use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use serde::{Deserialize, Serialize};
 use xvc_core::R1NStore;
 use xvc_core::XvcOutputSender;
 use xvc_core::XvcRoot;
 
-use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
 use super::async_common::XvcS3StorageOperations;
+use super::credentials::XvcCredentialSpec;
 
 /// Configure a new Digital Ocean Spaces storage.
 ///
@@ -21,6 +22,8 @@ use super::async_common::XvcS3StorageOperations;
 /// This creates a [XvcDigitalOceanStorage], calls its
 /// [init][XvcDigitalOceanStorage::init] function to create/update guid, and
 /// saves [XvcStorageInitEvent] and [XvcStorage] in ECS.
+/// TODO: Reduce the number of parameters of this function.
+#[allow(clippy::too_many_arguments)]
 pub fn cmd_new_digital_ocean(
     _input: std::io::StdinLock,
     output_snd: &XvcOutputSender,
@@ -29,6 +32,7 @@ pub fn cmd_new_digital_ocean(
     bucket_name: String,
     region: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcDigitalOceanStorage {
         guid: XvcStorageGuid::new(),
@@ -36,6 +40,7 @@ pub fn cmd_new_digital_ocean(
         region,
         bucket_name,
         storage_prefix,
+        profile,
     };
 
     let init_event = storage.init(output_snd, xvc_root)?;
@@ -45,7 +50,7 @@ pub fn cmd_new_digital_ocean(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::DigitalOcean(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -55,6 +60,62 @@ pub fn cmd_new_digital_ocean(
     Ok(())
 }
 
+/// Add a new Digital Ocean storage
+///
+/// Reads credentials from `DIGITAL_OCEAN_ACCESS_KEY_ID` and `DIGITAL_OCEAN_SECRET_ACCESS_KEY` environment variables.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+/// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+/// The keys can also be kept in the credentials file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "digital-ocean")]
+pub struct NewDigitalOceanStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Bucket name
+    #[arg(long)]
+    pub bucket_name: String,
+    /// Region of the server
+    #[arg(long)]
+    pub region: String,
+    /// You can set a directory in the bucket with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the DigitalOcean backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "DigitalOcean",
+    command: NewDigitalOceanStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewDigitalOceanStorageCLI {
+            name,
+            bucket_name,
+            region,
+            storage_prefix,
+            profile,
+        } = NewDigitalOceanStorageCLI::from_arg_matches(matches)?;
+        cmd_new_digital_ocean(
+            input,
+            output_snd,
+            xvc_root,
+            name,
+            bucket_name,
+            region,
+            storage_prefix,
+            profile,
+        )
+    },
+    deserialize: deserialize_backend::<XvcDigitalOceanStorage>,
+};
+
 /// A Digital Ocean Spaces storage.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcDigitalOceanStorage {
@@ -68,8 +129,16 @@ pub struct XvcDigitalOceanStorage {
     pub bucket_name: String,
     /// The path prefix of the storage.
     pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcDigitalOceanStorage, "DigitalOcean", |s| format!(
+    "DO:      {}\t{}\t{}.{}/{}",
+    s.name, s.guid, s.region, s.bucket_name, s.storage_prefix
+));
+
 impl XvcS3StorageOperations for XvcDigitalOceanStorage {
     fn storage_prefix(&self) -> String {
         self.storage_prefix.clone()
@@ -79,45 +148,24 @@ impl XvcS3StorageOperations for XvcDigitalOceanStorage {
         &self.guid
     }
 
-    fn credentials(&self) -> Result<Credentials> {
-        // Try storage-specific credentials
-        let specific_access_key_var = format!("XVC_STORAGE_ACCESS_KEY_ID_{}", self.name);
-        let specific_secret_key_var = format!("XVC_STORAGE_SECRET_ACCESS_KEY_{}", self.name);
-
-        if let (Ok(access_key), Ok(secret_key)) = (
-            env::var(&specific_access_key_var),
-            env::var(&specific_secret_key_var),
-        ) {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // Try storage-type credentials
-        let type_access_key_var = "DIGITAL_OCEAN_ACCESS_KEY_ID";
-        let type_secret_key_var = "DIGITAL_OCEAN_SECRET_ACCESS_KEY";
-        if let (Ok(access_key), Ok(secret_key)) =
-            (env::var(type_access_key_var), env::var(type_secret_key_var))
-        {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // If both fail, return an error
-        Err(Error::CloudCredentialsNotFound {
-            storage_name: self.name.clone(),
-            var_pairs: vec![
-                (specific_access_key_var, specific_secret_key_var),
-                (
-                    type_access_key_var.to_string(),
-                    type_secret_key_var.to_string(),
-                ),
-            ],
-        })
+    fn storage_name(&self) -> &str {
+        &self.name
+    }
+
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_key_pair(
+            "digital-ocean",
+            &self.name,
+            self.profile.as_deref(),
+            format!("{}.digitaloceanspaces.com", self.region),
+            "DIGITAL_OCEAN_ACCESS_KEY_ID",
+            "DIGITAL_OCEAN_SECRET_ACCESS_KEY",
+        )
     }
 
-    fn get_bucket(&self) -> Result<Box<Bucket>> {
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>> {
         // We'll just put guid file to endpoint/bucket/prefix/XVC_GUID_FILENAME
-        let credentials = self.credentials()?;
+        let credentials = self.credentials(xvc_root)?;
         let region: Region = self.region.parse().expect("Cannot parse region name");
         let bucket = Bucket::new(&self.bucket_name, region, credentials)?;
         Ok(bucket)
@@ -1,5 +1,5 @@
 //! Dropbox remote storage implementation.
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::fs;
 
 use regex::Regex;
@@ -12,9 +12,17 @@ use xvc_core::XvcOutputSender;
 use xvc_core::XvcRoot;
 use xvc_core::{error, info, output};
 
+use super::index::RUN_CACHE_ENTRY_PATTERN;
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
 use crate::{Error, Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
+use super::credentials::{
+    ACCESS_TOKEN, XvcCredentialResolver, XvcCredentialSource, XvcCredentialSpec,
+};
+use super::journal::XvcTransferJournal;
+use super::limit::{receive_progress, send_progress};
+use super::retry::XvcRetryPolicy;
 use super::{
     XVC_STORAGE_GUID_FILENAME, XvcStorageDeleteEvent, XvcStorageExpiringShareEvent,
     XvcStorageInitEvent, XvcStorageListEvent, XvcStoragePath, XvcStorageReceiveEvent,
@@ -38,11 +46,13 @@ pub fn cmd_new_dropbox(
     xvc_root: &XvcRoot,
     name: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcDropboxStorage {
         guid: XvcStorageGuid::new(),
         name,
         storage_prefix,
+        profile,
     };
 
     let init_event = storage.init(output_snd, xvc_root)?;
@@ -52,7 +62,7 @@ pub fn cmd_new_dropbox(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Dropbox(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -64,6 +74,45 @@ pub fn cmd_new_dropbox(
     Ok(())
 }
 
+/// Add a new Dropbox storage
+///
+/// Reads credentials from `DROPBOX_ACCESS_TOKEN` environment variable.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_TOKEN_<storage_name>` environment variable
+/// if you have multiple storages of this type. The token can also be kept in the credentials
+/// file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "dropbox")]
+pub struct NewDropboxStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// You can set a directory in Dropbox with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access token.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the Dropbox backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Dropbox",
+    command: NewDropboxStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewDropboxStorageCLI {
+            name,
+            storage_prefix,
+            profile,
+        } = NewDropboxStorageCLI::from_arg_matches(matches)?;
+        cmd_new_dropbox(input, output_snd, xvc_root, name, storage_prefix, profile)
+    },
+    deserialize: deserialize_backend::<XvcDropboxStorage>,
+};
+
 /// A Dropbox storage.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcDropboxStorage {
@@ -73,8 +122,16 @@ pub struct XvcDropboxStorage {
     pub name: String,
     /// The directory in Dropbox to store the files, without leading/trailing slashes.
     pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcDropboxStorage, "Dropbox", |s| format!(
+    "Dropbox: {}\t{}\t{}",
+    s.name, s.guid, s.storage_prefix
+));
+
 #[derive(Deserialize)]
 struct DbxListFolderEntry {
     #[serde(rename = ".tag")]
@@ -141,29 +198,47 @@ impl XvcDropboxStorage {
         }
     }
 
-    /// Reads the access token from `XVC_STORAGE_ACCESS_TOKEN_<name>` or `DROPBOX_ACCESS_TOKEN`
-    /// environment variables, in that order.
-    fn access_token(&self) -> Result<String> {
-        let specific_var = format!("XVC_STORAGE_ACCESS_TOKEN_{}", self.name);
-        if let Ok(token) = env::var(&specific_var) {
-            return Ok(token);
-        }
+    /// Describes the access token for [XvcCredentialResolver]
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_token(
+            "dropbox",
+            &self.name,
+            self.profile.as_deref(),
+            "api.dropboxapi.com".to_string(),
+            "DROPBOX_ACCESS_TOKEN",
+        )
+    }
 
-        let generic_var = "DROPBOX_ACCESS_TOKEN";
-        if let Ok(token) = env::var(generic_var) {
-            return Ok(token);
-        }
+    /// Finds the access token with [XvcCredentialResolver]
+    fn access_token(&self, xvc_root: &XvcRoot) -> Result<String> {
+        let credentials = XvcCredentialResolver::new(xvc_root).resolve(&self.credential_spec())?;
+        Ok(credentials
+            .get(ACCESS_TOKEN)
+            .unwrap_or_default()
+            .to_string())
+    }
 
-        Err(Error::CloudTokenNotFound {
-            storage_name: self.name.clone(),
-            vars: vec![specific_var, generic_var.to_string()],
-        })
+    /// Error for a failed API call. Throttling (429) and server errors (5xx) are
+    /// [transient][Error::is_transient], so the call is [retried][XvcRetryPolicy].
+    fn api_error(endpoint: &str, status: reqwest::StatusCode, text: String) -> Error {
+        if status.as_u16() == 429 || status.is_server_error() {
+            Error::TransientHttpError {
+                status: status.as_u16(),
+                message: format!("{endpoint}: {text}"),
+            }
+        } else {
+            Error::DropboxApiError(format!("{endpoint}: {status}: {text}"))
+        }
     }
 
     /// Calls a Dropbox RPC endpoint (`api.dropboxapi.com`) with a JSON body and returns the
     /// parsed JSON response.
-    fn rpc_call(&self, endpoint: &str, body: serde_json::Value) -> Result<serde_json::Value> {
-        let token = self.access_token()?;
+    fn rpc_call(
+        &self,
+        token: &str,
+        endpoint: &str,
+        body: serde_json::Value,
+    ) -> Result<serde_json::Value> {
         let client = Client::new();
         let response = client
             .post(format!("{DBX_RPC_URL}/{endpoint}"))
@@ -175,17 +250,14 @@ impl XvcDropboxStorage {
         let text = response.text()?;
 
         if !status.is_success() {
-            return Err(Error::DropboxApiError(format!(
-                "{endpoint}: {status}: {text}"
-            )));
+            return Err(Self::api_error(endpoint, status, text));
         }
 
         Ok(serde_json::from_str(&text)?)
     }
 
     /// Uploads `content` to `dropbox_path`, overwriting any existing file.
-    fn upload(&self, dropbox_path: &str, content: Vec<u8>) -> Result<()> {
-        let token = self.access_token()?;
+    fn upload(&self, token: &str, dropbox_path: &str, content: Vec<u8>) -> Result<()> {
         let client = Client::new();
         let arg = json!({
             "path": dropbox_path,
@@ -205,17 +277,14 @@ impl XvcDropboxStorage {
         let status = response.status();
         if !status.is_success() {
             let text = response.text()?;
-            return Err(Error::DropboxApiError(format!(
-                "files/upload: {status}: {text}"
-            )));
+            return Err(Self::api_error("files/upload", status, text));
         }
 
         Ok(())
     }
 
     /// Downloads and returns the content of `dropbox_path`.
-    fn download(&self, dropbox_path: &str) -> Result<Vec<u8>> {
-        let token = self.access_token()?;
+    fn download(&self, token: &str, dropbox_path: &str) -> Result<Vec<u8>> {
         let client = Client::new();
         let arg = json!({ "path": dropbox_path });
 
@@ -228,28 +297,31 @@ impl XvcDropboxStorage {
         let status = response.status();
         if !status.is_success() {
             let text = response.text()?;
-            return Err(Error::DropboxApiError(format!(
-                "files/download: {status}: {text}"
-            )));
+            return Err(Self::api_error("files/download", status, text));
         }
 
         Ok(response.bytes()?.to_vec())
     }
 
     /// Deletes `dropbox_path` from the storage.
-    fn delete_path(&self, dropbox_path: &str) -> Result<()> {
-        self.rpc_call("files/delete_v2", json!({ "path": dropbox_path }))?;
+    fn delete_path(&self, token: &str, dropbox_path: &str) -> Result<()> {
+        self.rpc_call(token, "files/delete_v2", json!({ "path": dropbox_path }))?;
         Ok(())
     }
 
     /// Recursively lists all file paths (relative, no leading slash) under `storage_prefix`.
-    fn list_all_files(&self) -> Result<Vec<String>> {
+    fn list_all_files(&self, output: &XvcOutputSender, token: &str) -> Result<Vec<String>> {
         let mut paths = Vec::new();
+        let retry = XvcRetryPolicy::default();
 
-        let mut result: DbxListFolderResult = serde_json::from_value(self.rpc_call(
-            "files/list_folder",
-            json!({ "path": self.root_dropbox_path(), "recursive": true }),
-        )?)?;
+        let mut result: DbxListFolderResult =
+            serde_json::from_value(retry.retry(output, "files/list_folder", || {
+                self.rpc_call(
+                    token,
+                    "files/list_folder",
+                    json!({ "path": self.root_dropbox_path(), "recursive": true }),
+                )
+            })?)?;
 
         loop {
             for entry in result.entries {
@@ -264,9 +336,16 @@ impl XvcDropboxStorage {
                 break;
             }
 
-            result = serde_json::from_value(self.rpc_call(
+            result = serde_json::from_value(retry.retry(
+                output,
                 "files/list_folder/continue",
-                json!({ "cursor": result.cursor }),
+                || {
+                    self.rpc_call(
+                        token,
+                        "files/list_folder/continue",
+                        json!({ "cursor": result.cursor }),
+                    )
+                },
             )?)?;
         }
 
@@ -279,10 +358,17 @@ impl XvcStorageOperations for XvcDropboxStorage {
     fn init(
         &mut self,
         output: &XvcOutputSender,
-        _xvc_root: &XvcRoot,
+        xvc_root: &XvcRoot,
     ) -> Result<XvcStorageInitEvent> {
+        let token = self.access_token(xvc_root)?;
         let guid_dropbox_path = self.guid_dropbox_path();
-        self.upload(&guid_dropbox_path, self.guid.to_string().into_bytes())?;
+        XvcRetryPolicy::default().retry(output, &guid_dropbox_path, || {
+            self.upload(
+                &token,
+                &guid_dropbox_path,
+                self.guid.to_string().into_bytes(),
+            )
+        })?;
 
         info!(output, "Initialized Dropbox storage at {guid_dropbox_path}");
 
@@ -294,7 +380,9 @@ impl XvcStorageOperations for XvcDropboxStorage {
     /// Lists all files in the storage that match the Xvc cache path pattern:
     ///
     /// {storage_prefix}/{XVC_GUID}/[a-zA-Z][0-9]/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{58}/0
-    fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
+    ///
+    /// and the run cache entries in `{XVC_GUID}/run-cache/`
+    fn list(&self, output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
         let xvc_guid = xvc_root.guid();
         let prefix = self.storage_prefix_trimmed();
         let prefix_pattern = if prefix.is_empty() {
@@ -304,15 +392,16 @@ impl XvcStorageOperations for XvcDropboxStorage {
         };
 
         let re = Regex::new(&format!(
-            "^{prefix_pattern}{xvc_guid}/{cp}/{d3}/{d3}/{d58}/0\\..*$",
+            "^{prefix_pattern}{xvc_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
             cp = r#"[a-zA-Z][0-9]"#,
             d3 = r#"[0-9A-Fa-f]{3}"#,
-            d58 = r#"[0-9A-Fa-f]{58}"#
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
         ))
         .unwrap();
 
         let paths = self
-            .list_all_files()?
+            .list_all_files(output, &self.access_token(xvc_root)?)?
             .into_iter()
             .filter(|p| re.is_match(p))
             .map(XvcStoragePath::from)
@@ -324,24 +413,34 @@ impl XvcStorageOperations for XvcDropboxStorage {
         })
     }
 
-    fn send(
+    fn send_from(
         &self,
         output: &XvcOutputSender,
         xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[XvcCachePath],
         _force: bool,
     ) -> Result<XvcStorageSendEvent> {
+        let token = self.access_token(xvc_root)?;
         let mut sent_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+        let mut journal = XvcTransferJournal::load(xvc_root, &self.guid)?;
+        let retry = XvcRetryPolicy::default();
+        let progress = send_progress(output, source, &self.name, paths);
 
         for cache_path in paths {
             let storage_path = self.build_storage_path(xvc_root, cache_path);
             let dropbox_path = Self::to_dropbox_path(storage_path.as_str());
-            let abs_cache_path = cache_path.to_absolute_path(xvc_root);
+            let abs_cache_path = source.temp_cache_path(cache_path)?;
 
             match fs::read(&abs_cache_path) {
-                Ok(content) => match self.upload(&dropbox_path, content) {
+                Ok(content) => match retry.retry(output, &dropbox_path, || {
+                    self.upload(&token, &dropbox_path, content.clone())
+                }) {
                     Ok(_) => {
+                        progress.inc(content.len() as u64);
                         info!(output, "{} -> {}", abs_cache_path, dropbox_path);
+                        journal.mark_sent(cache_path);
+                        journal.save()?;
                         sent_paths.push(storage_path);
                     }
                     Err(err) => error!(output, "{}", err),
@@ -363,20 +462,35 @@ impl XvcStorageOperations for XvcDropboxStorage {
         paths: &[XvcCachePath],
         _force: bool,
     ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
-        let temp_dir = XvcStorageTempDir::new()?;
+        let token = self.access_token(xvc_root)?;
+        let temp_dir = XvcTransferJournal::receive_dir(xvc_root, &self.guid)?;
+        let mut journal = XvcTransferJournal::load(xvc_root, &self.guid)?;
+        journal.prune_received(&temp_dir);
+        let retry = XvcRetryPolicy::default();
         let mut received_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+        let progress = receive_progress(output, &self.name);
 
         for cache_path in paths {
             let storage_path = self.build_storage_path(xvc_root, cache_path);
+            if journal.is_received(&temp_dir, cache_path)? {
+                info!(output, "[RESUME] {cache_path} is already received");
+                received_paths.push(storage_path);
+                continue;
+            }
             let dropbox_path = Self::to_dropbox_path(storage_path.as_str());
 
-            match self.download(&dropbox_path) {
+            match retry.retry(output, &dropbox_path, || {
+                self.download(&token, &dropbox_path)
+            }) {
                 Ok(content) => {
                     let cache_dir = temp_dir.temp_cache_dir(cache_path)?;
                     fs::create_dir_all(&cache_dir)?;
                     let local_path = temp_dir.temp_cache_path(cache_path)?;
+                    progress.inc(content.len() as u64);
                     fs::write(&local_path, content)?;
                     info!(output, "{} -> {}", dropbox_path, local_path);
+                    journal.mark_received(cache_path);
+                    journal.save()?;
                     received_paths.push(storage_path);
                 }
                 Err(err) => error!(output, "{}", err),
@@ -398,13 +512,16 @@ impl XvcStorageOperations for XvcDropboxStorage {
         xvc_root: &XvcRoot,
         paths: &[XvcCachePath],
     ) -> Result<XvcStorageDeleteEvent> {
+        let token = self.access_token(xvc_root)?;
         let mut deleted_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
 
         for cache_path in paths {
             let storage_path = self.build_storage_path(xvc_root, cache_path);
             let dropbox_path = Self::to_dropbox_path(storage_path.as_str());
 
-            match self.delete_path(&dropbox_path) {
+            match XvcRetryPolicy::default().retry(output, &dropbox_path, || {
+                self.delete_path(&token, &dropbox_path)
+            }) {
                 Ok(_) => {
                     info!(output, "[DELETE] {}", dropbox_path);
                     deleted_paths.push(storage_path);
@@ -430,11 +547,18 @@ impl XvcStorageOperations for XvcDropboxStorage {
         path: &XvcCachePath,
         _period: std::time::Duration,
     ) -> Result<XvcStorageExpiringShareEvent> {
+        let token = self.access_token(xvc_root)?;
         let storage_path = self.build_storage_path(xvc_root, path);
         let dropbox_path = Self::to_dropbox_path(storage_path.as_str());
 
         let result: DbxTemporaryLinkResult = serde_json::from_value(
-            self.rpc_call("files/get_temporary_link", json!({ "path": dropbox_path }))?,
+            XvcRetryPolicy::default().retry(output, &dropbox_path, || {
+                self.rpc_call(
+                    &token,
+                    "files/get_temporary_link",
+                    json!({ "path": dropbox_path }),
+                )
+            })?,
         )?;
 
         info!(output, "[SHARED] {}", dropbox_path);
@@ -447,4 +571,9 @@ impl XvcStorageOperations for XvcDropboxStorage {
             expiration_seconds: DBX_TEMPORARY_LINK_EXPIRATION_SECONDS,
         })
     }
+
+    fn credential_source(&self, xvc_root: &XvcRoot) -> Result<Option<XvcCredentialSource>> {
+        let credentials = XvcCredentialResolver::new(xvc_root).resolve(&self.credential_spec())?;
+        Ok(Some(credentials.source))
+    }
 }
@@ -1,6 +1,6 @@
 //! Storage events that we track when we created the storage, what we sent, received and deleted.
 use serde::{Deserialize, Serialize};
-use xvc_core::persist;
+use xvc_core::{XvcCachePath, persist};
 
 use super::{XvcStorageGuid, XvcStoragePath};
 
@@ -59,6 +59,28 @@ pub struct XvcStorageExpiringShareEvent {
     pub expiration_seconds: u32,
 }
 
+/// A file in a pack object
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcPackMember {
+    /// Cache path of the file
+    pub cache_path: XvcCachePath,
+    /// Byte offset of the file in the pack
+    pub offset: u64,
+    /// Length of the file in bytes
+    pub length: u64,
+}
+
+/// The pack event when small files are sent to the storage in a single pack object.
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcStoragePackEvent {
+    /// The GUID of the storage
+    pub guid: XvcStorageGuid,
+    /// Cache path of the pack object. Packs are addressed by the digest of their content.
+    pub pack: XvcCachePath,
+    /// Files in the pack
+    pub members: Vec<XvcPackMember>,
+}
+
 /// Collected storage events.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub enum XvcStorageEvent {
@@ -74,5 +96,7 @@ pub enum XvcStorageEvent {
     Delete(XvcStorageDeleteEvent),
     /// The share event when a file is shared via signed URL
     Share(XvcStorageExpiringShareEvent),
+    /// The pack event when small files are sent in a pack object
+    Pack(XvcStoragePackEvent),
 }
 persist!(XvcStorageEvent, "storage-event");
@@ -0,0 +1,490 @@
+//! Garbage collection for storages.
+//!
+//! `xvc storage gc` deletes the files in a storage that are not referenced by any of the selected
+//! Git refs. The files referenced by a ref are found by reading `.xvc/store` at that ref, so the
+//! storage doesn't need to be checked out. Files referenced by the current (possibly uncommitted)
+//! store are always kept. Pipeline run cache entries and the outputs they list are kept too.
+use std::collections::HashSet;
+use std::fs;
+
+use serde::Deserialize;
+use xvc_core::{
+    ContentDigest, Event, Storable, XvcCachePath, XvcOutputSender, XvcPath, XvcRoot, XvcStore,
+    exec_git, extract_store_at_ref, get_absolute_git_command, info, output,
+};
+
+use super::get_storage_record;
+use super::index::{RUN_CACHE_DIR, cache_path_of};
+use super::pack::XvcPackedFiles;
+use super::{XvcStorage, XvcStorageEvent, XvcStorageOperations, XvcStoragePath};
+use crate::{Error, Result, StorageIdentifier};
+
+/// The outputs listed in a pipeline run cache entry. Other fields of the entry are ignored.
+#[derive(Debug, Deserialize)]
+struct RunCacheEntryOutputs {
+    outputs: Vec<RunCacheOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunCacheOutput {
+    path: XvcPath,
+    content_digest: ContentDigest,
+}
+
+/// Entry point for `xvc storage gc`.
+///
+/// Finds the storage with `identifier` and runs [gc_storage] on it.
+pub fn cmd_storage_gc(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    identifier: StorageIdentifier,
+    refs: Vec<String>,
+    keep_last: usize,
+    dry_run: bool,
+) -> Result<()> {
+    let storage = get_storage_record(output_snd, xvc_root, &identifier)?;
+    gc_storage(output_snd, xvc_root, &storage, refs, keep_last, dry_run)?;
+    Ok(())
+}
+
+/// Collects the cache paths referenced by `refs` (all refs if empty), the current store and the
+/// run cache entries in the local cache and the storage, lists the storage and deletes the rest.
+/// Packs are kept if any of their files is referenced.
+///
+/// With `keep_last`, the last `keep_last` versions of each file in the history of each ref are also
+/// kept. With `dry_run`, the files are only listed.
+///
+/// Returns the unreferenced files that are deleted, or would be deleted with `dry_run`.
+pub fn gc_storage(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    storage: &XvcStorage,
+    refs: Vec<String>,
+    keep_last: usize,
+    dry_run: bool,
+) -> Result<Vec<XvcCachePath>> {
+    let git_command = get_absolute_git_command(&xvc_root.config().git.command)?;
+    let xvc_directory = xvc_root.as_path().to_string_lossy().to_string();
+    let refs = git_refs(&git_command, &xvc_directory, refs)?;
+
+    let mut referenced = HashSet::<XvcCachePath>::new();
+    collect_referenced(
+        &xvc_root.load_store()?,
+        &xvc_root.load_store()?,
+        keep_last,
+        &mut referenced,
+    )?;
+    for git_ref in &refs {
+        let before = referenced.len();
+        let temp_dir = tempfile::tempdir()?;
+        extract_store_at_ref(
+            &git_command,
+            &xvc_directory,
+            git_ref,
+            temp_dir.path(),
+            &[
+                <XvcPath as Storable>::type_description(),
+                <ContentDigest as Storable>::type_description(),
+            ],
+        )?;
+        collect_referenced(
+            &XvcStore::<XvcPath>::load_store(temp_dir.path())?,
+            &XvcStore::<ContentDigest>::load_store(temp_dir.path())?,
+            keep_last,
+            &mut referenced,
+        )?;
+        info!(
+            output_snd,
+            "{git_ref}: {} new files referenced",
+            referenced.len() - before
+        );
+    }
+
+    let list_event = storage.list(output_snd, xvc_root)?;
+    let before = referenced.len();
+    collect_run_cache_outputs(
+        output_snd,
+        xvc_root,
+        storage,
+        &list_event.paths,
+        &mut referenced,
+    )?;
+    info!(
+        output_snd,
+        "Run cache: {} new files referenced",
+        referenced.len() - before
+    );
+    let packed_files =
+        XvcPackedFiles::load_listed(storage, output_snd, xvc_root, &list_event.paths)?;
+    let kept_packs = referenced
+        .iter()
+        .filter_map(|cp| packed_files.get(cp).map(|pf| pf.pack.clone()))
+        .collect::<HashSet<_>>();
+
+    let mut unreferenced = list_event
+        .paths
+        .iter()
+        .filter_map(|p| cache_path_of(p.as_str()))
+        .map(|cp| XvcCachePath::custom(&cp))
+        .filter(|cp| !referenced.contains(cp) && !kept_packs.contains(cp))
+        .collect::<Vec<_>>();
+    unreferenced.sort();
+
+    if dry_run {
+        for cp in &unreferenced {
+            output!(output_snd, "[DRY-RUN] {cp}");
+        }
+        output!(
+            output_snd,
+            "{} of {} files in {} would be deleted",
+            unreferenced.len(),
+            list_event.paths.len(),
+            storage.name()
+        );
+        return Ok(unreferenced);
+    }
+
+    if unreferenced.is_empty() {
+        output!(output_snd, "No unreferenced files in {}", storage.name());
+        return Ok(unreferenced);
+    }
+
+    // Record the deleted files even if some of them cannot be deleted
+    let (delete_event, delete_error) = match storage.delete(output_snd, xvc_root, &unreferenced) {
+        Ok(delete_event) => (delete_event, None),
+        Err(Error::PartialDelete { deleted, errors }) => (
+            deleted.clone(),
+            Some(Error::PartialDelete { deleted, errors }),
+        ),
+        Err(e) => return Err(e),
+    };
+    output!(
+        output_snd,
+        "Deleted {} files from {}",
+        delete_event.paths.len(),
+        storage.name()
+    );
+
+    xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
+        store.insert(
+            xvc_root.new_entity(),
+            XvcStorageEvent::Delete(delete_event.clone()),
+        );
+        Ok(())
+    })?;
+
+    match delete_error {
+        Some(e) => Err(e),
+        None => Ok(unreferenced),
+    }
+}
+
+/// Returns `refs`, or all refs in the repository if it's empty.
+pub(crate) fn git_refs(
+    git_command: &str,
+    xvc_directory: &str,
+    refs: Vec<String>,
+) -> Result<Vec<String>> {
+    if !refs.is_empty() {
+        return Ok(refs);
+    }
+    Ok(exec_git(
+        git_command,
+        xvc_directory,
+        &["for-each-ref", "--format=%(refname)"],
+    )?
+    .lines()
+    .map(|l| l.trim().to_string())
+    .filter(|l| !l.is_empty())
+    .collect())
+}
+
+/// Adds the run cache entries in `listed` and the outputs listed in the run cache entries to
+/// `referenced`. Entries in `listed` that are not in the local run cache are received from
+/// `storage`.
+fn collect_run_cache_outputs<S: XvcStorageOperations>(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    storage: &S,
+    listed: &[XvcStoragePath],
+    referenced: &mut HashSet<XvcCachePath>,
+) -> Result<()> {
+    let mut entry_files = Vec::new();
+    let local_dir = xvc_root.xvc_dir().join(RUN_CACHE_DIR);
+    if local_dir.is_dir() {
+        for prefix_dir in fs::read_dir(&local_dir)? {
+            for entry in fs::read_dir(prefix_dir?.path())? {
+                entry_files.push(entry?.path());
+            }
+        }
+    }
+
+    let entries = listed
+        .iter()
+        .filter_map(|p| cache_path_of(p.as_str()))
+        .filter(|cp| cp.starts_with(RUN_CACHE_DIR))
+        .map(|cp| XvcCachePath::custom(&cp))
+        .collect::<Vec<_>>();
+    let missing = entries
+        .iter()
+        .filter(|cp| !cp.to_absolute_path(xvc_root).exists())
+        .cloned()
+        .collect::<Vec<_>>();
+    // The entries themselves are kept too
+    referenced.extend(entries);
+    let temp_dir = if missing.is_empty() {
+        None
+    } else {
+        let (temp_dir, _) = storage.receive(output_snd, xvc_root, &missing, false)?;
+        for cp in &missing {
+            entry_files.push(temp_dir.temp_cache_path(cp)?.to_path_buf());
+        }
+        Some(temp_dir)
+    };
+
+    for entry_file in entry_files.iter().filter(|f| f.is_file()) {
+        let entry: RunCacheEntryOutputs = serde_json::from_str(&fs::read_to_string(entry_file)?)?;
+        for output in entry.outputs {
+            referenced.insert(XvcCachePath::new(&output.path, &output.content_digest)?);
+        }
+    }
+
+    if let Some(temp_dir) = temp_dir {
+        fs::remove_dir_all(temp_dir.path())?;
+    }
+    Ok(())
+}
+
+/// Adds the cache paths of the current files in the stores, and the last `keep_last` versions of
+/// each file to `referenced`.
+fn collect_referenced(
+    path_store: &XvcStore<XvcPath>,
+    digest_store: &XvcStore<ContentDigest>,
+    keep_last: usize,
+    referenced: &mut HashSet<XvcCachePath>,
+) -> Result<()> {
+    for (xe, xvc_path) in path_store.iter() {
+        if let Some(digest) = digest_store.get(xe) {
+            referenced.insert(XvcCachePath::new(xvc_path, digest)?);
+        }
+
+        if keep_last > 0 {
+            let mut versions = Vec::<ContentDigest>::new();
+            for event in digest_store.all_event_log_for_entity(*xe)?.iter() {
+                if let Event::Add { value, .. } = event {
+                    versions.retain(|v| v != value);
+                    versions.push(*value);
+                }
+            }
+            for digest in versions.iter().rev().take(keep_last) {
+                referenced.insert(XvcCachePath::new(xvc_path, digest)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::pack::{XvcPackedFiles, send_packs};
+    use crate::storage::test_xvc_root;
+    use crate::{XvcLocalStorage, XvcStorageGuid};
+    use std::path::Path;
+    use tempfile::TempDir;
+    use xvc_core::{HashAlgorithm, XvcDigest};
+
+    fn local_storage(
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<(TempDir, XvcStorage)> {
+        let storage_dir = tempfile::tempdir()?;
+        let mut storage = XvcStorage::new(XvcLocalStorage {
+            guid: XvcStorageGuid::new(),
+            name: "gc-test".to_string(),
+            path: storage_dir.path().to_path_buf(),
+        });
+        storage.init(output_snd, xvc_root)?;
+        Ok((storage_dir, storage))
+    }
+
+    /// Writes `content` of `path` to the cache without recording it in the stores
+    fn cache_file(xvc_root: &XvcRoot, path: &str, content: &str) -> Result<XvcCachePath> {
+        let xvc_path = XvcPath::new(xvc_root, xvc_root.absolute_path(), Path::new(path))?;
+        let digest = ContentDigest::from(XvcDigest::from_content(content, HashAlgorithm::Blake3));
+        let cache_path = XvcCachePath::new(&xvc_path, &digest)?;
+        let abs_path = cache_path.to_absolute_path(xvc_root);
+        fs::create_dir_all(abs_path.parent().unwrap())?;
+        fs::write(abs_path, content)?;
+        Ok(cache_path)
+    }
+
+    /// Records `content` as the current version of `path` in the stores and writes it to the cache
+    fn track(xvc_root: &XvcRoot, path: &str, content: &str) -> Result<XvcCachePath> {
+        let xvc_path = XvcPath::new(xvc_root, xvc_root.absolute_path(), Path::new(path))?;
+        let digest = ContentDigest::from(XvcDigest::from_content(content, HashAlgorithm::Blake3));
+        let path_store: XvcStore<XvcPath> = xvc_root.load_store()?;
+        let xe = match path_store.entity_by_value(&xvc_path) {
+            Some(xe) => xe,
+            None => {
+                let xe = xvc_root.new_entity();
+                xvc_root.with_store_mut(|store: &mut XvcStore<XvcPath>| {
+                    store.insert(xe, xvc_path.clone());
+                    Ok(())
+                })?;
+                xe
+            }
+        };
+        xvc_root.with_store_mut(|store: &mut XvcStore<ContentDigest>| {
+            store.update(xe, digest);
+            Ok(())
+        })?;
+        cache_file(xvc_root, path, content)
+    }
+
+    /// Removes `path` from the stores
+    fn untrack(xvc_root: &XvcRoot, path: &str) -> Result<()> {
+        let xvc_path = XvcPath::new(xvc_root, xvc_root.absolute_path(), Path::new(path))?;
+        let path_store: XvcStore<XvcPath> = xvc_root.load_store()?;
+        let xe = path_store.entity_by_value(&xvc_path).unwrap();
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcPath>| {
+            store.remove(xe);
+            Ok(())
+        })?;
+        xvc_root.with_store_mut(|store: &mut XvcStore<ContentDigest>| {
+            store.remove(xe);
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Commits the stores and points `branch` to the commit
+    fn commit_store(xvc_root: &XvcRoot, branch: &str) -> Result<()> {
+        let xvc_directory = xvc_root.as_path().to_string_lossy();
+        exec_git("git", &xvc_directory, &["add", ".xvc/store"])?;
+        exec_git("git", &xvc_directory, &["commit", "--quiet", "-m", branch])?;
+        exec_git("git", &xvc_directory, &["branch", "--force", branch])?;
+        Ok(())
+    }
+
+    fn listed(
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        storage: &XvcStorage,
+    ) -> Result<HashSet<XvcCachePath>> {
+        Ok(storage
+            .list(output_snd, xvc_root)?
+            .paths
+            .iter()
+            .filter_map(|p| cache_path_of(p.as_str()))
+            .map(|cp| XvcCachePath::custom(&cp))
+            .collect())
+    }
+
+    #[test]
+    fn test_gc_keeps_referenced_files() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, _output_rcv) = crossbeam_channel::unbounded();
+        let (_storage_dir, storage) = local_storage(&output_snd, &xvc_root)?;
+
+        let a1 = track(&xvc_root, "gc-refs/a.txt", "a1")?;
+        let b = track(&xvc_root, "gc-refs/b.txt", "b")?;
+        commit_store(&xvc_root, "gc-refs")?;
+        let a2 = track(&xvc_root, "gc-refs/a.txt", "a2")?;
+        let a3 = track(&xvc_root, "gc-refs/a.txt", "a3")?;
+        untrack(&xvc_root, "gc-refs/b.txt")?;
+        let x = cache_file(&xvc_root, "gc-refs/x.txt", "x")?;
+        let all = vec![a1.clone(), a2.clone(), a3.clone(), b.clone(), x.clone()];
+        storage.send(&output_snd, &xvc_root, &all, false)?;
+        let all = all.into_iter().collect::<HashSet<_>>();
+        assert_eq!(listed(&output_snd, &xvc_root, &storage)?, all);
+
+        // a1 and b are only referenced by the other branch
+        let refs = vec!["gc-refs".to_string()];
+        let mut unreferenced = vec![a2.clone(), x.clone()];
+        unreferenced.sort();
+        assert_eq!(
+            gc_storage(&output_snd, &xvc_root, &storage, refs.clone(), 0, true)?,
+            unreferenced
+        );
+        // a2 is one of the last 2 versions of a.txt
+        assert_eq!(
+            gc_storage(&output_snd, &xvc_root, &storage, refs.clone(), 2, true)?,
+            vec![x.clone()]
+        );
+        // Dry runs don't delete anything
+        assert_eq!(listed(&output_snd, &xvc_root, &storage)?, all);
+
+        assert_eq!(
+            gc_storage(&output_snd, &xvc_root, &storage, refs, 2, false)?,
+            vec![x.clone()]
+        );
+        let mut kept = all;
+        kept.remove(&x);
+        assert_eq!(listed(&output_snd, &xvc_root, &storage)?, kept);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_packs_and_run_cache() -> Result<()> {
+        let xvc_root = test_xvc_root();
+        let (output_snd, _output_rcv) = crossbeam_channel::unbounded();
+        let (_storage_dir, storage) = local_storage(&output_snd, &xvc_root)?;
+
+        // A pack is kept if any of its files is referenced
+        let p = track(&xvc_root, "gc-packs/p.txt", "p")?;
+        let u1 = cache_file(&xvc_root, "gc-packs/u1.txt", "u1")?;
+        let u2 = cache_file(&xvc_root, "gc-packs/u2.txt", "u2")?;
+        send_packs(&storage, &output_snd, &xvc_root, &[p.clone(), u1], false)?;
+        send_packs(
+            &storage,
+            &output_snd,
+            &xvc_root,
+            std::slice::from_ref(&u2),
+            false,
+        )?;
+        let packed_files = XvcPackedFiles::from_events(&xvc_root, storage.backend().guid())?;
+        let kept_pack = packed_files.get(&p).unwrap().pack.clone();
+        let deleted_pack = packed_files.get(&u2).unwrap().pack.clone();
+
+        // Outputs of the run cache entries in the local cache and in the storage are kept
+        let mut run_cache = Vec::new();
+        for (entry_path, output_path) in [
+            ("run-cache/9c0/1de.json", "gc-run-cache/local.txt"),
+            ("run-cache/9c1/1de.json", "gc-run-cache/remote.txt"),
+        ] {
+            let output = cache_file(&xvc_root, output_path, output_path)?;
+            let digest =
+                ContentDigest::from(XvcDigest::from_content(output_path, HashAlgorithm::Blake3));
+            let entry = XvcCachePath::custom(entry_path);
+            let entry_file = entry.to_absolute_path(&xvc_root);
+            fs::create_dir_all(entry_file.parent().unwrap())?;
+            fs::write(
+                &entry_file,
+                serde_json::json!({
+                    "outputs": [{"path": output_path, "content_digest": digest}]
+                })
+                .to_string(),
+            )?;
+            run_cache.push(output);
+            run_cache.push(entry);
+        }
+        storage.send(&output_snd, &xvc_root, &run_cache, false)?;
+        fs::remove_file(
+            XvcCachePath::custom("run-cache/9c1/1de.json").to_absolute_path(&xvc_root),
+        )?;
+
+        assert_eq!(
+            gc_storage(&output_snd, &xvc_root, &storage, vec![], 0, false)?,
+            vec![deleted_pack.clone()]
+        );
+        let listed = listed(&output_snd, &xvc_root, &storage)?;
+        assert!(listed.contains(&kept_pack));
+        assert!(!listed.contains(&deleted_pack));
+        for cp in &run_cache {
+            assert!(listed.contains(cp), "{cp} is deleted");
+        }
+        Ok(())
+    }
+}
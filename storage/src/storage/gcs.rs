@@ -1,16 +1,18 @@
 //! Google Cloud Storage remote
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
-use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use serde::{Deserialize, Serialize};
 use xvc_core::R1NStore;
+use xvc_core::XvcRoot;
 use xvc_core::{XvcOutputSender, info, watch};
 
-use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
 use super::async_common::XvcS3StorageOperations;
+use super::credentials::XvcCredentialSpec;
 
 /// Configure a new Google Cloud Storage remote.
 ///
@@ -20,6 +22,8 @@ use super::async_common::XvcS3StorageOperations;
 /// This creates a [XvcGcsStorage], calls its
 /// [init][XvcGcsStorage::init] function to create/update guid, and
 /// saves [XvcStorageInitEvent] and [XvcStorage] in ECS.
+/// TODO: Reduce the number of parameters of this function.
+#[allow(clippy::too_many_arguments)]
 pub fn cmd_new_gcs(
     _input: std::io::StdinLock,
     output_snd: &XvcOutputSender,
@@ -28,6 +32,7 @@ pub fn cmd_new_gcs(
     bucket_name: String,
     region: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcGcsStorage {
         guid: XvcStorageGuid::new(),
@@ -35,6 +40,7 @@ pub fn cmd_new_gcs(
         region,
         bucket_name,
         storage_prefix,
+        profile,
     };
 
     info!(output_snd, "New Storage: {:#?}", storage);
@@ -47,7 +53,7 @@ pub fn cmd_new_gcs(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Gcs(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -57,6 +63,62 @@ pub fn cmd_new_gcs(
     Ok(())
 }
 
+/// Add a new Google Cloud Storage storage
+///
+/// Reads credentials from `GCS_ACCESS_KEY_ID` and `GCS_SECRET_ACCESS_KEY` environment variables.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+/// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+/// The keys can also be kept in the credentials file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "gcs")]
+pub struct NewGcsStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Bucket name
+    #[arg(long)]
+    pub bucket_name: String,
+    /// Region of the server, e.g., europe-west3
+    #[arg(long)]
+    pub region: String,
+    /// You can set a directory in the bucket with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the Gcs backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Gcs",
+    command: NewGcsStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewGcsStorageCLI {
+            name,
+            bucket_name,
+            region,
+            storage_prefix,
+            profile,
+        } = NewGcsStorageCLI::from_arg_matches(matches)?;
+        cmd_new_gcs(
+            input,
+            output_snd,
+            xvc_root,
+            name,
+            bucket_name,
+            region,
+            storage_prefix,
+            profile,
+        )
+    },
+    deserialize: deserialize_backend::<XvcGcsStorage>,
+};
+
 /// A Google Cloud Storage remote.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcGcsStorage {
@@ -70,8 +132,16 @@ pub struct XvcGcsStorage {
     pub bucket_name: String,
     /// The path prefix on the storage.
     pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcGcsStorage, "Gcs", |s| format!(
+    "GCS:     {}\t{}\t{}.{}/{}",
+    s.name, s.guid, s.region, s.bucket_name, s.storage_prefix
+));
+
 impl XvcS3StorageOperations for XvcGcsStorage {
     fn storage_prefix(&self) -> String {
         self.storage_prefix.clone()
@@ -80,8 +150,12 @@ impl XvcS3StorageOperations for XvcGcsStorage {
     fn guid(&self) -> &XvcStorageGuid {
         &self.guid
     }
-    fn get_bucket(&self) -> Result<Box<Bucket>> {
-        let credentials = self.credentials()?;
+
+    fn storage_name(&self) -> &str {
+        &self.name
+    }
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>> {
+        let credentials = self.credentials(xvc_root)?;
         let region = Region::Custom {
             region: self.region.to_owned(),
             endpoint: "https://storage.googleapis.com".to_owned(),
@@ -99,39 +173,14 @@ impl XvcS3StorageOperations for XvcGcsStorage {
         self.region.clone()
     }
 
-    fn credentials(&self) -> Result<Credentials> {
-        // Try storage-specific credentials
-        let specific_access_key_var = format!("XVC_STORAGE_ACCESS_KEY_ID_{}", self.name);
-        let specific_secret_key_var = format!("XVC_STORAGE_SECRET_ACCESS_KEY_{}", self.name);
-
-        if let (Ok(access_key), Ok(secret_key)) = (
-            env::var(&specific_access_key_var),
-            env::var(&specific_secret_key_var),
-        ) {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // Try storage-type credentials
-        let type_access_key_var = "GCS_ACCESS_KEY_ID";
-        let type_secret_key_var = "GCS_SECRET_ACCESS_KEY";
-        if let (Ok(access_key), Ok(secret_key)) =
-            (env::var(type_access_key_var), env::var(type_secret_key_var))
-        {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // If both fail, return an error
-        Err(Error::CloudCredentialsNotFound {
-            storage_name: self.name.clone(),
-            var_pairs: vec![
-                (specific_access_key_var, specific_secret_key_var),
-                (
-                    type_access_key_var.to_string(),
-                    type_secret_key_var.to_string(),
-                ),
-            ],
-        })
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_key_pair(
+            "gcs",
+            &self.name,
+            self.profile.as_deref(),
+            "storage.googleapis.com".to_string(),
+            "GCS_ACCESS_KEY_ID",
+            "GCS_SECRET_ACCESS_KEY",
+        )
     }
 }
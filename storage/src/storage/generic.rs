@@ -1,4 +1,5 @@
 //! The generic storage implementation with shell commands.
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::{collections::HashMap, env, fs, path::Path};
 
 use regex::Regex;
@@ -7,8 +8,12 @@ use serde::{Deserialize, Serialize};
 use subprocess::Exec;
 use xvc_core::R1NStore;
 use xvc_core::{XvcCachePath, XvcRoot};
-use xvc_core::{XvcOutputSender, error, info, warn, watch};
+use xvc_core::{XvcOutputSender, XvcProgress, info, warn, watch};
 
+use super::index::RUN_CACHE_ENTRY_PATTERN;
+use super::limit::{XvcTransferLimits, receive_progress, send_progress};
+use super::pool::{run_for_each, successful_storage_paths};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
 use crate::{Error, Result, XvcStorage, XvcStorageEvent, XvcStorageGuid, XvcStorageOperations};
 
 use super::{
@@ -56,7 +61,7 @@ pub fn cmd_storage_new_generic(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Generic(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -66,6 +71,91 @@ pub fn cmd_storage_new_generic(
     Ok(())
 }
 
+/// Add a new generic storage.
+///
+/// ⚠️ Please note that this is an advanced method to configure storages.
+/// You may damage your repository and local and storage files with incorrect configurations.
+///
+/// Please see https://docs.xvc.dev/ref/xvc-storage-new-generic.html for examples and make
+/// necessary backups.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "generic")]
+pub struct NewGenericStorageCLI {
+    /// Name of the storage.
+    ///
+    /// Recommended to keep this name unique to refer easily.
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Command to initialize the storage.
+    /// This command is run once after defining the storage.
+    ///
+    /// You can use {URL} and {STORAGE_DIR}  as shortcuts.
+    #[arg(long = "init", short = 'i', value_hint=clap::ValueHint::CommandString)]
+    pub init_command: String,
+    /// Command to list the files in storage
+    ///
+    /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
+    #[arg(long = "list", short = 'l', value_hint=clap::ValueHint::CommandString)]
+    pub list_command: String,
+    /// Command to download a file from storage.
+    ///
+    /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
+    #[arg(long = "download", short = 'd', value_hint=clap::ValueHint::CommandString)]
+    pub download_command: String,
+    /// Command to upload a file to storage.
+    ///
+    /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
+    #[arg(long = "upload", short = 'u',value_hint=clap::ValueHint::CommandString )]
+    pub upload_command: String,
+    /// The delete command to remove a file from storage
+    /// You can use {URL} and {STORAGE_DIR} placeholders and define values for these with --url and --storage_dir options.
+    #[arg(long = "delete", short = 'D',value_hint=clap::ValueHint::CommandString )]
+    pub delete_command: String,
+    /// Number of maximum processes to run simultaneously
+    #[arg(long = "processes", short = 'M', default_value_t = 1)]
+    pub max_processes: usize,
+    /// You can set a string to replace {URL} placeholder in commands
+    #[arg(long, value_hint=clap::ValueHint::Url)]
+    pub url: Option<String>,
+    /// You can set a string to replace {STORAGE_DIR} placeholder in commands
+    #[arg(long)]
+    pub storage_dir: Option<String>,
+}
+
+/// Registration of the Generic backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Generic",
+    command: NewGenericStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewGenericStorageCLI {
+            name,
+            init_command,
+            list_command,
+            download_command,
+            upload_command,
+            delete_command,
+            max_processes,
+            url,
+            storage_dir,
+        } = NewGenericStorageCLI::from_arg_matches(matches)?;
+        cmd_storage_new_generic(
+            input,
+            output_snd,
+            xvc_root,
+            name,
+            url,
+            storage_dir,
+            max_processes,
+            init_command,
+            list_command,
+            download_command,
+            upload_command,
+            delete_command,
+        )
+    },
+    deserialize: deserialize_backend::<XvcGenericStorage>,
+};
+
 /// Generic storage implementation
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcGenericStorage {
@@ -96,6 +186,14 @@ pub struct XvcGenericStorage {
     pub max_processes: usize,
 }
 
+crate::xvc_storage_backend!(XvcGenericStorage, "Generic", |s| format!(
+    "Generic: {}\t{}\t{}{}",
+    s.name,
+    s.guid,
+    s.url.as_deref().unwrap_or(""),
+    s.storage_dir.as_deref().unwrap_or("")
+));
+
 impl XvcGenericStorage {
     /// Replace keys with values in `template` using `hash_map`
     fn replace_map_elements(template: &str, hash_map: &HashMap<&str, String>) -> String {
@@ -163,7 +261,7 @@ impl XvcGenericStorage {
 
     /// returns a map that contains keys and values for path elements in commands.
     /// This is used for receive commands that need a temporary dir to download
-    /// before moving to cache.
+    /// before moving to cache, and send commands that send files from another directory.
     /// - `{XVC_GUID}`: The repository GUID used in storage paths.
     /// - `{RELATIVE_CACHE_PATH}` The portion of the cache path after `.xvc/`.
     /// - `{ABSOLUTE_CACHE_PATH}` The absolute path for the cache element in
@@ -206,8 +304,6 @@ impl XvcGenericStorage {
         ])
     }
 
-    // TODO: This and run_for_paths can be merged by receiving a parameter function to
-    // create paths.
     fn run_for_paths_in_temp_dir(
         &self,
         output_snd: &XvcOutputSender,
@@ -215,75 +311,74 @@ impl XvcGenericStorage {
         prepared_cmd: &str,
         temp_dir: &XvcStorageTempDir,
         paths: &[XvcCachePath],
-    ) -> Vec<XvcStoragePath> {
-        let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
-        // TODO: Create a thread/process pool here
-        // TODO: Refactor to use XvcStoragePath and XvcCachePath in replacements
-        paths.iter().for_each(|cache_path| {
+        progress: &XvcProgress,
+    ) -> Result<Vec<XvcStoragePath>> {
+        self.run_commands(output_snd, xvc_root, paths, progress, |cache_path| {
             let pm = self.path_map_with_temp_dir(xvc_root, temp_dir, cache_path);
-            let cmd = Self::replace_map_elements(prepared_cmd, &pm);
-            let cmd_output = Exec::shell(cmd).capture();
-            match cmd_output {
-                Ok(cmd_output) => {
-                    let stdout_str = cmd_output.stdout_str();
-                    let stderr_str = cmd_output.stderr_str();
-
-                    if cmd_output.success() {
-                        info!(output_snd, "{}", stdout_str);
-                        warn!(output_snd, "{}", stderr_str);
-                        let storage_path = XvcStoragePath::new(xvc_root, cache_path);
-                        storage_paths.push(storage_path);
-                    } else {
-                        error!(output_snd, "{}", stderr_str);
-                        warn!(output_snd, "{}", stdout_str);
-                    }
-                }
-
-                Err(err) => {
-                    error!(output_snd, "{}", err);
-                }
-            }
-        });
-
-        storage_paths
+            (
+                Self::replace_map_elements(prepared_cmd, &pm),
+                pm["{ABSOLUTE_CACHE_PATH}"].clone(),
+            )
+        })
     }
+
     fn run_for_paths(
         &self,
         output: &XvcOutputSender,
         xvc_root: &XvcRoot,
         prepared_cmd: &str,
         paths: &[XvcCachePath],
-    ) -> Vec<XvcStoragePath> {
-        let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
-        // TODO: Create a thread/process pool here
-        // TODO: Refactor to use XvcStoragePath and XvcCachePath in replacements
-        paths.iter().for_each(|cache_path| {
+        progress: &XvcProgress,
+    ) -> Result<Vec<XvcStoragePath>> {
+        self.run_commands(output, xvc_root, paths, progress, |cache_path| {
             let pm = self.path_map(xvc_root, cache_path);
-            let cmd = Self::replace_map_elements(prepared_cmd, &pm);
-            let cmd_output = Exec::shell(cmd).capture();
-            match cmd_output {
-                Ok(cmd_output) => {
-                    let stdout_str = cmd_output.stdout_str();
-                    let stderr_str = cmd_output.stderr_str();
-
-                    if cmd_output.success() {
-                        info!(output, "{}", stdout_str);
-                        warn!(output, "{}", stderr_str);
-                        let storage_path = XvcStoragePath::new(xvc_root, cache_path);
-                        storage_paths.push(storage_path);
-                    } else {
-                        error!(output, "{}", stderr_str);
-                        warn!(output, "{}", stdout_str);
-                    }
-                }
+            (
+                Self::replace_map_elements(prepared_cmd, &pm),
+                pm["{ABSOLUTE_CACHE_PATH}"].clone(),
+            )
+        })
+    }
 
-                Err(err) => {
-                    error!(output, "{}", err);
-                }
+    /// Runs the command returned by `cmd_for` for each of `paths` in at most `max_processes`
+    /// processes, or `storage.max_concurrency` if it's lower. Returns the storage paths of the
+    /// commands that succeeded.
+    ///
+    /// `cmd_for` also returns the local path of the file. Its size is reported to `progress` when
+    /// the command succeeds.
+    fn run_commands<F>(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        paths: &[XvcCachePath],
+        progress: &XvcProgress,
+        cmd_for: F,
+    ) -> Result<Vec<XvcStoragePath>>
+    where
+        F: Fn(&XvcCachePath) -> (String, String) + Sync,
+    {
+        // TODO: Refactor to use XvcStoragePath and XvcCachePath in replacements
+        let n_processes =
+            XvcTransferLimits::for_storage(xvc_root, &self.name)?.concurrency(self.max_processes);
+        let results = run_for_each(n_processes, paths, |cache_path| {
+            let (cmd, local_path) = cmd_for(cache_path);
+            let cmd_output = Exec::shell(cmd).capture()?;
+            let stdout_str = cmd_output.stdout_str();
+            let stderr_str = cmd_output.stderr_str();
+
+            if cmd_output.success() {
+                progress.inc(fs::metadata(&local_path).map_or(0, |md| md.len()));
+                info!(output, "{}", stdout_str);
+                warn!(output, "{}", stderr_str);
+                Ok(())
+            } else {
+                Err(Error::ProcessError {
+                    stdout: stdout_str,
+                    stderr: stderr_str,
+                })
             }
         });
 
-        storage_paths
+        Ok(successful_storage_paths(output, xvc_root, results))
     }
 }
 
@@ -340,6 +435,8 @@ impl XvcStorageOperations for XvcGenericStorage {
     ///
     /// {XVC_GUID}/[a-zA-Z][0-9]/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{58}/0
     ///
+    /// and the run cache entries in `{XVC_GUID}/run-cache/`
+    ///
     fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
         let address_map = self.address_map();
         let prepared_cmd = Self::replace_map_elements(&self.list_command, &address_map);
@@ -348,10 +445,11 @@ impl XvcStorageOperations for XvcGenericStorage {
         watch!(cmd_output);
         let xvc_guid = xvc_root.guid().to_owned();
         let re = Regex::new(&format!(
-            "{xvc_guid}/{cp}/{d3}/{d3}/{d58}/0\\..*$",
+            "{xvc_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
             cp = r#"[a-zA-Z][0-9]"#,
             d3 = r#"[0-9A-Fa-f]{3}"#,
-            d58 = r#"[0-9A-Fa-f]{58}"#
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
         ))
         .unwrap();
 
@@ -372,17 +470,26 @@ impl XvcStorageOperations for XvcGenericStorage {
         })
     }
 
-    fn send(
+    fn send_from(
         &self,
         output: &XvcOutputSender,
         xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[XvcCachePath],
         _force: bool,
     ) -> Result<XvcStorageSendEvent> {
         let address_map = self.address_map();
         let prepared_cmd = Self::replace_map_elements(&self.upload_command, &address_map);
         watch!(prepared_cmd);
-        let storage_paths = self.run_for_paths(output, xvc_root, &prepared_cmd, paths);
+        let progress = send_progress(output, source, &self.name, paths);
+        let storage_paths = self.run_for_paths_in_temp_dir(
+            output,
+            xvc_root,
+            &prepared_cmd,
+            source,
+            paths,
+            &progress,
+        )?;
 
         Ok(XvcStorageSendEvent {
             guid: self.guid.clone(),
@@ -401,8 +508,15 @@ impl XvcStorageOperations for XvcGenericStorage {
         let temp_dir = XvcStorageTempDir::new()?;
         let prepared_cmd = Self::replace_map_elements(&self.download_command, &address_map);
         watch!(prepared_cmd);
-        let storage_paths =
-            self.run_for_paths_in_temp_dir(output, xvc_root, &prepared_cmd, &temp_dir, paths);
+        let progress = receive_progress(output, &self.name);
+        let storage_paths = self.run_for_paths_in_temp_dir(
+            output,
+            xvc_root,
+            &prepared_cmd,
+            &temp_dir,
+            paths,
+            &progress,
+        )?;
 
         Ok((
             temp_dir,
@@ -422,7 +536,13 @@ impl XvcStorageOperations for XvcGenericStorage {
         let address_map = self.address_map();
         let prepared_cmd = Self::replace_map_elements(&self.delete_command, &address_map);
         watch!(prepared_cmd);
-        let storage_paths = self.run_for_paths(output, xvc_root, &prepared_cmd, paths);
+        let storage_paths = self.run_for_paths(
+            output,
+            xvc_root,
+            &prepared_cmd,
+            paths,
+            &XvcProgress::disabled(),
+        )?;
 
         Ok(XvcStorageDeleteEvent {
             guid: self.guid.clone(),
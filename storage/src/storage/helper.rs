@@ -0,0 +1,721 @@
+//! External storage helper implementation.
+//!
+//! A helper storage delegates all operations to a long-running executable named
+//! `xvc-storage-<helper>` found in `PATH`, similar to git remote helpers. The executable is started
+//! once per storage operation with the storage URL (if any) as its only argument, and Xvc talks to
+//! it with line-delimited JSON over stdin/stdout.
+//!
+//! Each request is a single JSON object on a line, tagged with a `command` field. The helper must
+//! reply to each request with a single JSON object on a line. Before the reply, it can write any
+//! number of `{"message": "..."}` lines that are shown to the user.
+//!
+//! | Request | Reply |
+//! |---------|-------|
+//! | `{"command": "capabilities"}` | `{"capabilities": ["init", "list", "send", "receive", "delete", "share"]}` |
+//! | `{"command": "init", "guid": "<storage-guid>"}` | `{"guid": "<storage-guid>"}` |
+//! | `{"command": "list", "repo_guid": "<repo-guid>"}` | `{"paths": ["<storage-path>", ...]}` |
+//! | `{"command": "send", "files": [{"local_path": "...", "storage_path": "..."}], "force": false}` | `{"paths": [...], "errors": [...]}` |
+//! | `{"command": "receive", "files": [{"local_path": "...", "storage_path": "..."}]}` | `{"paths": [...], "errors": [...]}` |
+//! | `{"command": "delete", "storage_paths": ["..."]}` | `{"paths": [...], "errors": [...]}` |
+//! | `{"command": "share", "storage_path": "...", "expiration_seconds": 3600}` | `{"url": "..."}` |
+//!
+//! Storage paths are in `{repo_guid}/{cache_path}` form. `local_path` is the absolute path of the
+//! file to upload in `send` and the absolute path to write the downloaded file in `receive`.
+//! Batch replies list the storage paths that succeeded in `paths` and error messages in `errors`.
+//! Any request may be answered with `{"error": "..."}` to report a failure for the whole request.
+//! When the [bandwidth is limited][super::limit], Xvc sends a `send` or `receive` request for each
+//! file and waits between them to keep the rate.
+//!
+//! The helper is expected to exit when its stdin is closed.
+//!
+//! See `storage/examples/xvc-storage-dir.rs` for an example helper that uses a local directory.
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use xvc_core::R1NStore;
+use xvc_core::{XvcCachePath, XvcRoot};
+use xvc_core::{XvcOutputSender, error, info, output, watch};
+
+use super::limit::{XvcRateLimiter, XvcTransferLimits, receive_progress, send_progress};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Error, Result, XvcStorage, XvcStorageEvent, XvcStorageGuid, XvcStorageOperations};
+
+use super::{
+    XvcStorageDeleteEvent, XvcStorageExpiringShareEvent, XvcStorageInitEvent, XvcStorageListEvent,
+    XvcStoragePath, XvcStorageReceiveEvent, XvcStorageSendEvent, XvcStorageTempDir,
+};
+
+/// The prefix of helper executable names. A helper named `foo` is run as `xvc-storage-foo`.
+pub const XVC_STORAGE_HELPER_PREFIX: &str = "xvc-storage-";
+
+/// Entry point for `xvc storage new helper` command.
+///
+/// Creates a new [XvcHelperStorage], calls its [init][XvcHelperStorage::init] and saves the
+/// storage record and init event in the ECS.
+pub fn cmd_new_helper(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    name: String,
+    helper: String,
+    url: Option<String>,
+) -> Result<()> {
+    let mut storage = XvcHelperStorage {
+        guid: XvcStorageGuid::new(),
+        name,
+        helper,
+        url,
+    };
+
+    let init_event = storage.init(output_snd, xvc_root)?;
+
+    xvc_root.with_r1nstore_mut(|store: &mut R1NStore<XvcStorage, XvcStorageEvent>| {
+        let store_e = xvc_root.new_entity();
+        let event_e = xvc_root.new_entity();
+        store.insert(
+            store_e,
+            XvcStorage::new(storage.clone()),
+            event_e,
+            XvcStorageEvent::Init(init_event.clone()),
+        );
+        Ok(())
+    })?;
+
+    info!(output_snd, "Created Helper Storage: {:#?}", storage);
+
+    Ok(())
+}
+
+/// A file to transfer in `send` and `receive` requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelperFile {
+    /// Absolute path of the file in the local file system
+    pub local_path: PathBuf,
+    /// Path of the file in the storage, in `{repo_guid}/{cache_path}` form
+    pub storage_path: String,
+}
+
+/// Requests sent from Xvc to the helper
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum HelperRequest {
+    /// Ask the list of supported commands
+    Capabilities,
+    /// Initialize the storage. The helper may reply with a different GUID if the storage was
+    /// initialized before.
+    Init {
+        /// GUID of the storage
+        guid: String,
+    },
+    /// List all files of the repository in the storage
+    List {
+        /// GUID of the repository
+        repo_guid: String,
+    },
+    /// Upload a batch of files
+    Send {
+        /// Files to upload
+        files: Vec<HelperFile>,
+        /// Overwrite the files in the storage
+        force: bool,
+    },
+    /// Download a batch of files
+    Receive {
+        /// Files to download
+        files: Vec<HelperFile>,
+    },
+    /// Delete a batch of files
+    Delete {
+        /// Files to delete
+        storage_paths: Vec<String>,
+    },
+    /// Create a URL to share a file
+    Share {
+        /// File to share
+        storage_path: String,
+        /// How long the URL should be valid
+        expiration_seconds: u32,
+    },
+}
+
+impl HelperRequest {
+    /// The capability name required to send this request
+    pub fn capability(&self) -> &'static str {
+        match self {
+            HelperRequest::Capabilities => "capabilities",
+            HelperRequest::Init { .. } => "init",
+            HelperRequest::List { .. } => "list",
+            HelperRequest::Send { .. } => "send",
+            HelperRequest::Receive { .. } => "receive",
+            HelperRequest::Delete { .. } => "delete",
+            HelperRequest::Share { .. } => "share",
+        }
+    }
+}
+
+/// Replies sent from the helper to Xvc
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HelperResponse {
+    /// The request failed
+    Error {
+        /// Error message
+        error: String,
+    },
+    /// A message to show to the user. This is not a reply, the helper must send a reply after
+    /// messages.
+    Message {
+        /// Message text
+        message: String,
+    },
+    /// Reply to [HelperRequest::Capabilities]
+    Capabilities {
+        /// Supported commands
+        capabilities: Vec<String>,
+    },
+    /// Reply to [HelperRequest::Init]
+    Init {
+        /// GUID of the storage
+        guid: String,
+    },
+    /// Reply to [HelperRequest::List], [HelperRequest::Send], [HelperRequest::Receive] and
+    /// [HelperRequest::Delete]
+    Paths {
+        /// Storage paths that were listed or transferred successfully
+        paths: Vec<String>,
+        /// Error messages for files that couldn't be transferred
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<String>,
+    },
+    /// Reply to [HelperRequest::Share]
+    Share {
+        /// URL of the shared file
+        url: String,
+    },
+}
+
+/// A running helper process
+pub struct HelperProcess {
+    helper: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    capabilities: Vec<String>,
+}
+
+impl HelperProcess {
+    /// Start the helper executable at `executable` with `url` as its argument and query its
+    /// capabilities.
+    pub fn spawn(executable: &Path, url: Option<&str>) -> Result<Self> {
+        let mut command = Command::new(executable);
+        if let Some(url) = url {
+            command.arg(url);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut process = HelperProcess {
+            helper: executable.to_string_lossy().to_string(),
+            child,
+            stdin,
+            stdout,
+            capabilities: Vec::new(),
+        };
+
+        match process.request_raw(&HelperRequest::Capabilities, &mut |_| ())? {
+            HelperResponse::Capabilities { capabilities } => {
+                process.capabilities = capabilities;
+                Ok(process)
+            }
+            other => Err(process.unexpected(other)),
+        }
+    }
+
+    /// Returns true if the helper supports `command`
+    pub fn supports(&self, command: &str) -> bool {
+        self.capabilities.iter().any(|c| c == command)
+    }
+
+    /// Send `request` and return the reply. `on_message` is called for every message line the
+    /// helper writes before the reply.
+    ///
+    /// Returns an error if the helper doesn't support the request or replies with an error.
+    pub fn request(
+        &mut self,
+        request: &HelperRequest,
+        on_message: &mut dyn FnMut(&str),
+    ) -> Result<HelperResponse> {
+        if !self.supports(request.capability()) {
+            return Err(Error::HelperDoesNotSupport {
+                helper: self.helper.clone(),
+                command: request.capability().to_string(),
+            });
+        }
+        self.request_raw(request, on_message)
+    }
+
+    fn request_raw(
+        &mut self,
+        request: &HelperRequest,
+        on_message: &mut dyn FnMut(&str),
+    ) -> Result<HelperResponse> {
+        let stdin = self.stdin.as_mut().expect("stdin is open until drop");
+        let line = serde_json::to_string(request)?;
+        watch!(line);
+        writeln!(stdin, "{line}")?;
+        stdin.flush()?;
+
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(Error::HelperProtocolError {
+                    helper: self.helper.clone(),
+                    message: format!("Helper exited before replying to {}", request.capability()),
+                });
+            }
+            watch!(line);
+            let response: HelperResponse =
+                serde_json::from_str(&line).map_err(|e| Error::HelperProtocolError {
+                    helper: self.helper.clone(),
+                    message: format!("Cannot parse reply: {e}: {}", line.trim_end()),
+                })?;
+            match response {
+                HelperResponse::Message { message } => on_message(&message),
+                HelperResponse::Error { error } => {
+                    return Err(Error::HelperError {
+                        helper: self.helper.clone(),
+                        message: error,
+                    });
+                }
+                response => return Ok(response),
+            }
+        }
+    }
+
+    fn unexpected(&self, response: HelperResponse) -> Error {
+        Error::HelperProtocolError {
+            helper: self.helper.clone(),
+            message: format!("Unexpected reply: {response:?}"),
+        }
+    }
+}
+
+impl Drop for HelperProcess {
+    fn drop(&mut self) {
+        // Closing stdin signals the helper to exit
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+    }
+}
+
+/// Add a new storage that uses an external helper executable
+///
+/// Runs `xvc-storage-<helper>` from `PATH` and communicates with it using line-delimited JSON
+/// over stdin/stdout. The helper receives the URL as its argument.
+///
+/// See the documentation of `xvc_storage::storage::helper` for the protocol.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "helper")]
+pub struct NewHelperStorageCLI {
+    /// Name of the storage.
+    ///
+    /// Recommended to keep this name unique to refer easily.
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Name of the helper. `xvc-storage-<helper>` executable must be in `PATH`.
+    #[arg(long)]
+    pub helper: String,
+    /// URL passed to the helper
+    #[arg(long)]
+    pub url: Option<String>,
+}
+
+/// Registration of the Helper backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Helper",
+    command: NewHelperStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewHelperStorageCLI { name, helper, url } =
+            NewHelperStorageCLI::from_arg_matches(matches)?;
+        cmd_new_helper(input, output_snd, xvc_root, name, helper, url)
+    },
+    deserialize: deserialize_backend::<XvcHelperStorage>,
+};
+
+/// A storage that delegates operations to an external `xvc-storage-<helper>` executable.
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcHelperStorage {
+    /// GUID of the storage
+    pub guid: XvcStorageGuid,
+    /// Name of the storage
+    pub name: String,
+    /// Name of the helper. The executable `xvc-storage-{helper}` must be in `PATH`.
+    pub helper: String,
+    /// The URL passed to the helper as its argument
+    pub url: Option<String>,
+}
+
+crate::xvc_storage_backend!(XvcHelperStorage, "Helper", |s| format!(
+    "Helper:  {}\t{}\t{} {}",
+    s.name,
+    s.guid,
+    s.helper,
+    s.url.as_deref().unwrap_or("")
+));
+
+impl XvcHelperStorage {
+    fn start(&self) -> Result<HelperProcess> {
+        let executable = which::which(format!("{XVC_STORAGE_HELPER_PREFIX}{}", self.helper))?;
+        HelperProcess::spawn(&executable, self.url.as_deref())
+    }
+
+    /// Runs a batch request and reports errors for individual files.
+    /// Returns the storage paths that succeeded.
+    fn run_batch(
+        &self,
+        output: &XvcOutputSender,
+        request: HelperRequest,
+    ) -> Result<Vec<XvcStoragePath>> {
+        let mut helper = self.start()?;
+        Self::request_paths(&mut helper, output, &request)
+    }
+
+    /// Runs the `send` or `receive` request built by `request_for` for `files`.
+    ///
+    /// When the bandwidth is limited, each file is requested separately from the same helper and
+    /// the next request waits until `limiter` allows the size of the transferred file. Otherwise
+    /// the sizes of the files are reported to the progress of `limiter` after the batch.
+    fn run_transfers(
+        &self,
+        output: &XvcOutputSender,
+        limiter: &XvcRateLimiter,
+        files: Vec<HelperFile>,
+        request_for: impl Fn(Vec<HelperFile>) -> HelperRequest,
+    ) -> Result<Vec<XvcStoragePath>> {
+        if !limiter.is_limited() {
+            let local_paths = files
+                .iter()
+                .map(|f| f.local_path.clone())
+                .collect::<Vec<_>>();
+            let paths = self.run_batch(output, request_for(files))?;
+            // Received files exist only after the batch
+            limiter.progress().inc(
+                local_paths
+                    .iter()
+                    .filter_map(|p| std::fs::metadata(p).ok())
+                    .map(|md| md.len())
+                    .sum(),
+            );
+            return Ok(paths);
+        }
+        let mut helper = self.start()?;
+        let mut paths = Vec::with_capacity(files.len());
+        for file in files {
+            let local_path = file.local_path.clone();
+            paths.extend(Self::request_paths(
+                &mut helper,
+                output,
+                &request_for(vec![file]),
+            )?);
+            limiter.throttle(std::fs::metadata(&local_path).map_or(0, |md| md.len()));
+        }
+        Ok(paths)
+    }
+
+    fn request_paths(
+        helper: &mut HelperProcess,
+        output: &XvcOutputSender,
+        request: &HelperRequest,
+    ) -> Result<Vec<XvcStoragePath>> {
+        match helper.request(request, &mut |m| info!(output, "{}", m))? {
+            HelperResponse::Paths { paths, errors } => {
+                errors.iter().for_each(|e| error!(output, "{}", e));
+                Ok(paths.into_iter().map(XvcStoragePath::from).collect())
+            }
+            other => Err(helper.unexpected(other)),
+        }
+    }
+}
+
+impl XvcStorageOperations for XvcHelperStorage {
+    fn init(
+        &mut self,
+        output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+    ) -> Result<XvcStorageInitEvent> {
+        let mut helper = self.start()?;
+        let request = HelperRequest::Init {
+            guid: self.guid.to_string(),
+        };
+        match helper.request(&request, &mut |m| info!(output, "{}", m))? {
+            HelperResponse::Init { guid } => {
+                self.guid = guid.parse()?;
+                info!(
+                    output,
+                    "Initialized helper storage {} with GUID: {}", self.helper, self.guid
+                );
+                Ok(XvcStorageInitEvent {
+                    guid: self.guid.clone(),
+                })
+            }
+            other => Err(helper.unexpected(other)),
+        }
+    }
+
+    fn list(&self, output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
+        let paths = self.run_batch(
+            output,
+            HelperRequest::List {
+                repo_guid: xvc_root.guid().to_string(),
+            },
+        )?;
+
+        Ok(XvcStorageListEvent {
+            guid: self.guid.clone(),
+            paths,
+        })
+    }
+
+    fn send_from(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
+        paths: &[XvcCachePath],
+        force: bool,
+    ) -> Result<XvcStorageSendEvent> {
+        let files = paths
+            .iter()
+            .map(|cp| {
+                Ok(HelperFile {
+                    local_path: source.temp_cache_path(cp)?.to_path_buf(),
+                    storage_path: XvcStoragePath::new(xvc_root, cp).to_string(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        let limiter = XvcTransferLimits::for_storage(xvc_root, &self.name)?
+            .rate_limiter()
+            .with_progress(send_progress(output, source, &self.name, paths));
+        let paths = self.run_transfers(output, &limiter, files, |files| HelperRequest::Send {
+            files,
+            force,
+        })?;
+
+        Ok(XvcStorageSendEvent {
+            guid: self.guid.clone(),
+            paths,
+        })
+    }
+
+    fn receive(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        paths: &[XvcCachePath],
+        _force: bool,
+    ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
+        let temp_dir = XvcStorageTempDir::new()?;
+        let mut files = Vec::with_capacity(paths.len());
+        for cp in paths {
+            std::fs::create_dir_all(temp_dir.temp_cache_dir(cp)?)?;
+            files.push(HelperFile {
+                local_path: temp_dir.temp_cache_path(cp)?.to_path_buf(),
+                storage_path: XvcStoragePath::new(xvc_root, cp).to_string(),
+            });
+        }
+        let limiter = XvcTransferLimits::for_storage(xvc_root, &self.name)?
+            .rate_limiter()
+            .with_progress(receive_progress(output, &self.name));
+        let paths = self.run_transfers(output, &limiter, files, |files| {
+            HelperRequest::Receive { files }
+        })?;
+
+        Ok((
+            temp_dir,
+            XvcStorageReceiveEvent {
+                guid: self.guid.clone(),
+                paths,
+            },
+        ))
+    }
+
+    fn delete(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        paths: &[XvcCachePath],
+    ) -> Result<XvcStorageDeleteEvent> {
+        let storage_paths = paths
+            .iter()
+            .map(|cp| XvcStoragePath::new(xvc_root, cp).to_string())
+            .collect();
+        let paths = self.run_batch(output, HelperRequest::Delete { storage_paths })?;
+
+        Ok(XvcStorageDeleteEvent {
+            guid: self.guid.clone(),
+            paths,
+        })
+    }
+
+    fn share(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        period: std::time::Duration,
+    ) -> Result<XvcStorageExpiringShareEvent> {
+        let mut helper = self.start()?;
+        if !helper.supports("share") {
+            return Err(Error::StorageDoesNotSupportSignedUrls);
+        }
+        let storage_path = XvcStoragePath::new(xvc_root, path);
+        let expiration_seconds = period.as_secs() as u32;
+        let request = HelperRequest::Share {
+            storage_path: storage_path.to_string(),
+            expiration_seconds,
+        };
+        match helper.request(&request, &mut |m| info!(output, "{}", m))? {
+            HelperResponse::Share { url } => {
+                output!(output, "{}", url);
+                Ok(XvcStorageExpiringShareEvent {
+                    guid: self.guid.clone(),
+                    path: storage_path,
+                    signed_url: url,
+                    expiration_seconds,
+                })
+            }
+            other => Err(helper.unexpected(other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Builds the `xvc-storage-dir` example helper and returns the path of its executable.
+    fn example_helper() -> PathBuf {
+        let output = std::process::Command::new(env!("CARGO"))
+            .args([
+                "build",
+                "--example",
+                "xvc-storage-dir",
+                "--message-format=json",
+            ])
+            .arg("--manifest-path")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+            .output()
+            .expect("Cannot run cargo");
+        assert!(
+            output.status.success(),
+            "Cannot build the example helper: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter(|msg| msg["target"]["name"] == "xvc-storage-dir")
+            .find_map(|msg| msg["executable"].as_str().map(PathBuf::from))
+            .expect("Cargo didn't report the example executable")
+    }
+
+    #[test]
+    fn test_helper_response_parse() -> Result<()> {
+        let r: HelperResponse = serde_json::from_str(r#"{"paths": ["a/b"]}"#)?;
+        assert_eq!(
+            r,
+            HelperResponse::Paths {
+                paths: vec!["a/b".to_string()],
+                errors: vec![]
+            }
+        );
+        let r: HelperResponse = serde_json::from_str(r#"{"error": "failed"}"#)?;
+        assert!(matches!(r, HelperResponse::Error { .. }));
+        let r = serde_json::to_string(&HelperRequest::Delete {
+            storage_paths: vec!["a/b".to_string()],
+        })?;
+        assert_eq!(r, r#"{"command":"delete","storage_paths":["a/b"]}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn test_example_helper_roundtrip() -> Result<()> {
+        let executable = example_helper();
+        let storage_dir = tempfile::tempdir()?;
+        let local_dir = tempfile::tempdir()?;
+        let mut helper = HelperProcess::spawn(&executable, storage_dir.path().to_str())?;
+        let mut ignore = |_: &str| ();
+
+        let guid = XvcStorageGuid::new().to_string();
+        let reply = helper.request(&HelperRequest::Init { guid: guid.clone() }, &mut ignore)?;
+        assert_eq!(reply, HelperResponse::Init { guid: guid.clone() });
+
+        let source = local_dir.path().join("source");
+        fs::write(&source, "content")?;
+        let storage_path = "repo/b3/000/000/0.txt".to_string();
+        let reply = helper.request(
+            &HelperRequest::Send {
+                files: vec![HelperFile {
+                    local_path: source,
+                    storage_path: storage_path.clone(),
+                }],
+                force: false,
+            },
+            &mut ignore,
+        )?;
+        assert!(
+            matches!(reply, HelperResponse::Paths { ref paths, .. } if paths.as_slice() == [storage_path.as_str()])
+        );
+
+        let reply = helper.request(
+            &HelperRequest::List {
+                repo_guid: "repo".to_string(),
+            },
+            &mut ignore,
+        )?;
+        assert!(
+            matches!(reply, HelperResponse::Paths { ref paths, .. } if paths.as_slice() == [storage_path.as_str()])
+        );
+
+        let target = local_dir.path().join("target");
+        helper.request(
+            &HelperRequest::Receive {
+                files: vec![HelperFile {
+                    local_path: target.clone(),
+                    storage_path: storage_path.clone(),
+                }],
+            },
+            &mut ignore,
+        )?;
+        assert_eq!(fs::read_to_string(target)?, "content");
+
+        helper.request(
+            &HelperRequest::Delete {
+                storage_paths: vec![storage_path.clone()],
+            },
+            &mut ignore,
+        )?;
+        assert!(!storage_dir.path().join(&storage_path).exists());
+
+        assert!(matches!(
+            helper.request(
+                &HelperRequest::Share {
+                    storage_path,
+                    expiration_seconds: 60
+                },
+                &mut ignore
+            ),
+            Err(Error::HelperDoesNotSupport { .. })
+        ));
+
+        Ok(())
+    }
+}
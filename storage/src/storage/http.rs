@@ -0,0 +1,387 @@
+//! Read-only HTTP(S) storage implementation.
+//!
+//! An HTTP storage is a static web server that serves files in the same layout as other storages:
+//! `{url}/{repo_guid}/{cache_path}`. It doesn't require credentials and only supports receiving
+//! files. Listing is done via an index file at `{url}/{repo_guid}/.xvc-index`.
+//!
+//! [cmd_export_static] writes this layout to a local directory that can be uploaded to any static
+//! host.
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use xvc_core::R1NStore;
+use xvc_core::XvcCachePath;
+use xvc_core::XvcOutputSender;
+use xvc_core::XvcRoot;
+use xvc_core::{error, info, output, warn};
+
+use super::index::{
+    XVC_STORAGE_INDEX_FILENAME, XvcStorageIndex, XvcStorageIndexObject, local_cache_paths,
+};
+use super::limit::receive_progress;
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use crate::{XvcStorageGuid, XvcStorageOperations};
+
+use super::{
+    XVC_STORAGE_GUID_FILENAME, XvcStorageDeleteEvent, XvcStorageExpiringShareEvent,
+    XvcStorageInitEvent, XvcStorageListEvent, XvcStoragePath, XvcStorageReceiveEvent,
+    XvcStorageSendEvent, XvcStorageTempDir,
+};
+
+/// Entry point for `xvc storage new http` command.
+///
+/// Creates a new [XvcHttpStorage], calls its [init][XvcHttpStorage::init] to read the
+/// `.xvc-guid` file from the server, and saves the storage record and init event in the ECS.
+pub fn cmd_new_http(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    name: String,
+    url: String,
+) -> Result<()> {
+    let mut storage = XvcHttpStorage {
+        guid: XvcStorageGuid::new(),
+        name,
+        url: url.trim_end_matches('/').to_string(),
+    };
+
+    let init_event = storage.init(output_snd, xvc_root)?;
+
+    xvc_root.with_r1nstore_mut(|store: &mut R1NStore<XvcStorage, XvcStorageEvent>| {
+        let store_e = xvc_root.new_entity();
+        let event_e = xvc_root.new_entity();
+        store.insert(
+            store_e,
+            XvcStorage::new(storage.clone()),
+            event_e,
+            XvcStorageEvent::Init(init_event.clone()),
+        );
+        Ok(())
+    })?;
+
+    info!(output_snd, "Created HTTP Storage: {:#?}", storage);
+
+    Ok(())
+}
+
+/// Entry point for `xvc storage export-static` command.
+///
+/// Copies all files in the local cache to `{dir}/{repo_guid}/{cache_path}`, and writes
+/// `{dir}/{repo_guid}/.xvc-index` and `{dir}/.xvc-guid` so the directory can be served by a static
+/// web server and used as an [XvcHttpStorage].
+///
+/// Files already in `dir` are not copied again, so the command can be run repeatedly to update an
+/// export.
+pub fn cmd_export_static(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    dir: PathBuf,
+) -> Result<()> {
+    let repo_guid = xvc_root.guid();
+    fs::create_dir_all(&dir)?;
+
+    let guid_path = dir.join(XVC_STORAGE_GUID_FILENAME);
+    let storage_guid = if guid_path.exists() {
+        XvcStorageGuid::from_str(fs::read_to_string(&guid_path)?.trim())?
+    } else {
+        let guid = XvcStorageGuid::new();
+        fs::write(&guid_path, guid.to_string())?;
+        guid
+    };
+
+    let repo_dir = dir.join(repo_guid);
+    let mut cache_paths = local_cache_paths(xvc_root)?;
+    cache_paths.sort();
+
+    for cache_path in &cache_paths {
+        let abs_cache_path = cache_path.to_absolute_path(xvc_root);
+        let target = cache_path.inner().to_logical_path(&repo_dir);
+        if target.exists() {
+            info!(output_snd, "[SKIPPED] {}", target.to_string_lossy());
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&abs_cache_path, &target)?;
+        info!(
+            output_snd,
+            "{} -> {}",
+            abs_cache_path,
+            target.to_string_lossy()
+        );
+    }
+
+    let index = cache_paths
+        .iter()
+        .map(|cp| format!("{cp}\n"))
+        .collect::<String>();
+    fs::write(repo_dir.join(XVC_STORAGE_INDEX_FILENAME), index)?;
+
+    output!(
+        output_snd,
+        "Exported {} files to {} (storage GUID: {})",
+        cache_paths.len(),
+        dir.to_string_lossy(),
+        storage_guid
+    );
+
+    Ok(())
+}
+
+/// Add a new read-only HTTP(S) storage
+///
+/// Downloads files from `{url}/{repo_guid}/{cache_path}` without credentials.
+/// The server must serve `{url}/.xvc-guid` and `{url}/{repo_guid}/.xvc-index` files.
+/// Use `xvc storage export-static` to create this layout.
+///
+/// Sending and deleting files are not supported.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "http")]
+pub struct NewHttpStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Base URL of the storage, e.g. https://data.example.com/xvc
+    #[arg(long, value_hint=clap::ValueHint::Url)]
+    pub url: String,
+}
+
+/// Registration of the Http backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Http",
+    command: NewHttpStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewHttpStorageCLI { name, url } = NewHttpStorageCLI::from_arg_matches(matches)?;
+        cmd_new_http(input, output_snd, xvc_root, name, url)
+    },
+    deserialize: deserialize_backend::<XvcHttpStorage>,
+};
+
+/// A read-only storage served from an HTTP(S) server.
+#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcHttpStorage {
+    /// The GUID of the storage.
+    pub guid: XvcStorageGuid,
+    /// The name of the storage.
+    pub name: String,
+    /// The base URL of the storage, without a trailing slash.
+    pub url: String,
+}
+
+crate::xvc_storage_backend!(XvcHttpStorage, "Http", |s| format!(
+    "HTTP:    {}\t{}\t{}",
+    s.name, s.guid, s.url
+));
+
+impl XvcHttpStorage {
+    fn build_storage_path(&self, xvc_root: &XvcRoot, cache_path: &XvcCachePath) -> XvcStoragePath {
+        XvcStoragePath::from(format!("{}/{}", xvc_root.guid(), cache_path))
+    }
+
+    fn file_url(&self, storage_path: &str) -> String {
+        format!("{}/{}", self.url, storage_path)
+    }
+
+    /// Sends a GET request and returns the response if the status is successful.
+    fn get(&self, url: &str) -> Result<reqwest::blocking::Response> {
+        let response = Client::new().get(url).send()?;
+        Ok(response.error_for_status()?)
+    }
+}
+
+impl XvcStorageOperations for XvcHttpStorage {
+    /// Reads the `.xvc-guid` file from the server and uses it as the storage GUID.
+    ///
+    /// The file is written by `xvc storage export-static` and must be available at `{url}/.xvc-guid`.
+    fn init(
+        &mut self,
+        output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+    ) -> Result<XvcStorageInitEvent> {
+        let guid_url = self.file_url(XVC_STORAGE_GUID_FILENAME);
+        let guid_str = self.get(&guid_url)?.text()?;
+        self.guid = XvcStorageGuid::from_str(guid_str.trim())?;
+
+        info!(
+            output,
+            "Found HTTP storage {} with GUID: {}", self.url, self.guid
+        );
+
+        Ok(XvcStorageInitEvent {
+            guid: self.guid.clone(),
+        })
+    }
+
+    /// Reads the list of files from `{url}/{repo_guid}/.xvc-index`.
+    fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
+        let repo_guid = xvc_root.guid();
+        let index_url = self.file_url(&format!("{repo_guid}/{XVC_STORAGE_INDEX_FILENAME}"));
+        let index = self.get(&index_url)?.text()?;
+
+        Ok(XvcStorageListEvent {
+            guid: self.guid.clone(),
+            paths: XvcStorageIndex::parse(&index).storage_paths(xvc_root),
+        })
+    }
+
+    fn send_from(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _source: &XvcStorageTempDir,
+        _paths: &[XvcCachePath],
+        _force: bool,
+    ) -> Result<XvcStorageSendEvent> {
+        Err(Error::StorageIsReadOnly {
+            storage_name: self.name.clone(),
+        })
+    }
+
+    fn receive(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        paths: &[XvcCachePath],
+        _force: bool,
+    ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
+        let temp_dir = XvcStorageTempDir::new()?;
+        let mut received_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+        let progress = receive_progress(output, &self.name);
+
+        for cache_path in paths {
+            let storage_path = self.build_storage_path(xvc_root, cache_path);
+            let url = self.file_url(storage_path.as_str());
+            let cache_dir = temp_dir.temp_cache_dir(cache_path)?;
+            fs::create_dir_all(&cache_dir)?;
+            let local_path = temp_dir.temp_cache_path(cache_path)?;
+
+            let result = self.get(&url).and_then(|response| {
+                let mut file = fs::File::create(&local_path)?;
+                std::io::copy(&mut progress.reader(response), &mut file)?;
+                Ok(())
+            });
+
+            match result {
+                Ok(_) => {
+                    info!(output, "{} -> {}", url, local_path);
+                    received_paths.push(storage_path);
+                }
+                Err(err) => {
+                    // Don't leave partial downloads in the temp dir
+                    let _ = fs::remove_file(&local_path);
+                    error!(output, "{}", err)
+                }
+            }
+        }
+
+        Ok((
+            temp_dir,
+            XvcStorageReceiveEvent {
+                guid: self.guid.clone(),
+                paths: received_paths,
+            },
+        ))
+    }
+
+    fn delete(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _paths: &[XvcCachePath],
+    ) -> Result<XvcStorageDeleteEvent> {
+        Err(Error::StorageIsReadOnly {
+            storage_name: self.name.clone(),
+        })
+    }
+
+    /// Files in an HTTP storage are already public. This returns the plain URL of the file, which
+    /// doesn't expire.
+    fn share(
+        &self,
+        output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        period: std::time::Duration,
+    ) -> Result<XvcStorageExpiringShareEvent> {
+        let storage_path = self.build_storage_path(xvc_root, path);
+        let url = self.file_url(storage_path.as_str());
+        warn!(output, "HTTP storage URLs are public and don't expire");
+        output!(output, "{}", url);
+
+        Ok(XvcStorageExpiringShareEvent {
+            guid: self.guid.clone(),
+            path: storage_path,
+            signed_url: url,
+            expiration_seconds: period.as_secs() as u32,
+        })
+    }
+
+    /// Reads `{url}/{repo_guid}/.xvc-index`. Returns `None` if the server responds with 404.
+    fn read_index(
+        &self,
+        _output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<Option<XvcStorageIndexObject>> {
+        let repo_guid = xvc_root.guid();
+        let index_url = self.file_url(&format!("{repo_guid}/{XVC_STORAGE_INDEX_FILENAME}"));
+        let response = Client::new().get(&index_url).send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let content = response.error_for_status()?.text()?;
+        Ok(Some(XvcStorageIndexObject {
+            content,
+            version: None,
+        }))
+    }
+
+    fn write_index(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _content: &str,
+        _version: Option<&str>,
+    ) -> Result<()> {
+        Err(Error::StorageIsReadOnly {
+            storage_name: self.name.clone(),
+        })
+    }
+
+    /// Sends a request with `Range` header. Returns `None` if the server ignores the header.
+    fn receive_range(
+        &self,
+        _output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        // An empty range can't be written in the header
+        if length == 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let storage_path = self.build_storage_path(xvc_root, path);
+        let url = self.file_url(storage_path.as_str());
+        let response = Client::new()
+            .get(&url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", offset, offset + length - 1),
+            )
+            .send()?
+            .error_for_status()?;
+        if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return Ok(None);
+        }
+        Ok(Some(response.bytes()?.to_vec()))
+    }
+}
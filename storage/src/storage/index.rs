@@ -0,0 +1,298 @@
+//! Index manifests for storages.
+//!
+//! Listing a large bucket is slow and may cost money. A storage can keep a manifest of the cache
+//! paths it contains for a repository in [XVC_STORAGE_INDEX_FILENAME], one cache path per line.
+//! When the index exists, [XvcStorage][crate::XvcStorage] uses it instead of listing the storage,
+//! skips the files that are already in the storage while sending, and updates it after sending and
+//! before deleting files.
+//!
+//! The index is always kept as a subset of the files in the storage: paths are added after they
+//! are sent and removed before they are deleted. If an update fails, the worst case is sending a
+//! file again. `xvc storage reindex` rebuilds the index from a real listing.
+//!
+//! Updates use optimistic concurrency. [XvcStorageOperations::read_index] returns a version along
+//! with the content and [XvcStorageOperations::write_index] fails with
+//! [Error::StorageIndexConflict] if the index is changed in the meantime. In that case the update
+//! is applied to the new index and tried again.
+//!
+//! Backends opt in by implementing these two functions. Others are listed as before.
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use xvc_core::{XvcCachePath, XvcOutputSender, XvcRoot, info, warn};
+
+use super::{XvcStorageOperations, XvcStoragePath};
+use crate::{Error, Result};
+
+/// The name of the file that lists the cache paths of a repository in a storage.
+///
+/// It contains one cache path per line. Empty lines and lines starting with `#` are ignored.
+pub const XVC_STORAGE_INDEX_FILENAME: &str = ".xvc-index";
+
+/// Number of times an index update is tried when other processes update the index concurrently.
+const INDEX_UPDATE_ATTEMPTS: usize = 5;
+
+/// The index content and its version as read from the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XvcStorageIndexObject {
+    /// Content of the index file
+    pub content: String,
+    /// An opaque version (e.g. an ETag) to check in [XvcStorageOperations::write_index].
+    /// `None` if the storage doesn't report versions.
+    pub version: Option<String>,
+}
+
+/// The set of cache paths in the index of a storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XvcStorageIndex {
+    paths: BTreeSet<String>,
+}
+
+impl XvcStorageIndex {
+    /// Parse the index from the content of an index file
+    pub fn parse(content: &str) -> Self {
+        let paths = content
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect();
+        Self { paths }
+    }
+
+    /// Build an index from the storage paths of a real listing.
+    ///
+    /// Storage paths end with the cache path, e.g. `{prefix}/{guid}/b3/123/456/789...abc/0.txt`.
+    /// Paths that don't look like cache paths are ignored.
+    pub fn from_storage_paths<'a>(paths: impl IntoIterator<Item = &'a XvcStoragePath>) -> Self {
+        let paths = paths
+            .into_iter()
+            .filter_map(|p| cache_path_of(p.as_str()))
+            .collect();
+        Self { paths }
+    }
+
+    /// Whether the cache path is in the index
+    pub fn contains(&self, cache_path: &XvcCachePath) -> bool {
+        self.paths.contains(&cache_path.to_string())
+    }
+
+    /// Add a cache path to the index
+    pub fn insert(&mut self, cache_path: String) -> bool {
+        self.paths.insert(cache_path)
+    }
+
+    /// Remove a cache path from the index
+    pub fn remove(&mut self, cache_path: &str) -> bool {
+        self.paths.remove(cache_path)
+    }
+
+    /// Number of paths in the index
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Cache paths in the index
+    pub fn cache_paths(&self) -> Vec<XvcCachePath> {
+        self.paths.iter().map(|p| XvcCachePath::custom(p)).collect()
+    }
+
+    /// Storage paths in `{repo_guid}/{cache_path}` form, as used in
+    /// [XvcStorageListEvent][super::XvcStorageListEvent]s built from the index.
+    pub fn storage_paths(&self, xvc_root: &XvcRoot) -> Vec<XvcStoragePath> {
+        self.cache_paths()
+            .iter()
+            .map(|cp| XvcStoragePath::new(xvc_root, cp))
+            .collect()
+    }
+}
+
+impl Display for XvcStorageIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for p in &self.paths {
+            writeln!(f, "{p}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The directory of pipeline run cache entries in the cache and in storages
+pub const RUN_CACHE_DIR: &str = "run-cache";
+
+/// The pattern of run cache entry paths in storages, `run-cache/{3 hex}/{rest of the key}.json`
+pub(crate) const RUN_CACHE_ENTRY_PATTERN: &str = r#"run-cache/[0-9A-Fa-f]{3}/[0-9A-Fa-f]+\.json"#;
+
+/// Returns the cache path part of a storage path.
+///
+/// Cache paths have 5 components: `{digest type}/{3 hex}/{3 hex}/{58 hex}/0.{ext}`. Run cache
+/// entries have 3: `run-cache/{3 hex}/{hex}.json`
+pub(crate) fn cache_path_of(storage_path: &str) -> Option<String> {
+    let mut components = storage_path.rsplitn(6, '/').take(5).collect::<Vec<_>>();
+    if components.len() >= 3 && components[2] == RUN_CACHE_DIR && components[0].ends_with(".json") {
+        components.truncate(3);
+    } else if components.len() != 5 || !components[0].starts_with("0.") {
+        return None;
+    }
+    components.reverse();
+    Some(components.join("/"))
+}
+
+/// Read and parse the index of `storage`.
+///
+/// Returns `None` if the storage doesn't have an index.
+pub fn read_index<S: XvcStorageOperations + ?Sized>(
+    storage: &S,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+) -> Result<Option<(XvcStorageIndex, Option<String>)>> {
+    Ok(storage
+        .read_index(output_snd, xvc_root)?
+        .map(|obj| (XvcStorageIndex::parse(&obj.content), obj.version)))
+}
+
+/// Add `added` and remove `removed` cache paths from the index of `storage`.
+///
+/// Does nothing if the storage doesn't have an index. Retries when the index is modified
+/// concurrently.
+pub fn update_index<S: XvcStorageOperations + ?Sized>(
+    storage: &S,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    added: &[String],
+    removed: &[String],
+) -> Result<()> {
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    for attempt in 1..=INDEX_UPDATE_ATTEMPTS {
+        let Some((mut index, version)) = read_index(storage, output_snd, xvc_root)? else {
+            return Ok(());
+        };
+        removed.iter().for_each(|p| {
+            index.remove(p);
+        });
+        added.iter().for_each(|p| {
+            index.insert(p.clone());
+        });
+
+        match storage.write_index(output_snd, xvc_root, &index.to_string(), version.as_deref()) {
+            Ok(()) => return Ok(()),
+            Err(Error::StorageIndexConflict) => {
+                info!(
+                    output_snd,
+                    "Storage index changed while updating. Retrying ({attempt}/{INDEX_UPDATE_ATTEMPTS})"
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    warn!(
+        output_snd,
+        "Cannot update the storage index. Run `xvc storage reindex` to rebuild it."
+    );
+    Err(Error::StorageIndexConflict)
+}
+
+/// Replace the index of `storage` with `index`.
+///
+/// Unlike [update_index], this creates the index if it doesn't exist.
+pub fn replace_index<S: XvcStorageOperations + ?Sized>(
+    storage: &S,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    index: &XvcStorageIndex,
+) -> Result<()> {
+    let content = index.to_string();
+    for _ in 0..INDEX_UPDATE_ATTEMPTS {
+        let version = storage
+            .read_index(output_snd, xvc_root)?
+            .and_then(|obj| obj.version);
+        match storage.write_index(output_snd, xvc_root, &content, version.as_deref()) {
+            Err(Error::StorageIndexConflict) => continue,
+            res => return res,
+        }
+    }
+    Err(Error::StorageIndexConflict)
+}
+
+/// Collects the files in the local cache with the cache path layout:
+/// `[a-zA-Z][0-9]/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{58}/0.*`
+pub(crate) fn local_cache_paths(xvc_root: &XvcRoot) -> Result<Vec<XvcCachePath>> {
+    let re = Regex::new(&format!(
+        "^{cp}/{d3}/{d3}/{d58}/0\\..*$",
+        cp = r#"[a-zA-Z][0-9]"#,
+        d3 = r#"[0-9A-Fa-f]{3}"#,
+        d58 = r#"[0-9A-Fa-f]{58}"#
+    ))
+    .unwrap();
+    let cp_dir_re = Regex::new("^[a-zA-Z][0-9]$").unwrap();
+
+    let xvc_dir = xvc_root.xvc_dir();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(xvc_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && cp_dir_re.is_match(&entry.file_name().to_string_lossy()) {
+            collect_files(&entry.path(), &mut files)?;
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .filter_map(|p| {
+            let rel = p.strip_prefix(xvc_dir).ok()?;
+            let rel = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            re.is_match(&rel).then(|| XvcCachePath::custom(&rel))
+        })
+        .collect())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_from_storage_paths() {
+        let d58 = "a".repeat(58);
+        let paths = [
+            XvcStoragePath::from(format!("prefix/guid/b3/123/456/{d58}/0.txt")),
+            XvcStoragePath::from(format!("guid/b3/abc/def/{d58}/0.bin")),
+            XvcStoragePath::from(format!("guid/run-cache/abc/{d58}.json")),
+            XvcStoragePath::from("guid/.xvc-index".to_string()),
+        ];
+        let index = XvcStorageIndex::from_storage_paths(&paths);
+        assert_eq!(index.len(), 3);
+        assert_eq!(
+            index.to_string(),
+            format!("b3/123/456/{d58}/0.txt\nb3/abc/def/{d58}/0.bin\nrun-cache/abc/{d58}.json\n")
+        );
+        assert_eq!(XvcStorageIndex::parse(&index.to_string()), index);
+    }
+}
@@ -0,0 +1,164 @@
+//! Transfer journal to continue interrupted transfers.
+//!
+//! Long transfers may be interrupted by a dropped connection or by the user. The journal of a
+//! storage keeps the progress of its transfers in `.xvc/transfers/{storage guid}.json`, so the next
+//! `xvc file send` or `xvc file bring` continues where the previous one left off:
+//!
+//! - Files sent in an unfinished `send` are skipped. These are forgotten when a `send` finishes
+//!   without errors.
+//! - Multipart uploads keep their upload id and the parts already uploaded. Only the remaining
+//!   parts are uploaded.
+//! - Storages that support it download files to `.xvc/transfers/{storage guid}/` instead of a
+//!   temporary directory. Received files that weren't moved to the cache are not downloaded
+//!   again, and partially downloaded files are resumed.
+//!
+//! `.xvc/transfers` is not tracked by Git.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+use xvc_core::{AbsolutePath, XvcCachePath, XvcRoot};
+
+use super::{XvcStorageGuid, XvcStorageTempDir};
+use crate::Result;
+
+/// The directory in `.xvc` that contains the transfer journals
+pub const XVC_TRANSFERS_DIR: &str = "transfers";
+
+/// A multipart upload in progress
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct XvcMultipartUpload {
+    /// Upload id returned by the storage
+    pub upload_id: String,
+    /// Size of each part in bytes. The last part may be smaller.
+    pub part_size: u64,
+    /// ETags of the uploaded parts by part number
+    pub parts: BTreeMap<u32, String>,
+}
+
+/// Progress of the transfers to and from a storage
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct XvcTransferJournal {
+    #[serde(skip)]
+    path: Option<AbsolutePath>,
+    /// Cache paths sent in an unfinished send
+    #[serde(default)]
+    sent: BTreeSet<String>,
+    /// Cache paths completely downloaded to the receive directory
+    #[serde(default)]
+    received: BTreeSet<String>,
+    /// Multipart uploads in progress by cache path
+    #[serde(default)]
+    uploads: BTreeMap<String, XvcMultipartUpload>,
+}
+
+impl XvcTransferJournal {
+    /// Load the journal of the storage with `guid`, or an empty journal if there is none.
+    pub fn load(xvc_root: &XvcRoot, guid: &XvcStorageGuid) -> Result<Self> {
+        let path = xvc_root
+            .xvc_dir()
+            .join(XVC_TRANSFERS_DIR)
+            .join(format!("{guid}.json"));
+        let mut journal = if path.exists() {
+            serde_json::from_str::<Self>(&fs::read_to_string(&path)?)?
+        } else {
+            Self::default()
+        };
+        journal.path = Some(path);
+        Ok(journal)
+    }
+
+    /// Write the journal to disk. The file is removed when the journal is empty.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Whether there are no transfers in progress
+    pub fn is_empty(&self) -> bool {
+        self.sent.is_empty() && self.received.is_empty() && self.uploads.is_empty()
+    }
+
+    /// Whether the file is sent in an unfinished send
+    pub fn is_sent(&self, cache_path: &XvcCachePath) -> bool {
+        self.sent.contains(&cache_path.to_string())
+    }
+
+    /// Record that the file is sent
+    pub fn mark_sent(&mut self, cache_path: &XvcCachePath) {
+        self.uploads.remove(&cache_path.to_string());
+        self.sent.insert(cache_path.to_string());
+    }
+
+    /// Forget that the file is sent, e.g., when it's deleted from the storage
+    pub fn forget_sent(&mut self, cache_path: &XvcCachePath) {
+        self.sent.remove(&cache_path.to_string());
+    }
+
+    /// Forget the sent files when a send finishes
+    pub fn clear_sent(&mut self) {
+        self.sent.clear();
+    }
+
+    /// Whether the file is completely downloaded to `receive_dir`
+    pub fn is_received(
+        &self,
+        receive_dir: &XvcStorageTempDir,
+        cache_path: &XvcCachePath,
+    ) -> Result<bool> {
+        Ok(self.received.contains(&cache_path.to_string())
+            && receive_dir.temp_cache_path(cache_path)?.exists())
+    }
+
+    /// Record that the file is completely downloaded
+    pub fn mark_received(&mut self, cache_path: &XvcCachePath) {
+        self.received.insert(cache_path.to_string());
+    }
+
+    /// Forget the received files that are moved out of `receive_dir`
+    pub fn prune_received(&mut self, receive_dir: &XvcStorageTempDir) {
+        self.received
+            .retain(|cp| receive_dir.path().join(cp).exists());
+    }
+
+    /// The multipart upload of the file in progress
+    pub fn upload(&self, cache_path: &XvcCachePath) -> Option<&XvcMultipartUpload> {
+        self.uploads.get(&cache_path.to_string())
+    }
+
+    /// Record the progress of a multipart upload
+    pub fn set_upload(&mut self, cache_path: &XvcCachePath, upload: XvcMultipartUpload) {
+        self.uploads.insert(cache_path.to_string(), upload);
+    }
+
+    /// Forget a multipart upload
+    pub fn remove_upload(&mut self, cache_path: &XvcCachePath) {
+        self.uploads.remove(&cache_path.to_string());
+    }
+
+    /// The directory to download the files of the storage with `guid`.
+    ///
+    /// Unlike [XvcStorageTempDir::new], the directory is kept between runs to resume downloads.
+    pub fn receive_dir(xvc_root: &XvcRoot, guid: &XvcStorageGuid) -> Result<XvcStorageTempDir> {
+        let dir = xvc_root
+            .xvc_dir()
+            .join(XVC_TRANSFERS_DIR)
+            .join(guid.to_string());
+        fs::create_dir_all(&dir)?;
+        Ok(XvcStorageTempDir::at(dir))
+    }
+}
@@ -0,0 +1,330 @@
+//! Bandwidth and concurrency limits for storage transfers.
+//!
+//! Large transfers can saturate a shared link. `storage.max_bandwidth` limits the transfer rate
+//! and `storage.max_concurrency` limits the number of concurrent transfers of each storage.
+//! `storage.limits` overrides these for individual storages:
+//!
+//! ```toml
+//! [storage]
+//! max_bandwidth = "10M"
+//! max_concurrency = 4
+//! limits = ["office-s3.max_bandwidth=2M", "office-s3.max_concurrency=1"]
+//! ```
+//!
+//! `--limit-rate` option of `xvc file send` and `xvc file bring` sets `storage.limit_rate` for that
+//! command, which overrides the bandwidth of all storages.
+//!
+//! The rate is enforced by a token bucket, [XvcRateLimiter], shared by all concurrent transfers of
+//! an operation. Local storages throttle the copied chunks, S3 compatible storages throttle each
+//! uploaded object or part and the downloaded bytes, and helper storages throttle each file.
+//!
+//! Since every transfer passes its bytes through the limiter, the limiter also reports them to the
+//! [XvcProgress] of the operation. Storages that run external commands report each file when it's
+//! transferred.
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use xvc_core::{XvcCachePath, XvcOutputSender, XvcProgress, XvcRoot};
+
+use super::XvcStorageTempDir;
+use crate::{Error, Result};
+
+/// Size of the chunks read and written by [XvcRateLimiter::copy]
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Parse a transfer rate in bytes per second with an optional `K`, `M` or `G` suffix (powers of
+/// 1024), e.g., `500K` or `1.5M`. A trailing `B` or `/s` is allowed. `0` means unlimited.
+pub fn parse_rate(value: &str) -> Result<u64> {
+    let invalid = || Error::InvalidTransferRate {
+        value: value.to_string(),
+    };
+    let s = value.trim();
+    let s = s.strip_suffix("/s").unwrap_or(s);
+    let s = s.strip_suffix(['B', 'b']).unwrap_or(s);
+    let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1024u64),
+        Some('M') => (&s[..s.len() - 1], 1024 * 1024),
+        Some('G') => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+    let number = number.trim().parse::<f64>().map_err(|_| invalid())?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(invalid());
+    }
+    Ok((number * multiplier as f64).round() as u64)
+}
+
+/// Bandwidth and concurrency limits of a storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XvcTransferLimits {
+    /// Maximum bytes per second. `0` is unlimited.
+    pub max_bandwidth: u64,
+    /// Maximum number of concurrent transfers. `0` uses the storage settings.
+    pub max_concurrency: usize,
+}
+
+impl XvcTransferLimits {
+    /// The limits of the storage named `storage_name` from the configuration.
+    ///
+    /// `storage.limit_rate`, set by `--limit-rate`, overrides the bandwidth of all storages.
+    pub fn for_storage(xvc_root: &XvcRoot, storage_name: &str) -> Result<Self> {
+        let config = &xvc_root.config().storage;
+        let mut limits = Self {
+            max_bandwidth: parse_rate(&config.max_bandwidth)?,
+            max_concurrency: config.max_concurrency,
+        };
+
+        for limit in &config.limits {
+            let invalid = || Error::InvalidStorageLimit {
+                limit: limit.clone(),
+            };
+            let (key, value) = limit.split_once('=').ok_or_else(invalid)?;
+            let (name, setting) = key.trim().rsplit_once('.').ok_or_else(invalid)?;
+            // Limits of other storages are validated too
+            match setting {
+                "max_bandwidth" => {
+                    let rate = parse_rate(value)?;
+                    if name == storage_name {
+                        limits.max_bandwidth = rate;
+                    }
+                }
+                "max_concurrency" => {
+                    let n = value.trim().parse().map_err(|_| invalid())?;
+                    if name == storage_name {
+                        limits.max_concurrency = n;
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        if !config.limit_rate.is_empty() {
+            limits.max_bandwidth = parse_rate(&config.limit_rate)?;
+        }
+
+        Ok(limits)
+    }
+
+    /// The number of concurrent transfers when the storage is configured for `requested`.
+    pub fn concurrency(&self, requested: usize) -> usize {
+        if self.max_concurrency == 0 {
+            requested.max(1)
+        } else {
+            requested.clamp(1, self.max_concurrency)
+        }
+    }
+
+    /// A new rate limiter with the bandwidth limit
+    pub fn rate_limiter(&self) -> XvcRateLimiter {
+        XvcRateLimiter::new(self.max_bandwidth)
+    }
+}
+
+/// A progress for sending `paths` in `source` to the storage named `storage_name`
+pub fn send_progress(
+    output_snd: &XvcOutputSender,
+    source: &XvcStorageTempDir,
+    storage_name: &str,
+    paths: &[XvcCachePath],
+) -> XvcProgress {
+    let total = paths
+        .iter()
+        .filter_map(|cp| source.temp_cache_path(cp).ok())
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|md| md.len())
+        .sum();
+    XvcProgress::new(
+        output_snd,
+        format!("Sending to {storage_name}"),
+        Some(total),
+    )
+}
+
+/// A progress for receiving from the storage named `storage_name`. The total isn't known before the
+/// files are received.
+pub fn receive_progress(output_snd: &XvcOutputSender, storage_name: &str) -> XvcProgress {
+    XvcProgress::new(output_snd, format!("Receiving from {storage_name}"), None)
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+/// A token bucket that limits the transfer rate.
+///
+/// The bucket holds up to a second of transfer. Each transfer takes tokens for its bytes and
+/// waits if the bucket is in debt. Clones share the same bucket.
+#[derive(Debug, Clone, Default)]
+pub struct XvcRateLimiter {
+    bucket: Option<Arc<Mutex<TokenBucket>>>,
+    progress: XvcProgress,
+}
+
+impl XvcRateLimiter {
+    /// A limiter for `rate` bytes per second. `0` is unlimited.
+    pub fn new(rate: u64) -> Self {
+        let bucket = (rate > 0).then(|| {
+            Arc::new(Mutex::new(TokenBucket {
+                rate: rate as f64,
+                tokens: rate as f64,
+                last: Instant::now(),
+            }))
+        });
+        Self {
+            bucket,
+            progress: XvcProgress::disabled(),
+        }
+    }
+
+    /// Report the transferred bytes to `progress`
+    pub fn with_progress(mut self, progress: XvcProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// The progress that receives the transferred bytes
+    pub fn progress(&self) -> &XvcProgress {
+        &self.progress
+    }
+
+    /// Whether the rate is limited
+    pub fn is_limited(&self) -> bool {
+        self.bucket.is_some()
+    }
+
+    /// Takes tokens for `bytes` and returns how long to wait before the transfer.
+    pub fn reserve(&self, bytes: u64) -> Duration {
+        self.progress.inc(bytes);
+        let Some(bucket) = &self.bucket else {
+            return Duration::ZERO;
+        };
+        let mut bucket = bucket.lock().expect("Token bucket lock is not poisoned");
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.last = now;
+        bucket.tokens = (bucket.tokens + elapsed * bucket.rate).min(bucket.rate) - bytes as f64;
+        if bucket.tokens < 0.0 {
+            Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Blocks until `bytes` can be transferred.
+    pub fn throttle(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Async version of [Self::throttle]
+    #[cfg(feature = "async")]
+    pub async fn a_throttle(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Copies `reader` to `writer` in chunks, throttling each chunk. Returns the number of bytes
+    /// copied.
+    pub fn copy<R: Read, W: Write>(&self, reader: &mut R, writer: &mut W) -> io::Result<u64> {
+        let mut buf = vec![0u8; COPY_CHUNK_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(copied),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.throttle(n as u64);
+            writer.write_all(&buf[..n])?;
+            copied += n as u64;
+        }
+    }
+
+    /// Wraps `writer` to throttle the bytes written to it
+    #[cfg(feature = "async")]
+    pub fn writer<W>(&self, writer: W) -> XvcThrottledWriter<W> {
+        XvcThrottledWriter {
+            inner: writer,
+            limiter: self.clone(),
+            delay: None,
+        }
+    }
+}
+
+/// An async writer that waits after each write to keep the rate of its [XvcRateLimiter]
+#[cfg(feature = "async")]
+pub struct XvcThrottledWriter<W> {
+    inner: W,
+    limiter: XvcRateLimiter,
+    delay: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+}
+
+#[cfg(feature = "async")]
+impl<W: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for XvcThrottledWriter<W> {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        use std::future::Future;
+        use std::task::{Poll, ready};
+
+        let this = self.get_mut();
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+        let n = ready!(std::pin::Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        let wait = this.limiter.reserve(n as u64);
+        if !wait.is_zero() {
+            this.delay = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        std::pin::Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        std::pin::Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_and_reserve() {
+        assert_eq!(parse_rate("0").unwrap(), 0);
+        assert_eq!(parse_rate("1000").unwrap(), 1000);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("1.5MB/s").unwrap(), 1024 * 1024 * 3 / 2);
+        assert_eq!(parse_rate("2g").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("-1M").is_err());
+
+        let limiter = XvcRateLimiter::new(1000);
+        // The bucket starts full
+        assert!(limiter.reserve(1000) < Duration::from_millis(10));
+        let wait = limiter.reserve(500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        assert_eq!(XvcRateLimiter::new(0).reserve(u64::MAX), Duration::ZERO);
+    }
+}
@@ -1,16 +1,25 @@
 //! Local storage implementation
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::{
     fs::{self, create_dir_all},
-    path::PathBuf,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
     str::FromStr,
+    time::Duration,
 };
 
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
 use xvc_core::R1NStore;
 use xvc_core::XvcRoot;
-use xvc_core::{XvcOutputSender, info};
+use xvc_core::{XvcOutputSender, error, info};
 
+use super::index::{RUN_CACHE_ENTRY_PATTERN, XVC_STORAGE_INDEX_FILENAME, XvcStorageIndexObject};
+use super::limit::{XvcRateLimiter, XvcTransferLimits, receive_progress, send_progress};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
 use super::{
     XVC_STORAGE_GUID_FILENAME, XvcCachePath, XvcStorageDeleteEvent, XvcStorageGuid,
     XvcStorageInitEvent, XvcStorageListEvent, XvcStorageOperations, XvcStoragePath,
@@ -38,7 +47,7 @@ pub fn cmd_storage_new_local(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Local(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -48,6 +57,34 @@ pub fn cmd_storage_new_local(
     Ok(())
 }
 
+/// Add a new local storage
+///
+/// A local storage is a directory accessible from the local file system.
+/// Xvc will use common file operations for this directory without accessing the network.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "local")]
+pub struct NewLocalStorageCLI {
+    /// Directory (outside the repository) to be set as a storage
+    #[arg(long, value_hint=clap::ValueHint::DirPath)]
+    pub path: PathBuf,
+    /// Name of the storage.
+    ///
+    /// Recommended to keep this name unique to refer easily.
+    #[arg(long, short)]
+    pub name: String,
+}
+
+/// Registration of the Local backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Local",
+    command: NewLocalStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewLocalStorageCLI { path, name } = NewLocalStorageCLI::from_arg_matches(matches)?;
+        cmd_storage_new_local(input, output_snd, xvc_root, path, name)
+    },
+    deserialize: deserialize_backend::<XvcLocalStorage>,
+};
+
 /// A local storage is a directory that is on the same machine as the repository.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcLocalStorage {
@@ -59,10 +96,53 @@ pub struct XvcLocalStorage {
     pub path: PathBuf,
 }
 
+crate::xvc_storage_backend!(XvcLocalStorage, "Local", |s| format!(
+    "Local:   {}\t{}\t{}",
+    s.name,
+    s.guid,
+    s.path.to_string_lossy()
+));
+
+/// A lock file older than this is considered left over from a crashed process.
+const INDEX_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
 impl XvcLocalStorage {
     fn storage_path(&self, repo_guid: &str, cache_path: &XvcCachePath) -> XvcStoragePath {
         XvcStoragePath::from(format!("{}/{}", repo_guid, cache_path))
     }
+
+    fn index_path(&self, repo_guid: &str) -> PathBuf {
+        self.path.join(repo_guid).join(XVC_STORAGE_INDEX_FILENAME)
+    }
+
+    /// The version of a local index is the hash of its content.
+    fn index_version(content: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Creates `{index}.lock` exclusively. Returns [Error::StorageIndexConflict] if another process
+    /// holds the lock.
+    fn lock_index(&self, index_path: &Path) -> Result<PathBuf> {
+        let lock_path = index_path.with_extension("lock");
+        if let Ok(modified) = fs::metadata(&lock_path).and_then(|md| md.modified())
+            && modified.elapsed().unwrap_or_default() > INDEX_LOCK_TIMEOUT
+        {
+            fs::remove_file(&lock_path)?;
+        }
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(_) => Ok(lock_path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                Err(Error::StorageIndexConflict)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 impl XvcStorageOperations for XvcLocalStorage {
@@ -108,19 +188,53 @@ impl XvcStorageOperations for XvcLocalStorage {
         })
     }
 
-    fn list(&self, _output: &XvcOutputSender, _xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
-        todo!()
+    /// Lists the files in `{path}/{repo_guid}/` that match the Xvc cache path pattern.
+    fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
+        let repo_guid = xvc_root.guid();
+        let re = Regex::new(&format!(
+            "^{repo_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
+            cp = r#"[a-zA-Z][0-9]"#,
+            d3 = r#"[0-9A-Fa-f]{3}"#,
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
+        ))
+        .unwrap();
+
+        let repo_dir = self.path.join(repo_guid);
+        let paths = if repo_dir.exists() {
+            WalkDir::new(&repo_dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .filter_map(|e| {
+                    let rel = e.path().strip_prefix(&self.path).ok()?;
+                    let rel = rel.to_string_lossy().replace('\\', "/");
+                    re.is_match(&rel).then(|| XvcStoragePath::from(rel))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(XvcStorageListEvent {
+            guid: self.guid.clone(),
+            paths,
+        })
     }
 
-    fn send(
+    fn send_from(
         &self,
         output: &XvcOutputSender,
         xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[XvcCachePath],
         force: bool,
     ) -> Result<XvcStorageSendEvent> {
         let repo_guid = xvc_root.guid();
         let mut copied_paths = Vec::<XvcStoragePath>::new();
+        let limiter = XvcTransferLimits::for_storage(xvc_root, &self.name)?
+            .rate_limiter()
+            .with_progress(send_progress(output, source, &self.name, paths));
 
         for cache_path in paths {
             let storage_path = self.storage_path(&repo_guid, cache_path);
@@ -130,10 +244,10 @@ impl XvcStorageOperations for XvcLocalStorage {
             } else {
                 info!(output, "[SKIPPED] {}", storage_path)
             }
-            let abs_cache_path = cache_path.to_absolute_path(xvc_root);
+            let abs_cache_path = source.temp_cache_path(cache_path)?;
             let abs_storage_dir = abs_storage_path.parent().unwrap();
             fs::create_dir_all(abs_storage_dir)?;
-            fs::copy(&abs_cache_path, &abs_storage_path)?;
+            copy_file(&limiter, &abs_cache_path, &abs_storage_path)?;
             copied_paths.push(storage_path);
             info!(
                 output,
@@ -159,14 +273,25 @@ impl XvcStorageOperations for XvcLocalStorage {
         let repo_guid = xvc_root.guid();
         let mut copied_paths = Vec::<XvcStoragePath>::new();
         let temp_dir = XvcStorageTempDir::new()?;
+        let limiter = XvcTransferLimits::for_storage(xvc_root, &self.name)?
+            .rate_limiter()
+            .with_progress(receive_progress(output, &self.name));
 
         for cache_path in paths {
             let storage_path = self.storage_path(&repo_guid, cache_path);
             let abs_storage_path = storage_path.as_ref().to_logical_path(&self.path);
+            if !abs_storage_path.exists() {
+                error!(
+                    output,
+                    "Not found in storage: {}",
+                    abs_storage_path.to_string_lossy()
+                );
+                continue;
+            }
             let abs_cache_path = temp_dir.temp_cache_path(cache_path)?;
             let abs_cache_dir = temp_dir.temp_cache_dir(cache_path)?;
             fs::create_dir_all(&abs_cache_dir)?;
-            fs::copy(&abs_storage_path, &abs_cache_path)?;
+            copy_file(&limiter, &abs_storage_path, &abs_cache_path)?;
             copied_paths.push(storage_path);
             info!(
                 output,
@@ -217,4 +342,79 @@ impl XvcStorageOperations for XvcLocalStorage {
     ) -> Result<super::XvcStorageExpiringShareEvent> {
         Err(Error::StorageDoesNotSupportSignedUrls)
     }
+
+    /// Reads `{path}/{repo_guid}/.xvc-index`
+    fn read_index(
+        &self,
+        _output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<Option<XvcStorageIndexObject>> {
+        let index_path = self.index_path(xvc_root.guid());
+        if !index_path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(index_path)?;
+        let version = Self::index_version(&content);
+        Ok(Some(XvcStorageIndexObject {
+            content,
+            version: Some(version),
+        }))
+    }
+
+    /// Writes `{path}/{repo_guid}/.xvc-index` while holding a lock file, if its content hasn't
+    /// changed since it's read.
+    fn write_index(
+        &self,
+        _output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        content: &str,
+        version: Option<&str>,
+    ) -> Result<()> {
+        let index_path = self.index_path(xvc_root.guid());
+        if let Some(parent) = index_path.parent() {
+            create_dir_all(parent)?;
+        }
+        let lock_path = self.lock_index(&index_path)?;
+        let result = (|| {
+            let current = if index_path.exists() {
+                Some(Self::index_version(&fs::read_to_string(&index_path)?))
+            } else {
+                None
+            };
+            if current.as_deref() != version {
+                return Err(Error::StorageIndexConflict);
+            }
+            let temp_path = index_path.with_extension("tmp");
+            fs::write(&temp_path, content)?;
+            fs::rename(&temp_path, &index_path)?;
+            Ok(())
+        })();
+        fs::remove_file(lock_path)?;
+        result
+    }
+
+    fn receive_range(
+        &self,
+        _output: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        path: &XvcCachePath,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        let storage_path = self.storage_path(xvc_root.guid(), path);
+        let mut file = fs::File::open(storage_path.as_ref().to_logical_path(&self.path))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut content = vec![0; length as usize];
+        file.read_exact(&mut content)?;
+        Ok(Some(content))
+    }
+}
+
+/// Copies `from` to `to` in chunks at the rate of `limiter`, reporting the copied bytes to its
+/// progress
+fn copy_file(limiter: &XvcRateLimiter, from: &Path, to: &Path) -> Result<()> {
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    limiter.copy(&mut reader, &mut writer)?;
+    Ok(())
 }
@@ -1,18 +1,18 @@
 //! Minio remote storage implementation.
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
-use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use serde::{Deserialize, Serialize};
 use xvc_core::R1NStore;
 use xvc_core::XvcOutputSender;
-use xvc_core::{XvcCachePath, XvcRoot};
+use xvc_core::XvcRoot;
 
-use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
-use super::XvcStoragePath;
 use super::async_common::XvcS3StorageOperations;
+use super::credentials::{self, XvcCredentialSpec};
 
 /// Configure a new Minio remote storage.
 ///
@@ -33,6 +33,7 @@ pub fn cmd_new_minio(
     bucket_name: String,
     region: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcMinioStorage {
         guid: XvcStorageGuid::new(),
@@ -41,6 +42,7 @@ pub fn cmd_new_minio(
         bucket_name,
         storage_prefix,
         endpoint,
+        profile,
     };
 
     let init_event = storage.init(output_snd, xvc_root)?;
@@ -50,7 +52,7 @@ pub fn cmd_new_minio(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Minio(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -60,6 +62,67 @@ pub fn cmd_new_minio(
     Ok(())
 }
 
+/// Add a new Minio storage
+///
+/// Reads credentials from `MINIO_ACCESS_KEY` and `MINIO_SECRET_ACCESS_KEY` environment variables.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+/// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+/// The keys can also be kept in the credentials file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "minio")]
+pub struct NewMinioStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Minio server url in the form https://myserver.example.com:9090
+    #[arg(long, value_hint=clap::ValueHint::Url)]
+    pub endpoint: String,
+    /// Bucket name
+    #[arg(long)]
+    pub bucket_name: String,
+    /// Region of the server
+    #[arg(long)]
+    pub region: String,
+    /// You can set a directory in the bucket with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the Minio backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Minio",
+    command: NewMinioStorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewMinioStorageCLI {
+            name,
+            endpoint,
+            bucket_name,
+            storage_prefix,
+            region,
+            profile,
+        } = NewMinioStorageCLI::from_arg_matches(matches)?;
+        cmd_new_minio(
+            input,
+            output_snd,
+            xvc_root,
+            name,
+            endpoint,
+            bucket_name,
+            region,
+            storage_prefix,
+            profile,
+        )
+    },
+    deserialize: deserialize_backend::<XvcMinioStorage>,
+};
+
 /// A Minio storage is a remote storage that is compatible with the S3 protocol.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcMinioStorage {
@@ -75,8 +138,16 @@ pub struct XvcMinioStorage {
     pub storage_prefix: String,
     /// Full endpoint of the storage
     pub endpoint: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcMinioStorage, "Minio", |s| format!(
+    "Minio:   {}\t{}\t{}.{}/{}",
+    s.name, s.guid, s.endpoint, s.bucket_name, s.storage_prefix
+));
+
 impl XvcS3StorageOperations for XvcMinioStorage {
     fn storage_prefix(&self) -> String {
         self.storage_prefix.clone()
@@ -86,9 +157,13 @@ impl XvcS3StorageOperations for XvcMinioStorage {
         &self.guid
     }
 
-    fn get_bucket(&self) -> Result<Box<Bucket>> {
+    fn storage_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>> {
         // We'll just put guid file to endpoint/bucket/prefix/XVC_GUID_FILENAME
-        let credentials = self.credentials()?;
+        let credentials = self.credentials(xvc_root)?;
         let region = Region::Custom {
             region: self.region.clone(),
             endpoint: self.endpoint.clone(),
@@ -97,55 +172,21 @@ impl XvcS3StorageOperations for XvcMinioStorage {
         Ok(bucket.with_path_style())
     }
 
-    fn credentials(&self) -> Result<Credentials> {
-        // Try storage-specific credentials
-        let specific_access_key_var = format!("XVC_STORAGE_ACCESS_KEY_ID_{}", self.name);
-        let specific_secret_key_var = format!("XVC_STORAGE_SECRET_ACCESS_KEY_{}", self.name);
-
-        if let (Ok(access_key), Ok(secret_key)) = (
-            env::var(&specific_access_key_var),
-            env::var(&specific_secret_key_var),
-        ) {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // Try storage-type credentials
-        let type_access_key_var = "MINIO_ACCESS_KEY_ID";
-        let type_secret_key_var = "MINIO_SECRET_ACCESS_KEY";
-        if let (Ok(access_key), Ok(secret_key)) =
-            (env::var(type_access_key_var), env::var(type_secret_key_var))
-        {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // If both fail, return an error
-        Err(Error::CloudCredentialsNotFound {
-            storage_name: self.name.clone(),
-            var_pairs: vec![
-                (specific_access_key_var, specific_secret_key_var),
-                (
-                    type_access_key_var.to_string(),
-                    type_secret_key_var.to_string(),
-                ),
-            ],
-        })
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_key_pair(
+            "minio",
+            &self.name,
+            self.profile.as_deref(),
+            credentials::url_host(&self.endpoint),
+            "MINIO_ACCESS_KEY_ID",
+            "MINIO_SECRET_ACCESS_KEY",
+        )
     }
 
     fn bucket_name(&self) -> String {
         self.bucket_name.clone()
     }
 
-    fn build_storage_path(&self, cache_path: &XvcCachePath) -> XvcStoragePath {
-        XvcStoragePath::from(format!(
-            "{}/{}/{}",
-            self.storage_prefix,
-            self.guid(),
-            cache_path
-        ))
-    }
-
     fn region(&self) -> String {
         self.region.clone()
     }
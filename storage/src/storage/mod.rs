@@ -1,43 +1,64 @@
 //! Cloud storage implementations for xvc.
 pub mod common_ops;
+pub mod credentials;
 
 #[cfg(feature = "async")]
 pub mod async_common;
 
+#[cfg(feature = "azure")]
+pub mod azure;
 #[cfg(feature = "digital-ocean")]
 pub mod digital_ocean;
 #[cfg(feature = "dropbox")]
 pub mod dropbox;
 pub mod event;
+pub mod gc;
 #[cfg(feature = "gcs")]
 pub mod gcs;
 pub mod generic;
+pub mod helper;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod index;
+pub mod journal;
+pub mod limit;
 pub mod local;
 #[cfg(feature = "minio")]
 pub mod minio;
+pub mod pack;
+mod pool;
 #[cfg(feature = "r2")]
 pub mod r2;
+pub mod registry;
+pub mod retry;
 pub mod rsync;
 #[cfg(feature = "s3")]
 pub mod s3;
+pub mod usage;
+mod verify;
 #[cfg(feature = "wasabi")]
 pub mod wasabi;
 
 #[cfg(feature = "rclone")]
 pub mod rclone;
 
-use std::{env, ffi::OsStr, str::FromStr};
+use std::{any::Any, collections::BTreeMap, env, ffi::OsStr, str::FromStr};
 
 pub use common_ops::XvcStorageOperations;
 
 use derive_more::Display;
 pub use event::{
-    XvcStorageDeleteEvent, XvcStorageEvent, XvcStorageExpiringShareEvent, XvcStorageInitEvent,
-    XvcStorageListEvent, XvcStorageReceiveEvent, XvcStorageSendEvent,
+    XvcPackMember, XvcStorageDeleteEvent, XvcStorageEvent, XvcStorageExpiringShareEvent,
+    XvcStorageInitEvent, XvcStorageListEvent, XvcStoragePackEvent, XvcStorageReceiveEvent,
+    XvcStorageSendEvent,
 };
 
 pub use local::XvcLocalStorage;
+pub use registry::{
+    XvcStorageBackend, XvcStorageBackendRegistration, XvcStorageRegistry, XvcUnknownStorage,
+};
 
+use serde::ser::SerializeMap;
 use serde::{Deserialize, Serialize};
 use tempfile::TempDir;
 use uuid::Uuid;
@@ -53,232 +74,123 @@ use relative_path::{RelativePath, RelativePathBuf};
 use xvc_core::{XvcCachePath, XvcRoot, util::completer::load_store_for_completion};
 use xvc_core::{XvcStore, persist};
 
-use self::generic::XvcGenericStorage;
-
 /// A storage that can be used to send and receive files with several different backends
 ///
-/// TODO: Convert this to a supertrait of [XvcStorageOperations] and remove match statements
-/// Using dyn XvcStorageOperations is much cleaner
-#[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
-pub enum XvcStorage {
-    /// A local storage is a directory that is on the same machine as the repository.
-    Local(XvcLocalStorage),
-    /// A generic storage creates the storage and sends, receives, deletes files with shell
-    /// commands
-    Generic(XvcGenericStorage),
-    /// A rsync storage is a directory in an Rsync/SSH host
-    Rsync(rsync::XvcRsyncStorage),
-    /// An Rclone storage is a directory in an Rclone remote
-    #[cfg(feature = "rclone")]
-    Rclone(rclone::XvcRcloneStorage),
-    /// An S3 storage is a bucket in AWS S3
-    #[cfg(feature = "s3")]
-    S3(s3::XvcS3Storage),
-    /// An R2 storage is a bucket in R2
-    #[cfg(feature = "r2")]
-    R2(r2::XvcR2Storage),
-    /// A GCS storage is a bucket in Google Cloud Storage
-    #[cfg(feature = "gcs")]
-    Gcs(gcs::XvcGcsStorage),
-    /// A Minio storage is a bucket in Minio
-    #[cfg(feature = "minio")]
-    Minio(minio::XvcMinioStorage),
-    /// A Wasabi storage is a bucket in Wasabi
-    #[cfg(feature = "wasabi")]
-    Wasabi(wasabi::XvcWasabiStorage),
-    /// A DigitalOcean storage is a bucket in DigitalOcean
-    #[cfg(feature = "digital-ocean")]
-    DigitalOcean(digital_ocean::XvcDigitalOceanStorage),
-    /// A Dropbox storage is a folder in a Dropbox account
-    #[cfg(feature = "dropbox")]
-    Dropbox(dropbox::XvcDropboxStorage),
-}
+/// It keeps a [XvcStorageBackend] trait object and dispatches all operations to it. Records are
+/// serialized as `{tag: storage}`, the same as the previous enum representation. They are loaded
+/// as [XvcUnknownStorage] and [resolved][XvcStorageRegistry::resolve] to their backends by tag.
+#[derive(Debug)]
+pub struct XvcStorage(Box<dyn XvcStorageBackend>);
 persist!(XvcStorage, "storage");
 
 impl XvcStorage {
+    /// Wrap a storage backend
+    pub fn new<T: XvcStorageBackend>(backend: T) -> Self {
+        Self(Box::new(backend))
+    }
+
     /// Return the user visible name of storage
     pub fn name(&self) -> String {
-        match &self {
-            XvcStorage::Local(s) => s.name.clone(),
-            XvcStorage::Generic(s) => s.name.clone(),
-            XvcStorage::Rsync(s) => s.name.clone(),
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(s) => s.name.clone(),
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(s) => s.name.clone(),
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(s) => s.name.clone(),
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(s) => s.name.clone(),
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(s) => s.name.clone(),
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(s) => s.name.clone(),
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(s) => s.name.clone(),
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(s) => s.name.clone(),
-        }
+        self.0.name().to_string()
     }
 
-    /// Return guid Xvc generates for all storages
+    /// Return the GUID of the storage as a string
     pub fn guid(&self) -> String {
-        match &self {
-            XvcStorage::Local(s) => s.guid.to_string(),
-            XvcStorage::Generic(s) => s.guid.to_string(),
-            XvcStorage::Rsync(s) => s.guid.to_string(),
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(s) => s.guid.to_string(),
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(s) => s.guid.to_string(),
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(s) => s.guid.to_string(),
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(s) => s.guid.to_string(),
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(s) => s.guid.to_string(),
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(s) => s.guid.to_string(),
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(s) => s.guid.to_string(),
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(s) => s.guid.to_string(),
-        }
+        self.0.guid().to_string()
+    }
+
+    /// Return the serde tag of the storage backend
+    pub fn tag(&self) -> &str {
+        self.0.tag()
+    }
+
+    /// Returns the storage backend as [XvcStorageBackend]
+    pub fn backend(&self) -> &dyn XvcStorageBackend {
+        self.0.as_ref()
+    }
+
+    /// Returns the backend as `T` if it's of that type
+    pub fn downcast_ref<T: XvcStorageBackend>(&self) -> Option<&T> {
+        let any: &dyn Any = self.0.as_ref();
+        any.downcast_ref::<T>()
     }
 
-    /// Return a dynamic reference to the underlying storage
+    /// Returns the storage backend as [XvcStorageOperations]
     pub fn as_dyn(&self) -> &dyn XvcStorageOperations {
-        match self {
-            XvcStorage::Local(s) => s,
-            XvcStorage::Generic(s) => s,
-            XvcStorage::Rsync(s) => s,
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(s) => s,
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(s) => s,
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(s) => s,
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(s) => s,
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(s) => s,
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(s) => s,
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(s) => s,
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(s) => s,
-        }
+        self.0.as_ref()
     }
 
-    /// Return a mutable dynamic reference to the underlying storage
+    /// Returns the storage backend as mutable [XvcStorageOperations]
     pub fn as_dyn_mut(&mut self) -> &mut dyn XvcStorageOperations {
-        match self {
-            XvcStorage::Local(s) => s,
-            XvcStorage::Generic(s) => s,
-            XvcStorage::Rsync(s) => s,
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(s) => s,
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(s) => s,
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(s) => s,
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(s) => s,
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(s) => s,
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(s) => s,
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(s) => s,
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(s) => s,
+        self.0.as_mut()
+    }
+
+    /// The key used to compare storages: tag and serialized configuration
+    fn sort_key(&self) -> (String, String) {
+        (
+            self.0.tag().to_string(),
+            self.0.to_value().map(|v| v.to_string()).unwrap_or_default(),
+        )
+    }
+}
+
+impl Clone for XvcStorage {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_box())
+    }
+}
+
+impl PartialEq for XvcStorage {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for XvcStorage {}
+
+impl PartialOrd for XvcStorage {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for XvcStorage {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl Serialize for XvcStorage {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        let value = self.0.to_value().map_err(serde::ser::Error::custom)?;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(self.0.tag(), &value)?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for XvcStorage {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let record = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+        if record.len() != 1 {
+            return Err(serde::de::Error::invalid_length(
+                record.len(),
+                &"a single storage record",
+            ));
         }
+        let (tag, value) = record.into_iter().next().unwrap();
+        Ok(XvcStorage(Box::new(XvcUnknownStorage::new(tag, value))))
     }
 }
 
 impl std::fmt::Display for XvcStorage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            XvcStorage::Local(lr) => {
-                write!(
-                    f,
-                    "Local:   {}\t{}\t{}",
-                    lr.name,
-                    lr.guid,
-                    lr.path.to_string_lossy()
-                )
-            }
-            XvcStorage::Generic(gr) => write!(
-                f,
-                "Generic: {}\t{}\t{}{}",
-                gr.name,
-                gr.guid,
-                gr.url.as_ref().unwrap_or(&String::new()),
-                gr.storage_dir.as_ref().unwrap_or(&String::new())
-            ),
-            XvcStorage::Rsync(r) => write!(
-                f,
-                "Rsync:   {}\t{}\t{}:{}{}",
-                r.name,
-                r.guid,
-                r.host,
-                r.port
-                    .map(|p| p.to_string())
-                    .unwrap_or_else(|| "".to_string()),
-                r.storage_dir
-            ),
-
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(r) => write!(
-                f,
-                "Rclone:  {}\t{}\t{}:{}",
-                r.name, r.guid, r.remote, r.storage_prefix,
-            ),
-
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(r) => write!(
-                f,
-                "S3:      {}\t{}\t{}.{}/{}",
-                r.name, r.guid, r.region, r.bucket_name, r.storage_prefix
-            ),
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(mr) => write!(
-                f,
-                "Minio:   {}\t{}\t{}.{}/{}",
-                mr.name, mr.guid, mr.endpoint, mr.bucket_name, mr.storage_prefix
-            ),
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(r2r) => write!(
-                f,
-                "R2:      {}\t{}\t{} {}/{}",
-                r2r.name, r2r.guid, r2r.account_id, r2r.bucket_name, r2r.storage_prefix
-            ),
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(gcsr) => write!(
-                f,
-                "GCS:     {}\t{}\t{}.{}/{}",
-                gcsr.name, gcsr.guid, gcsr.region, gcsr.bucket_name, gcsr.storage_prefix
-            ),
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(wr) => write!(
-                f,
-                "Wasabi:  {}\t{}\t{}.{}/{}",
-                wr.name, wr.guid, wr.endpoint, wr.bucket_name, wr.storage_prefix
-            ),
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(dor) => write!(
-                f,
-                "DO:      {}\t{}\t{}.{}/{}",
-                dor.name, dor.guid, dor.region, dor.bucket_name, dor.storage_prefix
-            ),
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(dbr) => write!(
-                f,
-                "Dropbox: {}\t{}\t{}",
-                dbr.name, dbr.guid, dbr.storage_prefix
-            ),
-        }
+        write!(f, "{}", self.0.describe())
     }
 }
 
@@ -293,6 +205,11 @@ impl XvcStorageTempDir {
         Ok(Self(temp_dir))
     }
 
+    /// Use an existing directory, e.g., to keep the files between runs
+    pub fn at(dir: AbsolutePath) -> Self {
+        Self(dir)
+    }
+
     /// The path of the temporary directory
     pub fn path(&self) -> &AbsolutePath {
         &self.0
@@ -398,49 +315,8 @@ pub fn get_storage_record(
 ) -> Result<XvcStorage> {
     let store: XvcStore<XvcStorage> = xvc_root.load_store()?;
     let storage_store = store.filter(|_, r| match identifier {
-        StorageIdentifier::Name(n) => match r {
-            XvcStorage::Local(r) => r.name == *n,
-            XvcStorage::Generic(r) => r.name == *n,
-            XvcStorage::Rsync(r) => r.name == *n,
-
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(r) => r.name == *n,
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(r) => r.name == *n,
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(r) => r.name == *n,
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(r) => r.name == *n,
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(r) => r.name == *n,
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(r) => r.name == *n,
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(r) => r.name == *n,
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(r) => r.name == *n,
-        },
-        StorageIdentifier::Uuid(id) => match r {
-            XvcStorage::Local(lr) => lr.guid == (*id).into(),
-            XvcStorage::Generic(gr) => gr.guid == (*id).into(),
-            XvcStorage::Rsync(r) => r.guid == (*id).into(),
-            #[cfg(feature = "rclone")]
-            XvcStorage::Rclone(r) => r.guid == (*id).into(),
-            #[cfg(feature = "s3")]
-            XvcStorage::S3(s3r) => s3r.guid == (*id).into(),
-            #[cfg(feature = "minio")]
-            XvcStorage::Minio(mr) => mr.guid == (*id).into(),
-            #[cfg(feature = "r2")]
-            XvcStorage::R2(r) => r.guid == (*id).into(),
-            #[cfg(feature = "gcs")]
-            XvcStorage::Gcs(r) => r.guid == (*id).into(),
-            #[cfg(feature = "wasabi")]
-            XvcStorage::Wasabi(r) => r.guid == (*id).into(),
-            #[cfg(feature = "digital-ocean")]
-            XvcStorage::DigitalOcean(r) => r.guid == (*id).into(),
-            #[cfg(feature = "dropbox")]
-            XvcStorage::Dropbox(r) => r.guid == (*id).into(),
-        },
+        StorageIdentifier::Name(n) => r.backend().name() == n,
+        StorageIdentifier::Uuid(id) => *r.backend().guid() == (*id).into(),
     });
 
     if storage_store.is_empty() {
@@ -459,7 +335,7 @@ pub fn get_storage_record(
             .ok_or_else(|| Error::CannotFindStorageWithIdentifier {
                 identifier: identifier.clone(),
             })?;
-    Ok(storage.clone())
+    XvcStorageRegistry::of(xvc_root).resolve(storage.clone())
 }
 
 /// Complete storage names or identifiers by loading the store and finding all names starting with
@@ -494,3 +370,69 @@ pub fn storage_identifier_completer(prefix: &OsStr) -> Vec<CompletionCandidate>
         })
         .unwrap_or_default()
 }
+
+/// The Xvc repository for unit tests. Other tests wait until it's dropped.
+#[cfg(test)]
+pub(crate) struct TestXvcRoot {
+    xvc_root: XvcRoot,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestXvcRoot {
+    type Target = XvcRoot;
+
+    fn deref(&self) -> &Self::Target {
+        &self.xvc_root
+    }
+}
+
+/// Returns the Xvc repository for unit tests.
+///
+/// The entity generator can be loaded only once in a process, so all unit tests share a
+/// repository in a temporary Git repository. Tests must use distinct paths in it. Git isn't used
+/// by Xvc commands, tests commit the files themselves.
+#[cfg(test)]
+pub(crate) fn test_xvc_root() -> TestXvcRoot {
+    use std::sync::{LazyLock, Mutex};
+    use xvc_core::configuration::OptionalGitConfig;
+    use xvc_core::types::xvcroot::init_xvc_root;
+    use xvc_core::{XvcLoadParams, XvcOptionalConfiguration, blank_optional_config, exec_git};
+
+    static XVC_ROOT: LazyLock<XvcRoot> = LazyLock::new(|| {
+        let dir = tempfile::tempdir().unwrap().keep();
+        let dir_str = dir.to_string_lossy();
+        exec_git("git", &dir_str, &["init", "--quiet"]).unwrap();
+        exec_git("git", &dir_str, &["config", "user.name", "Xvc Test"]).unwrap();
+        exec_git("git", &dir_str, &["config", "user.email", "test@xvc.dev"]).unwrap();
+        let config_opts = XvcLoadParams {
+            xvc_root_dir: None,
+            current_dir: AbsolutePath::from(&dir),
+            include_system_config: false,
+            include_user_config: false,
+            include_project_config: true,
+            include_local_config: true,
+            project_config_path: None,
+            local_config_path: None,
+            include_environment_config: false,
+            command_line_config: None,
+        };
+        let initial_config = XvcOptionalConfiguration {
+            git: Some(OptionalGitConfig {
+                use_git: Some(false),
+                command: None,
+                auto_commit: Some(false),
+                auto_stage: Some(false),
+            }),
+            ..blank_optional_config()
+        };
+        init_xvc_root(&dir, config_opts, &initial_config).unwrap()
+    });
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    TestXvcRoot {
+        xvc_root: XVC_ROOT.clone(),
+        _lock: lock,
+    }
+}
@@ -0,0 +1,473 @@
+//! Pack objects for small files.
+//!
+//! Sending many small files to a storage is dominated by per-request overhead. When
+//! `storage.pack_threshold` is set, [XvcStorage][crate::XvcStorage] concatenates files smaller than
+//! the threshold into pack objects of about `storage.pack_size` bytes and sends each pack as a
+//! single file. A pack is named by the digest of its content like other cache files, e.g.,
+//! `b3/123/456/789...abc/0.pack`, so it's immutable and content addressed.
+//!
+//! Packs are built in a temporary directory, not in the cache. The offset and length of each file
+//! in a pack is recorded in an [XvcStoragePackEvent] and sent next to the pack as
+//! [XVC_PACK_MEMBERS_FILENAME], e.g., `b3/123/456/789...abc/members.json`. Packs that don't have an
+//! event, e.g., those sent from another branch or repository, are found in the storage listing and
+//! their members are read from this file.
+//!
+//! When packed files are received, storages that support [range
+//! requests][XvcStorageOperations::receive_range] download only the requested files. Otherwise
+//! (or when most of the pack is requested) the whole pack is downloaded and files are extracted
+//! from it. Received files keep their cache paths.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use xvc_core::{
+    HashAlgorithm, XvcCachePath, XvcDigest, XvcOutputSender, XvcRoot, XvcStore, error, info, warn,
+};
+
+use super::index::{XvcStorageIndex, cache_path_of};
+use super::{
+    XvcPackMember, XvcStorageEvent, XvcStorageGuid, XvcStorageOperations, XvcStoragePackEvent,
+    XvcStoragePath, XvcStorageSendEvent, XvcStorageTempDir,
+};
+use crate::{Result, XvcStorage};
+
+/// The name of the file next to each pack that lists the [XvcPackMember]s of the pack as JSON.
+///
+/// It doesn't look like a cache path, so it's not listed in the [index][super::index].
+pub const XVC_PACK_MEMBERS_FILENAME: &str = "members.json";
+
+/// Location of a file in a pack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XvcPackedFile {
+    /// Cache path of the pack
+    pub pack: XvcCachePath,
+    /// Byte offset of the file in the pack
+    pub offset: u64,
+    /// Length of the file in bytes
+    pub length: u64,
+}
+
+/// Files in the packs of a storage, collected from [XvcStoragePackEvent]s.
+#[derive(Debug, Clone, Default)]
+pub struct XvcPackedFiles {
+    files: HashMap<XvcCachePath, XvcPackedFile>,
+    pack_sizes: HashMap<XvcCachePath, u64>,
+}
+
+impl XvcPackedFiles {
+    /// Load the packs sent to the storage with `guid` from the storage events.
+    ///
+    /// Packs deleted from the storage later are not included.
+    pub fn from_events(xvc_root: &XvcRoot, guid: &XvcStorageGuid) -> Result<Self> {
+        let store: XvcStore<XvcStorageEvent> = xvc_root.load_store()?;
+        let mut packs = HashMap::<XvcCachePath, Vec<XvcPackMember>>::new();
+        // Events are in the order of entities, i.e., the order they are recorded.
+        for (_, event) in store.iter() {
+            match event {
+                XvcStorageEvent::Pack(pack_event) if pack_event.guid == *guid => {
+                    packs.insert(pack_event.pack.clone(), pack_event.members.clone());
+                }
+                XvcStorageEvent::Delete(delete_event) if delete_event.guid == *guid => {
+                    for path in &delete_event.paths {
+                        if let Some(cp) = cache_path_of(path.as_str()) {
+                            packs.remove(&XvcCachePath::custom(&cp));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut packed_files = Self::default();
+        for (pack, members) in packs {
+            packed_files.insert_pack(pack, members);
+        }
+
+        Ok(packed_files)
+    }
+
+    /// Load the packs in `storage`.
+    ///
+    /// Lists the storage (or reads its index) and reads the members of the packs without an event
+    /// from the storage. See [Self::load_listed].
+    pub fn load(
+        storage: &XvcStorage,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+    ) -> Result<Self> {
+        let list_event = storage.list(output_snd, xvc_root)?;
+        Self::load_listed(storage, output_snd, xvc_root, &list_event.paths)
+    }
+
+    /// Load the packs in `storage` from the events and the `listed` storage paths.
+    ///
+    /// The members of listed packs without an event are received from
+    /// [XVC_PACK_MEMBERS_FILENAME] and recorded as [XvcStoragePackEvent]s, so they are read from
+    /// the storage only once.
+    pub fn load_listed(
+        storage: &XvcStorage,
+        output_snd: &XvcOutputSender,
+        xvc_root: &XvcRoot,
+        listed: &[XvcStoragePath],
+    ) -> Result<Self> {
+        let guid = storage.0.guid();
+        let mut packed_files = Self::from_events(xvc_root, guid)?;
+        let unknown_packs = listed
+            .iter()
+            .filter_map(|p| cache_path_of(p.as_str()))
+            .map(|cp| XvcCachePath::custom(&cp))
+            .filter(|cp| is_pack(cp) && !packed_files.pack_sizes.contains_key(cp))
+            .collect::<HashSet<_>>();
+        if unknown_packs.is_empty() {
+            return Ok(packed_files);
+        }
+
+        let members_paths = unknown_packs.iter().map(members_path).collect::<Vec<_>>();
+        let (temp_dir, _) = storage
+            .as_dyn()
+            .receive(output_snd, xvc_root, &members_paths, true)?;
+        let mut events = Vec::new();
+        for pack in unknown_packs {
+            let Ok(content) = fs::read(temp_dir.temp_cache_path(&members_path(&pack))?) else {
+                warn!(output_snd, "Cannot read the members of pack {pack}");
+                continue;
+            };
+            let members: Vec<XvcPackMember> = serde_json::from_slice(&content)?;
+            packed_files.insert_pack(pack.clone(), members.clone());
+            events.push(XvcStoragePackEvent {
+                guid: guid.clone(),
+                pack,
+                members,
+            });
+        }
+        let _ = fs::remove_dir_all(temp_dir.path());
+
+        if !events.is_empty() {
+            xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
+                for event in &events {
+                    store.insert(xvc_root.new_entity(), XvcStorageEvent::Pack(event.clone()));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(packed_files)
+    }
+
+    fn insert_pack(&mut self, pack: XvcCachePath, members: Vec<XvcPackMember>) {
+        let size = members.iter().map(|m| m.offset + m.length).max();
+        self.pack_sizes.insert(pack.clone(), size.unwrap_or(0));
+        for m in members {
+            self.files.insert(
+                m.cache_path,
+                XvcPackedFile {
+                    pack: pack.clone(),
+                    offset: m.offset,
+                    length: m.length,
+                },
+            );
+        }
+    }
+
+    /// Whether the file is in a pack
+    pub fn contains(&self, cache_path: &XvcCachePath) -> bool {
+        self.files.contains_key(cache_path)
+    }
+
+    /// Location of the file in its pack
+    pub fn get(&self, cache_path: &XvcCachePath) -> Option<&XvcPackedFile> {
+        self.files.get(cache_path)
+    }
+
+    /// Number of files in packs
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether there are no packed files
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Whether the cache path is a pack
+pub(crate) fn is_pack(cache_path: &XvcCachePath) -> bool {
+    cache_path.to_string().ends_with(".pack")
+}
+
+/// The path of the [XVC_PACK_MEMBERS_FILENAME] next to `pack`
+pub(crate) fn members_path(pack: &XvcCachePath) -> XvcCachePath {
+    XvcCachePath::custom(&format!("{}/{XVC_PACK_MEMBERS_FILENAME}", pack.directory()))
+}
+
+/// Splits `paths` into files smaller than `threshold` bytes and others.
+pub(crate) fn split_small_files(
+    xvc_root: &XvcRoot,
+    paths: Vec<XvcCachePath>,
+    threshold: u64,
+) -> (Vec<XvcCachePath>, Vec<XvcCachePath>) {
+    paths.into_iter().partition(|cp| {
+        fs::metadata(cp.to_absolute_path(xvc_root)).is_ok_and(|md| md.len() < threshold)
+    })
+}
+
+/// Sends `paths` to `storage` in packs of about `storage.pack_size` bytes and records an
+/// [XvcStoragePackEvent] for each pack.
+///
+/// Each pack is built in a temporary directory and sent after its [XVC_PACK_MEMBERS_FILENAME].
+/// Files already in a pack in the storage are skipped unless `force` is set.
+pub(crate) fn send_packs(
+    storage: &XvcStorage,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    paths: &[XvcCachePath],
+    force: bool,
+) -> Result<XvcStorageSendEvent> {
+    let guid = storage.0.guid().clone();
+    let pack_size = xvc_root.config().storage.pack_size;
+    let packed_files = XvcPackedFiles::from_events(xvc_root, &guid)?;
+    let mut sent_paths = Vec::<XvcStoragePath>::new();
+
+    let mut paths = paths
+        .iter()
+        .filter(|cp| {
+            let packed = packed_files.get(cp);
+            if let Some(pf) = packed
+                && !force
+            {
+                info!(output_snd, "[SKIPPED] {cp} is already in pack {}", pf.pack);
+            }
+            force || packed.is_none()
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .peekable();
+
+    while paths.peek().is_some() {
+        let mut content = Vec::<u8>::new();
+        let mut members = Vec::<XvcPackMember>::new();
+        for cp in paths.by_ref() {
+            let file_content = fs::read(cp.to_absolute_path(xvc_root))?;
+            members.push(XvcPackMember {
+                cache_path: cp.clone(),
+                offset: content.len() as u64,
+                length: file_content.len() as u64,
+            });
+            content.extend(file_content);
+            if content.len() as u64 >= pack_size {
+                break;
+            }
+        }
+
+        let digest = XvcDigest::from_bytes(&content, HashAlgorithm::Blake3);
+        let pack = XvcCachePath::custom(&format!("{}/0.pack", digest.cache_dir()));
+        let members_file = members_path(&pack);
+        let temp_dir = XvcStorageTempDir::new()?;
+        let send_result = write_temp_file(&temp_dir, &pack, &content)
+            .and_then(|_| write_temp_file(&temp_dir, &members_file, &serde_json::to_vec(&members)?))
+            .and_then(|_| {
+                // The members are sent first to find them for every pack in the storage
+                storage.send_from(
+                    output_snd,
+                    xvc_root,
+                    &temp_dir,
+                    &[members_file.clone(), pack.clone()],
+                    force,
+                )
+            });
+        let _ = fs::remove_dir_all(temp_dir.path());
+        let send_event = send_result?;
+
+        if !XvcStorageIndex::from_storage_paths(&send_event.paths).contains(&pack) {
+            error!(output_snd, "Cannot send pack {pack}");
+            continue;
+        }
+
+        info!(
+            output_snd,
+            "[PACK] {} files ({} bytes) -> {}",
+            members.len(),
+            content.len(),
+            pack
+        );
+        sent_paths.extend(send_event.paths);
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
+            store.insert(
+                xvc_root.new_entity(),
+                XvcStorageEvent::Pack(XvcStoragePackEvent {
+                    guid: guid.clone(),
+                    pack: pack.clone(),
+                    members: members.clone(),
+                }),
+            );
+            Ok(())
+        })?;
+    }
+
+    Ok(XvcStorageSendEvent {
+        guid,
+        paths: sent_paths,
+    })
+}
+
+/// Receives packed `paths` from `storage` into `temp_dir`.
+///
+/// Files are downloaded with range requests if the storage supports them and less than half of
+/// the pack is requested. Otherwise the pack is downloaded and the files are extracted from it.
+/// Returns the storage paths of the received files.
+pub(crate) fn receive_packed(
+    storage: &XvcStorage,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    packed_files: &XvcPackedFiles,
+    paths: &[XvcCachePath],
+    temp_dir: &XvcStorageTempDir,
+) -> Result<Vec<XvcStoragePath>> {
+    let mut by_pack = HashMap::<&XvcCachePath, Vec<(&XvcCachePath, &XvcPackedFile)>>::new();
+    for cp in paths {
+        if let Some(pf) = packed_files.get(cp) {
+            by_pack.entry(&pf.pack).or_default().push((cp, pf));
+        }
+    }
+
+    let mut received = Vec::<XvcStoragePath>::new();
+    for (pack, files) in by_pack {
+        let requested: u64 = files.iter().map(|(_, pf)| pf.length).sum();
+        let pack_size = packed_files.pack_sizes.get(pack).copied().unwrap_or(0);
+        let mut remaining = files;
+
+        if requested * 2 < pack_size {
+            let mut done = HashSet::<&XvcCachePath>::new();
+            for (cp, pf) in &remaining {
+                let content = if pf.length == 0 {
+                    Some(vec![])
+                } else {
+                    storage.receive_range(output_snd, xvc_root, pack, pf.offset, pf.length)?
+                };
+                match content {
+                    Some(content) => {
+                        write_temp_file(temp_dir, cp, &content)?;
+                        info!(output_snd, "{pack}[{}..] -> {cp}", pf.offset);
+                        done.insert(*cp);
+                    }
+                    // The storage doesn't support range requests
+                    None => break,
+                }
+            }
+            received.extend(done.iter().map(|cp| XvcStoragePath::new(xvc_root, cp)));
+            remaining.retain(|(cp, _)| !done.contains(cp));
+        }
+
+        if remaining.is_empty() {
+            continue;
+        }
+
+        let (pack_temp_dir, _) =
+            storage
+                .as_dyn()
+                .receive(output_snd, xvc_root, std::slice::from_ref(pack), true)?;
+        let pack_path = pack_temp_dir.temp_cache_path(pack)?;
+        if !pack_path.exists() {
+            error!(output_snd, "Cannot receive pack {pack}");
+            continue;
+        }
+        let content = fs::read(&pack_path)?;
+        // The receive directory may be kept between runs
+        let _ = fs::remove_file(&pack_path);
+        for (cp, pf) in remaining {
+            let start = pf.offset as usize;
+            let end = start + pf.length as usize;
+            match content.get(start..end) {
+                Some(file_content) => {
+                    write_temp_file(temp_dir, cp, file_content)?;
+                    info!(output_snd, "{pack} -> {cp}");
+                    received.push(XvcStoragePath::new(xvc_root, cp));
+                }
+                None => error!(output_snd, "{cp} is out of the bounds of pack {pack}"),
+            }
+        }
+    }
+
+    Ok(received)
+}
+
+fn write_temp_file(
+    temp_dir: &XvcStorageTempDir,
+    cache_path: &XvcCachePath,
+    content: &[u8],
+) -> Result<()> {
+    fs::create_dir_all(temp_dir.temp_cache_dir(cache_path)?)?;
+    fs::write(temp_dir.temp_cache_path(cache_path)?, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XvcLocalStorage;
+    use crate::storage::test_xvc_root;
+
+    #[test]
+    fn test_pack_roundtrip() -> Result<()> {
+        let storage_dir = tempfile::tempdir()?;
+        let xvc_root = test_xvc_root();
+        let (output, _output_rcv) = crossbeam_channel::unbounded();
+        let mut storage = XvcStorage::new(XvcLocalStorage {
+            guid: XvcStorageGuid::new(),
+            name: "local".to_string(),
+            path: storage_dir.path().to_path_buf(),
+        });
+        storage.init(&output, &xvc_root)?;
+
+        let files = [("a", 10), ("b", 100), ("c", 100)].map(|(name, size)| {
+            let cp = XvcCachePath::custom(&format!("b3/{name}00/000/{}/0.bin", "0".repeat(58)));
+            let content = name.repeat(size).into_bytes();
+            (cp, content)
+        });
+        for (cp, content) in &files {
+            let path = cp.to_absolute_path(&xvc_root);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, content)?;
+        }
+        let paths = files.iter().map(|(cp, _)| cp.clone()).collect::<Vec<_>>();
+        send_packs(&storage, &output, &xvc_root, &paths, false)?;
+
+        // Forget the pack events to read the members from the storage
+        xvc_root.with_store_mut(|store: &mut XvcStore<XvcStorageEvent>| {
+            let packs = store
+                .iter()
+                .filter(|(_, event)| {
+                    matches!(event, XvcStorageEvent::Pack(pack) if pack.guid == *storage.backend().guid())
+                })
+                .map(|(e, _)| *e)
+                .collect::<Vec<_>>();
+            assert_eq!(packs.len(), 1);
+            packs.into_iter().for_each(|e| {
+                store.remove(e);
+            });
+            Ok(())
+        })?;
+        let packed_files = XvcPackedFiles::load(&storage, &output, &xvc_root)?;
+        assert_eq!(packed_files.len(), files.len());
+        let pack = &packed_files.get(&paths[0]).unwrap().pack;
+        assert!(!pack.to_absolute_path(&xvc_root).exists());
+
+        // A small part of the pack is read with a range request, all of it by extraction
+        for requested in [&paths[..1], &paths[..]] {
+            let temp_dir = XvcStorageTempDir::new()?;
+            let received = receive_packed(
+                &storage,
+                &output,
+                &xvc_root,
+                &packed_files,
+                requested,
+                &temp_dir,
+            )?;
+            assert_eq!(received.len(), requested.len());
+            for (cp, content) in files.iter().take(requested.len()) {
+                assert_eq!(&fs::read(temp_dir.temp_cache_path(cp)?)?, content);
+            }
+            fs::remove_dir_all(temp_dir.path())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! Bounded worker pool for storages that run a process per file.
+//!
+//! Generic and Rsync storages run an external command for each file. [run_for_each] runs these
+//! commands in at most `max_processes` threads and collects the result of each file, so a failed
+//! transfer doesn't abort the rest of the batch.
+use crossbeam_channel::unbounded;
+use xvc_core::{XvcCachePath, XvcOutputSender, XvcRoot, error, warn};
+
+use super::XvcStoragePath;
+use crate::{Error, Result};
+
+/// Runs `f` for each of `paths` in at most `max_processes` threads.
+///
+/// Returns the result for each path in the order of `paths`.
+pub(crate) fn run_for_each<F>(
+    max_processes: usize,
+    paths: &[XvcCachePath],
+    f: F,
+) -> Vec<(&XvcCachePath, Result<()>)>
+where
+    F: Fn(&XvcCachePath) -> Result<()> + Sync,
+{
+    let n_workers = max_processes.clamp(1, paths.len().max(1));
+    let (path_snd, path_rec) = unbounded::<(usize, &XvcCachePath)>();
+    let (result_snd, result_rec) = unbounded::<(usize, Result<()>)>();
+    paths
+        .iter()
+        .enumerate()
+        .for_each(|p| path_snd.send(p).expect("Path channel is open"));
+    drop(path_snd);
+
+    // A panic in a worker is reported for the paths that didn't return a result below.
+    let _ = crossbeam::scope(|s| {
+        for _ in 0..n_workers {
+            let path_rec = path_rec.clone();
+            let result_snd = result_snd.clone();
+            let f = &f;
+            s.spawn(move |_| {
+                for (i, cache_path) in path_rec {
+                    if result_snd.send((i, f(cache_path))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    drop(result_snd);
+
+    let mut results = paths
+        .iter()
+        .map(|_| None)
+        .collect::<Vec<Option<Result<()>>>>();
+    for (i, res) in result_rec {
+        results[i] = Some(res);
+    }
+
+    paths
+        .iter()
+        .zip(results)
+        .map(|(cache_path, res)| {
+            (
+                cache_path,
+                res.unwrap_or_else(|| {
+                    Err(Error::TransferWorkerPanicked {
+                        path: cache_path.to_string(),
+                    })
+                }),
+            )
+        })
+        .collect()
+}
+
+/// Reports the failed transfers in `results` and returns the storage paths of the successful ones.
+pub(crate) fn successful_storage_paths(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    results: Vec<(&XvcCachePath, Result<()>)>,
+) -> Vec<XvcStoragePath> {
+    let total = results.len();
+    let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(total);
+    for (cache_path, res) in results {
+        match res {
+            Ok(()) => storage_paths.push(XvcStoragePath::new(xvc_root, cache_path)),
+            Err(e) => error!(output_snd, "{cache_path}: {e}"),
+        }
+    }
+
+    let failed = total - storage_paths.len();
+    if failed > 0 {
+        warn!(output_snd, "{failed} of {total} files failed");
+    }
+
+    storage_paths
+}
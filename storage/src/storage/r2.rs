@@ -1,19 +1,22 @@
 //! Cloudflare R2 remote storage implementation.
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::str::FromStr;
 
 use regex::Regex;
-use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use serde::{Deserialize, Serialize};
 use xvc_core::R1NStore;
-use xvc_core::{XvcCachePath, XvcRoot};
+use xvc_core::XvcRoot;
 use xvc_core::{XvcOutputSender, error, info};
 
-use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use super::index::RUN_CACHE_ENTRY_PATTERN;
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
 use super::async_common::XvcS3StorageOperations;
+use super::credentials::XvcCredentialSpec;
+use super::retry::XvcRetryPolicy;
 use super::{XvcStorageListEvent, XvcStoragePath};
 
 /// Configure a new Cloudflare R2 remote storage.
@@ -24,6 +27,8 @@ use super::{XvcStorageListEvent, XvcStoragePath};
 /// This creates a [XvcR2Storage], calls its
 /// [init][XvcR2Storage::init] function to create/update guid, and
 /// saves [XvcStorageInitEvent] and [XvcStorage] in ECS.
+/// TODO: Reduce the number of parameters of this function.
+#[allow(clippy::too_many_arguments)]
 pub fn cmd_new_r2(
     _input: std::io::StdinLock,
     output_snd: &XvcOutputSender,
@@ -32,6 +37,7 @@ pub fn cmd_new_r2(
     account_id: String,
     bucket_name: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcR2Storage {
         guid: XvcStorageGuid::new(),
@@ -39,6 +45,7 @@ pub fn cmd_new_r2(
         account_id,
         bucket_name,
         storage_prefix,
+        profile,
     };
 
     info!(output_snd, "R2 Storage: {:#?}", storage);
@@ -50,7 +57,7 @@ pub fn cmd_new_r2(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::R2(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -60,6 +67,62 @@ pub fn cmd_new_r2(
     Ok(())
 }
 
+/// Add a new R2 storage
+///
+/// Reads credentials from `R2_ACCESS_KEY_ID` and `R2_SECRET_ACCESS_KEY` environment variables.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+/// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+/// The keys can also be kept in the credentials file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "r2")]
+pub struct NewR2StorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// R2 account ID
+    #[arg(long)]
+    pub account_id: String,
+    /// Bucket name
+    #[arg(long)]
+    pub bucket_name: String,
+    /// You can set a directory in the bucket with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the R2 backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "R2",
+    command: NewR2StorageCLI::command,
+    run_new: |input, output_snd, xvc_root, matches| {
+        let NewR2StorageCLI {
+            name,
+            account_id,
+            bucket_name,
+            storage_prefix,
+            profile,
+        } = NewR2StorageCLI::from_arg_matches(matches)?;
+        cmd_new_r2(
+            input,
+            output_snd,
+            xvc_root,
+            name,
+            account_id,
+            bucket_name,
+            storage_prefix,
+            profile,
+        )
+    },
+    deserialize: deserialize_backend::<XvcR2Storage>,
+};
+
 /// A Cloudflare R2 remote storage
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcR2Storage {
@@ -73,8 +136,16 @@ pub struct XvcR2Storage {
     pub bucket_name: String,
     /// Remote path prefix in the bucket
     pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcR2Storage, "R2", |s| format!(
+    "R2:      {}\t{}\t{} {}/{}",
+    s.name, s.guid, s.account_id, s.bucket_name, s.storage_prefix
+));
+
 impl XvcS3StorageOperations for XvcR2Storage {
     fn storage_prefix(&self) -> String {
         self.storage_prefix.clone()
@@ -83,62 +154,32 @@ impl XvcS3StorageOperations for XvcR2Storage {
     fn guid(&self) -> &XvcStorageGuid {
         &self.guid
     }
+
+    fn storage_name(&self) -> &str {
+        &self.name
+    }
     fn bucket_name(&self) -> String {
         self.bucket_name.clone()
     }
-    fn build_storage_path(&self, cache_path: &XvcCachePath) -> XvcStoragePath {
-        XvcStoragePath::from(format!(
-            "{}/{}/{}",
-            self.storage_prefix,
-            self.guid(),
-            cache_path
-        ))
-    }
-
     /// This doesn't apply to R2
     fn region(&self) -> String {
         self.account_id.clone()
     }
 
-    fn credentials(&self) -> Result<Credentials> {
-        // Try storage-specific credentials
-        let specific_access_key_var = format!("XVC_STORAGE_ACCESS_KEY_ID_{}", self.name);
-        let specific_secret_key_var = format!("XVC_STORAGE_SECRET_ACCESS_KEY_{}", self.name);
-
-        if let (Ok(access_key), Ok(secret_key)) = (
-            env::var(&specific_access_key_var),
-            env::var(&specific_secret_key_var),
-        ) {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // Try storage-type credentials
-        let type_access_key_var = "R2_ACCESS_KEY_ID";
-        let type_secret_key_var = "R2_SECRET_ACCESS_KEY";
-        if let (Ok(access_key), Ok(secret_key)) =
-            (env::var(type_access_key_var), env::var(type_secret_key_var))
-        {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // If both fail, return an error
-        Err(Error::CloudCredentialsNotFound {
-            storage_name: self.name.clone(),
-            var_pairs: vec![
-                (specific_access_key_var, specific_secret_key_var),
-                (
-                    type_access_key_var.to_string(),
-                    type_secret_key_var.to_string(),
-                ),
-            ],
-        })
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_key_pair(
+            "r2",
+            &self.name,
+            self.profile.as_deref(),
+            format!("{}.r2.cloudflarestorage.com", self.account_id),
+            "R2_ACCESS_KEY_ID",
+            "R2_SECRET_ACCESS_KEY",
+        )
     }
 
-    fn get_bucket(&self) -> Result<Box<Bucket>> {
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>> {
         // We'll just put guid file to endpoint/bucket/prefix/XVC_GUID_FILENAME
-        let credentials = self.credentials()?;
+        let credentials = self.credentials(xvc_root)?;
         let region = Region::R2 {
             account_id: self.account_id.clone(),
         };
@@ -151,25 +192,28 @@ impl XvcS3StorageOperations for XvcR2Storage {
         output: &XvcOutputSender,
         xvc_root: &xvc_core::XvcRoot,
     ) -> Result<XvcStorageListEvent> {
-        let bucket = self.get_bucket()?;
-        let xvc_guid = xvc_root.guid();
+        let bucket = self.get_bucket(xvc_root)?;
+        // Files are sent to {prefix}/{storage guid}/ in [XvcS3StorageOperations::build_storage_path]
+        let xvc_guid = self.guid.to_string();
         let prefix = self.storage_prefix.clone();
 
-        let res_list = bucket
-            .list(
-                format!("{}/{}", self.storage_prefix, xvc_guid),
-                Some("/".to_string()),
-            )
+        let bucket = &bucket;
+        let list_prefix = &format!("{}/{}/", self.storage_prefix, xvc_guid);
+        let res_list = XvcRetryPolicy::default()
+            .a_retry(output, list_prefix, || async move {
+                Ok(bucket.list(list_prefix.clone(), None).await?)
+            })
             .await;
 
         match res_list {
             Ok(list_all) => {
                 // select only the matching elements
                 let re = Regex::new(&format!(
-                    "{prefix}/{xvc_guid}/{cp}/{d3}/{d3}/{d58}/0\\..*$",
+                    "{prefix}/{xvc_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
                     cp = r#"[a-zA-Z][0-9]"#,
                     d3 = r#"[0-9A-Fa-f]{3}"#,
-                    d58 = r#"[0-9A-Fa-f]{58}"#
+                    d58 = r#"[0-9A-Fa-f]{58}"#,
+                    run_cache = RUN_CACHE_ENTRY_PATTERN,
                 ))
                 .unwrap();
 
@@ -192,7 +236,7 @@ impl XvcS3StorageOperations for XvcR2Storage {
 
             Err(err) => {
                 error!(output, "{}", err);
-                Err(Error::S3Error { source: err })
+                Err(err)
             }
         }
     }
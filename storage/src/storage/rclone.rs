@@ -4,6 +4,7 @@
 //! for the time being. In the future, it can use the rclone library or RPC interface.
 //!
 //! TODO: Use the rclone library or RPC interface instead of the command line.
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::env;
 use std::fs;
 
@@ -16,6 +17,9 @@ use xvc_core::XvcCachePath;
 use xvc_core::XvcRoot;
 use xvc_core::{XvcOutputSender, error, info, trace, uwr, warn};
 
+use super::index::RUN_CACHE_ENTRY_PATTERN;
+use super::limit::{receive_progress, send_progress};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
 use crate::{Error, Result, XvcStorage, XvcStorageEvent, XvcStorageGuid, XvcStorageOperations};
 
 use super::{
@@ -45,7 +49,7 @@ pub fn cmd_new_rclone(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Rclone(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -57,6 +61,45 @@ pub fn cmd_new_rclone(
     Ok(())
 }
 
+/// Add a new rclone storage
+///
+/// Uses the rclone configuration to connect to the storage. The remotestorage must already be
+/// configure with `rclone config`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "rclone")]
+pub struct NewRcloneStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// The name of the remote in rclone configuration
+    ///
+    /// This is the "remote" part in "remote://dir/" URL.
+    #[arg(long)]
+    pub remote_name: String,
+    /// The directory in the remote to store the files.
+    ///
+    /// This is the "dir" part in "remote://dir/" URL.
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+}
+
+/// Registration of the Rclone backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Rclone",
+    command: NewRcloneStorageCLI::command,
+    run_new: |_input, output_snd, xvc_root, matches| {
+        let NewRcloneStorageCLI {
+            name,
+            remote_name,
+            storage_prefix,
+        } = NewRcloneStorageCLI::from_arg_matches(matches)?;
+        cmd_new_rclone(output_snd, xvc_root, name, remote_name, storage_prefix)
+    },
+    deserialize: deserialize_backend::<XvcRcloneStorage>,
+};
+
 /// Specifies an Rsync remote storage
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcRcloneStorage {
@@ -72,6 +115,11 @@ pub struct XvcRcloneStorage {
     pub storage_prefix: String,
 }
 
+crate::xvc_storage_backend!(XvcRcloneStorage, "Rclone", |s| format!(
+    "Rclone:  {}\t{}\t{}:{}",
+    s.name, s.guid, s.remote, s.storage_prefix
+));
+
 #[cfg(unix)]
 fn rclone_executable() -> Result<AbsolutePath> {
     // TODO: Make rclone executable configurable
@@ -254,6 +302,8 @@ impl XvcStorageOperations for XvcRcloneStorage {
     /// Lists all files in rclone remote directory that match to the regex:
     ///
     /// {XVC_GUID}/[a-zA-Z][0-9]/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{58}/0
+    ///
+    /// and the run cache entries in `{XVC_GUID}/run-cache/`
     fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
         let rclone_executable = rclone_executable()?;
         let storage_dir = self.storage_prefix.trim_end_matches('/');
@@ -263,10 +313,11 @@ impl XvcStorageOperations for XvcRcloneStorage {
         let xvc_guid = xvc_root.guid();
         // TODO: Move this regex to a central place
         let re = Regex::new(&format!(
-            "{xvc_guid}/{cp}/{d3}/{d3}/{d58}/0\\..*$",
+            "{xvc_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
             cp = r#"[a-zA-Z][0-9]"#,
             d3 = r#"[0-9A-Fa-f]{3}"#,
-            d58 = r#"[0-9A-Fa-f]{58}"#
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
         ))
         .unwrap();
 
@@ -287,10 +338,11 @@ impl XvcStorageOperations for XvcRcloneStorage {
         })
     }
 
-    fn send(
+    fn send_from(
         &self,
         output: &XvcOutputSender,
         xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[XvcCachePath],
         _force: bool,
     ) -> Result<XvcStorageSendEvent> {
@@ -300,8 +352,9 @@ impl XvcStorageOperations for XvcRcloneStorage {
 
         let xvc_guid = xvc_root.guid();
         let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
-        paths.iter().for_each(|cache_path| {
-            let local_path = cache_path.to_absolute_path(xvc_root);
+        let progress = send_progress(output, source, &self.name, paths);
+        for cache_path in paths {
+            let local_path = source.temp_cache_path(cache_path)?;
             let storage_url = self.rclone_cache_url(&xvc_guid, cache_path);
             uwr!(
                 self.create_storage_dir(&rclone_executable, &xvc_guid, cache_path),
@@ -313,6 +366,7 @@ impl XvcStorageOperations for XvcRcloneStorage {
 
             match cmd_output {
                 Ok(cmd_output) => {
+                    progress.inc(fs::metadata(&local_path).map_or(0, |md| md.len()));
                     let stdout_str = cmd_output.stdout_str();
                     let stderr_str = cmd_output.stderr_str();
                     info!(output, "{}", stdout_str);
@@ -325,7 +379,7 @@ impl XvcStorageOperations for XvcRcloneStorage {
                     error!(output, "{}", err);
                 }
             }
-        });
+        }
 
         Ok(XvcStorageSendEvent {
             guid: self.guid.clone(),
@@ -345,6 +399,7 @@ impl XvcStorageOperations for XvcRcloneStorage {
 
         let xvc_guid = xvc_root.guid();
         let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
+        let progress = receive_progress(output, &self.name);
         paths.iter().for_each(|cache_path| {
             let local_path = temp_dir.temp_cache_path(cache_path).unwrap();
             let remote_url = self.rclone_cache_url(&xvc_guid, cache_path);
@@ -361,6 +416,7 @@ impl XvcStorageOperations for XvcRcloneStorage {
 
             match cmd_output {
                 Ok(cmd_output) => {
+                    progress.inc(fs::metadata(&local_path).map_or(0, |md| md.len()));
                     let stdout_str = cmd_output.stdout_str();
                     let stderr_str = cmd_output.stderr_str();
                     info!(output, "{}", stdout_str);
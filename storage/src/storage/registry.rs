@@ -0,0 +1,367 @@
+//! Storage backend registry.
+//!
+//! Each storage backend implements [XvcStorageBackend] and provides an
+//! [XvcStorageBackendRegistration] that contains its `xvc storage new` subcommand and the serde tag
+//! used in stored records. [XvcStorageRegistry::default] contains the built-in backends. Other
+//! crates can add backends with [XvcStorageRegistry::register] and attach the registry to the
+//! repository with [XvcRootInner::insert_extension][xvc_core::types::xvcroot::XvcRootInner::insert_extension]
+//! before dispatching the command line.
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use xvc_core::{XvcOutputSender, XvcRoot};
+
+use crate::{Error, Result, XvcStorageGuid, XvcStorageOperations};
+
+use super::{
+    XvcStorage, XvcStorageDeleteEvent, XvcStorageExpiringShareEvent, XvcStorageInitEvent,
+    XvcStorageListEvent, XvcStorageReceiveEvent, XvcStorageSendEvent, XvcStorageTempDir,
+};
+
+/// A storage backend that can be kept in [crate::XvcStorage].
+///
+/// Use [crate::xvc_storage_backend] macro to implement this for structs with `name` and `guid`
+/// fields.
+pub trait XvcStorageBackend: XvcStorageOperations + Debug + Send + Sync + Any {
+    /// The serde tag of the backend. Stored records are serialized as `{tag: storage}`.
+    fn tag(&self) -> &str;
+    /// User visible name of the storage
+    fn name(&self) -> &str;
+    /// GUID of the storage
+    fn guid(&self) -> &XvcStorageGuid;
+    /// One line description shown in `xvc storage list`
+    fn describe(&self) -> String;
+    /// Serialize the storage configuration
+    fn to_value(&self) -> Result<serde_json::Value>;
+    /// Clone the storage into a new box
+    fn clone_box(&self) -> Box<dyn XvcStorageBackend>;
+}
+
+/// Implements [XvcStorageBackend] for a struct that has `name: String` and `guid: XvcStorageGuid`
+/// fields.
+///
+/// ```ignore
+/// xvc_storage_backend!(XvcLocalStorage, "Local", |s| format!("Local: {}", s.name));
+/// ```
+#[macro_export]
+macro_rules! xvc_storage_backend {
+    ( $t:ty, $tag:literal, |$s:ident| $describe:expr ) => {
+        impl $crate::storage::XvcStorageBackend for $t {
+            fn tag(&self) -> &str {
+                $tag
+            }
+            fn name(&self) -> &str {
+                &self.name
+            }
+            fn guid(&self) -> &$crate::XvcStorageGuid {
+                &self.guid
+            }
+            fn describe(&self) -> String {
+                let $s = self;
+                $describe
+            }
+            fn to_value(&self) -> $crate::Result<serde_json::Value> {
+                Ok(serde_json::to_value(self)?)
+            }
+            fn clone_box(&self) -> Box<dyn $crate::storage::XvcStorageBackend> {
+                Box::new(self.clone())
+            }
+        }
+    };
+}
+
+/// Runs `xvc storage new <subcommand>` for a backend: parses the arguments, initializes the
+/// storage and saves it to the store.
+pub type XvcStorageNewFn =
+    fn(std::io::StdinLock, &XvcOutputSender, &XvcRoot, &clap::ArgMatches) -> Result<()>;
+
+/// Registration information for a storage backend
+#[derive(Clone, Copy, Debug)]
+pub struct XvcStorageBackendRegistration {
+    /// The serde tag of the backend. This must be unique and must not change, as stored records
+    /// refer to it.
+    pub tag: &'static str,
+    /// Builds the `xvc storage new <subcommand>` command with its arguments
+    pub command: fn() -> clap::Command,
+    /// Runs the `xvc storage new <subcommand>` command
+    pub run_new: XvcStorageNewFn,
+    /// Deserializes a stored record with this backend's tag
+    pub deserialize: fn(serde_json::Value) -> Result<Box<dyn XvcStorageBackend>>,
+}
+
+/// Deserialize function for [XvcStorageBackendRegistration::deserialize]
+pub fn deserialize_backend<T: XvcStorageBackend + DeserializeOwned>(
+    value: serde_json::Value,
+) -> Result<Box<dyn XvcStorageBackend>> {
+    Ok(Box::new(serde_json::from_value::<T>(value)?))
+}
+
+fn builtin_backends() -> Vec<XvcStorageBackendRegistration> {
+    vec![
+        super::local::REGISTRATION,
+        super::generic::REGISTRATION,
+        super::rsync::REGISTRATION,
+        super::helper::REGISTRATION,
+        #[cfg(feature = "rclone")]
+        super::rclone::REGISTRATION,
+        #[cfg(feature = "s3")]
+        super::s3::REGISTRATION,
+        #[cfg(feature = "minio")]
+        super::minio::REGISTRATION,
+        #[cfg(feature = "digital-ocean")]
+        super::digital_ocean::REGISTRATION,
+        #[cfg(feature = "r2")]
+        super::r2::REGISTRATION,
+        #[cfg(feature = "gcs")]
+        super::gcs::REGISTRATION,
+        #[cfg(feature = "wasabi")]
+        super::wasabi::REGISTRATION,
+        #[cfg(feature = "dropbox")]
+        super::dropbox::REGISTRATION,
+        #[cfg(feature = "azure")]
+        super::azure::REGISTRATION,
+        #[cfg(feature = "http")]
+        super::http::REGISTRATION,
+    ]
+}
+
+/// The storage backends available to a repository.
+///
+/// Commands use the registry attached to the [XvcRoot] with
+/// [XvcRootInner::insert_extension][xvc_core::types::xvcroot::XvcRootInner::insert_extension], or
+/// the built-in backends if there is none.
+#[derive(Clone, Debug)]
+pub struct XvcStorageRegistry {
+    backends: Vec<XvcStorageBackendRegistration>,
+}
+
+impl Default for XvcStorageRegistry {
+    /// A registry with the built-in backends enabled by features
+    fn default() -> Self {
+        Self {
+            backends: builtin_backends(),
+        }
+    }
+}
+
+impl XvcStorageRegistry {
+    /// The registry attached to `xvc_root`, or the built-in backends if none is attached.
+    pub fn of(xvc_root: &XvcRoot) -> Arc<Self> {
+        xvc_root
+            .extension::<Self>()
+            .unwrap_or_else(|| Arc::new(Self::default()))
+    }
+
+    /// Add a storage backend to the registry.
+    ///
+    /// If a backend with the same tag is already registered, it's replaced.
+    pub fn register(&mut self, registration: XvcStorageBackendRegistration) {
+        self.backends.retain(|r| r.tag != registration.tag);
+        self.backends.push(registration);
+    }
+
+    /// All registered storage backends in registration order
+    pub fn backends(&self) -> &[XvcStorageBackendRegistration] {
+        &self.backends
+    }
+
+    /// Find the registration with the serde `tag`
+    pub fn find_by_tag(&self, tag: &str) -> Option<XvcStorageBackendRegistration> {
+        self.backends.iter().find(|r| r.tag == tag).copied()
+    }
+
+    /// Find the registration with the `xvc storage new` subcommand `name`
+    pub fn find_by_subcommand(&self, name: &str) -> Option<XvcStorageBackendRegistration> {
+        self.backends
+            .iter()
+            .find(|r| (r.command)().get_name() == name)
+            .copied()
+    }
+
+    /// Converts a stored record to its backend.
+    ///
+    /// Records are loaded as [XvcUnknownStorage] and stay so if their tag isn't registered.
+    pub fn resolve(&self, storage: XvcStorage) -> Result<XvcStorage> {
+        match storage.downcast_ref::<XvcUnknownStorage>() {
+            Some(unknown) => match self.find_by_tag(&unknown.tag) {
+                Some(registration) => {
+                    (registration.deserialize)(unknown.value.clone()).map(XvcStorage)
+                }
+                None => Ok(storage),
+            },
+            None => Ok(storage),
+        }
+    }
+}
+
+/// A stored storage record whose backend isn't registered in this binary.
+///
+/// This may happen when a storage is created by a binary with a third-party backend, or with a
+/// feature that's not enabled in this binary. The record is kept as is, but all operations fail.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XvcUnknownStorage {
+    /// Serde tag of the record
+    pub tag: String,
+    /// The record as it's stored
+    pub value: serde_json::Value,
+    name: String,
+    guid: XvcStorageGuid,
+}
+
+impl XvcUnknownStorage {
+    /// Create a new unknown storage from the stored record. `name` and `guid` fields are read from
+    /// `value` if they are present.
+    pub fn new(tag: String, value: serde_json::Value) -> Self {
+        let name = value
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let guid = value
+            .get("guid")
+            .and_then(|g| g.as_str())
+            .and_then(|g| g.parse().ok())
+            .unwrap_or_default();
+        Self {
+            tag,
+            value,
+            name,
+            guid,
+        }
+    }
+
+    fn error(&self) -> Error {
+        Error::UnknownStorageBackend {
+            tag: self.tag.clone(),
+        }
+    }
+}
+
+impl XvcStorageBackend for XvcUnknownStorage {
+    fn tag(&self) -> &str {
+        &self.tag
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn guid(&self) -> &XvcStorageGuid {
+        &self.guid
+    }
+    fn describe(&self) -> String {
+        format!(
+            "{}: {}\t{}\t(unknown backend)",
+            self.tag, self.name, self.guid
+        )
+    }
+    fn to_value(&self) -> Result<serde_json::Value> {
+        Ok(self.value.clone())
+    }
+    fn clone_box(&self) -> Box<dyn XvcStorageBackend> {
+        Box::new(self.clone())
+    }
+}
+
+impl XvcStorageOperations for XvcUnknownStorage {
+    fn init(
+        &mut self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+    ) -> Result<XvcStorageInitEvent> {
+        Err(self.error())
+    }
+
+    fn list(&self, _output: &XvcOutputSender, _xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
+        Err(self.error())
+    }
+
+    fn send_from(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _source: &XvcStorageTempDir,
+        _paths: &[xvc_core::XvcCachePath],
+        _force: bool,
+    ) -> Result<XvcStorageSendEvent> {
+        Err(self.error())
+    }
+
+    fn receive(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _paths: &[xvc_core::XvcCachePath],
+        _force: bool,
+    ) -> Result<(XvcStorageTempDir, XvcStorageReceiveEvent)> {
+        Err(self.error())
+    }
+
+    fn delete(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _paths: &[xvc_core::XvcCachePath],
+    ) -> Result<XvcStorageDeleteEvent> {
+        Err(self.error())
+    }
+
+    fn share(
+        &self,
+        _output: &XvcOutputSender,
+        _xvc_root: &XvcRoot,
+        _path: &xvc_core::XvcCachePath,
+        _period: std::time::Duration,
+    ) -> Result<XvcStorageExpiringShareEvent> {
+        Err(self.error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XvcLocalStorage;
+
+    #[test]
+    fn test_storage_record_compatibility() -> Result<()> {
+        let record = r#"{"Local":{"guid":"63ae8f6c-b66b-40f0-8b37-197a9e7fdbed","name":"loc","path":"/tmp/loc"}}"#;
+        let storage: XvcStorage = serde_json::from_str(record)?;
+        let storage = XvcStorageRegistry::default().resolve(storage)?;
+        assert_eq!(storage.tag(), "Local");
+        assert_eq!(storage.name(), "loc");
+        assert!(storage.downcast_ref::<XvcLocalStorage>().is_some());
+        assert_eq!(serde_json::to_string(&storage)?, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_storage_record() -> Result<()> {
+        let record = r#"{"ThirdParty":{"endpoint":"x","guid":"63ae8f6c-b66b-40f0-8b37-197a9e7fdbed","name":"tp"}}"#;
+        let storage: XvcStorage = serde_json::from_str(record)?;
+        let storage = XvcStorageRegistry::default().resolve(storage)?;
+        assert_eq!(storage.tag(), "ThirdParty");
+        assert_eq!(storage.name(), "tp");
+        assert_eq!(storage.guid(), "63ae8f6c-b66b-40f0-8b37-197a9e7fdbed");
+        assert!(storage.downcast_ref::<XvcUnknownStorage>().is_some());
+        assert_eq!(serde_json::to_string(&storage)?, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_backend() -> Result<()> {
+        let record = r#"{"ThirdParty":{"guid":"63ae8f6c-b66b-40f0-8b37-197a9e7fdbed","name":"tp","path":"/tmp/tp"}}"#;
+        let mut registry = XvcStorageRegistry::default();
+        assert!(registry.find_by_tag("ThirdParty").is_none());
+        registry.register(XvcStorageBackendRegistration {
+            tag: "ThirdParty",
+            ..super::super::local::REGISTRATION
+        });
+        assert!(registry.find_by_tag("ThirdParty").is_some());
+
+        let storage = registry.resolve(serde_json::from_str(record)?)?;
+        assert!(storage.downcast_ref::<XvcLocalStorage>().is_some());
+        let storage = XvcStorageRegistry::default().resolve(serde_json::from_str(record)?)?;
+        assert!(storage.downcast_ref::<XvcUnknownStorage>().is_some());
+        Ok(())
+    }
+}
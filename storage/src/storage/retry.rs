@@ -0,0 +1,148 @@
+//! Retries with exponential backoff for storage operations.
+//!
+//! Network operations fail temporarily for many reasons: dropped connections, timeouts, throttling
+//! and server errors. [XvcRetryPolicy] tries an operation again when it fails with a [transient
+//! error][crate::Error::is_transient], waiting longer between each attempt. A random jitter is
+//! added to the delays so that concurrent clients don't retry at the same time.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use xvc_core::{XvcOutputSender, warn};
+
+use crate::Result;
+
+/// Number of times an operation is tried by default
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first retry by default
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+/// Maximum delay between attempts by default
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How many times and how long to wait between retries of an operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XvcRetryPolicy {
+    /// Number of times an operation is tried, including the first attempt
+    pub max_attempts: u32,
+    /// Delay before the first retry. It's doubled after each retry.
+    pub initial_delay: Duration,
+    /// Maximum delay between attempts
+    pub max_delay: Duration,
+}
+
+impl Default for XvcRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+impl XvcRetryPolicy {
+    /// The delay after the `attempt`th failed attempt (starting from 1).
+    ///
+    /// The delay is `initial_delay * 2^(attempt - 1)` capped at `max_delay`, and a random value
+    /// between half of it and itself is returned.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        let jitter_range = (backoff - half).as_millis() as u64;
+        let jitter = if jitter_range == 0 {
+            0
+        } else {
+            random_u64() % (jitter_range + 1)
+        };
+        half + Duration::from_millis(jitter)
+    }
+
+    /// Runs `f` until it succeeds, fails with a non-transient error or `max_attempts` is reached.
+    ///
+    /// `what` describes the operation in the warning messages.
+    pub fn retry<T>(
+        &self,
+        output_snd: &XvcOutputSender,
+        what: &str,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(e) if e.is_transient() && attempt < self.max_attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        output_snd,
+                        "{what}: {e}. Retrying in {delay:?} ({attempt}/{})", self.max_attempts
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Async version of [Self::retry]
+    #[cfg(feature = "async")]
+    pub async fn a_retry<T, Fut>(
+        &self,
+        output_snd: &XvcOutputSender,
+        what: &str,
+        mut f: impl FnMut() -> Fut,
+    ) -> Result<T>
+    where
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if e.is_transient() && attempt < self.max_attempts => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        output_snd,
+                        "{what}: {e}. Retrying in {delay:?} ({attempt}/{})", self.max_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// A random number from the randomly seeded std hasher, to avoid a dependency for jitter.
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_is_capped_and_jittered() {
+        let policy = XvcRetryPolicy {
+            max_attempts: 10,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        for attempt in 1..=10 {
+            let expected =
+                (Duration::from_millis(100) * 2u32.pow(attempt - 1)).min(Duration::from_secs(1));
+            let delay = policy.delay(attempt);
+            assert!(delay >= expected / 2 && delay <= expected, "{delay:?}");
+        }
+    }
+}
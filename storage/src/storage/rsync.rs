@@ -1,4 +1,5 @@
 //! Rsync remote storage implementation.
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::{env, fs};
 
 use regex::Regex;
@@ -7,8 +8,12 @@ use subprocess::{Capture, Exec};
 use xvc_core::AbsolutePath;
 use xvc_core::R1NStore;
 use xvc_core::{XvcCachePath, XvcRoot};
-use xvc_core::{XvcOutputSender, error, info, trace, uwr, warn};
+use xvc_core::{XvcOutputSender, info, trace, warn};
 
+use super::index::RUN_CACHE_ENTRY_PATTERN;
+use super::limit::{XvcTransferLimits, receive_progress, send_progress};
+use super::pool::{run_for_each, successful_storage_paths};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
 use crate::{Error, Result, XvcStorage, XvcStorageEvent, XvcStorageGuid, XvcStorageOperations};
 
 use super::{
@@ -23,6 +28,7 @@ use super::{
 ///
 /// If the connection options are not valid, [XvcRsyncStorage::init] will fail,
 /// and this function will return an error before recording the storage.
+#[allow(clippy::too_many_arguments)]
 pub fn cmd_new_rsync(
     output_snd: &XvcOutputSender,
     xvc_root: &XvcRoot,
@@ -31,6 +37,7 @@ pub fn cmd_new_rsync(
     port: Option<usize>,
     user: Option<String>,
     storage_dir: String,
+    max_processes: usize,
 ) -> Result<()> {
     let mut storage = XvcRsyncStorage {
         guid: XvcStorageGuid::new(),
@@ -39,6 +46,7 @@ pub fn cmd_new_rsync(
         port,
         user,
         storage_dir,
+        max_processes,
     };
 
     let init_event = storage.init(output_snd, xvc_root)?;
@@ -48,7 +56,7 @@ pub fn cmd_new_rsync(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Rsync(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -60,6 +68,66 @@ pub fn cmd_new_rsync(
     Ok(())
 }
 
+/// Add a new rsync storages
+///
+/// Uses rsync in separate processes to communicate.
+/// This can be used when you already have an SSH/Rsync connection.
+/// It doesn't prompt for any passwords. The connection must be set up with ssh keys beforehand.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "rsync")]
+pub struct NewRsyncStorageCLI {
+    /// Name of the storage.
+    ///
+    /// Recommended to keep this name unique to refer easily.
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Hostname for the connection in the form host.example.com  (without @, : or protocol)
+    #[arg(long, value_hint=clap::ValueHint::Hostname)]
+    pub host: String,
+    /// Port number for the connection in the form 22.
+    /// Doesn't add port number to connection string if not given.
+    #[arg(long)]
+    pub port: Option<usize>,
+    /// User name for the connection, the part before @ in user@example.com (without @,
+    /// hostname).
+    /// User name isn't included in connection strings if not given.
+    #[arg(long, value_hint=clap::ValueHint::Username)]
+    pub user: Option<String>,
+    /// storage directory in the host to store the files.
+    #[arg(long)]
+    pub storage_dir: String,
+    /// Number of maximum rsync processes to run simultaneously
+    #[arg(long = "processes", short = 'M', default_value_t = 1)]
+    pub max_processes: usize,
+}
+
+/// Registration of the Rsync backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Rsync",
+    command: NewRsyncStorageCLI::command,
+    run_new: |_input, output_snd, xvc_root, matches| {
+        let NewRsyncStorageCLI {
+            name,
+            host,
+            port,
+            user,
+            storage_dir,
+            max_processes,
+        } = NewRsyncStorageCLI::from_arg_matches(matches)?;
+        cmd_new_rsync(
+            output_snd,
+            xvc_root,
+            name,
+            host,
+            port,
+            user,
+            storage_dir,
+            max_processes,
+        )
+    },
+    deserialize: deserialize_backend::<XvcRsyncStorage>,
+};
+
 /// Specifies an Rsync remote storage
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcRsyncStorage {
@@ -75,8 +143,24 @@ pub struct XvcRsyncStorage {
     pub user: Option<String>,
     /// The storage directory on the remote
     pub storage_dir: String,
+    /// How many rsync processes to run simultaneously
+    #[serde(default = "default_max_processes")]
+    pub max_processes: usize,
+}
+
+fn default_max_processes() -> usize {
+    1
 }
 
+crate::xvc_storage_backend!(XvcRsyncStorage, "Rsync", |s| format!(
+    "Rsync:   {}\t{}\t{}:{}{}",
+    s.name,
+    s.guid,
+    s.host,
+    s.port.map(|p| p.to_string()).unwrap_or_default(),
+    s.storage_dir
+));
+
 impl XvcRsyncStorage {
     fn ssh_url(&self) -> String {
         match (self.port, &self.user) {
@@ -146,15 +230,41 @@ impl XvcRsyncStorage {
         }
     }
 
+    /// The rsync command limited to `bwlimit` KiB per second. `0` is unlimited.
+    fn rsync_cmd(rsync_executable: &AbsolutePath, bwlimit: u64) -> Exec {
+        let cmd = Exec::cmd(rsync_executable.as_path());
+        if bwlimit > 0 {
+            cmd.arg(format!("--bwlimit={bwlimit}"))
+        } else {
+            cmd
+        }
+    }
+
+    /// The number of rsync processes and the bandwidth limit of each in KiB per second.
+    ///
+    /// The [bandwidth limit][super::limit] of the storage is divided between the processes, as
+    /// rsync limits them separately.
+    fn transfer_limits(&self, xvc_root: &XvcRoot) -> Result<(usize, u64)> {
+        let limits = XvcTransferLimits::for_storage(xvc_root, &self.name)?;
+        let n_processes = limits.concurrency(self.max_processes);
+        let bwlimit = if limits.max_bandwidth == 0 {
+            0
+        } else {
+            (limits.max_bandwidth / 1024 / n_processes as u64).max(1)
+        };
+        Ok((n_processes, bwlimit))
+    }
+
     fn rsync_copy_to_storage(
         &self,
         rsync_executable: &AbsolutePath,
         local_path: &AbsolutePath,
         remote_url: &str,
+        bwlimit: u64,
     ) -> Result<Capture> {
         let rsync_opts = "-av";
 
-        let cmd_res = Exec::cmd(rsync_executable.as_path())
+        let cmd_res = Self::rsync_cmd(rsync_executable, bwlimit)
             .arg(rsync_opts)
             .arg(local_path.to_string())
             .arg(remote_url)
@@ -180,12 +290,13 @@ impl XvcRsyncStorage {
         rsync_executable: &AbsolutePath,
         remote_url: &str,
         local_path: &AbsolutePath,
+        bwlimit: u64,
     ) -> Result<Capture> {
         trace!(remote_url);
 
         let rsync_opts = "-av";
 
-        let cmd_res = Exec::cmd(rsync_executable.as_path())
+        let cmd_res = Self::rsync_cmd(rsync_executable, bwlimit)
             .arg(rsync_opts)
             .arg(remote_url)
             .arg(local_path.to_string())
@@ -262,7 +373,7 @@ impl XvcStorageOperations for XvcRsyncStorage {
         let storage_guid_path = self.rsync_path_url(XVC_STORAGE_GUID_FILENAME);
 
         let rsync_result =
-            self.rsync_copy_to_storage(&rsync_executable, &local_guid_path, &storage_guid_path)?;
+            self.rsync_copy_to_storage(&rsync_executable, &local_guid_path, &storage_guid_path, 0)?;
 
         info!(
             output,
@@ -281,6 +392,8 @@ impl XvcStorageOperations for XvcRsyncStorage {
     /// Lists all files in the remote directory that match to regex:
     ///
     /// {XVC_GUID}/[a-zA-Z][0-9]/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{3}/[0-9A-Fa-f]{58}/0
+    ///
+    /// and the run cache entries in `{XVC_GUID}/run-cache/`
     fn list(&self, _output: &XvcOutputSender, xvc_root: &XvcRoot) -> Result<XvcStorageListEvent> {
         // "--list",
         // "ssh {URL} 'ls -1R {STORAGE_DIR}'",
@@ -290,10 +403,11 @@ impl XvcStorageOperations for XvcRsyncStorage {
         let cmd_output = self.ssh_cmd(&ssh_executable, &format!("ls -1R {}", self.storage_dir))?;
         let xvc_guid = xvc_root.guid();
         let re = Regex::new(&format!(
-            "{xvc_guid}/{cp}/{d3}/{d3}/{d58}/0\\..*$",
+            "{xvc_guid}/(?:{cp}/{d3}/{d3}/{d58}/0\\..*|{run_cache})$",
             cp = r#"[a-zA-Z][0-9]"#,
             d3 = r#"[0-9A-Fa-f]{3}"#,
-            d58 = r#"[0-9A-Fa-f]{58}"#
+            d58 = r#"[0-9A-Fa-f]{58}"#,
+            run_cache = RUN_CACHE_ENTRY_PATTERN,
         ))
         .unwrap();
 
@@ -315,49 +429,36 @@ impl XvcStorageOperations for XvcRsyncStorage {
         })
     }
 
-    fn send(
+    fn send_from(
         &self,
         output: &XvcOutputSender,
         xvc_root: &XvcRoot,
+        source: &XvcStorageTempDir,
         paths: &[XvcCachePath],
         _force: bool,
     ) -> Result<XvcStorageSendEvent> {
         // "--upload",
         // "ssh {URL} 'mkdir -p {STORAGE_DIR}{XVC_GUID}/{RELATIVE_CACHE_DIR}' ; rsync -av {ABSOLUTE_CACHE_PATH} {URL}:{STORAGE_DIR}{XVC_GUID}/{RELATIVE_CACHE_PATH}",
         //
-        // TODO: We can make this parallel
 
         let rsync_executable = Self::rsync_executable()?;
         let ssh_executable = Self::ssh_executable()?;
 
         let xvc_guid = xvc_root.guid();
-        let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
-        paths.iter().for_each(|cache_path| {
-            let local_path = cache_path.to_absolute_path(xvc_root);
-            let storage_url = self.rsync_cache_url(&xvc_guid, cache_path);
-            uwr!(
-                self.create_storage_dir(&ssh_executable, &xvc_guid, cache_path),
-                output
-            );
-            // TODO: Handle possible error.
+        let (n_processes, bwlimit) = self.transfer_limits(xvc_root)?;
+        let progress = send_progress(output, source, &self.name, paths);
+        let results = run_for_each(n_processes, paths, |cache_path| {
+            let local_path = source.temp_cache_path(cache_path)?;
+            let storage_url = self.rsync_cache_url(xvc_guid, cache_path);
+            self.create_storage_dir(&ssh_executable, xvc_guid, cache_path)?;
             let cmd_output =
-                self.rsync_copy_to_storage(&rsync_executable, &local_path, &storage_url);
-
-            match cmd_output {
-                Ok(cmd_output) => {
-                    let stdout_str = cmd_output.stdout_str();
-                    let stderr_str = cmd_output.stderr_str();
-                    info!(output, "{}", stdout_str);
-                    warn!(output, "{}", stderr_str);
-                    let storage_path = XvcStoragePath::new(xvc_root, cache_path);
-                    storage_paths.push(storage_path);
-                }
-
-                Err(err) => {
-                    error!(output, "{}", err);
-                }
-            }
+                self.rsync_copy_to_storage(&rsync_executable, &local_path, &storage_url, bwlimit)?;
+            progress.inc(fs::metadata(&local_path).map_or(0, |md| md.len()));
+            info!(output, "{}", cmd_output.stdout_str());
+            warn!(output, "{}", cmd_output.stderr_str());
+            Ok(())
         });
+        let storage_paths = successful_storage_paths(output, xvc_root, results);
 
         Ok(XvcStorageSendEvent {
             guid: self.guid.clone(),
@@ -379,36 +480,27 @@ impl XvcStorageOperations for XvcRsyncStorage {
         let temp_dir = XvcStorageTempDir::new()?;
 
         let xvc_guid = xvc_root.guid();
-        let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
-        paths.iter().for_each(|cache_path| {
-            let local_path = temp_dir.temp_cache_path(cache_path).unwrap();
-            let remote_url = self.rsync_cache_url(&xvc_guid, cache_path);
-            let cache_dir = temp_dir.temp_cache_dir(cache_path).unwrap();
+        let (n_processes, bwlimit) = self.transfer_limits(xvc_root)?;
+        let progress = receive_progress(output, &self.name);
+        let results = run_for_each(n_processes, paths, |cache_path| {
+            let local_path = temp_dir.temp_cache_path(cache_path)?;
+            let remote_url = self.rsync_cache_url(xvc_guid, cache_path);
+            let cache_dir = temp_dir.temp_cache_dir(cache_path)?;
             trace!(cache_dir);
             if !cache_dir.exists() {
-                uwr!(fs::create_dir_all(&cache_dir), output);
+                fs::create_dir_all(&cache_dir)?;
             }
 
             trace!(remote_url);
 
             let cmd_output =
-                self.rsync_copy_from_storage(&rsync_executable, &remote_url, &local_path);
-
-            match cmd_output {
-                Ok(cmd_output) => {
-                    let stdout_str = cmd_output.stdout_str();
-                    let stderr_str = cmd_output.stderr_str();
-                    info!(output, "{}", stdout_str);
-                    warn!(output, "{}", stderr_str);
-                    let storage_path = XvcStoragePath::new(xvc_root, cache_path);
-                    storage_paths.push(storage_path);
-                }
-
-                Err(err) => {
-                    error!(output, "{}", err);
-                }
-            }
+                self.rsync_copy_from_storage(&rsync_executable, &remote_url, &local_path, bwlimit)?;
+            progress.inc(fs::metadata(&local_path).map_or(0, |md| md.len()));
+            info!(output, "{}", cmd_output.stdout_str());
+            warn!(output, "{}", cmd_output.stderr_str());
+            Ok(())
         });
+        let storage_paths = successful_storage_paths(output, xvc_root, results);
 
         Ok((
             temp_dir,
@@ -431,28 +523,17 @@ impl XvcStorageOperations for XvcRsyncStorage {
         let ssh_executable = Self::ssh_executable()?;
 
         let xvc_guid = xvc_root.guid();
-        let mut storage_paths = Vec::<XvcStoragePath>::with_capacity(paths.len());
-        paths.iter().for_each(|cache_path| {
+        let (n_processes, _) = self.transfer_limits(xvc_root)?;
+        let results = run_for_each(n_processes, paths, |cache_path| {
             let remote_path = self.ssh_cache_path(xvc_guid, cache_path);
             let delete_cmd = format!("rm -f '{}'", remote_path);
-            let cmd_output = self.ssh_cmd(&ssh_executable, &delete_cmd);
-
-            match cmd_output {
-                Ok(cmd_output) => {
-                    let stdout_str = cmd_output.stdout_str();
-                    let stderr_str = cmd_output.stderr_str();
-                    info!(output, "[REMOTE DELETE] {}", remote_path);
-                    info!(output, "{}", stdout_str);
-                    warn!(output, "{}", stderr_str);
-                    let storage_path = XvcStoragePath::new(xvc_root, cache_path);
-                    storage_paths.push(storage_path);
-                }
-
-                Err(err) => {
-                    error!(output, "{}", err);
-                }
-            }
+            let cmd_output = self.ssh_cmd(&ssh_executable, &delete_cmd)?;
+            info!(output, "[REMOTE DELETE] {}", remote_path);
+            info!(output, "{}", cmd_output.stdout_str());
+            warn!(output, "{}", cmd_output.stderr_str());
+            Ok(())
         });
+        let storage_paths = successful_storage_paths(output, xvc_root, results);
         Ok(XvcStorageDeleteEvent {
             guid: self.guid.clone(),
             paths: storage_paths,
@@ -1,19 +1,18 @@
 //! AWS S3 remote storage implementation
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
-use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use serde::{Deserialize, Serialize};
 use xvc_core::R1NStore;
-use xvc_core::{XvcCachePath, XvcRoot};
+use xvc_core::XvcRoot;
 use xvc_core::{XvcOutputSender, info, watch};
 
-use crate::storage::XVC_STORAGE_GUID_FILENAME;
-use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
-use super::XvcStoragePath;
 use super::async_common::XvcS3StorageOperations;
+use super::credentials::XvcCredentialSpec;
 
 /// Configure a new Amazon Web Services S3 remote storage.
 ///
@@ -30,6 +29,7 @@ pub fn cmd_new_s3(
     region: String,
     bucket_name: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcS3Storage {
         guid: XvcStorageGuid::new(),
@@ -37,6 +37,7 @@ pub fn cmd_new_s3(
         region,
         bucket_name,
         storage_prefix,
+        profile,
     };
 
     let init_event = storage.init(output_snd, xvc_root)?;
@@ -47,7 +48,7 @@ pub fn cmd_new_s3(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::S3(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -59,6 +60,61 @@ pub fn cmd_new_s3(
     Ok(())
 }
 
+/// Add a new S3 storage
+///
+/// Reads credentials from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+/// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+/// The keys can also be kept in the credentials file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "s3")]
+pub struct NewS3StorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// You can set a directory in the bucket with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// S3 bucket name
+    #[arg(long)]
+    pub bucket_name: String,
+    /// AWS region
+    #[arg(long)]
+    pub region: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the S3 backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "S3",
+    command: NewS3StorageCLI::command,
+    run_new: |_input, output_snd, xvc_root, matches| {
+        let NewS3StorageCLI {
+            name,
+            storage_prefix,
+            bucket_name,
+            region,
+            profile,
+        } = NewS3StorageCLI::from_arg_matches(matches)?;
+        cmd_new_s3(
+            output_snd,
+            xvc_root,
+            name,
+            region,
+            bucket_name,
+            storage_prefix,
+            profile,
+        )
+    },
+    deserialize: deserialize_backend::<XvcS3Storage>,
+};
+
 /// An AWS S3 configuration as a remote storage location
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcS3Storage {
@@ -84,8 +140,17 @@ pub struct XvcS3Storage {
     ///
     /// Xvc checks the presence of Guid file before creating this folder.
     pub storage_prefix: String,
+
+    /// Profile (section) in the credentials file to read the access keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcS3Storage, "S3", |s| format!(
+    "S3:      {}\t{}\t{}.{}/{}",
+    s.name, s.guid, s.region, s.bucket_name, s.storage_prefix
+));
+
 impl XvcS3StorageOperations for XvcS3Storage {
     fn storage_prefix(&self) -> String {
         self.storage_prefix.clone()
@@ -95,81 +160,33 @@ impl XvcS3StorageOperations for XvcS3Storage {
         &self.guid
     }
 
-    fn get_bucket(&self) -> Result<Box<Bucket>> {
+    fn storage_name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>> {
         // We'll just put guid file to endpoint/bucket/prefix/XVC_GUID_FILENAME
-        let credentials = self.credentials()?;
+        let credentials = self.credentials(xvc_root)?;
         let region: Region = self.region.parse().expect("Cannot parse region name");
         let bucket = Bucket::new(&self.bucket_name, region, credentials)?;
         Ok(bucket)
     }
 
-    fn credentials(&self) -> Result<Credentials> {
-        // Try storage-specific credentials
-        let specific_access_key_var = format!("XVC_STORAGE_ACCESS_KEY_ID_{}", self.name);
-        let specific_secret_key_var = format!("XVC_STORAGE_SECRET_ACCESS_KEY_{}", self.name);
-
-        if let (Ok(access_key), Ok(secret_key)) = (
-            env::var(&specific_access_key_var),
-            env::var(&specific_secret_key_var),
-        ) {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // Try storage-type credentials
-        let type_access_key_var = "AWS_ACCESS_KEY_ID";
-        let type_secret_key_var = "AWS_SECRET_ACCESS_KEY";
-        if let (Ok(access_key), Ok(secret_key)) =
-            (env::var(type_access_key_var), env::var(type_secret_key_var))
-        {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // If both fail, return an error
-        Err(Error::CloudCredentialsNotFound {
-            storage_name: self.name.clone(),
-            var_pairs: vec![
-                (specific_access_key_var, specific_secret_key_var),
-                (
-                    type_access_key_var.to_string(),
-                    type_secret_key_var.to_string(),
-                ),
-            ],
-        })
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_key_pair(
+            "s3",
+            &self.name,
+            self.profile.as_deref(),
+            format!("s3.{}.amazonaws.com", self.region),
+            "AWS_ACCESS_KEY_ID",
+            "AWS_SECRET_ACCESS_KEY",
+        )
     }
 
     fn bucket_name(&self) -> String {
         self.bucket_name.clone()
     }
 
-    async fn write_storage_guid(&self) -> Result<()> {
-        let guid_str = self.guid().to_string();
-        let guid_bytes = guid_str.as_bytes();
-        let bucket = self.get_bucket()?;
-        let response = bucket
-            .put_object(
-                format!("{}/{}", self.storage_prefix(), XVC_STORAGE_GUID_FILENAME),
-                guid_bytes,
-            )
-            .await;
-
-        match response {
-            Ok(_) => Ok(()),
-            Err(err) => Err(Error::S3Error { source: err }),
-        }
-    }
-
-    fn build_storage_path(&self, cache_path: &XvcCachePath) -> XvcStoragePath {
-        XvcStoragePath::from(format!(
-            "{}/{}/{}/{}",
-            self.bucket_name(),
-            self.storage_prefix(),
-            self.guid(),
-            cache_path
-        ))
-    }
-
     fn region(&self) -> String {
         self.region.clone()
     }
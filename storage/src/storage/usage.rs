@@ -0,0 +1,308 @@
+//! Space used by a repository in storages.
+//!
+//! `xvc storage usage` joins the listing (or the index) of each storage with the sizes recorded in
+//! `XvcMetadata` at each Git ref. Files are counted once per storage even if several refs or paths
+//! refer to them. A file is _unique_ to a ref if no other ref refers to it, and _shared_ otherwise.
+//!
+//! Files in the storage that are not referenced by any of the refs are counted, but their sizes are
+//! unknown.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Serialize;
+use xvc_core::{
+    ContentDigest, Storable, XvcCachePath, XvcMetadata, XvcOutputSender, XvcPath, XvcRoot,
+    XvcStore, extract_store_at_ref, get_absolute_git_command, info, output,
+};
+use xvc_logging::progress::format_bytes;
+
+use super::gc::git_refs;
+use super::index::cache_path_of;
+use super::pack::XvcPackedFiles;
+use super::{XvcStorage, XvcStorageOperations, XvcStorageRegistry, get_storage_record};
+use crate::{Result, StorageIdentifier};
+
+/// Name of the top level directory for files in the repository root
+const ROOT_DIR: &str = ".";
+
+/// Space used in a storage by the files of a ref
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct XvcRefUsage {
+    /// Git ref
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    /// Number of files of the ref in the storage
+    pub files: usize,
+    /// Total bytes of the files of the ref in the storage
+    pub bytes: u64,
+    /// Bytes of the files that are not referred by other refs
+    pub unique_bytes: u64,
+    /// Bytes of the files that are also referred by other refs
+    pub shared_bytes: u64,
+}
+
+/// Space used by the repository in a storage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct XvcStorageUsage {
+    /// Name of the storage
+    pub name: String,
+    /// GUID of the storage
+    pub guid: String,
+    /// Number of referenced files in the storage
+    pub files: usize,
+    /// Total bytes of the referenced files in the storage. Each file is counted once.
+    pub bytes: u64,
+    /// Sum of the bytes of all refs, i.e., the space needed without deduplication
+    pub logical_bytes: u64,
+    /// Bytes of the files referred by a single ref
+    pub unique_bytes: u64,
+    /// Bytes of the files referred by more than one ref
+    pub shared_bytes: u64,
+    /// Number of files in the storage not referred by any of the refs
+    pub unreferenced_files: usize,
+    /// Bytes by top level directory of the repository
+    pub directories: BTreeMap<String, u64>,
+    /// Usage by Git ref
+    pub refs: Vec<XvcRefUsage>,
+}
+
+/// The JSON output of `xvc storage usage`
+#[derive(Debug, Serialize)]
+struct XvcUsageReport {
+    storages: Vec<XvcStorageUsage>,
+}
+
+/// A file referred by one or more refs
+#[derive(Debug, Clone, Default)]
+struct ReferencedFile {
+    size: u64,
+    top_dirs: HashSet<String>,
+    refs: HashSet<usize>,
+}
+
+/// Entry point for `xvc storage usage`.
+///
+/// Reports the usage of the storage with `identifier` (all storages if `None`) by the files of
+/// `refs` (all refs if empty). With `json`, the report is printed as JSON.
+pub fn cmd_storage_usage(
+    _input: std::io::StdinLock,
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    identifier: Option<StorageIdentifier>,
+    refs: Vec<String>,
+    json: bool,
+) -> Result<()> {
+    let storages = match identifier {
+        Some(identifier) => vec![get_storage_record(output_snd, xvc_root, &identifier)?],
+        None => {
+            let registry = XvcStorageRegistry::of(xvc_root);
+            let store: XvcStore<XvcStorage> = xvc_root.load_store()?;
+            store
+                .values()
+                .map(|storage| registry.resolve(storage.clone()))
+                .collect::<Result<Vec<_>>>()?
+        }
+    };
+
+    let git_command = get_absolute_git_command(&xvc_root.config().git.command)?;
+    let xvc_directory = xvc_root.as_path().to_string_lossy().to_string();
+    let refs = git_refs(&git_command, &xvc_directory, refs)?;
+    let referenced = referenced_files(&git_command, &xvc_directory, &refs)?;
+
+    let mut usages = Vec::with_capacity(storages.len());
+    for storage in storages {
+        let usage = storage_usage(output_snd, xvc_root, &storage, &refs, &referenced)?;
+        usages.push(usage);
+    }
+
+    if json {
+        output!(
+            output_snd,
+            "{}",
+            serde_json::to_string_pretty(&XvcUsageReport { storages: usages })?
+        );
+    } else {
+        usages
+            .iter()
+            .for_each(|usage| print_usage(output_snd, usage));
+    }
+
+    Ok(())
+}
+
+/// Collects the files referred by each of `refs`, with their sizes recorded at that ref.
+fn referenced_files(
+    git_command: &str,
+    xvc_directory: &str,
+    refs: &[String],
+) -> Result<HashMap<XvcCachePath, ReferencedFile>> {
+    let mut referenced = HashMap::<XvcCachePath, ReferencedFile>::new();
+    for (ref_index, git_ref) in refs.iter().enumerate() {
+        let temp_dir = tempfile::tempdir()?;
+        extract_store_at_ref(
+            git_command,
+            xvc_directory,
+            git_ref,
+            temp_dir.path(),
+            &[
+                <XvcPath as Storable>::type_description(),
+                <ContentDigest as Storable>::type_description(),
+                <XvcMetadata as Storable>::type_description(),
+            ],
+        )?;
+        let path_store = XvcStore::<XvcPath>::load_store(temp_dir.path())?;
+        let digest_store = XvcStore::<ContentDigest>::load_store(temp_dir.path())?;
+        let metadata_store = XvcStore::<XvcMetadata>::load_store(temp_dir.path())?;
+
+        for (xe, xvc_path) in path_store.iter() {
+            let Some(digest) = digest_store.get(xe) else {
+                continue;
+            };
+            let Some(size) = metadata_store.get(xe).and_then(|md| md.size) else {
+                continue;
+            };
+            let top_dir = match xvc_path.as_str().split_once('/') {
+                Some((dir, _)) => dir.to_string(),
+                None => ROOT_DIR.to_string(),
+            };
+            let file = referenced
+                .entry(XvcCachePath::new(xvc_path, digest)?)
+                .or_default();
+            file.size = size;
+            file.top_dirs.insert(top_dir);
+            file.refs.insert(ref_index);
+        }
+    }
+    Ok(referenced)
+}
+
+/// Joins the files in `storage` with the `referenced` files of `refs`.
+fn storage_usage(
+    output_snd: &XvcOutputSender,
+    xvc_root: &XvcRoot,
+    storage: &XvcStorage,
+    refs: &[String],
+    referenced: &HashMap<XvcCachePath, ReferencedFile>,
+) -> Result<XvcStorageUsage> {
+    let list_event = storage.list(output_snd, xvc_root)?;
+    let packed_files =
+        XvcPackedFiles::load_listed(storage, output_snd, xvc_root, &list_event.paths)?;
+    let mut in_storage = list_event
+        .paths
+        .iter()
+        .filter_map(|p| cache_path_of(p.as_str()))
+        .map(|cp| XvcCachePath::custom(&cp))
+        .collect::<HashSet<_>>();
+    let packs = in_storage
+        .iter()
+        .filter(|cp| cp.to_string().ends_with(".pack"))
+        .cloned()
+        .collect::<HashSet<_>>();
+    info!(
+        output_snd,
+        "{}: {} files, {} packs",
+        storage.name(),
+        in_storage.len(),
+        packs.len()
+    );
+
+    // Packed files are in the storage if their pack is
+    in_storage.extend(
+        referenced
+            .keys()
+            .filter(|cp| {
+                packed_files
+                    .get(cp)
+                    .is_some_and(|pf| packs.contains(&pf.pack))
+            })
+            .cloned(),
+    );
+
+    let mut usage = XvcStorageUsage {
+        name: storage.name(),
+        guid: storage.guid(),
+        refs: refs
+            .iter()
+            .map(|git_ref| XvcRefUsage {
+                git_ref: git_ref.clone(),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let mut used_packs = HashSet::new();
+    for (cp, file) in referenced {
+        if !in_storage.contains(cp) {
+            continue;
+        }
+        if let Some(pf) = packed_files.get(cp) {
+            used_packs.insert(pf.pack.clone());
+        }
+        let shared = file.refs.len() > 1;
+        usage.files += 1;
+        usage.bytes += file.size;
+        if shared {
+            usage.shared_bytes += file.size;
+        } else {
+            usage.unique_bytes += file.size;
+        }
+        for top_dir in &file.top_dirs {
+            *usage.directories.entry(top_dir.clone()).or_default() += file.size;
+        }
+        for ref_index in &file.refs {
+            let ref_usage = &mut usage.refs[*ref_index];
+            ref_usage.files += 1;
+            ref_usage.bytes += file.size;
+            if shared {
+                ref_usage.shared_bytes += file.size;
+            } else {
+                ref_usage.unique_bytes += file.size;
+            }
+        }
+    }
+    usage.logical_bytes = usage.refs.iter().map(|r| r.bytes).sum();
+    usage.unreferenced_files = in_storage
+        .iter()
+        .filter(|cp| {
+            if packs.contains(cp) {
+                !used_packs.contains(cp)
+            } else {
+                !referenced.contains_key(cp) && !packed_files.contains(cp)
+            }
+        })
+        .count();
+
+    Ok(usage)
+}
+
+fn print_usage(output_snd: &XvcOutputSender, usage: &XvcStorageUsage) {
+    output!(
+        output_snd,
+        "{}: {} in {} files ({} without deduplication)",
+        usage.name,
+        format_bytes(usage.bytes),
+        usage.files,
+        format_bytes(usage.logical_bytes)
+    );
+    output!(
+        output_snd,
+        "  unique: {}, shared: {}, unreferenced files: {}",
+        format_bytes(usage.unique_bytes),
+        format_bytes(usage.shared_bytes),
+        usage.unreferenced_files
+    );
+    for (dir, bytes) in &usage.directories {
+        output!(output_snd, "  [DIR] {dir}: {}", format_bytes(*bytes));
+    }
+    for ref_usage in &usage.refs {
+        output!(
+            output_snd,
+            "  [REF] {}: {} in {} files (unique: {}, shared: {})",
+            ref_usage.git_ref,
+            format_bytes(ref_usage.bytes),
+            ref_usage.files,
+            format_bytes(ref_usage.unique_bytes),
+            format_bytes(ref_usage.shared_bytes)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Verification of received files.
+//!
+//! Cache paths contain the digest of the file, e.g., `b3/123/456/789...abc/0.bin`. A received file
+//! is verified by calculating its digest with the algorithm in the cache path and comparing it to
+//! the digest in the cache path. Files are read in chunks, so large files are not loaded into
+//! memory.
+use std::fs;
+use std::str::FromStr;
+
+use xvc_core::{AbsolutePath, HashAlgorithm, XvcCachePath, XvcDigest};
+
+use crate::{Error, Result};
+
+/// Checks that the content of `path` matches the digest in `cache_path`.
+///
+/// Text files may be tracked with digests calculated without line endings. If the binary digest
+/// doesn't match, the text digest is also checked. Cache paths without a known digest algorithm are
+/// not verified.
+pub(crate) fn verify_digest(cache_path: &XvcCachePath, path: &AbsolutePath) -> Result<()> {
+    let expected = cache_path.directory();
+    let Some(algorithm) = expected
+        .iter()
+        .next()
+        .and_then(|prefix| HashAlgorithm::from_str(prefix).ok())
+    else {
+        return Ok(());
+    };
+
+    let mut actual = XvcDigest::from_reader(fs::File::open(path)?, algorithm, false)?;
+    if actual.cache_dir() != expected {
+        let text_digest = XvcDigest::from_reader(fs::File::open(path)?, algorithm, true)?;
+        if text_digest.cache_dir() == expected {
+            return Ok(());
+        }
+        actual = text_digest;
+    }
+
+    if actual.cache_dir() == expected {
+        Ok(())
+    } else {
+        Err(Error::DigestMismatch {
+            cache_path: cache_path.to_string(),
+            actual: actual.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_digest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = AbsolutePath::from(dir.path().join("file"));
+        let content = b"line 1\r\nline 2\n";
+        fs::write(&path, content)?;
+
+        let binary = XvcDigest::from_bytes(content, HashAlgorithm::Blake3);
+        let text = XvcDigest::from_bytes(b"line 1line 2", HashAlgorithm::Blake3);
+        for digest in [binary, text] {
+            let cache_path = XvcCachePath::custom(&format!("{}/0.txt", digest.cache_dir()));
+            verify_digest(&cache_path, &path)?;
+        }
+
+        let other = XvcDigest::from_bytes(b"other", HashAlgorithm::Blake3);
+        let cache_path = XvcCachePath::custom(&format!("{}/0.txt", other.cache_dir()));
+        assert!(matches!(
+            verify_digest(&cache_path, &path),
+            Err(Error::DigestMismatch { .. })
+        ));
+        Ok(())
+    }
+}
@@ -1,18 +1,18 @@
 //! Wasabi storage implementation.
-use std::env;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
-use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use serde::{Deserialize, Serialize};
 use xvc_core::R1NStore;
-use xvc_core::XvcCachePath;
+use xvc_core::XvcRoot;
 use xvc_core::{XvcOutputSender, watch};
 
-use crate::{Error, Result, XvcStorage, XvcStorageEvent};
+use super::registry::{XvcStorageBackendRegistration, deserialize_backend};
+use crate::{Result, XvcStorage, XvcStorageEvent};
 use crate::{XvcStorageGuid, XvcStorageOperations};
 
-use super::XvcStoragePath;
 use super::async_common::XvcS3StorageOperations;
+use super::credentials::{self, XvcCredentialSpec};
 
 /// Configure a new Wasabi remote storage.
 ///
@@ -29,6 +29,7 @@ pub(crate) fn cmd_new_wasabi(
     bucket_name: String,
     endpoint: String,
     storage_prefix: String,
+    profile: Option<String>,
 ) -> Result<()> {
     let mut storage = XvcWasabiStorage {
         guid: XvcStorageGuid::new(),
@@ -36,6 +37,7 @@ pub(crate) fn cmd_new_wasabi(
         endpoint,
         bucket_name,
         storage_prefix,
+        profile,
     };
 
     watch!(storage);
@@ -48,7 +50,7 @@ pub(crate) fn cmd_new_wasabi(
         let event_e = xvc_root.new_entity();
         store.insert(
             store_e,
-            XvcStorage::Wasabi(storage.clone()),
+            XvcStorage::new(storage.clone()),
             event_e,
             XvcStorageEvent::Init(init_event.clone()),
         );
@@ -58,6 +60,63 @@ pub(crate) fn cmd_new_wasabi(
     Ok(())
 }
 
+/// Add a new Wasabi storage
+///
+/// Reads credentials from `WASABI_ACCESS_KEY_ID` and `WASABI_SECRET_ACCESS_KEY` environment variables.
+/// Alternatively you can use `XVC_STORAGE_ACCESS_KEY_ID_<storage_name>` and
+/// `XVC_STORAGE_SECRET_ACCESS_KEY_<storage_name>` environment variables if you have multiple storages of this type.
+/// The keys can also be kept in the credentials file or supplied by `credential.helper`.
+#[derive(Debug, Clone, Parser)]
+#[command(name = "wasabi")]
+pub struct NewWasabiStorageCLI {
+    /// Name of the storage
+    ///
+    /// This must be unique among all storages of the project
+    #[arg(long = "name", short = 'n')]
+    pub name: String,
+    /// Bucket name
+    #[arg(long)]
+    pub bucket_name: String,
+    /// Endpoint for the server, complete with the region if there is
+    ///
+    /// e.g. for eu-central-1 region, use s3.eu-central-1.wasabisys.com as the endpoint.
+    #[arg(long, default_value = "s3.wasabisys.com")]
+    pub endpoint: String,
+    /// You can set a directory in the bucket with this prefix
+    #[arg(long, default_value = "")]
+    pub storage_prefix: String,
+    /// Profile (section) in the credentials file to read the access keys.
+    ///
+    /// If not given, the section with the storage name is used.
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+/// Registration of the Wasabi backend in the [storage registry][super::registry]
+pub const REGISTRATION: XvcStorageBackendRegistration = XvcStorageBackendRegistration {
+    tag: "Wasabi",
+    command: NewWasabiStorageCLI::command,
+    run_new: |_input, output_snd, xvc_root, matches| {
+        let NewWasabiStorageCLI {
+            name,
+            bucket_name,
+            endpoint,
+            storage_prefix,
+            profile,
+        } = NewWasabiStorageCLI::from_arg_matches(matches)?;
+        cmd_new_wasabi(
+            output_snd,
+            xvc_root,
+            name,
+            bucket_name,
+            endpoint,
+            storage_prefix,
+            profile,
+        )
+    },
+    deserialize: deserialize_backend::<XvcWasabiStorage>,
+};
+
 /// A Wasabi storage configuration.
 #[derive(Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
 pub struct XvcWasabiStorage {
@@ -86,8 +145,17 @@ pub struct XvcWasabiStorage {
     ///
     /// Xvc checks the presence of Guid file before creating this folder.
     pub storage_prefix: String,
+
+    /// Profile (section) in the credentials file to read the access keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
+crate::xvc_storage_backend!(XvcWasabiStorage, "Wasabi", |s| format!(
+    "Wasabi:  {}\t{}\t{}.{}/{}",
+    s.name, s.guid, s.endpoint, s.bucket_name, s.storage_prefix
+));
+
 impl XvcS3StorageOperations for XvcWasabiStorage {
     fn storage_prefix(&self) -> String {
         self.storage_prefix.clone()
@@ -96,6 +164,10 @@ impl XvcS3StorageOperations for XvcWasabiStorage {
     fn guid(&self) -> &XvcStorageGuid {
         &self.guid
     }
+
+    fn storage_name(&self) -> &str {
+        &self.name
+    }
     fn bucket_name(&self) -> String {
         self.bucket_name.clone()
     }
@@ -104,9 +176,9 @@ impl XvcS3StorageOperations for XvcWasabiStorage {
         self.endpoint.clone()
     }
 
-    fn get_bucket(&self) -> Result<Box<Bucket>> {
+    fn get_bucket(&self, xvc_root: &XvcRoot) -> Result<Box<Bucket>> {
         // We'll just put guid file to endpoint/bucket/prefix/XVC_GUID_FILENAME
-        let credentials = self.credentials()?;
+        let credentials = self.credentials(xvc_root)?;
         let region: Region = Region::Custom {
             region: "".to_string(),
             endpoint: self.endpoint.clone(),
@@ -117,48 +189,14 @@ impl XvcS3StorageOperations for XvcWasabiStorage {
         Ok(bucket)
     }
 
-    fn credentials(&self) -> Result<Credentials> {
-        // Try storage-specific credentials
-        let specific_access_key_var = format!("XVC_STORAGE_ACCESS_KEY_ID_{}", self.name);
-        let specific_secret_key_var = format!("XVC_STORAGE_SECRET_ACCESS_KEY_{}", self.name);
-
-        if let (Ok(access_key), Ok(secret_key)) = (
-            env::var(&specific_access_key_var),
-            env::var(&specific_secret_key_var),
-        ) {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // Try storage-type credentials
-        let type_access_key_var = "WASABI_ACCESS_KEY_ID";
-        let type_secret_key_var = "WASABI_SECRET_ACCESS_KEY";
-        if let (Ok(access_key), Ok(secret_key)) =
-            (env::var(type_access_key_var), env::var(type_secret_key_var))
-        {
-            return Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
-                .map_err(|e| e.into());
-        }
-
-        // If both fail, return an error
-        Err(Error::CloudCredentialsNotFound {
-            storage_name: self.name.clone(),
-            var_pairs: vec![
-                (specific_access_key_var, specific_secret_key_var),
-                (
-                    type_access_key_var.to_string(),
-                    type_secret_key_var.to_string(),
-                ),
-            ],
-        })
-    }
-
-    fn build_storage_path(&self, cache_path: &XvcCachePath) -> XvcStoragePath {
-        XvcStoragePath::from(format!(
-            "{}/{}/{}",
-            self.storage_prefix,
-            self.guid(),
-            cache_path
-        ))
+    fn credential_spec(&self) -> XvcCredentialSpec {
+        XvcCredentialSpec::access_key_pair(
+            "wasabi",
+            &self.name,
+            self.profile.as_deref(),
+            credentials::url_host(&self.endpoint),
+            "WASABI_ACCESS_KEY_ID",
+            "WASABI_SECRET_ACCESS_KEY",
+        )
     }
 }